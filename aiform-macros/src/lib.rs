@@ -1,54 +1,270 @@
 //! Proc macros for aiform.
 
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, DeriveInput, ItemFn, LitStr};
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{
+    ext::IdentExt,
+    parse::{Parse, ParseStream},
+    parse_macro_input, DeriveInput, ItemFn, LitStr, Token,
+};
 
 /// Generates JSON schema for tool arguments.
 ///
-/// Supports structs and enums. Fields can use `#[desc("...")]` to add descriptions.
+/// Supports structs and enums. The type, its fields and enum variants can use
+/// `#[desc("...")]` to add descriptions, and otherwise use their doc comments.
+///
+/// Integer fields are bounded by their type: unsigned ones have a `minimum`
+/// of 0, and 8- and 16-bit ones also a `maximum`. `char` fields are strings
+/// of one character.
+///
+/// `std::time::Duration` fields deserialized with
+/// `#[serde(with = "aiform::duration")]`, or `aiform::duration::option` for
+/// `Option<Duration>`, are advertised as strings like `"30s"` or `"1h30m"`.
+/// Without it they are a compile error, as serde reads them as
+/// `{secs, nanos}`, which models do not send. `PathBuf` and
+/// `IpAddr` fields are strings described as such, and `Ipv4Addr` and
+/// `Ipv6Addr` fields strings with the `ipv4` and `ipv6` formats. With aiform's
+/// `chrono`, `uuid` and `url` features, `DateTime`, `NaiveDate`, `Uuid` and
+/// `Url` fields are strings with the `date-time`, `date`, `uuid` and `uri`
+/// formats.
+///
+/// `Option` fields are not required, and their schema also accepts `null`.
+/// Arrays like `[f64; 2]` are arrays of exactly that length, and tuples like
+/// `(u32, u32)` fixed-length arrays with one `prefixItems` schema per element.
+///
+/// `Box<T>`, `Rc<T>`, `Arc<T>` and `Cow<'static, T>` fields have the schema
+/// of `T`, so `Box<str>` and `Cow<'static, str>` are strings. Fields cannot
+/// be references, as arguments are deserialized from a value that is
+/// dropped after the call.
+///
+/// A newtype struct such as `struct UserId(String)` has the schema of its
+/// field, as serde reads it from the bare value. Other tuple structs are
+/// fixed-length arrays with one `prefixItems` schema per field.
+///
+/// An enum whose variants are all unit variants is a string with one of the
+/// variant names. Other enums are a `oneOf` with one schema per variant, in
+/// the representation serde uses: externally tagged by default, or as set by
+/// `#[serde(tag = "...")]`, `#[serde(tag = "...", content = "...")]` or
+/// `#[serde(untagged)]`.
+///
+/// Fields can be constrained with `#[schema(...)]`:
+///
+/// - integers and floats: `minimum`, `maximum`
+/// - strings: `min_length`, `max_length`, `pattern`, `format`
+/// - `Vec`s: `min_items`, `max_items`
+///
+/// - any field: `default`, a literal advertised as the field's default value
+///
+/// A constraint that does not apply to the field's type is a compile error.
+/// `default` only describes the default; pair it with `#[serde(default)]` or
+/// `#[serde(default = "path")]`, which leave the field out of `required`.
+///
+/// Property and variant names follow serde's `rename` and `rename_all`
+/// attributes, fields with `skip` or `skip_deserializing` are left out, and
+/// fields with `default` are not required. Aliases are accepted by serde but
+/// not advertised.
+///
+/// The generated code refers to `::aiform`. If the dependency is renamed, point
+/// the derive at it with `#[aiform(crate = "renamed")]`.
+///
+/// Generic types get a `T: ToolArg` bound for each type parameter their
+/// fields use. Replace the inferred bounds with
+/// `#[tool_arg(bound = "T: ToolArg + Clone")]`.
 ///
 /// # Example
 ///
 /// ```ignore
 /// #[derive(ToolArg, Deserialize)]
+/// #[desc("Where and how often to check")]
 /// struct Args {
 ///     #[desc("The location to check")]
+///     #[schema(min_length = 1, max_length = 100)]
 ///     location: String,
+///     #[schema(minimum = 1, maximum = 24)]
 ///     count: i32,
 /// }
+///
+/// #[derive(ToolArg, Deserialize)]
+/// enum Unit {
+///     #[desc("Degrees Celsius")]
+///     Celsius,
+///     Fahrenheit,
+/// }
 /// ```
-#[proc_macro_derive(ToolArg)]
+#[proc_macro_derive(ToolArg, attributes(desc, aiform, schema, tool_arg))]
 pub fn tool_arg_derive(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    impl_tool_arg(&input).into()
+    impl_tool_arg(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
 }
 
-fn impl_tool_arg(ast: &DeriveInput) -> proc_macro2::TokenStream {
+fn impl_tool_arg(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
-    match &ast.data {
-        syn::Data::Struct(s) => impl_tool_arg_struct(name, &s.fields),
-        syn::Data::Enum(e) => impl_tool_arg_enum(name, &e.variants, &ast.attrs),
-        _ => panic!("ToolArg supports structs and enums"),
+    let krate = crate_path(&ast.attrs)?;
+    let schema = schema_body(ast, &krate, Derive::ToolArg)?;
+    let json = quote!(#krate::__private::serde_json);
+    let generics = bounded_generics(ast, &syn::parse_quote!(#krate::ToolArg))?;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let schema_fn = cached(
+        &ast.generics,
+        &json,
+        quote!(#krate::__private::with_defs(<Self as #krate::ToolArg>::schema_with_defs)),
+    );
+    Ok(quote! {
+        impl #impl_generics #krate::ToolArg for #name #ty_generics #where_clause {
+            fn schema() -> #json::Value {
+                #schema_fn
+            }
+
+            fn schema_with_defs(defs: &mut #json::Map<::std::string::String, #json::Value>) -> #json::Value {
+                #schema
+            }
+        }
+    })
+}
+
+/// The derive a schema is generated for, which decides how the schemas of
+/// nested types are looked up.
+#[derive(Clone, Copy)]
+enum Derive {
+    /// Nested types implement `ToolArg`.
+    ToolArg,
+    /// Nested types implement `StructuredOutput`, or failing that `ToolArg`.
+    StructuredOutput,
+}
+
+/// What a schema is generated for.
+struct Target<'a> {
+    derive: Derive,
+    /// The deriving type, which its fields refer to by `$ref`.
+    this: Option<&'a syn::Ident>,
+    /// Set once a field refers to the deriving type.
+    recursive: std::cell::Cell<bool>,
+}
+
+impl<'a> Target<'a> {
+    fn new(derive: Derive, this: Option<&'a syn::Ident>) -> Self {
+        Target {
+            derive,
+            this,
+            recursive: std::cell::Cell::new(false),
+        }
+    }
+
+    /// Returns whether a field is listed in `required`.
+    ///
+    /// Strict schemas require every property and make the unlisted ones
+    /// nullable, but `null` does not deserialize into a `#[serde(default)]`
+    /// field, so structured outputs keep those required with their own type.
+    fn requires(&self, ty: &syn::Type, defaulted: bool) -> bool {
+        !is_option(ty) && (!defaulted || matches!(self.derive, Derive::StructuredOutput))
+    }
+
+    /// Returns the deriving type if a type names it.
+    fn this_named(&self, ty: &syn::TypePath, seg: &syn::PathSegment) -> Option<&'a syn::Ident> {
+        self.this.filter(|this| {
+            ty.qself.is_none()
+                && seg.arguments.is_empty()
+                && (seg.ident == **this || ty.path.is_ident("Self"))
+        })
+    }
+}
+
+/// Generates the expression building a struct's or an enum's schema, which
+/// collects the definitions of recursive types into `defs`.
+///
+/// A type that refers to itself is defined in `defs` under its name, and its
+/// schema is a `$ref` to the definition.
+fn schema_body(
+    ast: &DeriveInput,
+    krate: &syn::Path,
+    derive: Derive,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let target = Target::new(derive, Some(&ast.ident));
+    let schema = match &ast.data {
+        syn::Data::Struct(s) => struct_schema(krate, &s.fields, &ast.attrs, &target)?,
+        syn::Data::Enum(e) => enum_schema(krate, &e.variants, &ast.attrs, &target)?,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &ast.ident,
+                match derive {
+                    Derive::ToolArg => "ToolArg supports structs and enums",
+                    Derive::StructuredOutput => "StructuredOutput supports structs and enums",
+                },
+            ))
+        }
+    };
+    if !target.recursive.get() {
+        return Ok(schema);
     }
+    let def_name = ast.ident.unraw().to_string();
+    Ok(quote!(#krate::__private::define(defs, #def_name, |defs| #schema)))
 }
 
-fn impl_tool_arg_struct(name: &syn::Ident, fields: &syn::Fields) -> proc_macro2::TokenStream {
+fn struct_schema(
+    krate: &syn::Path,
+    fields: &syn::Fields,
+    attrs: &[syn::Attribute],
+    target: &Target,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let json = quote!(#krate::__private::serde_json);
+    let desc = get_desc(attrs);
+    let field_schema = |field| field_schema(krate, field, target);
+    match fields {
+        // A newtype is serialized as its field, so it has the field's schema.
+        syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+            let schema = field_schema(&fields.unnamed[0])?;
+            if desc.is_empty() {
+                return Ok(schema);
+            }
+            // Parenthesized, as `json!` reads a bare block as an object.
+            return Ok(quote!(({
+                let mut s = #schema;
+                s["description"] = #json::Value::String(#desc.to_string());
+                s
+            })));
+        }
+        syn::Fields::Unnamed(fields) => {
+            let items = fields
+                .unnamed
+                .iter()
+                .map(field_schema)
+                .collect::<syn::Result<Vec<_>>>()?;
+            let len = items.len();
+            let desc_expr = desc_expr(&desc);
+            return Ok(quote!(#json::json!({
+                "type": "array",
+                "prefixItems": [#(#items),*],
+                "minItems": #len,
+                "maxItems": #len
+                #desc_expr
+            })));
+        }
+        _ => {}
+    }
+
+    let desc_expr = desc_expr(&desc);
+    let container = serde_attrs(attrs)?;
     let mut properties = vec![];
     let mut required = vec![];
 
     for field in fields.iter() {
         let ident = field.ident.as_ref().unwrap();
+        let serde = serde_attrs(&field.attrs)?;
+        if serde.skip {
+            continue;
+        }
         let ty = &field.ty;
-        let desc = get_desc(&field.attrs);
-        let field_schema = schema_expr(ty, &desc);
-        let ident_str = ident.to_string();
+        let field_schema = field_schema(field)?;
+        let ident_str = serde.name(&ident.unraw().to_string(), &container, Case::field);
 
         properties.push(quote! {
             #ident_str: #field_schema
         });
 
-        if !is_option(ty) {
+        if target.requires(ty, serde.default || container.default) {
             required.push(quote!(#ident_str));
         }
     }
@@ -56,95 +272,194 @@ fn impl_tool_arg_struct(name: &syn::Ident, fields: &syn::Fields) -> proc_macro2:
     let properties_tokens = quote! { #(#properties),* };
     let required_tokens = quote! { #(#required),* };
 
-    quote! {
-        impl ToolArg for #name {
-            fn schema() -> serde_json::Value {
-                serde_json::json!({
-                    "type": "object",
-                    "properties": { #properties_tokens },
-                    "required": [#required_tokens]
-                })
-            }
-        }
-    }
+    Ok(quote! {
+        #krate::__private::serde_json::json!({
+            "type": "object",
+            "properties": { #properties_tokens },
+            "required": [#required_tokens]
+            #desc_expr
+        })
+    })
 }
 
-fn impl_tool_arg_enum(
-    name: &syn::Ident,
+fn enum_schema(
+    krate: &syn::Path,
     variants: &syn::punctuated::Punctuated<syn::Variant, syn::Token![,]>,
     attrs: &[syn::Attribute],
-) -> proc_macro2::TokenStream {
+    target: &Target,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let json = quote!(#krate::__private::serde_json);
     let desc = get_desc(attrs);
-    let mut one_of = vec![];
+    let container = serde_attrs(attrs)?;
+    let tagging = container.tagging();
 
+    let mut kept = vec![];
     for variant in variants.iter() {
-        let variant_name = variant.ident.to_string();
-        let mut properties = vec![quote!("type": serde_json::json!({"const": #variant_name}))];
-        let mut required = vec![quote!("type")];
-
-        match &variant.fields {
-            syn::Fields::Unit => {
-                // Unit variant: just the type
-            }
-            syn::Fields::Unnamed(fields) => {
-                if fields.unnamed.len() == 1 {
-                    // Single tuple field
-                    let field = &fields.unnamed[0];
-                    let field_ty = &field.ty;
-                    let value_schema = schema_expr(field_ty, "");
-                    properties.push(quote!("value": #value_schema));
-                    required.push(quote!("value"));
-                } else {
-                    // Multiple tuple fields: treat as array
-                    let items: Vec<_> = fields
-                        .unnamed
-                        .iter()
-                        .map(|f| schema_expr(&f.ty, ""))
-                        .collect();
-                    let items_tokens = quote! { #(#items),* };
-                    properties.push(quote!("value": serde_json::json!({"type": "array", "items": [#items_tokens]})));
-                    required.push(quote!("value"));
-                }
+        let variant_serde = serde_attrs(&variant.attrs)?;
+        if !variant_serde.skip {
+            kept.push((variant, variant_serde));
+        }
+    }
+    let all_unit = kept
+        .iter()
+        .all(|(variant, _)| matches!(variant.fields, syn::Fields::Unit));
+    if all_unit && matches!(tagging, Tagging::External) {
+        return Ok(unit_enum_schema(krate, &kept, &container, desc));
+    }
+
+    let mut one_of = vec![];
+    for (variant, variant_serde) in &kept {
+        let variant_name = variant_serde.name(
+            &variant.ident.unraw().to_string(),
+            &container,
+            Case::variant,
+        );
+        let desc = get_desc(&variant.attrs);
+        let variant_desc = desc_expr(&desc);
+        // For variants whose schema is built at runtime.
+        let set_desc = (!desc.is_empty())
+            .then(|| quote!(s["description"] = #json::Value::String(#desc.to_string());));
+        let content = variant_content(krate, variant, variant_serde, target)?;
+
+        let schema = match (&tagging, content) {
+            (Tagging::External, VariantContent::Unit) => {
+                quote!(#json::json!({"type": "string", "const": #variant_name #variant_desc}))
+            }
+            (Tagging::External, content) => {
+                let content = content.schema(&json);
+                quote!(#json::json!({
+                    "type": "object",
+                    "properties": {#variant_name: #content},
+                    "required": [#variant_name]
+                    #variant_desc
+                }))
+            }
+            (Tagging::Internal { tag }, VariantContent::Unit) => quote!(#json::json!({
+                "type": "object",
+                "properties": {#tag: {"const": #variant_name}},
+                "required": [#tag]
+                #variant_desc
+            })),
+            (
+                Tagging::Internal { tag },
+                VariantContent::Struct {
+                    properties,
+                    required,
+                },
+            ) => quote!(#json::json!({
+                "type": "object",
+                "properties": {#tag: {"const": #variant_name}, #(#properties),*},
+                "required": [#tag, #(#required),*]
+                #variant_desc
+            })),
+            (Tagging::Internal { tag }, VariantContent::Newtype(inner)) => {
+                // The tag sits among the fields of the inner struct.
+                // Parenthesized, as `json!` reads a bare block as an object.
+                quote!(({
+                    let mut s = #inner;
+                    s["properties"][#tag] = #json::json!({"const": #variant_name});
+                    match s["required"].as_array_mut() {
+                        ::std::option::Option::Some(required) => {
+                            required.insert(0, #json::json!(#tag))
+                        }
+                        ::std::option::Option::None => s["required"] = #json::json!([#tag]),
+                    }
+                    #set_desc
+                    s
+                }))
+            }
+            (Tagging::Internal { .. }, VariantContent::Tuple(_)) => {
+                return Err(syn::Error::new_spanned(
+                    &variant.ident,
+                    "internally tagged enums cannot have tuple variants",
+                ))
+            }
+            (Tagging::Adjacent { tag, .. }, VariantContent::Unit) => quote!(#json::json!({
+                "type": "object",
+                "properties": {#tag: {"const": #variant_name}},
+                "required": [#tag]
+                #variant_desc
+            })),
+            (Tagging::Adjacent { tag, content: key }, content) => {
+                let content = content.schema(&json);
+                quote!(#json::json!({
+                    "type": "object",
+                    "properties": {#tag: {"const": #variant_name}, #key: #content},
+                    "required": [#tag, #key]
+                    #variant_desc
+                }))
+            }
+            (Tagging::Untagged, VariantContent::Unit) => {
+                quote!(#json::json!({"type": "null" #variant_desc}))
             }
-            syn::Fields::Named(fields) => {
-                // Named fields
-                for field in &fields.named {
-                    let field_name = field.ident.as_ref().unwrap().to_string();
-                    let field_ty = &field.ty;
-                    let field_schema = schema_expr(field_ty, "");
-                    properties.push(quote!(#field_name: #field_schema));
-                    required.push(quote!(#field_name));
+            (Tagging::Untagged, content) => {
+                let content = content.schema(&json);
+                match set_desc {
+                    None => content,
+                    // Parenthesized, as `json!` reads a bare block as an object.
+                    Some(set_desc) => quote!(({
+                        let mut s = #content;
+                        #set_desc
+                        s
+                    })),
                 }
             }
-        }
+        };
+        one_of.push(schema);
+    }
 
-        let props_tokens = quote! { #(#properties),* };
-        let req_tokens = quote! { #(#required),* };
+    let desc_expr = desc_expr(&desc);
+    Ok(quote!(#json::json!({"oneOf": [#(#one_of),*] #desc_expr})))
+}
 
-        one_of.push(quote! {
-            serde_json::json!({"type": "object", "properties": {#props_tokens}, "required": [#req_tokens]})
-        });
+/// Generates the schema of an enum whose variants are all unit variants,
+/// which serde reads from plain strings.
+///
+/// Variant descriptions are listed in the enum's description, since an
+/// `enum` schema cannot describe its values.
+fn unit_enum_schema(
+    krate: &syn::Path,
+    variants: &[(&syn::Variant, SerdeAttrs)],
+    container: &SerdeAttrs,
+    desc: String,
+) -> proc_macro2::TokenStream {
+    let mut values = vec![];
+    let mut described = vec![];
+    for (variant, serde) in variants {
+        let value = serde.name(&variant.ident.unraw().to_string(), container, Case::variant);
+        let variant_desc = get_desc(&variant.attrs);
+        if !variant_desc.is_empty() {
+            described.push(format!(
+                "- {}: {}",
+                value,
+                variant_desc.replace("\n\n", " ")
+            ));
+        }
+        values.push(value);
     }
-
-    let one_of_tokens = quote! { #(#one_of),* };
-    let desc_expr = if desc.is_empty() {
-        quote!()
-    } else {
-        quote!(, "description": #desc)
+    let desc = match (desc.is_empty(), described.is_empty()) {
+        (_, true) => desc,
+        (true, false) => described.join("\n"),
+        (false, false) => format!("{}\n\n{}", desc, described.join("\n")),
     };
+    let desc_expr = desc_expr(&desc);
 
     quote! {
-        impl ToolArg for #name {
-            fn schema() -> serde_json::Value {
-                serde_json::json!({"oneOf": [#one_of_tokens] #desc_expr})
-            }
-        }
+        #krate::__private::serde_json::json!({
+            "type": "string",
+            "enum": [#(#values),*]
+            #desc_expr
+        })
     }
 }
 
-/// Turns an async function into an OpenAI tool.
+/// Turns a function into an OpenAI tool.
 ///
-/// The function must take one parameter that implements `ToolArg`.
+/// The function takes no parameters, one parameter that implements `ToolArg`,
+/// or several parameters that are each offered as a property of the tool's
+/// arguments. It returns a `Result` of a `String`, an `aiform::ToolOutput`, or
+/// any `Serialize` type, which is sent to the model as JSON. Without a
+/// description in the attribute, the function's doc comment is used.
 ///
 /// # Example
 ///
@@ -156,42 +471,391 @@ fn impl_tool_arg_enum(
 /// ```
 ///
 /// This generates a `GetWeatherTool` struct that implements the `Tool` trait.
+/// The struct has the function's visibility, and the function's `#[cfg]`,
+/// `#[cfg_attr]`, `#[allow]` and `#[expect]` attributes.
+///
+/// ```ignore
+/// /// Get the weather for a location.
+/// #[tool]
+/// async fn get_weather(args: WeatherArgs) -> Result<String> { /* ... */ }
+/// ```
+///
+/// A tool without parameters is offered with an empty object schema, and
+/// accepts `{}` or `null` as its arguments:
+///
+/// ```ignore
+/// #[tool("Get the current time")]
+/// async fn now() -> Result<String> { /* ... */ }
+/// ```
+///
+/// Synchronous functions are called directly, which suits quick computations.
+/// With the `blocking` option they run on tokio's blocking thread pool
+/// instead, so CPU-heavy work does not stall other tasks:
+///
+/// ```ignore
+/// #[tool("Convert Celsius to Fahrenheit")]
+/// fn to_fahrenheit(celsius: f64, digits: u32) -> Result<String> { /* ... */ }
+///
+/// #[tool("Hash a file", blocking)]
+/// fn hash_file(args: HashArgs) -> Result<String> { /* ... */ }
+/// ```
+///
+/// A first parameter that is a reference, or is marked `#[context]`, is the
+/// tool's context rather than an argument: the generated struct holds an
+/// `Arc` of it, created with `new` or `aiform::FromContext`, and passes it to
+/// every call.
+///
+/// ```ignore
+/// #[tool("Query the database")]
+/// async fn query(state: &AppState, args: QueryArgs) -> Result<String> { /* ... */ }
+///
+/// let tools = tools![with state: QueryTool, GetWeatherTool];
+/// ```
+///
+/// Parameters of a tool with several are described with `args(...)`:
+///
+/// ```ignore
+/// #[tool("Add numbers", args(a = "first operand", b = "second operand"))]
+/// async fn add(a: f64, b: f64) -> Result<String> { /* ... */ }
+/// ```
+///
+/// # Options
+///
+/// Options follow the description as `key = "value"` pairs:
+///
+/// - `name = "read_file"` sets the name the model calls the tool by. Defaults to
+///   the function's name. Tool names must be 1 to 64 ASCII letters, digits,
+///   underscores or hyphens.
+/// - `description = "..."` sets the description, in place of the leading string.
+/// - `struct_name = "ReaderTool"` sets the name of the generated struct.
+/// - `vis = "pub(crate)"` sets the visibility of the generated struct. Defaults
+///   to the function's visibility.
+/// - `blocking` runs a synchronous function with `tokio::task::spawn_blocking`.
+/// - `effects = "read_only"` or `effects = "mutating"` classifies the tool's side
+///   effects (see `aiform::ToolEffects`). Tools are mutating unless declared otherwise.
+/// - `crate = "renamed"` sets the path to the aiform crate when the dependency is
+///   renamed. Defaults to `::aiform`.
+///
+/// ```ignore
+/// #[tool("Look up an order", effects = "read_only")]
+/// async fn get_order(args: OrderArgs) -> Result<String> { /* ... */ }
+///
+/// #[tool(name = "fs-read", description = "Read a file", struct_name = "ReaderTool")]
+/// async fn read(args: ReadArgs) -> Result<String> { /* ... */ }
+/// ```
 #[proc_macro_attribute]
 pub fn tool(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let desc = if attr.is_empty() {
-        String::new()
-    } else {
-        let lit: LitStr = syn::parse(attr).unwrap();
-        lit.value()
-    };
+    let attr = parse_macro_input!(attr as ToolAttr);
     let func = parse_macro_input!(item as ItemFn);
-    impl_tool(&func, &desc).into()
+    impl_tool(&func, &attr)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Parsed arguments of `#[tool(...)]`.
+#[derive(Default)]
+struct ToolAttr {
+    desc: String,
+    name: Option<LitStr>,
+    struct_name: Option<LitStr>,
+    /// Parameter descriptions from `args(name = "...")`.
+    args: Vec<(syn::Ident, LitStr)>,
+    /// Whether a synchronous tool runs on the blocking thread pool.
+    blocking: bool,
+    effects: Option<syn::Ident>,
+    krate: Option<syn::Path>,
+    /// The generated struct's visibility, from `vis = "..."`.
+    vis: Option<syn::Visibility>,
+}
+
+impl Parse for ToolAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut attr = ToolAttr::default();
+
+        if input.peek(LitStr) {
+            attr.desc = input.parse::<LitStr>()?.value();
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        while !input.is_empty() {
+            if !input.peek(syn::Ident::peek_any) {
+                return Err(input.error(
+                    "expected a description string or `key = \"value\"` options, \
+                     e.g. `#[tool(\"Get the weather\", effects = \"read_only\")]`",
+                ));
+            }
+            let key = syn::Ident::parse_any(input)?;
+            if key == "args" && input.peek(syn::token::Paren) {
+                let content;
+                syn::parenthesized!(content in input);
+                while !content.is_empty() {
+                    let param = syn::Ident::parse_any(&content)?;
+                    content.parse::<Token![=]>()?;
+                    attr.args.push((param, content.parse()?));
+                    if !content.is_empty() {
+                        content.parse::<Token![,]>()?;
+                    }
+                }
+                if !input.is_empty() {
+                    input.parse::<Token![,]>()?;
+                }
+                continue;
+            }
+            if key == "blocking" && (input.is_empty() || input.peek(Token![,])) {
+                attr.blocking = true;
+                if !input.is_empty() {
+                    input.parse::<Token![,]>()?;
+                }
+                continue;
+            }
+            input.parse::<Token![=]>()?;
+            let value: LitStr = input.parse()?;
+
+            match key.to_string().as_str() {
+                "effects" => {
+                    let variant =
+                        match value.value().as_str() {
+                            "read_only" => "ReadOnly",
+                            "mutating" => "Mutating",
+                            _ => return Err(syn::Error::new(
+                                value.span(),
+                                "expected `effects = \"read_only\"` or `effects = \"mutating\"`",
+                            )),
+                        };
+                    attr.effects = Some(syn::Ident::new(variant, value.span()));
+                }
+                "name" => {
+                    validate_tool_name(&value.value(), value.span())?;
+                    attr.name = Some(value);
+                }
+                "description" => attr.desc = value.value(),
+                "struct_name" => {
+                    value.parse::<syn::Ident>()?;
+                    attr.struct_name = Some(value);
+                }
+                "crate" => attr.krate = Some(value.parse()?),
+                "vis" => attr.vis = Some(value.parse()?),
+                _ => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        format!(
+                            "unknown tool option `{}`; expected `name`, `description`, \
+                             `struct_name`, `vis`, `args`, `blocking`, `effects` or `crate`",
+                            key
+                        ),
+                    ))
+                }
+            }
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        Ok(attr)
+    }
+}
+
+/// Checks that `name` is a tool name OpenAI accepts: 1 to 64 ASCII letters,
+/// digits, underscores or hyphens.
+fn validate_tool_name(name: &str, span: proc_macro2::Span) -> syn::Result<()> {
+    let valid = (1..=64).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+    if valid {
+        Ok(())
+    } else {
+        Err(syn::Error::new(
+            span,
+            format!(
+                "invalid tool name `{}`; tool names must be 1 to 64 ASCII letters, digits, \
+                 underscores or hyphens",
+                name
+            ),
+        ))
+    }
 }
 
-fn impl_tool(func: &ItemFn, desc: &str) -> proc_macro2::TokenStream {
+fn impl_tool(func: &ItemFn, attr: &ToolAttr) -> syn::Result<proc_macro2::TokenStream> {
+    let desc = if attr.desc.is_empty() {
+        doc_comment(&func.attrs)
+    } else {
+        attr.desc.clone()
+    };
+    let krate = attr
+        .krate
+        .clone()
+        .unwrap_or_else(|| syn::parse_quote!(::aiform));
+    let effects = attr
+        .effects
+        .as_ref()
+        .map(|effects| quote!(const EFFECTS: #krate::ToolEffects = #krate::ToolEffects::#effects;));
+    let mut func = func.clone();
+    let context = context_param(&mut func)?;
     let name = &func.sig.ident;
-    let param = func
-        .sig
-        .inputs
-        .first()
-        .expect("Tool function must have at least one parameter");
-    let param_ty = if let syn::FnArg::Typed(p) = param {
-        &*p.ty
+    let json = quote!(#krate::__private::serde_json);
+    let is_async = func.sig.asyncness.is_some();
+    if attr.blocking && is_async {
+        return Err(syn::Error::new_spanned(
+            func.sig.asyncness,
+            "`blocking` runs synchronous functions on the blocking thread pool; \
+             remove `async` or `blocking`",
+        ));
+    }
+    let context_arg = match (&context, attr.blocking) {
+        (Some(_), false) => quote!(&self.context,),
+        (Some(_), true) => quote!(&context,),
+        (None, _) => quote!(),
+    };
+    let mut params = vec![];
+    for input in func.sig.inputs.iter().skip(context.is_some() as usize) {
+        match input {
+            syn::FnArg::Typed(param) => params.push(param),
+            syn::FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new_spanned(
+                    receiver,
+                    "tool functions cannot take `self`",
+                ))
+            }
+        }
+    }
+    let (parameters, parse_args, values) = match params.as_slice() {
+        [] => (
+            quote!(#json::json!({"type": "object", "properties": {}})),
+            quote!(),
+            vec![],
+        ),
+        [param] => {
+            let param_ty = &param.ty;
+            (
+                quote!(<#param_ty as #krate::ToolArg>::schema()),
+                quote!(),
+                vec![quote!(#krate::__private::parse_args::<#param_ty>(args)?)],
+            )
+        }
+        params => multi_param_tool(&krate, name, params, &attr.args)?,
+    };
+    let bindings: Vec<_> = (0..values.len())
+        .map(|i| format_ident!("arg{}", i))
+        .collect();
+    let invoke = quote!(#name(#context_arg #(#bindings),*));
+    let call = if attr.blocking {
+        let context = context
+            .as_ref()
+            .map(|_| quote!(let context = ::std::sync::Arc::clone(&self.context);));
+        quote!({
+            #context
+            #krate::__private::spawn_blocking(move || #invoke).await?
+        })
+    } else if is_async {
+        quote!(#invoke.await)
     } else {
-        panic!("Tool function parameters must be typed");
+        invoke
+    };
+    if params.len() < 2 {
+        if let Some((param, _)) = attr.args.first() {
+            return Err(syn::Error::new_spanned(
+                param,
+                "`args(...)` describes the parameters of tools with several parameters",
+            ));
+        }
+    }
+
+    let tool_name = match &attr.name {
+        Some(tool_name) => tool_name.value(),
+        None => {
+            let tool_name = name.unraw().to_string();
+            validate_tool_name(&tool_name, name.span())?;
+            tool_name
+        }
+    };
+    let tool_struct = match &attr.struct_name {
+        Some(struct_name) => struct_name.parse::<syn::Ident>()?,
+        None => syn::Ident::new(
+            &format!("{}Tool", to_pascal_case(&name.unraw().to_string())),
+            name.span(),
+        ),
     };
 
-    let pascal_name = to_pascal_case(&name.to_string());
-    let tool_struct = syn::Ident::new(&format!("{}Tool", pascal_name), name.span());
+    let vis = attr.vis.as_ref().unwrap_or(&func.vis);
+    // Applied to every generated item, so they compile exactly when the
+    // function does and share its lint settings.
+    let forwarded: Vec<_> = func
+        .attrs
+        .iter()
+        .filter(|attr| {
+            ["allow", "cfg", "cfg_attr", "expect"]
+                .iter()
+                .any(|name| attr.path().is_ident(name))
+        })
+        .collect();
+    let struct_doc = format!("The tool generated by `#[tool]` from `{}`.", name);
+    let struct_doc = if desc.is_empty() {
+        struct_doc
+    } else {
+        format!("{}\n\n{}", struct_doc, desc)
+    };
 
-    quote! {
+    let tool_struct_def = match &context {
+        Some(context_ty) => {
+            let new_doc = format!(
+                "Creates the tool, sharing `context` with every call to `{}`.",
+                name
+            );
+            quote! {
+                #(#forwarded)*
+                #[doc = #struct_doc]
+                #vis struct #tool_struct {
+                    context: ::std::sync::Arc<#context_ty>,
+                }
+
+                #(#forwarded)*
+                impl #tool_struct {
+                    #[doc = #new_doc]
+                    pub fn new(context: impl ::std::convert::Into<::std::sync::Arc<#context_ty>>) -> Self {
+                        Self {
+                            context: context.into(),
+                        }
+                    }
+                }
+
+                #(#forwarded)*
+                impl #krate::FromContext<#context_ty> for #tool_struct {
+                    fn from_context(context: &::std::sync::Arc<#context_ty>) -> Self {
+                        Self {
+                            context: ::std::sync::Arc::clone(context),
+                        }
+                    }
+                }
+            }
+        }
+        None => quote! {
+            #(#forwarded)*
+            #[doc = #struct_doc]
+            #vis struct #tool_struct;
+
+            #(#forwarded)*
+            impl<C: ?::std::marker::Sized> #krate::FromContext<C> for #tool_struct {
+                fn from_context(_context: &::std::sync::Arc<C>) -> Self {
+                    Self
+                }
+            }
+        },
+    };
+
+    Ok(quote! {
         #func
 
-        pub struct #tool_struct;
+        #tool_struct_def
 
-        impl Tool for #tool_struct {
-            const NAME: &'static str = stringify!(#name);
+        #(#forwarded)*
+        impl #krate::Tool for #tool_struct {
+            const NAME: &'static str = #tool_name;
             const DESCRIPTION: &'static str = #desc;
+            #effects
 
             fn name() -> &'static str {
                 Self::NAME
@@ -201,135 +865,1120 @@ fn impl_tool(func: &ItemFn, desc: &str) -> proc_macro2::TokenStream {
                 Self::DESCRIPTION
             }
 
-            fn parameters() -> serde_json::Value {
-                <#param_ty as ToolArg>::schema()
+            fn parameters() -> #krate::__private::serde_json::Value {
+                #parameters
             }
 
-            async fn call(&self, args: serde_json::Value) -> std::result::Result<String, Box<dyn std::error::Error + Send + Sync>> {
-                let parsed_args: #param_ty = serde_json::from_value(args)?;
-                match #name(parsed_args).await {
-                    Ok(result) => Ok(result),
-                    Err(e) => Err(Box::new(e) as Box<dyn std::error::Error + Send + Sync>),
+            async fn call(
+                &self,
+                args: #krate::__private::serde_json::Value,
+            ) -> ::std::result::Result<
+                ::std::string::String,
+                ::std::boxed::Box<dyn ::std::error::Error + ::std::marker::Send + ::std::marker::Sync>,
+            > {
+                #parse_args
+                #(let #bindings = #values;)*
+                match #call {
+                    ::std::result::Result::Ok(result) => {
+                        #[allow(unused_imports)]
+                        use #krate::__private::{IntoStringOutput as _, SerializeOutput as _};
+                        let kind = (&&#krate::__private::OutputKind(&result)).output_kind();
+                        ::std::result::Result::Ok(kind.convert(result)?)
+                    }
+                    ::std::result::Result::Err(e) => ::std::result::Result::Err(
+                        ::std::boxed::Box::new(e)
+                            as ::std::boxed::Box<
+                                dyn ::std::error::Error + ::std::marker::Send + ::std::marker::Sync,
+                            >,
+                    ),
                 }
             }
         }
+    })
+}
+
+/// Finds the context parameter of a tool function: its first parameter, if
+/// that is a reference or marked `#[context]`. Removes the `#[context]`
+/// marker and returns the referenced type.
+fn context_param(func: &mut ItemFn) -> syn::Result<Option<syn::Type>> {
+    let Some(syn::FnArg::Typed(first)) = func.sig.inputs.first_mut() else {
+        return Ok(None);
+    };
+    let marked = first
+        .attrs
+        .iter()
+        .any(|attr| attr.path().is_ident("context"));
+    first.attrs.retain(|attr| !attr.path().is_ident("context"));
+    match &*first.ty {
+        syn::Type::Reference(reference) if reference.mutability.is_none() => {
+            Ok(Some((*reference.elem).clone()))
+        }
+        _ if marked => Err(syn::Error::new_spanned(
+            &first.ty,
+            "a tool's context parameter must be a shared reference, e.g. `&AppState`",
+        )),
+        _ => Ok(None),
     }
 }
 
+/// Generates the parameter schema of a tool function with several
+/// parameters, one property per parameter, and the statements and
+/// expressions that read each parameter from the arguments.
+fn multi_param_tool(
+    krate: &syn::Path,
+    name: &syn::Ident,
+    params: &[&syn::PatType],
+    descs: &[(syn::Ident, LitStr)],
+) -> syn::Result<(
+    proc_macro2::TokenStream,
+    proc_macro2::TokenStream,
+    Vec<proc_macro2::TokenStream>,
+)> {
+    let json = quote!(#krate::__private::serde_json);
+    let mut names = vec![];
+    for param in params {
+        let syn::Pat::Ident(pat) = &*param.pat else {
+            return Err(syn::Error::new_spanned(
+                &param.pat,
+                "tool parameters must be plain names",
+            ));
+        };
+        names.push(pat.ident.unraw().to_string());
+    }
+    for (param, _) in descs {
+        if !names.contains(&param.unraw().to_string()) {
+            return Err(syn::Error::new_spanned(
+                param,
+                format!("`{}` is not a parameter of `{}`", param, name),
+            ));
+        }
+    }
+
+    let mut properties = vec![];
+    let mut required = vec![];
+    let mut values = vec![];
+    for (param, param_name) in params.iter().zip(&names) {
+        let ty = &param.ty;
+        let desc = descs
+            .iter()
+            .find(|(ident, _)| ident.unraw() == param_name)
+            .map(|(_, desc)| desc.value())
+            .unwrap_or_default();
+        let schema = schema_expr(krate, ty, &desc, &Target::new(Derive::ToolArg, None))?;
+        properties.push(quote!(#param_name: #schema));
+        if !is_option(ty) {
+            required.push(param_name);
+        }
+        let missing = format!("missing field `{}`", param_name);
+        values.push(quote! {
+            match args.remove(#param_name) {
+                ::std::option::Option::Some(value) => #json::from_value::<#ty>(value)?,
+                ::std::option::Option::None => #json::from_value::<#ty>(#json::Value::Null)
+                    .map_err(|_| #missing)?,
+            }
+        });
+    }
+
+    let parameters = cached(
+        &syn::Generics::default(),
+        &json,
+        quote!(#krate::__private::with_defs(|defs| #json::json!({
+            "type": "object",
+            "properties": {#(#properties),*},
+            "required": [#(#required),*]
+        }))),
+    );
+    let parse_args = quote! {
+        let mut args = match args {
+            #json::Value::Object(args) => args,
+            #json::Value::Null => #json::Map::new(),
+            other => {
+                return ::std::result::Result::Err(
+                    ::std::format!("expected an object of arguments, got {}", other).into(),
+                )
+            }
+        };
+    };
+    Ok((parameters, parse_args, values))
+}
+
 /// Generates JSON schema for structured output.
 ///
 /// Use this with OpenAI's structured output feature to get typed responses.
+/// The schema is built like the `ToolArg` one, from the same `#[desc]`,
+/// `#[schema]` and serde attributes, and adapted to strict mode: every
+/// property is required, `Option` fields are nullable, and extra properties
+/// are rejected. Fields with `#[serde(default)]` keep their own type, since
+/// `null` would not deserialize into them; the model always sends a value.
+/// It does not need `ToolArg`; nested types implement either trait, and a
+/// nested type with defaulted fields should derive `StructuredOutput` too.
 ///
 /// # Example
 ///
 /// ```ignore
-/// #[derive(StructuredOutput, ToolArg, Deserialize)]
+/// #[derive(StructuredOutput, Deserialize)]
 /// struct Response {
 ///     summary: String,
 ///     confidence: f64,
 /// }
 /// ```
-#[proc_macro_derive(StructuredOutput)]
+///
+/// Like `ToolArg`, accepts `#[aiform(crate = "renamed")]`, and bounds the
+/// type parameters of generic types, by `StructuredOutput` here, unless
+/// `#[tool_arg(bound = "...")]` says otherwise.
+#[proc_macro_derive(StructuredOutput, attributes(desc, aiform, schema, tool_arg))]
 pub fn structured_output_derive(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    impl_structured_output(&input).into()
+    impl_structured_output(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
 }
 
-fn impl_structured_output(ast: &DeriveInput) -> proc_macro2::TokenStream {
+fn impl_structured_output(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
+    let krate = crate_path(&ast.attrs)?;
+    let schema = schema_body(ast, &krate, Derive::StructuredOutput)?;
+    let schema_name = name.unraw().to_string();
+    let generics = bounded_generics(ast, &syn::parse_quote!(#krate::StructuredOutput))?;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let schema = cached(
+        &ast.generics,
+        &quote!(#krate::__private::serde_json),
+        quote!(#krate::__private::strict(#krate::__private::with_defs(|defs| #schema))),
+    );
+    Ok(quote! {
+        impl #impl_generics #krate::StructuredOutput for #name #ty_generics #where_clause {
+            fn schema() -> #krate::__private::serde_json::Value {
+                #schema
+            }
+
+            fn schema_name() -> ::std::string::String {
+                ::std::string::String::from(#schema_name)
+            }
+        }
+    })
+}
+
+// Helper functions
+
+/// Wraps the expression building a schema so it runs once and later calls
+/// return a clone. Generic types build theirs every time, since a static in
+/// a generic function is shared by all its instantiations.
+fn cached(
+    generics: &syn::Generics,
+    json: &proc_macro2::TokenStream,
+    build: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    if generics.type_params().next().is_some() || generics.const_params().next().is_some() {
+        return build;
+    }
     quote! {
-        impl StructuredOutput for #name {
-            fn schema() -> serde_json::Value {
-                // For structured output, we can use the same schema as ToolArg
-                // But in practice, OpenAI structured output might require specific format
-                // For now, assume similar to ToolArg
-                <#name as ToolArg>::schema()
+        static SCHEMA: ::std::sync::OnceLock<#json::Value> = ::std::sync::OnceLock::new();
+        ::std::clone::Clone::clone(SCHEMA.get_or_init(|| #build))
+    }
+}
+
+/// Returns the path to the aiform crate, from `#[aiform(crate = "...")]` if present.
+fn crate_path(attrs: &[syn::Attribute]) -> syn::Result<syn::Path> {
+    let mut krate = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("aiform")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                krate = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unknown aiform option; expected `crate`"))
+            }
+        })?;
+    }
+    Ok(krate.unwrap_or_else(|| syn::parse_quote!(::aiform)))
+}
+
+/// Returns the type's generics with a `T: Trait` bound for each type
+/// parameter its schema reads, as serde bounds its derives. Parameters only
+/// used by skipped fields are left unbounded, and
+/// `#[tool_arg(bound = "...")]` replaces the inferred bounds.
+fn bounded_generics(ast: &DeriveInput, bound: &syn::Path) -> syn::Result<syn::Generics> {
+    let mut generics = ast.generics.clone();
+    let predicates = match bound_override(&ast.attrs)? {
+        Some(predicates) => predicates,
+        None => {
+            let mut used = std::collections::HashSet::new();
+            for ty in schema_field_types(ast)? {
+                collect_idents(quote!(#ty), &mut used);
+            }
+            ast.generics
+                .type_params()
+                .filter(|param| used.contains(&param.ident))
+                .map(|param| {
+                    let ident = &param.ident;
+                    syn::parse_quote!(#ident: #bound)
+                })
+                .collect()
+        }
+    };
+    generics.make_where_clause().predicates.extend(predicates);
+    Ok(generics)
+}
+
+/// Reads `#[tool_arg(bound = "...")]`, the where predicates to use instead
+/// of the inferred bounds.
+fn bound_override(attrs: &[syn::Attribute]) -> syn::Result<Option<Vec<syn::WherePredicate>>> {
+    let mut predicates = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("tool_arg")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("bound") {
+                let bound = meta.value()?.parse::<LitStr>()?;
+                let parsed = bound.parse_with(
+                    syn::punctuated::Punctuated::<syn::WherePredicate, Token![,]>::parse_terminated,
+                )?;
+                predicates = Some(parsed.into_iter().collect());
+                Ok(())
+            } else {
+                Err(meta.error("unknown tool_arg option; expected `bound`"))
+            }
+        })?;
+    }
+    Ok(predicates)
+}
+
+/// Returns the types of the fields a struct's or an enum's schema includes.
+fn schema_field_types(ast: &DeriveInput) -> syn::Result<Vec<&syn::Type>> {
+    let fields: Vec<&syn::Field> = match &ast.data {
+        syn::Data::Struct(s) => s.fields.iter().collect(),
+        syn::Data::Enum(e) => {
+            let mut fields = vec![];
+            for variant in &e.variants {
+                if !serde_attrs(&variant.attrs)?.skip {
+                    fields.extend(variant.fields.iter());
+                }
             }
+            fields
+        }
+        syn::Data::Union(_) => vec![],
+    };
+    let mut types = vec![];
+    for field in fields {
+        if !serde_attrs(&field.attrs)?.skip {
+            types.push(&field.ty);
         }
     }
+    Ok(types)
 }
 
-// Helper functions
+/// Collects every identifier in a token stream, such as the type parameters
+/// a field type mentions.
+fn collect_idents(
+    tokens: proc_macro2::TokenStream,
+    idents: &mut std::collections::HashSet<syn::Ident>,
+) {
+    for token in tokens {
+        match token {
+            proc_macro2::TokenTree::Ident(ident) => {
+                idents.insert(ident);
+            }
+            proc_macro2::TokenTree::Group(group) => collect_idents(group.stream(), idents),
+            _ => {}
+        }
+    }
+}
+
+/// What the serde attributes of a type, field or variant change about its
+/// schema.
+#[derive(Default)]
+struct SerdeAttrs {
+    rename: Option<String>,
+    rename_all: Option<Case>,
+    skip: bool,
+    default: bool,
+    /// Set by `with` or `deserialize_with`.
+    with: bool,
+    tag: Option<String>,
+    content: Option<String>,
+    untagged: bool,
+}
+
+impl SerdeAttrs {
+    /// Returns the name serde deserializes `ident` under, given the
+    /// `rename_all` of its container.
+    fn name(&self, ident: &str, container: &SerdeAttrs, apply: fn(Case, &str) -> String) -> String {
+        match (&self.rename, container.rename_all) {
+            (Some(rename), _) => rename.clone(),
+            (None, Some(case)) => apply(case, ident),
+            (None, None) => ident.to_string(),
+        }
+    }
+
+    /// Returns how an enum with these attributes represents its variants.
+    fn tagging(&self) -> Tagging {
+        match (&self.tag, &self.content) {
+            _ if self.untagged => Tagging::Untagged,
+            (Some(tag), Some(content)) => Tagging::Adjacent {
+                tag: tag.clone(),
+                content: content.clone(),
+            },
+            (Some(tag), None) => Tagging::Internal { tag: tag.clone() },
+            (None, _) => Tagging::External,
+        }
+    }
+}
+
+/// How serde represents an enum's variants.
+enum Tagging {
+    /// `{"Variant": content}`, or `"Variant"` for unit variants.
+    External,
+    /// `{"tag": "Variant", ...fields}`, from `#[serde(tag = "...")]`.
+    Internal { tag: String },
+    /// `{"tag": "Variant", "content": content}`, from
+    /// `#[serde(tag = "...", content = "...")]`.
+    Adjacent { tag: String, content: String },
+    /// The content alone, from `#[serde(untagged)]`.
+    Untagged,
+}
+
+/// The data a variant carries, as schema expressions.
+enum VariantContent {
+    Unit,
+    Newtype(proc_macro2::TokenStream),
+    Tuple(Vec<proc_macro2::TokenStream>),
+    Struct {
+        properties: Vec<proc_macro2::TokenStream>,
+        required: Vec<String>,
+    },
+}
+
+impl VariantContent {
+    /// Returns the schema of the content on its own.
+    fn schema(self, json: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        match self {
+            VariantContent::Unit => quote!(#json::Value::Null),
+            VariantContent::Newtype(schema) => schema,
+            VariantContent::Tuple(items) => {
+                let len = items.len();
+                quote!(#json::json!({
+                    "type": "array",
+                    "items": [#(#items),*],
+                    "minItems": #len,
+                    "maxItems": #len
+                }))
+            }
+            VariantContent::Struct {
+                properties,
+                required,
+            } => quote!(#json::json!({
+                "type": "object",
+                "properties": {#(#properties),*},
+                "required": [#(#required),*]
+            })),
+        }
+    }
+}
+
+/// Reads the fields of an enum variant.
+fn variant_content(
+    krate: &syn::Path,
+    variant: &syn::Variant,
+    variant_serde: &SerdeAttrs,
+    target: &Target,
+) -> syn::Result<VariantContent> {
+    let field_schema = |field| field_schema(krate, field, target);
+    Ok(match &variant.fields {
+        syn::Fields::Unit => VariantContent::Unit,
+        syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+            VariantContent::Newtype(field_schema(&fields.unnamed[0])?)
+        }
+        syn::Fields::Unnamed(fields) => VariantContent::Tuple(
+            fields
+                .unnamed
+                .iter()
+                .map(field_schema)
+                .collect::<syn::Result<_>>()?,
+        ),
+        syn::Fields::Named(fields) => {
+            // Renamed by the variant's own `rename_all`
+            let mut properties = vec![];
+            let mut required = vec![];
+            for field in &fields.named {
+                let serde = serde_attrs(&field.attrs)?;
+                if serde.skip {
+                    continue;
+                }
+                let field_name = serde.name(
+                    &field.ident.as_ref().unwrap().unraw().to_string(),
+                    variant_serde,
+                    Case::field,
+                );
+                let schema = field_schema(field)?;
+                properties.push(quote!(#field_name: #schema));
+                if target.requires(&field.ty, serde.default) {
+                    required.push(field_name);
+                }
+            }
+            VariantContent::Struct {
+                properties,
+                required,
+            }
+        }
+    })
+}
+
+/// A `rename_all` casing.
+#[derive(Clone, Copy)]
+enum Case {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl Case {
+    fn parse(value: &LitStr) -> syn::Result<Self> {
+        Ok(match value.value().as_str() {
+            "lowercase" => Case::Lower,
+            "UPPERCASE" => Case::Upper,
+            "PascalCase" => Case::Pascal,
+            "camelCase" => Case::Camel,
+            "snake_case" => Case::Snake,
+            "SCREAMING_SNAKE_CASE" => Case::ScreamingSnake,
+            "kebab-case" => Case::Kebab,
+            "SCREAMING-KEBAB-CASE" => Case::ScreamingKebab,
+            _ => return Err(syn::Error::new(value.span(), "unknown rename_all casing")),
+        })
+    }
+
+    /// Renames a snake_case field the way serde does.
+    fn field(self, field: &str) -> String {
+        match self {
+            Case::Lower | Case::Snake => field.to_string(),
+            Case::Upper | Case::ScreamingSnake => field.to_ascii_uppercase(),
+            Case::Pascal => to_pascal_case(field),
+            Case::Camel => camel_case(field),
+            Case::Kebab => field.replace('_', "-"),
+            Case::ScreamingKebab => field.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+
+    /// Renames a PascalCase variant the way serde does.
+    fn variant(self, variant: &str) -> String {
+        let snake = || {
+            let mut snake = String::new();
+            for (i, c) in variant.char_indices() {
+                if c.is_uppercase() && i != 0 {
+                    snake.push('_');
+                }
+                snake.push(c.to_ascii_lowercase());
+            }
+            snake
+        };
+        match self {
+            Case::Lower => variant.to_ascii_lowercase(),
+            Case::Upper => variant.to_ascii_uppercase(),
+            Case::Pascal => variant.to_string(),
+            Case::Camel => {
+                let mut chars = variant.chars();
+                match chars.next() {
+                    Some(first) => first.to_lowercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            }
+            Case::Snake => snake(),
+            Case::ScreamingSnake => snake().to_ascii_uppercase(),
+            Case::Kebab => snake().replace('_', "-"),
+            Case::ScreamingKebab => snake().to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+}
+
+/// Reads the serde attributes that change a schema, ignoring the others.
+///
+/// For `rename` and `rename_all` with separate `serialize` and
+/// `deserialize` values, the `deserialize` value is used, since that is what
+/// the tool call's arguments must match.
+fn serde_attrs(attrs: &[syn::Attribute]) -> syn::Result<SerdeAttrs> {
+    let mut serde = SerdeAttrs::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                if let Some(name) = deserialize_value(&meta)? {
+                    serde.rename = Some(name.value());
+                }
+            } else if meta.path.is_ident("rename_all") {
+                if let Some(case) = deserialize_value(&meta)? {
+                    serde.rename_all = Some(Case::parse(&case)?);
+                }
+            } else if meta.path.is_ident("tag") {
+                serde.tag = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("content") {
+                serde.content = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("untagged") {
+                serde.untagged = true;
+            } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_deserializing") {
+                serde.skip = true;
+            } else if meta.path.is_ident("default") {
+                serde.default = true;
+                skip_meta_value(&meta)?;
+            } else if meta.path.is_ident("with") || meta.path.is_ident("deserialize_with") {
+                serde.with = true;
+                skip_meta_value(&meta)?;
+            } else {
+                skip_meta_value(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(serde)
+}
+
+/// Reads `key = "value"` or the `deserialize` entry of
+/// `key(serialize = "...", deserialize = "...")`.
+fn deserialize_value(meta: &syn::meta::ParseNestedMeta) -> syn::Result<Option<LitStr>> {
+    if meta.input.peek(Token![=]) {
+        return Ok(Some(meta.value()?.parse()?));
+    }
+    let mut value = None;
+    meta.parse_nested_meta(|nested| {
+        let lit: LitStr = nested.value()?.parse()?;
+        if nested.path.is_ident("deserialize") {
+            value = Some(lit);
+        }
+        Ok(())
+    })?;
+    Ok(value)
+}
 
-/// Extracts description from #[desc("...")] attribute.
+/// Consumes the value of a serde option this derive does not need.
+fn skip_meta_value(meta: &syn::meta::ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<syn::Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        let content;
+        syn::parenthesized!(content in meta.input);
+        content.parse::<proc_macro2::TokenStream>()?;
+    }
+    Ok(())
+}
+
+/// Extracts description from #[desc("...")] attribute, falling back to the
+/// doc comment.
 fn get_desc(attrs: &[syn::Attribute]) -> String {
     for attr in attrs {
         if attr.path().is_ident("desc") {
-            if let Ok(lit) = attr.parse_args::<syn::Lit>() {
-                if let syn::Lit::Str(s) = lit {
-                    return s.value();
-                }
+            if let Ok(syn::Lit::Str(s)) = attr.parse_args::<syn::Lit>() {
+                return s.value();
+            }
+        }
+    }
+    doc_comment(attrs)
+}
+
+/// Joins the lines of a doc comment. Lines of a paragraph are joined with
+/// spaces, and paragraphs with a blank line.
+fn doc_comment(attrs: &[syn::Attribute]) -> String {
+    let mut paragraphs: Vec<Vec<String>> = vec![Vec::new()];
+    for attr in attrs {
+        let syn::Meta::NameValue(ref meta) = attr.meta else {
+            continue;
+        };
+        if !meta.path.is_ident("doc") {
+            continue;
+        }
+        let syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Str(ref line),
+            ..
+        }) = meta.value
+        else {
+            continue;
+        };
+        // An empty `///` line has no lines at all.
+        let value = line.value();
+        for line in value.lines().chain(value.is_empty().then_some("")) {
+            let line = line.trim();
+            if line.is_empty() {
+                paragraphs.push(Vec::new());
+            } else {
+                paragraphs.last_mut().unwrap().push(line.to_string());
             }
         }
     }
-    String::new()
+    paragraphs
+        .into_iter()
+        .filter(|lines| !lines.is_empty())
+        .map(|lines| lines.join(" "))
+        .collect::<Vec<_>>()
+        .join("\n\n")
 }
 
-/// Generates a JSON schema expression for a Rust type.
-fn schema_expr(ty: &syn::Type, desc: &str) -> proc_macro2::TokenStream {
-    let desc_expr = if desc.is_empty() {
+/// Returns a trailing `"description"` entry for a `json!` object, or nothing
+/// if `desc` is empty.
+fn desc_expr(desc: &str) -> proc_macro2::TokenStream {
+    if desc.is_empty() {
         quote!()
     } else {
         quote!(, "description": #desc)
+    }
+}
+
+/// Appends a format hint to a field description, or uses it as the
+/// description if there is none.
+fn hinted(desc: &str, hint: &str) -> String {
+    if desc.is_empty() {
+        hint.to_string()
+    } else {
+        format!("{} ({})", desc, hint)
+    }
+}
+
+/// Generates the schema of a field, with its `#[schema(...)]` constraints.
+///
+/// `Duration` fields deserialized `with` a helper such as `aiform::duration`
+/// are strings with a hint of the format.
+fn field_schema(
+    krate: &syn::Path,
+    field: &syn::Field,
+    target: &Target,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let desc = get_desc(&field.attrs);
+    let duration = match option_inner(&field.ty) {
+        Some(inner) if is_std_duration(inner) => Some(true),
+        _ => is_std_duration(&field.ty).then_some(false),
+    };
+    let schema = match duration {
+        Some(optional) if serde_attrs(&field.attrs)?.with => {
+            let desc = if desc.is_empty() {
+                quote!(#krate::duration::SCHEMA_HINT)
+            } else {
+                quote!(::std::format!("{} ({})", #desc, #krate::duration::SCHEMA_HINT))
+            };
+            let schema = quote!(#krate::__private::serde_json::json!({
+                "type": "string",
+                "description": #desc
+            }));
+            if optional {
+                quote!(#krate::__private::nullable(#schema))
+            } else {
+                schema
+            }
+        }
+        _ => schema_expr(krate, &field.ty, &desc, target)?,
     };
+    constrained(krate, schema, &field.ty, &field.attrs)
+}
 
-    if let syn::Type::Path(p) = ty {
-        if let Some(seg) = p.path.segments.last() {
-            match seg.ident.to_string().as_str() {
-                "String" => quote!(serde_json::json!({"type": "string" #desc_expr})),
-                "i32" | "i64" | "u32" | "u64" | "isize" | "usize" => {
-                    quote!(serde_json::json!({"type": "integer" #desc_expr}))
+/// Generates the schema of a field type.
+///
+/// Types not known here are assumed to implement the trait the target's
+/// derive names, and add their definitions to `defs`. Types that cannot, such
+/// as references and tuples, are a compile error.
+fn schema_expr(
+    krate: &syn::Path,
+    ty: &syn::Type,
+    desc: &str,
+    target: &Target,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let json = quote!(#krate::__private::serde_json);
+    let desc_expr = desc_expr(desc);
+    let unsupported = || {
+        syn::Error::new_spanned(
+            ty,
+            "unsupported tool argument type; expected `String`, a number, `bool`, `char`, \
+             `Duration`, `Vec<T>`, `Option<T>`, an array, a tuple or a type deriving `ToolArg`",
+        )
+    };
+
+    let p = match ty {
+        syn::Type::Path(p) => p,
+        syn::Type::Array(array) => {
+            let items = schema_expr(krate, &array.elem, "", target)?;
+            let len = &array.len;
+            return Ok(quote!(#json::json!({
+                "type": "array",
+                "items": #items,
+                "minItems": (#len),
+                "maxItems": (#len)
+                #desc_expr
+            })));
+        }
+        // Serde reads `()` from `null`.
+        syn::Type::Tuple(tuple) if tuple.elems.is_empty() => {
+            return Ok(quote!(#json::json!({"type": "null" #desc_expr})));
+        }
+        syn::Type::Tuple(tuple) => {
+            let items = tuple
+                .elems
+                .iter()
+                .map(|elem| schema_expr(krate, elem, "", target))
+                .collect::<syn::Result<Vec<_>>>()?;
+            let len = items.len();
+            return Ok(quote!(#json::json!({
+                "type": "array",
+                "prefixItems": [#(#items),*],
+                "minItems": #len,
+                "maxItems": #len
+                #desc_expr
+            })));
+        }
+        syn::Type::Paren(paren) => return schema_expr(krate, &paren.elem, desc, target),
+        // Arguments are deserialized from a value that does not outlive the
+        // call, so fields cannot borrow from it.
+        syn::Type::Reference(reference) => {
+            let name = |ty: &syn::Type| quote!(#ty).to_string().replace(' ', "");
+            let borrowed = name(&reference.elem);
+            let owned = match &*reference.elem {
+                syn::Type::Path(p) if p.path.is_ident("str") => "String".to_string(),
+                syn::Type::Slice(slice) => format!("Vec<{}>", name(&slice.elem)),
+                _ => borrowed.clone(),
+            };
+            return Err(syn::Error::new_spanned(
+                ty,
+                format!(
+                    "ToolArg fields must be owned; use {} instead of &{}",
+                    owned, borrowed
+                ),
+            ));
+        }
+        _ => return Err(unsupported()),
+    };
+    let Some(seg) = p.path.segments.last() else {
+        return Err(unsupported());
+    };
+    let inner_ty = || match &seg.arguments {
+        syn::PathArguments::AngleBracketed(args) => match args.args.first() {
+            Some(syn::GenericArgument::Type(inner_ty)) if args.args.len() == 1 => Ok(inner_ty),
+            _ => Err(unsupported()),
+        },
+        _ => Err(unsupported()),
+    };
+    Ok(match seg.ident.to_string().as_str() {
+        // `str` behind an owning pointer, such as `Box<str>`.
+        "String" | "str" => quote!(#json::json!({"type": "string" #desc_expr})),
+        name @ ("i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64"
+        | "u128" | "usize") => {
+            let bounds = integer_bounds(name);
+            quote!(#json::json!({"type": "integer" #bounds #desc_expr}))
+        }
+        "f32" | "f64" => quote!(#json::json!({"type": "number" #desc_expr})),
+        "bool" => quote!(#json::json!({"type": "boolean" #desc_expr})),
+        "char" => quote!(#json::json!({
+            "type": "string",
+            "minLength": 1,
+            "maxLength": 1
+            #desc_expr
+        })),
+        // Fields deserialized `with` a helper are handled by `field_schema`.
+        "Duration" if is_std_duration(ty) => {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "serde reads `Duration` as `{secs, nanos}`; add \
+                 `#[serde(with = \"aiform::duration\")]` to the field, or \
+                 `aiform::duration::option` for `Option<Duration>`, to read strings like \"30s\"",
+            ))
+        }
+        // No standard format covers these, so the description carries it.
+        "PathBuf" => {
+            let desc = hinted(desc, "file path");
+            quote!(#json::json!({"type": "string", "description": #desc}))
+        }
+        "IpAddr" => {
+            let desc = hinted(desc, "IPv4 or IPv6 address");
+            quote!(#json::json!({"type": "string", "description": #desc}))
+        }
+        "Ipv4Addr" => quote!(#json::json!({"type": "string", "format": "ipv4" #desc_expr})),
+        "Ipv6Addr" => quote!(#json::json!({"type": "string", "format": "ipv6" #desc_expr})),
+        #[cfg(feature = "chrono")]
+        "DateTime" => quote!(#json::json!({"type": "string", "format": "date-time" #desc_expr})),
+        #[cfg(feature = "chrono")]
+        "NaiveDate" => quote!(#json::json!({"type": "string", "format": "date" #desc_expr})),
+        #[cfg(feature = "uuid")]
+        "Uuid" => quote!(#json::json!({"type": "string", "format": "uuid" #desc_expr})),
+        #[cfg(feature = "url")]
+        "Url" => quote!(#json::json!({"type": "string", "format": "uri" #desc_expr})),
+        "Vec" => {
+            let inner_schema = schema_expr(krate, inner_ty()?, "", target)?;
+            quote!(#json::json!({"type": "array", "items": #inner_schema #desc_expr}))
+        }
+        "Option" => {
+            let inner_schema = schema_expr(krate, inner_ty()?, desc, target)?;
+            quote!(#krate::__private::nullable(#inner_schema))
+        }
+        "Box" | "Rc" | "Arc" => schema_expr(krate, inner_ty()?, desc, target)?,
+        // Deserialized owned, so a `Cow` is its owned form.
+        "Cow" => {
+            let syn::PathArguments::AngleBracketed(args) = &seg.arguments else {
+                return Err(unsupported());
+            };
+            let borrowed = args.args.iter().find_map(|arg| match arg {
+                syn::GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            });
+            match borrowed {
+                Some(syn::Type::Slice(slice)) => {
+                    let inner_schema = schema_expr(krate, &slice.elem, "", target)?;
+                    quote!(#json::json!({"type": "array", "items": #inner_schema #desc_expr}))
                 }
-                "f32" | "f64" => quote!(serde_json::json!({"type": "number" #desc_expr})),
-                "bool" => quote!(serde_json::json!({"type": "boolean" #desc_expr})),
-                "Vec" => {
-                    if let syn::PathArguments::AngleBracketed(args) = &seg.arguments {
-                        if let Some(syn::GenericArgument::Type(inner_ty)) = args.args.first() {
-                            let inner_schema = schema_expr(inner_ty, "");
-                            quote!(
-                                serde_json::json!({"type": "array", "items": #inner_schema #desc_expr})
-                            )
-                        } else {
-                            quote!(serde_json::json!({"type": "array" #desc_expr}))
-                        }
-                    } else {
-                        quote!(serde_json::json!({"type": "array" #desc_expr}))
-                    }
+                Some(borrowed) => schema_expr(krate, borrowed, desc, target)?,
+                None => return Err(unsupported()),
+            }
+        }
+        _ => {
+            // Spanned so a missing impl points at the field.
+            let schema = match (target.this_named(p, seg), target.derive) {
+                (Some(this), _) => {
+                    target.recursive.set(true);
+                    let reference = format!("#/$defs/{}", this.unraw());
+                    quote!(#json::json!({"$ref": #reference}))
                 }
-                "Option" => {
-                    if let syn::PathArguments::AngleBracketed(args) = &seg.arguments {
-                        if let Some(syn::GenericArgument::Type(inner_ty)) = args.args.first() {
-                            schema_expr(inner_ty, desc)
-                        } else {
-                            quote!(serde_json::json!({"type": "string" #desc_expr}))
-                        }
+                (None, Derive::ToolArg) => {
+                    quote_spanned!(ty.span()=> <#ty as #krate::ToolArg>::schema_with_defs(defs))
+                }
+                // Parenthesized, as `json!` reads a bare block as an object.
+                (None, Derive::StructuredOutput) => {
+                    let nested = quote_spanned!(ty.span()=>
+                        (&&#krate::__private::NestedSchema::<#ty>::new()).nested_schema(defs)
+                    );
+                    quote!(({
+                        // Only the trait the lookup resolves to is used.
+                        #[allow(unused_imports)]
+                        use #krate::__private::{StructuredOutputSchema as _, ToolArgSchema as _};
+                        #nested
+                    }))
+                }
+            };
+            if desc.is_empty() {
+                schema
+            } else {
+                // Parenthesized, as `json!` reads a bare block as an object.
+                quote!(({
+                    let mut s = #schema;
+                    s["description"] = #json::Value::String(#desc.to_string());
+                    s
+                }))
+            }
+        }
+    })
+}
+
+/// The `minimum` and `maximum` of an integer type's schema, so models do
+/// not send values the type cannot hold. Types of 32 bits and more are only
+/// bounded below, and only if unsigned, as models rarely exceed them.
+fn integer_bounds(ty: &str) -> proc_macro2::TokenStream {
+    let (min, max): (i64, Option<i64>) = match ty {
+        "i8" => (i8::MIN.into(), Some(i8::MAX.into())),
+        "i16" => (i16::MIN.into(), Some(i16::MAX.into())),
+        "u8" => (0, Some(u8::MAX.into())),
+        "u16" => (0, Some(u16::MAX.into())),
+        "u32" | "u64" | "u128" | "usize" => (0, None),
+        _ => return quote!(),
+    };
+    let min = proc_macro2::Literal::i64_unsuffixed(min);
+    match max.map(proc_macro2::Literal::i64_unsuffixed) {
+        Some(max) => quote!(, "minimum": #min, "maximum": #max),
+        None => quote!(, "minimum": #min),
+    }
+}
+
+/// The kinds of field a `#[schema(...)]` constraint can apply to.
+#[derive(Clone, Copy, PartialEq)]
+enum ValueKind {
+    Number,
+    String,
+    Array,
+    Other,
+}
+
+impl ValueKind {
+    /// Classifies a field type, looking through `Option`.
+    fn of(ty: &syn::Type) -> Self {
+        let syn::Type::Path(p) = ty else {
+            return ValueKind::Other;
+        };
+        let Some(seg) = p.path.segments.last() else {
+            return ValueKind::Other;
+        };
+        match seg.ident.to_string().as_str() {
+            "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64"
+            | "u128" | "usize" | "f32" | "f64" => ValueKind::Number,
+            "String" => ValueKind::String,
+            "Vec" => ValueKind::Array,
+            "Option" => match &seg.arguments {
+                syn::PathArguments::AngleBracketed(args) => match args.args.first() {
+                    Some(syn::GenericArgument::Type(inner)) => ValueKind::of(inner),
+                    _ => ValueKind::Other,
+                },
+                _ => ValueKind::Other,
+            },
+            _ => ValueKind::Other,
+        }
+    }
+}
+
+/// Merges the `#[schema(...)]` constraints of a field into its schema.
+fn constrained(
+    krate: &syn::Path,
+    schema: proc_macro2::TokenStream,
+    ty: &syn::Type,
+    attrs: &[syn::Attribute],
+) -> syn::Result<proc_macro2::TokenStream> {
+    let json = quote!(#krate::__private::serde_json);
+    let kind = ValueKind::of(ty);
+    let mut constraints = vec![];
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("schema")) {
+        attr.parse_nested_meta(|meta| {
+            let key = meta.path.require_ident()?.to_string();
+            let (applies_to, json_key, value) = match key.as_str() {
+                "minimum" | "maximum" => {
+                    let value = number(meta.value()?.parse()?)?;
+                    (ValueKind::Number, key.clone(), quote!(#value))
+                }
+                "min_length" | "max_length" | "min_items" | "max_items" => {
+                    let value: syn::LitInt = meta.value()?.parse()?;
+                    let value = value.base10_parse::<u64>()?;
+                    let kind = if key.ends_with("items") {
+                        ValueKind::Array
                     } else {
-                        quote!(serde_json::json!({"type": "string" #desc_expr}))
-                    }
+                        ValueKind::String
+                    };
+                    (kind, camel_case(&key), quote!(#value))
+                }
+                "pattern" | "format" => {
+                    let value: LitStr = meta.value()?.parse()?;
+                    (ValueKind::String, key.clone(), quote!(#value))
+                }
+                "default" => {
+                    let value = default_value(meta.value()?.parse()?, kind)?;
+                    (kind, key.clone(), quote!(#value))
                 }
                 _ => {
-                    // Assume it's a ToolArg
-                    let ty_ident = &seg.ident;
-                    if desc.is_empty() {
-                        quote!(<#ty_ident as ToolArg>::schema())
-                    } else {
-                        quote!({
-                            let mut s = <#ty_ident as ToolArg>::schema();
-                            s["description"] = serde_json::Value::String(#desc.to_string());
-                            s
-                        })
-                    }
+                    return Err(meta.error(format!(
+                        "unknown schema constraint `{}`; expected `minimum`, `maximum`, \
+                         `min_length`, `max_length`, `pattern`, `format`, `min_items`, \
+                         `max_items` or `default`",
+                        key
+                    )))
                 }
+            };
+            if kind != applies_to {
+                let expected = match applies_to {
+                    ValueKind::Number => "integer and float",
+                    ValueKind::String => "string",
+                    _ => "Vec",
+                };
+                return Err(syn::Error::new_spanned(
+                    &meta.path,
+                    format!("`{}` only applies to {} fields", key, expected),
+                ));
             }
-        } else {
-            quote!(serde_json::json!({"type": "string" #desc_expr}))
-        }
-    } else {
-        quote!(serde_json::json!({"type": "string" #desc_expr}))
+            constraints.push(quote!(s[#json_key] = #json::json!(#value);));
+            Ok(())
+        })?;
+    }
+    if constraints.is_empty() {
+        return Ok(schema);
+    }
+    // Parenthesized, as `json!` reads a bare block as an object.
+    Ok(quote!(({
+        let mut s = #schema;
+        #(#constraints)*
+        s
+    })))
+}
+
+/// Checks that a `default` is a literal of the field's kind.
+fn default_value(expr: syn::Expr, kind: ValueKind) -> syn::Result<syn::Expr> {
+    match (&expr, kind) {
+        (_, ValueKind::Number) => number(expr),
+        (
+            syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Str(_),
+                ..
+            }),
+            ValueKind::String | ValueKind::Other,
+        ) => Ok(expr),
+        (
+            syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Bool(_),
+                ..
+            }),
+            ValueKind::Other,
+        ) => Ok(expr),
+        (_, ValueKind::String) => Err(syn::Error::new_spanned(&expr, "expected a string")),
+        (_, ValueKind::Array) => Err(syn::Error::new_spanned(
+            &expr,
+            "`default` does not apply to Vec fields",
+        )),
+        (_, ValueKind::Other) => number(expr)
+            .map_err(|e| syn::Error::new(e.span(), "expected a number, string or bool literal")),
+    }
+}
+
+/// Checks that a constraint value is a number literal, possibly negated.
+fn number(expr: syn::Expr) -> syn::Result<syn::Expr> {
+    let literal = match &expr {
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr,
+            ..
+        }) => expr,
+        expr => expr,
+    };
+    match literal {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(_) | syn::Lit::Float(_),
+            ..
+        }) => Ok(expr),
+        _ => Err(syn::Error::new_spanned(&expr, "expected a number")),
+    }
+}
+
+/// Converts snake_case to camelCase.
+fn camel_case(s: &str) -> String {
+    let pascal = to_pascal_case(s);
+    let mut chars = pascal.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
     }
 }
 
 /// Checks if a type is Option<T>.
+/// Returns whether a type is `Duration`, `std::time::Duration` or
+/// `core::time::Duration`.
+fn is_std_duration(ty: &syn::Type) -> bool {
+    let syn::Type::Path(p) = ty else {
+        return false;
+    };
+    if p.qself.is_some() || p.path.segments.iter().any(|seg| !seg.arguments.is_empty()) {
+        return false;
+    }
+    let names: Vec<String> = p
+        .path
+        .segments
+        .iter()
+        .map(|seg| seg.ident.to_string())
+        .collect();
+    names == ["Duration"]
+        || names == ["std", "time", "Duration"]
+        || names == ["core", "time", "Duration"]
+}
+
+/// Returns `T` if a type is `Option<T>`.
+fn option_inner(ty: &syn::Type) -> Option<&syn::Type> {
+    let syn::Type::Path(p) = ty else {
+        return None;
+    };
+    let seg = p.path.segments.last().filter(|seg| seg.ident == "Option")?;
+    match &seg.arguments {
+        syn::PathArguments::AngleBracketed(args) => match args.args.first() {
+            Some(syn::GenericArgument::Type(inner)) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 fn is_option(ty: &syn::Type) -> bool {
     if let syn::Type::Path(p) = ty {
         p.path
@@ -0,0 +1,94 @@
+//! Measures what building the messages of each request costs the agent loop.
+//!
+//! An agent continues a 200-message conversation with large tool outputs
+//! against a scripted backend that calls a tool for 10 iterations before
+//! answering, so the history grows by one tool round per request. Compare
+//! against another revision with criterion's `--save-baseline` and
+//! `--baseline`.
+
+use aiform::prelude::*;
+use aiform::testing::MockChatBackend;
+use async_openai::types::{ChatCompletionMessageToolCall, ChatCompletionToolType, FunctionCall};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use serde_json::json;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+const MESSAGES: usize = 200;
+const ITERATIONS: usize = 10;
+
+#[derive(ToolArg, serde::Deserialize)]
+struct SearchFlightsArgs {
+    #[desc("IATA code of the arrival airport")]
+    to: String,
+}
+
+fn flights(to: &str) -> String {
+    format!(
+        "{{\"to\":\"{}\",\"flights\":[{}]}}",
+        to,
+        "\"TP1234\",".repeat(200)
+    )
+}
+
+#[tool("Searches flights to an airport")]
+async fn search_flights(args: SearchFlightsArgs) -> Result<String> {
+    Ok(flights(&args.to))
+}
+
+fn tool_call(id: usize) -> ChatCompletionMessageToolCall {
+    ChatCompletionMessageToolCall {
+        id: format!("call_{}", id),
+        r#type: ChatCompletionToolType::Function,
+        function: FunctionCall {
+            name: "search_flights".into(),
+            arguments: r#"{"to":"LIS"}"#.into(),
+        },
+    }
+}
+
+fn conversation() -> Conversation {
+    let mut conversation = Conversation::with_system("You book flights");
+    while conversation.len() < MESSAGES {
+        let id = conversation.len();
+        conversation.add_user_message(format!("Find me a flight, request {}", id));
+        conversation.add_assistant_message_with_tools(None, vec![tool_call(id)]);
+        conversation.add_tool_message(format!("call_{}", id), flights("LIS"));
+        conversation.add_assistant_message("Here are the flights I found");
+    }
+    conversation.add_user_message("And what about Porto?");
+    conversation
+}
+
+fn scripted_agent() -> Agent {
+    let backend = (0..ITERATIONS)
+        .fold(MockChatBackend::new(), |backend, _| {
+            backend.respond_with_tool_call("search_flights", json!({ "to": "OPO" }))
+        })
+        .respond_with_text("Here are the flights to Porto");
+    Agent::builder()
+        .model("mock-model")
+        .backend(Arc::new(backend))
+        .tools(tools![SearchFlightsTool])
+        .max_iterations(ITERATIONS + 1)
+        .build()
+        .unwrap()
+}
+
+fn request_messages(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    c.bench_function("request_messages/run_conversation", |b| {
+        b.iter_batched(
+            || (scripted_agent(), conversation()),
+            |(agent, mut conversation)| {
+                runtime
+                    .block_on(agent.run_conversation(&mut conversation))
+                    .unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, request_messages);
+criterion_main!(benches);
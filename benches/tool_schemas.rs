@@ -0,0 +1,156 @@
+//! Measures what offering tools costs the agent loop.
+//!
+//! An agent with three derived tools runs against a scripted backend that
+//! calls one tool per iteration for 10 iterations before answering, so each
+//! run sends 11 requests with the tool list. The allocations of one run are
+//! printed before the timings; compare both against another revision with
+//! criterion's `--save-baseline` and `--baseline`.
+
+use aiform::prelude::*;
+use aiform::testing::MockChatBackend;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use serde_json::json;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+const ITERATIONS: usize = 10;
+
+/// Counts allocations, so revisions can be compared by how many a run makes.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[derive(ToolArg, serde::Deserialize)]
+#[allow(dead_code)]
+enum Cabin {
+    Economy,
+    Business,
+    First,
+}
+
+#[derive(ToolArg, serde::Deserialize)]
+#[allow(dead_code)]
+struct SearchFlightsArgs {
+    #[desc("IATA code of the departure airport")]
+    from: String,
+    #[desc("IATA code of the arrival airport")]
+    to: String,
+    #[desc("Departure date, as YYYY-MM-DD")]
+    date: String,
+    #[desc("Number of passengers")]
+    passengers: u8,
+    cabin: Option<Cabin>,
+    #[desc("Airlines to leave out")]
+    exclude: Vec<String>,
+}
+
+#[derive(ToolArg, serde::Deserialize)]
+#[allow(dead_code)]
+struct CheckSeatsArgs {
+    #[desc("Flight number, e.g. TP1234")]
+    flight: String,
+    cabin: Cabin,
+}
+
+#[derive(ToolArg, serde::Deserialize)]
+#[allow(dead_code)]
+struct BookFlightArgs {
+    #[desc("Flight number, e.g. TP1234")]
+    flight: String,
+    #[desc("Names of the passengers, as on their passports")]
+    passengers: Vec<String>,
+    cabin: Cabin,
+}
+
+#[tool("Searches flights between two airports")]
+async fn search_flights(args: SearchFlightsArgs) -> Result<String> {
+    Ok(format!(
+        "TP1234 and FR8361 from {} to {}",
+        args.from, args.to
+    ))
+}
+
+#[tool("Checks the seats left on a flight")]
+async fn check_seats(args: CheckSeatsArgs) -> Result<String> {
+    Ok(format!("4 seats left on {}", args.flight))
+}
+
+#[tool("Books a flight")]
+async fn book_flight(args: BookFlightArgs) -> Result<String> {
+    Ok(format!(
+        "Booked {} for {}",
+        args.flight,
+        args.passengers.join(", ")
+    ))
+}
+
+fn scripted_agent() -> Agent {
+    let calls = [
+        (
+            "search_flights",
+            json!({ "from": "AMS", "to": "LIS", "date": "2025-06-01", "passengers": 2, "exclude": [] }),
+        ),
+        (
+            "check_seats",
+            json!({ "flight": "TP1234", "cabin": "Economy" }),
+        ),
+        (
+            "book_flight",
+            json!({ "flight": "TP1234", "passengers": ["Ada", "Alan"], "cabin": "Economy" }),
+        ),
+    ];
+    let backend = (0..ITERATIONS)
+        .fold(MockChatBackend::new(), |backend, iteration| {
+            let (name, args) = &calls[iteration % calls.len()];
+            backend.respond_with_tool_call(name, args.clone())
+        })
+        .respond_with_text("Booked TP1234 to Lisbon for Ada and Alan.");
+    Agent::builder()
+        .model("mock-model")
+        .backend(Arc::new(backend))
+        .tools(tools![SearchFlightsTool, CheckSeatsTool, BookFlightTool])
+        .max_iterations(ITERATIONS + 1)
+        .build()
+        .unwrap()
+}
+
+fn tool_schemas(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let run = |agent: Agent| {
+        runtime
+            .block_on(agent.run("Book two economy seats to Lisbon"))
+            .unwrap()
+    };
+
+    let agent = scripted_agent();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    run(agent);
+    println!(
+        "allocations for {} iterations with 3 tools: {}",
+        ITERATIONS,
+        ALLOCATIONS.load(Ordering::Relaxed) - before
+    );
+
+    c.bench_function("tool_schemas/run", |b| {
+        b.iter_batched(scripted_agent, run, BatchSize::SmallInput)
+    });
+}
+
+criterion_group!(benches, tool_schemas);
+criterion_main!(benches);
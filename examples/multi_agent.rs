@@ -1,8 +1,9 @@
 use aiform::agent_tool::AgentTool;
 use aiform::prelude::*;
+use aiform::AgentRegistry;
 use serde::Deserialize;
+use serde_json::Value;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
 #[derive(ToolArg, Deserialize)]
 struct SearchArgs {
@@ -64,9 +65,9 @@ async fn main() -> Result<()> {
         .build()?;
 
     // Researcher agent: Can search and delegate to analyst
-    let analyst_shared = Arc::new(Mutex::new(analyst));
+    let analyst_shared = Arc::new(analyst);
 
-    let _analyst_tool = AgentTool::new(
+    let analyst_tool = AgentTool::new(
         "ask_analyst",
         "Ask the analyst agent to analyze data and provide insights",
         analyst_shared.clone(),
@@ -79,15 +80,27 @@ async fn main() -> Result<()> {
              the analyst to analyze data. Always search first, then ask the analyst \
              to provide insights on what you found.",
         )
-        .tools(tools![SearchWebTool]) // Note: analyst_tool would go here when we support it
+        .tools(tools![SearchWebTool, analyst_tool])
         .build()?;
 
-    // Example 1: Simple researcher query
-    println!("Example 1: Researcher finding information");
-    println!("----------------------------------------");
-    let response = researcher.run("Research Rust adoption trends").await?;
+    // Example 1: Researcher delegating to the analyst through its tool
+    println!("Example 1: Researcher finding and analyzing information");
+    println!("------------------------------------------------------");
+    let mut conversation = Conversation::new();
+    conversation.add_user_message("Research Rust adoption trends and get the analyst's view");
+    let response = researcher.run_conversation(&mut conversation).await?;
     println!("Researcher: {}\n", response);
 
+    let messages = serde_json::to_value(conversation.messages())?;
+    let asked_analyst = messages
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|message| message["tool_calls"].as_array().into_iter().flatten())
+        .any(|call: &Value| call["function"]["name"] == "ask_analyst");
+    assert!(asked_analyst, "the researcher never called the analyst");
+    println!("The researcher consulted the analyst through the ask_analyst tool.\n");
+
     // Example 2: Multi-turn with analyst
     println!("Example 2: Researcher with analyst collaboration");
     println!("-----------------------------------------------");
@@ -98,34 +111,35 @@ async fn main() -> Result<()> {
 
     // Then, analyst analyzes it
     let analysis = analyst_shared
-        .lock()
-        .await
         .call_as_tool(format!("Analyze this data: {}", research_result))
         .await?;
     println!("Analyst analysis: {}\n", analysis);
 
-    // Example 3: Demonstrating private conversations
-    println!("Example 3: Private agent conversations");
-    println!("-------------------------------------");
+    // Example 3: A supervisor delegating through a registry
+    println!("Example 3: Supervisor delegating to registered agents");
+    println!("----------------------------------------------------");
+
+    let registry = AgentRegistry::new();
+    registry.register("researcher", Arc::new(researcher))?;
+    registry.register("analyst", analyst_shared)?;
 
-    // Create a supervisor that delegates tasks
-    let _supervisor = Agent::builder()
+    // Every registered agent is offered to the supervisor as a tool named after it
+    let supervisor = Agent::builder()
         .model("gpt-4")
         .system_prompt(
             "You are a project supervisor. You delegate research tasks to the \
              researcher and analysis tasks to the analyst. Coordinate their work \
              to provide comprehensive answers.",
         )
+        .tools(registry.as_tools())
         .build()?;
 
-    println!("Supervisor delegating work...");
-
-    // Supervisor could orchestrate both agents
-    // In a real implementation, supervisor would have tools to call both agents
     let task = "Investigate Rust adoption and analyze the trends";
     println!("Task: {}", task);
-    println!("\n(In production, supervisor would call researcher and analyst tools)");
-    println!("(Each agent maintains private context, only final results are shared)");
+    println!("Agents: {}", registry.list().join(", "));
+    let report = supervisor.run(task).await?;
+    println!("Supervisor: {}", report);
+    // Each agent keeps its own private context; only final answers are shared
 
     Ok(())
 }
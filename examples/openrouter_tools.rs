@@ -76,10 +76,9 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error + Send + Sy
 
         // Check if there are tool calls
         if let Some(tool_calls) = &assistant_message.tool_calls {
-            let results = dispatch_tool_calls(tool_calls, &tools).await?;
-
-            for (tool_call, result) in tool_calls.iter().zip(results) {
-                messages.push(msg!(tool tool_call.id, result));
+            // Every tool call needs a tool message, including failed ones.
+            for outcome in dispatch_tool_calls_all(tool_calls, &tools).await {
+                messages.push(msg!(tool outcome.tool_call_id, outcome.content()));
             }
         } else {
             // No more tool calls, print final response
@@ -61,7 +61,6 @@ async fn main() -> Result<()> {
     conversation.add_user_message("I need to calculate 8 * 12");
     let response = agent.run_conversation(&mut conversation).await?;
     println!("Agent: {}", response);
-    conversation.add_assistant_message(&response);
 
     conversation.add_user_message("Now divide that by 4");
     let response = agent.run_conversation(&mut conversation).await?;
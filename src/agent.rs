@@ -1,15 +1,547 @@
 //! Agent implementation with tool execution and conversation management.
 
 use crate::{
-    conversation::Conversation,
+    approval::{ApprovalDecision, Approver},
+    attachment::{Attachments, READ_ATTACHMENT_TOOL},
+    backend::{self, ChatBackend, ChatRequest},
+    cancel::CancelHandle,
+    context::{self, ContextStrategy},
+    conversation::{Conversation, ImageInput},
     error::{Error, Result},
-    ToolSet,
+    fixture::{self, Fixture, MatchRules},
+    format::{Format, Rendered},
+    gate::{self, AmbiguityGate, GateClassification, GateReport, GateVerdict},
+    hook::{AgentHook, HookAction},
+    limiter::{Limiter, LimiterPermit, Priority},
+    metrics::{AgentMetrics, MetricsSnapshot},
+    openrouter::OpenRouterOptions,
+    plain_text,
+    profile::{EffectivePolicy, Profile},
+    provider::{self, Provider},
+    registry::AgentRegistry,
+    render::Template,
+    retry::RetryPolicy,
+    store::ConversationStore,
+    stream::{self, AgentEvent, EventSender},
+    tokens::{ApproxTokenCounter, TokenCounter},
+    tool_error::{ToolError, ToolErrorAction, ToolErrorPolicy},
+    trace,
+    warning::{Warning, WarningHandler},
+    ErasedTool, StructuredOutput, ToolEffects, ToolSet,
 };
-use async_openai::{types::CreateChatCompletionRequestArgs, Client};
+use async_openai::{
+    config::Config,
+    types::{
+        ChatCompletionMessageToolCall, ChatCompletionNamedToolChoice, ChatCompletionRequestMessage,
+        ChatCompletionTool, ChatCompletionToolChoiceOption, ChatCompletionToolType,
+        CompletionUsage, CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
+        CreateChatCompletionResponse, FinishReason, FunctionName, ResponseFormat,
+        ResponseFormatJsonSchema,
+    },
+    Client,
+};
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
 
 /// Maximum number of agent loop iterations before stopping.
 const DEFAULT_MAX_ITERATIONS: usize = 10;
 
+/// Maximum number of transfers in [`Agent::run_with_handoffs`].
+const DEFAULT_MAX_HANDOFFS: usize = 5;
+
+/// Name of the pseudo-tool enabled by [`AgentBuilder::ask_user`].
+pub const ASK_USER_TOOL: &str = "ask_user";
+
+/// How a run ended, as returned by [`Agent::run_outcome`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RunOutcome {
+    /// The model answered.
+    Answer(String),
+    /// The model asked the user a question via the `ask_user` pseudo-tool.
+    ///
+    /// Add the user's reply to the conversation and call
+    /// [`Agent::run_conversation_outcome`] to continue.
+    NeedsUserInput {
+        /// The question to show the user.
+        question: String,
+    },
+    /// The model handed the conversation over to another agent.
+    Handoff {
+        /// The target registered with [`AgentBuilder::handoff_targets`] or
+        /// given to [`Agent::handoff_tools`].
+        target: String,
+    },
+    /// The model declined to answer, or the provider filtered the response.
+    Refused {
+        /// The refusal message, if the provider gave one.
+        reason: String,
+    },
+}
+
+/// Per-run settings for [`Agent::run_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    priority: Priority,
+    cancel: Option<CancelHandle>,
+    tool_choice: Option<ToolChoice>,
+    extra_body: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl RunOptions {
+    /// Creates options with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the priority used when acquiring permits from the agent's
+    /// [`Limiter`]. Has no effect on agents without a limiter.
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets a handle that cancels the run; see [`Agent::run_cancellable`].
+    pub fn cancel(mut self, handle: CancelHandle) -> Self {
+        self.cancel = Some(handle);
+        self
+    }
+
+    /// Overrides the agent's [tool choice](AgentBuilder::tool_choice) for
+    /// this run.
+    pub fn tool_choice(mut self, choice: ToolChoice) -> Self {
+        self.tool_choice = Some(choice);
+        self
+    }
+
+    /// Adds fields to the body of this run's chat requests, on top of the
+    /// agent's [extra body fields](AgentBuilder::extra_body).
+    pub fn extra_body(mut self, fields: serde_json::Map<String, serde_json::Value>) -> Self {
+        self.extra_body
+            .get_or_insert_with(Default::default)
+            .extend(fields);
+        self
+    }
+}
+
+/// Whether and which tools the model must call.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ToolChoice {
+    /// The model decides whether to call tools.
+    #[default]
+    Auto,
+    /// The model must not call tools and answers with text.
+    None,
+    /// The model must call at least one tool.
+    Required,
+    /// The model must call the named tool.
+    Named(String),
+}
+
+impl ToolChoice {
+    fn to_option(&self) -> ChatCompletionToolChoiceOption {
+        match self {
+            ToolChoice::Auto => ChatCompletionToolChoiceOption::Auto,
+            ToolChoice::None => ChatCompletionToolChoiceOption::None,
+            ToolChoice::Required => ChatCompletionToolChoiceOption::Required,
+            ToolChoice::Named(name) => {
+                ChatCompletionToolChoiceOption::Named(ChatCompletionNamedToolChoice {
+                    r#type: ChatCompletionToolType::Function,
+                    function: FunctionName { name: name.clone() },
+                })
+            }
+        }
+    }
+}
+
+/// Where a run spent its time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunTimings {
+    /// Wall-clock duration of the whole run.
+    pub total: Duration,
+    /// Time spent waiting for [`Limiter`] permits, summed over every
+    /// completion request of the run.
+    pub scheduling_wait: Duration,
+}
+
+/// The answer of a run together with its timings, as returned by
+/// [`Agent::run_with_options`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunReport {
+    /// The agent's answer.
+    pub content: String,
+    /// Where the run spent its time.
+    pub timings: RunTimings,
+}
+
+/// Token usage summed over every completion request of a run.
+///
+/// The sums stop at `u32::MAX` instead of overflowing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Tokens sent in prompts.
+    pub prompt_tokens: u32,
+    /// Tokens generated in completions.
+    pub completion_tokens: u32,
+    /// Prompt and completion tokens together.
+    pub total_tokens: u32,
+}
+
+impl Usage {
+    fn add(&mut self, usage: &CompletionUsage) {
+        self.prompt_tokens = self.prompt_tokens.saturating_add(usage.prompt_tokens);
+        self.completion_tokens = self
+            .completion_tokens
+            .saturating_add(usage.completion_tokens);
+        self.total_tokens = self.total_tokens.saturating_add(usage.total_tokens);
+    }
+}
+
+/// The answer of a run together with what it cost, as returned by
+/// [`Agent::run_with_metadata`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentResponse {
+    /// The agent's answer.
+    pub content: String,
+    /// Tokens used by every completion request of the run, including
+    /// requests made between tool calls.
+    pub usage: Usage,
+    /// Agent loop iterations the run took.
+    pub iterations: usize,
+    /// Tool calls the model made during the run.
+    pub tool_calls_made: usize,
+}
+
+/// A tool call the model made that has no result yet, as returned by
+/// [`AgentRun::step`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingToolCall {
+    /// The id the result has to be reported under.
+    pub id: String,
+    /// The name of the called tool.
+    pub name: String,
+    /// The parsed arguments of the call.
+    pub arguments: serde_json::Value,
+}
+
+/// What one [`AgentRun::step`] produced.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum AgentStep {
+    /// The model called tools. Their results are needed before the next
+    /// step, from [`AgentRun::execute_tools`] or
+    /// [`AgentRun::provide_tool_result`].
+    ToolCalls(Vec<PendingToolCall>),
+    /// The agent's answer; the run is over.
+    Final(String),
+}
+
+/// How a run ended together with what it cost, as returned by
+/// [`Agent::run_outcome_with_metadata`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutcomeResponse {
+    /// How the run ended.
+    pub outcome: RunOutcome,
+    /// Tokens used by every completion request of the run, including the
+    /// ambiguity gate's.
+    pub usage: Usage,
+    /// Agent loop iterations the run took.
+    pub iterations: usize,
+    /// Tool calls the model made during the run.
+    pub tool_calls_made: usize,
+    /// The [ambiguity gate](AgentBuilder::ambiguity_gate)'s decision, if the
+    /// agent has one.
+    pub gate: Option<GateReport>,
+}
+
+/// How a run with handoffs ended, as returned by
+/// [`Agent::run_with_handoffs`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandoffResponse {
+    /// How the run of the agent owning the conversation ended; never
+    /// [`RunOutcome::Handoff`].
+    pub outcome: RunOutcome,
+    /// The agents the conversation was transferred to, in order. The last
+    /// one owns it, or the agent the run started with if there are none.
+    pub transfers: Vec<String>,
+    /// The conversation the agents shared.
+    pub conversation: Conversation,
+}
+
+/// State tracked across the requests of one run.
+#[derive(Debug, Default)]
+struct RunContext {
+    priority: Priority,
+    timings: RunTimings,
+    events: Option<EventSender>,
+    /// Whether completions are streamed, sending their text as events.
+    stream: bool,
+    usage: Usage,
+    iterations: usize,
+    tool_calls_made: usize,
+    dropped_parameters: HashSet<String>,
+    cancel: Option<CancelHandle>,
+    tool_choice: Option<ToolChoice>,
+    gate: Option<GateReport>,
+    output_schema: Option<ResponseFormatJsonSchema>,
+    /// The agent's extra body fields merged with the run's, if the run has
+    /// any.
+    extra_body: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl RunContext {
+    /// Returns whether the run's cancel handle was cancelled.
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|handle| handle.is_cancelled())
+    }
+
+    /// Adds a response's reported usage to the run's totals.
+    fn record_usage(&mut self, response: &CreateChatCompletionResponse) {
+        if let Some(ref usage) = response.usage {
+            self.usage.add(usage);
+        }
+    }
+
+    /// Reports an event to the caller of a streaming run or a run with
+    /// events.
+    fn emit(&self, event: AgentEvent) {
+        if let Some(ref events) = self.events {
+            // The caller may have dropped the receiver; the run still finishes.
+            let _ = events.send(event);
+        }
+    }
+}
+
+/// Sampling parameters sent with every completion request. Unset values
+/// are left to the provider's defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Sampling {
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    max_tokens: Option<u32>,
+    frequency_penalty: Option<f32>,
+    presence_penalty: Option<f32>,
+}
+
+impl Sampling {
+    /// Checks that every set value is within the range the API accepts.
+    fn validate(&self) -> Result<()> {
+        let ranges = [
+            ("temperature", self.temperature, 0.0..=2.0),
+            ("top_p", self.top_p, 0.0..=1.0),
+            ("frequency_penalty", self.frequency_penalty, -2.0..=2.0),
+            ("presence_penalty", self.presence_penalty, -2.0..=2.0),
+        ];
+        for (name, value, range) in ranges {
+            if let Some(value) = value.filter(|value| !range.contains(value)) {
+                return Err(Error::InvalidConfiguration(format!(
+                    "{} must be between {} and {}, got {}",
+                    name,
+                    range.start(),
+                    range.end(),
+                    value
+                )));
+            }
+        }
+        if self.max_tokens == Some(0) {
+            return Err(Error::InvalidConfiguration(
+                "max_tokens must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, request: &mut CreateChatCompletionRequest) {
+        request.temperature = self.temperature;
+        request.top_p = self.top_p;
+        request.frequency_penalty = self.frequency_penalty;
+        request.presence_penalty = self.presence_penalty;
+        // OpenAI-compatible servers still widely accept only `max_tokens`,
+        // not its replacement `max_completion_tokens`.
+        #[allow(deprecated)]
+        {
+            request.max_tokens = self.max_tokens;
+        }
+    }
+}
+
+/// What ends an agent loop besides a final answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LoopMode {
+    Text,
+    Rendered,
+    Outcome,
+}
+
+/// Where an agent loop is between two calls to `Agent::advance`.
+#[derive(Debug)]
+struct LoopState {
+    mode: LoopMode,
+    started: bool,
+    pseudo_tools: Vec<ChatCompletionTool>,
+    tool_choice: Option<ToolChoice>,
+    iterations: usize,
+    round: Option<ToolRound>,
+    /// The messages of the last request, reused so each iteration only
+    /// copies the messages added since.
+    messages: Vec<ChatCompletionRequestMessage>,
+    /// The tools offered in the last request, reused so they are not
+    /// copied each iteration.
+    tools: Option<Vec<ChatCompletionTool>>,
+}
+
+impl LoopState {
+    fn new(mode: LoopMode) -> Self {
+        Self {
+            mode,
+            started: false,
+            pseudo_tools: Vec::new(),
+            tool_choice: None,
+            iterations: 0,
+            round: None,
+            messages: Vec::new(),
+            tools: None,
+        }
+    }
+}
+
+/// What one step of the agent loop produced.
+enum Progress {
+    /// The model called tools; their results are still missing.
+    ToolCalls(Vec<PendingToolCall>),
+    /// The run ended.
+    Done(RunOutcome),
+}
+
+/// The tool calls of one response and the results gathered for them.
+#[derive(Debug)]
+struct ToolRound {
+    calls: Vec<ChatCompletionMessageToolCall>,
+    arguments: Vec<serde_json::Value>,
+    results: Vec<Option<String>>,
+    /// When each call started running, until it has a result.
+    started: Vec<Option<Instant>>,
+    /// How long each call took, once it has a result.
+    durations: Vec<Duration>,
+    /// Results already added to the conversation, which are always the
+    /// first ones so tool messages keep the order of the calls.
+    added: usize,
+    /// Conversation length before the round, to remove it on cancellation.
+    round_start: usize,
+    /// How the run ends after this round, if a call ended it.
+    terminal: Option<Result<RunOutcome>>,
+}
+
+impl ToolRound {
+    /// The calls that have no result yet.
+    fn pending(&self) -> Vec<PendingToolCall> {
+        self.calls
+            .iter()
+            .zip(&self.arguments)
+            .zip(&self.results)
+            .filter(|(_, result)| result.is_none())
+            .map(|((call, arguments), _)| PendingToolCall {
+                id: call.id.clone(),
+                name: call.function.name.clone(),
+                arguments: arguments.clone(),
+            })
+            .collect()
+    }
+
+    /// Marks a call as running and reports it to the caller.
+    fn start(&mut self, index: usize, ctx: &RunContext) {
+        let tool_call = &self.calls[index];
+        ctx.emit(AgentEvent::ToolCallStarted {
+            id: tool_call.id.clone(),
+            name: tool_call.function.name.clone(),
+        });
+        self.started[index] = Some(Instant::now());
+    }
+
+    /// Records the result of a call and adds every result that is next in
+    /// line to the conversation.
+    fn answer(
+        &mut self,
+        index: usize,
+        result: String,
+        conversation: &mut Conversation,
+        ctx: &RunContext,
+    ) {
+        if let Some(started) = self.started[index].take() {
+            self.durations[index] = started.elapsed();
+        }
+        self.results[index] = Some(result);
+        while let Some(Some(result)) = self.results.get(self.added) {
+            let call = &self.calls[self.added];
+            let duration = self.durations[self.added];
+            add_tool_result(conversation, ctx, call, result.clone(), duration);
+            self.added += 1;
+        }
+    }
+}
+
+/// A transformation applied to an agent's final answer.
+///
+/// Registered with [`AgentBuilder::post_process`]; processors run in the
+/// order they were added.
+pub type PostProcessor = Arc<dyn Fn(String) -> String + Send + Sync>;
+
+/// Which tool results are converted to plain text before the model sees them.
+#[derive(Debug, Clone, Default)]
+enum PlainTextTools {
+    #[default]
+    None,
+    All,
+    Only(HashSet<String>),
+}
+
+impl PlainTextTools {
+    fn applies_to(&self, tool_name: &str) -> bool {
+        match self {
+            PlainTextTools::None => false,
+            PlainTextTools::All => true,
+            PlainTextTools::Only(names) => names.contains(tool_name),
+        }
+    }
+}
+
+/// How the agent recovers tool-call arguments cut off by the token limit.
+///
+/// When a response ends with `finish_reason: length` while the last tool
+/// call's arguments are unterminated JSON, the agent asks the model to
+/// continue the arguments and stitches the fragments together before
+/// dispatching. Each continuation is reported as a
+/// [`Warning::TruncatedToolArguments`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgumentContinuation {
+    /// Maximum continuation requests per truncated tool call. Zero disables
+    /// recovery.
+    pub max_continuations: usize,
+    /// End the continuation request with the partial arguments as an
+    /// assistant message for the model to extend, instead of adding a user
+    /// instruction. Only use this with providers that support prefill.
+    pub assistant_prefill: bool,
+}
+
+impl Default for ArgumentContinuation {
+    fn default() -> Self {
+        Self {
+            max_continuations: 2,
+            assistant_prefill: false,
+        }
+    }
+}
+
 /// An AI agent that can use tools and maintain conversations.
 ///
 /// Agents execute a loop where they:
@@ -37,11 +569,52 @@ const DEFAULT_MAX_ITERATIONS: usize = 10;
 /// # }
 /// ```
 pub struct Agent {
-    client: Client<async_openai::config::OpenAIConfig>,
+    client: Arc<dyn ChatBackend>,
     model: String,
     system_prompt: Option<String>,
-    tools: Option<ToolSet>,
+    tools: Option<Arc<ToolSet>>,
     max_iterations: usize,
+    terminal_tools: HashMap<String, Template>,
+    argument_continuation: ArgumentContinuation,
+    warning_handler: Option<WarningHandler>,
+    post_processors: Vec<PostProcessor>,
+    plain_text_tools: PlainTextTools,
+    tool_error_policy: ToolErrorPolicy,
+    read_only: bool,
+    ask_user: bool,
+    ambiguity_gate: Option<AmbiguityGate>,
+    context_strategy: Option<ContextStrategy>,
+    token_counter: Arc<dyn TokenCounter>,
+    handoff_targets: Vec<String>,
+    max_handoffs: usize,
+    attachments: Option<Attachments>,
+    limiter: Option<Limiter>,
+    profile: Option<Profile>,
+    request_timeout: Option<Duration>,
+    tool_timeout: Option<Duration>,
+    tool_timeouts: HashMap<String, Duration>,
+    max_tool_output_bytes: Option<usize>,
+    tool_output_limits: HashMap<String, usize>,
+    retry: Option<RetryPolicy>,
+    verbose_warnings: bool,
+    sampling: Sampling,
+    json_mode: bool,
+    structured_output_retry: bool,
+    tool_choice: Option<ToolChoice>,
+    tool_concurrency: usize,
+    provider: Provider,
+    strip_rejected_parameters: bool,
+    append_final_response: bool,
+    inherit_system_prompt: bool,
+    hooks: Vec<Arc<dyn AgentHook>>,
+    approvals: HashMap<String, Approver>,
+    autosave: Option<(Arc<dyn ConversationStore>, String)>,
+    extra_body: serde_json::Map<String, serde_json::Value>,
+    allow_extra_body_overrides: bool,
+    name: Option<String>,
+    #[cfg(feature = "jsonschema")]
+    validate_tool_arguments: bool,
+    metrics: Arc<AgentMetrics>,
 }
 
 impl Agent {
@@ -50,6 +623,118 @@ impl Agent {
         AgentBuilder::new()
     }
 
+    /// Returns the name given with [`AgentBuilder::name`], if any.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns a builder pre-populated with this agent's configuration.
+    ///
+    /// The client and tools are shared with this agent rather than copied,
+    /// so deriving variants that differ only in prompt or model is cheap.
+    /// Settings changed on the builder only affect the new agent.
+    ///
+    /// ```no_run
+    /// use aiform::prelude::*;
+    ///
+    /// # fn example(base: Agent) -> Result<()> {
+    /// let formal = base
+    ///     .with_overrides()
+    ///     .system_prompt("Answer formally and concisely.")
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_overrides(&self) -> AgentBuilder {
+        AgentBuilder {
+            client: Some(self.client.clone()),
+            model: Some(self.model.clone()),
+            system_prompt: self.system_prompt.clone(),
+            tools: self.tools.clone(),
+            max_iterations: Some(self.max_iterations),
+            terminal_tools: self.terminal_tools.clone(),
+            argument_continuation: self.argument_continuation,
+            warning_handler: self.warning_handler.clone(),
+            post_processors: self.post_processors.clone(),
+            plain_text_tools: self.plain_text_tools.clone(),
+            read_only: self.read_only,
+            tool_error_policy: Some(self.tool_error_policy.clone()),
+            ask_user: self.ask_user,
+            ambiguity_gate: self.ambiguity_gate.clone(),
+            context_strategy: self.context_strategy.clone(),
+            token_counter: self.token_counter.clone(),
+            handoff_targets: self.handoff_targets.clone(),
+            max_handoffs: self.max_handoffs,
+            attachments: self.attachments.clone(),
+            limiter: self.limiter.clone(),
+            profile: self.profile.clone(),
+            request_timeout: self.request_timeout,
+            tool_timeout: self.tool_timeout,
+            tool_timeouts: self.tool_timeouts.clone(),
+            max_tool_output_bytes: self.max_tool_output_bytes,
+            tool_output_limits: self.tool_output_limits.clone(),
+            retry: self.retry,
+            sampling: self.sampling,
+            json_mode: self.json_mode,
+            structured_output_retry: self.structured_output_retry,
+            tool_choice: self.tool_choice.clone(),
+            tool_concurrency: self.tool_concurrency,
+            provider: self.provider.clone(),
+            strip_rejected_parameters: self.strip_rejected_parameters,
+            append_final_response: self.append_final_response,
+            inherit_system_prompt: self.inherit_system_prompt,
+            hooks: self.hooks.clone(),
+            approvals: self.approvals.clone(),
+            autosave: self.autosave.clone(),
+            extra_body: self.extra_body.clone(),
+            allow_extra_body_overrides: self.allow_extra_body_overrides,
+            name: self.name.clone(),
+            #[cfg(feature = "jsonschema")]
+            validate_tool_arguments: self.validate_tool_arguments,
+            #[cfg(feature = "jsonschema")]
+            tools_validated: self.validate_tool_arguments && self.tools.is_some(),
+            openrouter: None,
+            record_to: None,
+            replay_from: None,
+            replay_rules: MatchRules::default(),
+        }
+    }
+
+    /// Returns the reliability settings in effect, after applying the
+    /// [profile](AgentBuilder::profile) and explicit settings.
+    pub fn effective_policy(&self) -> EffectivePolicy {
+        EffectivePolicy {
+            profile: self.profile.as_ref().map(|profile| profile.name.clone()),
+            max_iterations: self.max_iterations,
+            request_timeout: self.request_timeout,
+            tool_timeout: self.tool_timeout,
+            retry: self.retry,
+            tool_retries: self.tool_error_policy.retries(),
+            verbose_warnings: self.verbose_warnings,
+        }
+    }
+
+    /// The agent's tools, if it has any.
+    #[cfg(feature = "blocking")]
+    pub(crate) fn tool_set(&self) -> Option<&ToolSet> {
+        self.tools.as_deref()
+    }
+
+    /// Returns the agent's counters, accumulated over all its runs since it
+    /// was built or [`reset_metrics`](Self::reset_metrics) was called. See
+    /// the [`metrics`](crate::metrics) module.
+    ///
+    /// Agents derived with [`with_overrides`](Self::with_overrides) start
+    /// their own counters.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Sets the agent's counters back to zero.
+    pub fn reset_metrics(&self) {
+        self.metrics.reset();
+    }
+
     /// Runs the agent with a single user message.
     ///
     /// This creates a new conversation with the given message and executes
@@ -60,219 +745,6084 @@ impl Agent {
     /// Returns an error if the API call fails, tool execution fails, or
     /// the maximum number of iterations is exceeded.
     pub async fn run(&self, message: impl Into<String>) -> Result<String> {
-        let mut conversation = if let Some(ref prompt) = self.system_prompt {
-            Conversation::with_system(prompt.clone())
-        } else {
-            Conversation::new()
-        };
+        let mut conversation = self.new_conversation();
 
         conversation.add_user_message(message);
         self.run_conversation(&mut conversation).await
     }
 
-    /// Runs the agent with an existing conversation.
+    /// Runs the agent with a single user message with images, for vision
+    /// models.
     ///
-    /// This allows multi-turn conversations where the agent can reference
-    /// previous messages.
+    /// ```no_run
+    /// use aiform::conversation::ImageInput;
+    /// use aiform::prelude::*;
+    ///
+    /// # async fn example(agent: Agent) -> Result<()> {
+    /// let answer = agent
+    ///     .run_with_images(
+    ///         "Which items on this receipt are food?",
+    ///         vec![ImageInput::url("https://example.com/receipt.jpg")],
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
     ///
     /// # Errors
     ///
-    /// Returns an error if the API call fails, tool execution fails, or
-    /// the maximum number of iterations is exceeded.
-    pub async fn run_conversation(&self, conversation: &mut Conversation) -> Result<String> {
-        self.execute_loop(conversation).await
+    /// Returns any error [`run`](Self::run) can return.
+    pub async fn run_with_images(
+        &self,
+        message: impl Into<String>,
+        images: Vec<ImageInput>,
+    ) -> Result<String> {
+        let mut conversation = self.new_conversation();
+
+        conversation.add_user_message_with_images(message, images);
+        self.run_conversation(&mut conversation).await
     }
 
-    /// Calls this agent as if it were a tool.
-    ///
-    /// This creates a fresh, isolated conversation for the request and returns
-    /// only the final response. All intermediate tool calls and reasoning are
-    /// hidden from the caller.
-    ///
-    /// This is used when one agent calls another agent.
+    /// Runs the agent with a single user message, reporting token usage,
+    /// iterations and tool calls along with the answer.
     ///
     /// # Errors
     ///
-    /// Returns an error if the agent execution fails.
-    pub async fn call_as_tool(&self, message: impl Into<String>) -> Result<String> {
-        let mut private_conversation = if let Some(ref prompt) = self.system_prompt {
-            Conversation::with_system(prompt.clone())
-        } else {
-            Conversation::new()
-        };
+    /// Returns any error [`run`](Self::run) can return.
+    pub async fn run_with_metadata(&self, message: impl Into<String>) -> Result<AgentResponse> {
+        let mut conversation = self.new_conversation();
 
-        private_conversation.add_user_message(message);
-        self.execute_loop(&mut private_conversation).await
+        conversation.add_user_message(message);
+        self.run_conversation_with_metadata(&mut conversation).await
     }
 
-    /// Executes the agent loop: LLM call -> tool execution -> repeat.
-    async fn execute_loop(&self, conversation: &mut Conversation) -> Result<String> {
-        for _iteration in 0..self.max_iterations {
-            let mut request = CreateChatCompletionRequestArgs::default();
-            request.model(&self.model);
-            request.messages(conversation.messages().to_vec());
+    /// Runs the agent with an existing conversation, reporting token usage,
+    /// iterations and tool calls along with the answer.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error [`run`](Self::run) can return, or an error from the
+    /// [`autosave`](AgentBuilder::autosave) store.
+    pub async fn run_conversation_with_metadata(
+        &self,
+        conversation: &mut Conversation,
+    ) -> Result<AgentResponse> {
+        let mut ctx = RunContext::default();
+        let content = self
+            .execute_text_loop(conversation, LoopMode::Text, &mut ctx)
+            .await?;
+        Ok(AgentResponse {
+            content,
+            usage: ctx.usage,
+            iterations: ctx.iterations,
+            tool_calls_made: ctx.tool_calls_made,
+        })
+    }
 
-            if let Some(ref toolset) = self.tools {
-                request.tools(toolset.tools().to_vec());
-            }
+    /// Runs the agent with a single user message until it answers or `cancel`
+    /// is cancelled.
+    ///
+    /// Cancellation is checked before every completion request and tool call,
+    /// and abandons a completion request in flight. A tool that is already
+    /// running is allowed to finish. Tool calls are recorded in the
+    /// conversation only together with all of their results: if the run is
+    /// cancelled part way through a round of tool calls, the round is removed
+    /// again.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Cancelled`] when cancelled, plus any error
+    /// [`run`](Self::run) can return.
+    pub async fn run_cancellable(
+        &self,
+        message: impl Into<String>,
+        cancel: CancelHandle,
+    ) -> Result<String> {
+        let mut conversation = self.new_conversation();
 
-            let request = request.build().map_err(|e| {
-                Error::InvalidConfiguration(format!("Failed to build chat request: {}", e))
-            })?;
+        conversation.add_user_message(message);
+        self.run_conversation_cancellable(&mut conversation, cancel)
+            .await
+    }
 
-            let response = self.client.chat().create(request).await?;
+    /// Runs the agent with an existing conversation until it answers or
+    /// `cancel` is cancelled.
+    ///
+    /// After cancellation the conversation holds the messages of every
+    /// completed round and can be run again.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error [`run_cancellable`](Self::run_cancellable) can
+    /// return.
+    pub async fn run_conversation_cancellable(
+        &self,
+        conversation: &mut Conversation,
+        cancel: CancelHandle,
+    ) -> Result<String> {
+        let mut ctx = RunContext {
+            cancel: Some(cancel),
+            ..Default::default()
+        };
+        self.execute_text_loop(conversation, LoopMode::Text, &mut ctx)
+            .await
+    }
 
-            let choice = response
-                .choices
-                .first()
-                .ok_or_else(|| Error::Other("No response from API".into()))?;
+    /// Runs the agent with a single user message and returns its answer as
+    /// a `T`, rendered in `format`.
+    ///
+    /// The model is asked for JSON matching `T`'s schema, tools are called as
+    /// in [`run`](Self::run), and the answer is deserialized into `T` before
+    /// it is rendered, so the rendering always describes a valid `T`. See the
+    /// [`format`](crate::format) module.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Json`] if the answer is not a valid `T`, an error if
+    /// it cannot be rendered in `format`, plus any error [`run`](Self::run)
+    /// can return.
+    pub async fn run_structured_as<T>(
+        &self,
+        message: impl Into<String>,
+        format: Format,
+    ) -> Result<Rendered<T>>
+    where
+        T: StructuredOutput + DeserializeOwned + Serialize,
+    {
+        let mut conversation = self.new_conversation();
+        conversation.add_user_message(message);
 
-            let message = &choice.message;
+        let value: T = self.run_conversation_structured(&mut conversation).await?;
+        let text = crate::format::render(&value, &format)?;
+        Ok(Rendered { value, text })
+    }
 
-            // Check if there are tool calls
-            if let Some(ref tool_calls) = message.tool_calls {
-                // Add assistant message with tool calls
-                conversation
-                    .add_assistant_message_with_tools(message.content.clone(), tool_calls.clone());
-
-                // Execute tools
-                let toolset = self.tools.as_ref().ok_or_else(|| {
-                    Error::InvalidConfiguration(
-                        "Agent received tool calls but has no tools configured".to_string(),
-                    )
-                })?;
-
-                for tool_call in tool_calls {
-                    let tool_name = &tool_call.function.name;
-                    let args: serde_json::Value =
-                        serde_json::from_str(&tool_call.function.arguments)?;
-
-                    let result = toolset
-                        .dispatch(tool_name.clone(), args)
-                        .await
-                        .map_err(|e| Error::ToolExecution {
-                            tool_name: tool_name.clone(),
-                            message: e.to_string(),
-                        })?;
-
-                    conversation.add_tool_message(&tool_call.id, result);
-                }
+    /// Runs the agent with a single user message and deserializes its
+    /// answer into `T`.
+    ///
+    /// The request's `response_format` is a strict JSON schema generated
+    /// from `T`, and tools are called as in [`run`](Self::run):
+    ///
+    /// ```no_run
+    /// use aiform::prelude::*;
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(StructuredOutput, ToolArg, Deserialize)]
+    /// struct Sentiment {
+    ///     label: String,
+    ///     score: f64,
+    /// }
+    ///
+    /// # async fn example(agent: Agent) -> Result<()> {
+    /// let sentiment: Sentiment = agent.run_structured("I love this!").await?;
+    /// println!("{} ({})", sentiment.label, sentiment.score);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// If the answer does not deserialize, the model is shown the parse
+    /// error and asked once more, unless
+    /// [`AgentBuilder::structured_output_retry`] disabled it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::StructuredOutputParse`] if the answer is not a valid
+    /// `T`, plus any error [`run`](Self::run) can return.
+    pub async fn run_structured<T>(&self, message: impl Into<String>) -> Result<T>
+    where
+        T: StructuredOutput + DeserializeOwned,
+    {
+        let mut conversation = self.new_conversation();
+        conversation.add_user_message(message);
+        self.run_conversation_structured(&mut conversation).await
+    }
 
-                // Continue the loop to get the next response
-                continue;
+    /// Runs the agent with an existing conversation and deserializes its
+    /// answer into `T`.
+    ///
+    /// If the first answer did not deserialize, it is added to the
+    /// conversation along with the request to correct it.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error [`run_structured`](Self::run_structured) can
+    /// return.
+    pub async fn run_conversation_structured<T>(&self, conversation: &mut Conversation) -> Result<T>
+    where
+        T: StructuredOutput + DeserializeOwned,
+    {
+        let mut ctx = RunContext {
+            output_schema: Some(output_schema::<T>()),
+            ..Default::default()
+        };
+        let mut retried = !self.structured_output_retry;
+        loop {
+            let raw = self
+                .execute_text_loop(conversation, LoopMode::Text, &mut ctx)
+                .await?;
+            let source = match serde_json::from_str(&raw) {
+                Ok(value) => return Ok(value),
+                Err(source) => source,
+            };
+            if retried {
+                return Err(Error::StructuredOutputParse { raw, source });
             }
-
-            // No tool calls, this is the final response
-            if let Some(content) = &message.content {
-                return Ok(content.clone());
+            retried = true;
+            if !self.append_final_response {
+                conversation.add_assistant_message(raw);
+                self.sign(conversation);
             }
-
-            return Err(Error::Other(
-                "Agent returned no content or tool calls".into(),
+            conversation.add_user_message(format!(
+                "Your answer could not be parsed: {}. Answer again with only a JSON object \
+                 that matches the schema.",
+                source
             ));
         }
-
-        Err(Error::MaxIterationsExceeded {
-            max: self.max_iterations,
-        })
     }
-}
 
-/// Builder for creating agents.
-///
-/// # Example
-///
-/// ```no_run
-/// use aiform::prelude::*;
-/// # use async_openai::Client;
-///
-/// # async fn example() -> Result<()> {
-/// let agent = Agent::builder()
-///     .model("gpt-4")
-///     .system_prompt("You are a helpful coding assistant")
-///     .max_iterations(15)
-///     .build()?;
-/// # Ok(())
-/// # }
-/// ```
-pub struct AgentBuilder {
-    client: Option<Client<async_openai::config::OpenAIConfig>>,
-    model: Option<String>,
-    system_prompt: Option<String>,
-    tools: Option<ToolSet>,
-    max_iterations: Option<usize>,
-}
+    /// Runs the agent with a single user message and per-run options,
+    /// reporting timings along with the answer.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error [`run`](Self::run) can return.
+    pub async fn run_with_options(
+        &self,
+        message: impl Into<String>,
+        options: RunOptions,
+    ) -> Result<RunReport> {
+        let mut conversation = self.new_conversation();
 
-impl AgentBuilder {
-    /// Creates a new agent builder with default settings.
-    pub fn new() -> Self {
-        Self {
-            client: None,
-            model: None,
-            system_prompt: None,
-            tools: None,
-            max_iterations: None,
-        }
+        conversation.add_user_message(message);
+        self.run_conversation_with_options(&mut conversation, options)
+            .await
     }
 
-    /// Sets the OpenAI client to use.
+    /// Runs the agent with an existing conversation and per-run options,
+    /// reporting timings along with the answer.
     ///
-    /// If not set, a default client will be created.
-    pub fn client(mut self, client: Client<async_openai::config::OpenAIConfig>) -> Self {
-        self.client = Some(client);
-        self
+    /// # Errors
+    ///
+    /// Returns any error [`run`](Self::run) can return.
+    pub async fn run_conversation_with_options(
+        &self,
+        conversation: &mut Conversation,
+        options: RunOptions,
+    ) -> Result<RunReport> {
+        let started = Instant::now();
+        let mut ctx = RunContext {
+            priority: options.priority,
+            cancel: options.cancel,
+            tool_choice: options.tool_choice,
+            extra_body: options.extra_body.map(|fields| {
+                let mut merged = self.extra_body.clone();
+                merged.extend(fields);
+                merged
+            }),
+            ..Default::default()
+        };
+        let content = self
+            .execute_text_loop(conversation, LoopMode::Text, &mut ctx)
+            .await?;
+        ctx.timings.total = started.elapsed();
+        Ok(RunReport {
+            content,
+            timings: ctx.timings,
+        })
     }
 
-    /// Sets the model to use (e.g., "gpt-4", "gpt-3.5-turbo").
-    pub fn model(mut self, model: impl Into<String>) -> Self {
-        self.model = Some(model.into());
-        self
+    /// Runs the agent with a single user message, streaming its progress.
+    ///
+    /// Completions are streamed so text arrives as
+    /// [`AgentEvent::TextDelta`]s, tool calls are reported as they run, and
+    /// the stream ends with [`AgentEvent::Completed`] or the run's error. See
+    /// the [`stream`](crate::stream) module for an example.
+    pub fn run_stream(
+        &self,
+        message: impl Into<String>,
+    ) -> impl futures::Stream<Item = Result<AgentEvent>> + Send + '_ {
+        let mut conversation = self.new_conversation();
+        conversation.add_user_message(message);
+
+        stream::event_stream(move |events| async move {
+            self.run_streaming(&mut conversation, events).await
+        })
     }
 
-    /// Sets the system prompt for the agent.
-    pub fn system_prompt(mut self, prompt: impl Into<String>) -> Self {
-        self.system_prompt = Some(prompt.into());
-        self
+    /// Runs the agent with an existing conversation, streaming its progress.
+    ///
+    /// Messages are added to the conversation as in
+    /// [`run_conversation`](Self::run_conversation).
+    pub fn run_conversation_stream<'a>(
+        &'a self,
+        conversation: &'a mut Conversation,
+    ) -> impl futures::Stream<Item = Result<AgentEvent>> + Send + 'a {
+        stream::event_stream(move |events| self.run_streaming(conversation, events))
     }
 
-    /// Sets the tools available to the agent.
-    pub fn tools(mut self, tools: ToolSet) -> Self {
-        self.tools = Some(tools);
-        self
+    /// Runs the agent with a single user message, reporting its progress
+    /// on a channel.
+    ///
+    /// The receiver gets an event when each iteration starts, when each
+    /// response arrives, when each tool call starts and finishes, and
+    /// [`AgentEvent::Completed`] with the answer, for showing progress
+    /// while the returned future runs. Unlike
+    /// [`run_stream`](Self::run_stream), completions are not streamed and
+    /// the answer is the future's output.
+    ///
+    /// The channel is unbounded so the run never waits for the receiver; a
+    /// receiver that is dropped or falls behind does not slow it down.
+    ///
+    /// ```no_run
+    /// use aiform::prelude::*;
+    /// use aiform::stream::AgentEvent;
+    ///
+    /// # async fn example(agent: Agent) -> Result<()> {
+    /// let (run, mut events) = agent.run_with_events("What's the weather in Oslo?");
+    /// tokio::spawn(async move {
+    ///     while let Some(event) = events.recv().await {
+    ///         match event {
+    ///             AgentEvent::IterationStarted { iteration, max_iterations } => {
+    ///                 println!("iteration {} of {}", iteration, max_iterations)
+    ///             }
+    ///             AgentEvent::ToolCallStarted { name, .. } => println!("calling {}…", name),
+    ///             _ => {}
+    ///         }
+    ///     }
+    /// });
+    /// let answer = run.await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn run_with_events(
+        &self,
+        message: impl Into<String>,
+    ) -> (
+        impl Future<Output = Result<String>> + Send + '_,
+        mpsc::UnboundedReceiver<AgentEvent>,
+    ) {
+        let mut conversation = self.new_conversation();
+        conversation.add_user_message(message);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let run = async move {
+            let mut ctx = RunContext {
+                events: Some(sender),
+                ..Default::default()
+            };
+            let answer = self
+                .execute_text_loop(&mut conversation, LoopMode::Text, &mut ctx)
+                .await?;
+            ctx.emit(AgentEvent::Completed(answer.clone()));
+            Ok(answer)
+        };
+        (run, receiver)
     }
 
-    /// Sets the maximum number of iterations for the agent loop.
+    async fn run_streaming(
+        &self,
+        conversation: &mut Conversation,
+        events: EventSender,
+    ) -> Result<String> {
+        let mut ctx = RunContext {
+            events: Some(events),
+            stream: true,
+            ..Default::default()
+        };
+        self.execute_text_loop(conversation, LoopMode::Text, &mut ctx)
+            .await
+    }
+
+    /// Runs the agent, rendering the answer from a terminal tool's output.
     ///
-    /// Default is 10 iterations.
-    pub fn max_iterations(mut self, max: usize) -> Self {
-        self.max_iterations = Some(max);
-        self
+    /// When the model calls a tool registered with
+    /// [`AgentBuilder::terminal_tool`], the tool is executed and its JSON
+    /// output is rendered through the tool's template. The rendered text is
+    /// returned as the answer without asking the model to phrase it, so the
+    /// values shown to the user are exactly what the tool produced. If the
+    /// model answers without calling a terminal tool, its text is returned
+    /// as in [`run`](Self::run).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Render`] if the terminal tool's output cannot be
+    /// rendered, plus any error [`run`](Self::run) can return.
+    pub async fn run_rendered(&self, message: impl Into<String>) -> Result<String> {
+        let mut conversation = self.new_conversation();
+
+        conversation.add_user_message(message);
+        self.execute_text_loop(
+            &mut conversation,
+            LoopMode::Rendered,
+            &mut RunContext::default(),
+        )
+        .await
     }
 
-    /// Builds the agent.
+    /// Runs the agent, reporting how the run ended.
+    ///
+    /// Unlike [`run`](Self::run), the model can end the run without an
+    /// answer: by asking the user a question (see [`AgentBuilder::ask_user`]),
+    /// by handing off to another agent (see
+    /// [`AgentBuilder::handoff_targets`]), or by refusing.
     ///
     /// # Errors
     ///
-    /// Returns an error if required fields (model) are not set.
-    pub fn build(self) -> Result<Agent> {
-        let model = self
-            .model
-            .ok_or_else(|| Error::InvalidConfiguration("Model must be specified".to_string()))?;
+    /// Returns any error [`run`](Self::run) can return.
+    pub async fn run_outcome(&self, message: impl Into<String>) -> Result<RunOutcome> {
+        let mut conversation = self.new_conversation();
 
-        let client = self.client.unwrap_or_else(Client::new);
+        conversation.add_user_message(message);
+        self.run_conversation_outcome(&mut conversation).await
+    }
 
-        Ok(Agent {
-            client,
-            model,
-            system_prompt: self.system_prompt,
-            tools: self.tools,
-            max_iterations: self.max_iterations.unwrap_or(DEFAULT_MAX_ITERATIONS),
+    /// Runs the agent with an existing conversation, reporting how the run
+    /// ended.
+    ///
+    /// After [`RunOutcome::NeedsUserInput`], add the user's reply with
+    /// [`Conversation::add_user_message`] and call this again to continue.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error [`run`](Self::run) can return.
+    pub async fn run_conversation_outcome(
+        &self,
+        conversation: &mut Conversation,
+    ) -> Result<RunOutcome> {
+        self.execute_loop(conversation, LoopMode::Outcome, &mut RunContext::default())
+            .await
+    }
+
+    /// Runs the agent, reporting how the run ended along with token usage,
+    /// iterations, tool calls and the ambiguity gate's decision.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error [`run`](Self::run) can return.
+    pub async fn run_outcome_with_metadata(
+        &self,
+        message: impl Into<String>,
+    ) -> Result<OutcomeResponse> {
+        let mut conversation = self.new_conversation();
+
+        conversation.add_user_message(message);
+        self.run_conversation_outcome_with_metadata(&mut conversation)
+            .await
+    }
+
+    /// Runs the agent with an existing conversation, reporting how the run
+    /// ended along with token usage, iterations, tool calls and the
+    /// ambiguity gate's decision.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error [`run`](Self::run) can return.
+    pub async fn run_conversation_outcome_with_metadata(
+        &self,
+        conversation: &mut Conversation,
+    ) -> Result<OutcomeResponse> {
+        let mut ctx = RunContext::default();
+        let outcome = self
+            .execute_loop(conversation, LoopMode::Outcome, &mut ctx)
+            .await?;
+        Ok(OutcomeResponse {
+            outcome,
+            usage: ctx.usage,
+            iterations: ctx.iterations,
+            tool_calls_made: ctx.tool_calls_made,
+            gate: ctx.gate,
         })
     }
-}
 
-impl Default for AgentBuilder {
-    fn default() -> Self {
-        Self::new()
+    /// Returns tools that hand the conversation over to agents of
+    /// `registry`, one `transfer_to_<name>` tool per name.
+    ///
+    /// When the model calls one in a run that reports its outcome, such as
+    /// [`run_with_handoffs`](Self::run_with_handoffs), the run ends with
+    /// [`RunOutcome::Handoff`]. In other runs the call fails like any
+    /// failing tool, as handled by the agent's [`ToolErrorPolicy`].
+    ///
+    /// ```no_run
+    /// use aiform::prelude::*;
+    /// use aiform::AgentRegistry;
+    ///
+    /// # async fn example(registry: &AgentRegistry) -> Result<()> {
+    /// let triage = Agent::builder()
+    ///     .model("gpt-4")
+    ///     .system_prompt("Route billing questions to billing and the rest to support.")
+    ///     .tools(Agent::handoff_tools(registry, &["billing", "support"])?)
+    ///     .build()?;
+    /// let response = triage.run_with_handoffs(registry, "I was charged twice").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AgentNotFound`] for a name that is not registered.
+    /// Agents that hand off to each other cannot all be registered first;
+    /// give those their targets with [`AgentBuilder::handoff_targets`],
+    /// which are looked up when the handoff happens.
+    pub fn handoff_tools(registry: &AgentRegistry, names: &[&str]) -> Result<ToolSet> {
+        let mut builder = ToolSet::builder();
+        let mut handoffs = HashMap::new();
+        for &target in names {
+            registry.get(target)?;
+            let name = handoff_tool_name(target);
+            handoffs.insert(name.clone(), target.to_string());
+            builder = builder.register(Box::new(HandoffTool {
+                name,
+                description: handoff_description(target),
+                target: target.to_string(),
+            }));
+        }
+        let mut tools = builder.build()?;
+        tools.handoffs = handoffs;
+        Ok(tools)
+    }
+
+    /// Runs the agent, letting the conversation move between the agents of
+    /// `registry`.
+    ///
+    /// When the agent owning the conversation hands it off, through a tool
+    /// from [`handoff_tools`](Self::handoff_tools) or a
+    /// [handoff target](AgentBuilder::handoff_targets), the target continues
+    /// the same conversation under its own system prompt. The run ends when
+    /// an agent ends its run any other way, usually by answering.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AgentNotFound`] for a target that is not registered,
+    /// [`Error::MaxHandoffsExceeded`] when the conversation is transferred
+    /// more often than this agent's [`AgentBuilder::max_handoffs`], and any
+    /// error [`run`](Self::run) can return.
+    pub async fn run_with_handoffs(
+        &self,
+        registry: &AgentRegistry,
+        message: impl Into<String>,
+    ) -> Result<HandoffResponse> {
+        let mut conversation = self.new_conversation();
+        conversation.add_user_message(message);
+
+        let mut owner: Option<Arc<Agent>> = None;
+        let mut transfers = Vec::new();
+        loop {
+            let agent = owner.as_deref().unwrap_or(self);
+            match agent.run_conversation_outcome(&mut conversation).await? {
+                RunOutcome::Handoff { target } => {
+                    if transfers.len() == self.max_handoffs {
+                        transfers.push(target);
+                        return Err(Error::MaxHandoffsExceeded {
+                            max: self.max_handoffs,
+                            transfers,
+                        });
+                    }
+                    owner = Some(registry.get(&target)?);
+                    transfers.push(target);
+                }
+                outcome => {
+                    return Ok(HandoffResponse {
+                        outcome,
+                        transfers,
+                        conversation,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Runs the agent with an existing conversation.
+    ///
+    /// This allows multi-turn conversations where the agent can reference
+    /// previous messages. The answer is added to the conversation unless
+    /// [`AgentBuilder::append_final_response`] is turned off.
+    ///
+    /// The conversation's system prompt is set to the agent's, if it has
+    /// one, unless [`AgentBuilder::inherit_system_prompt`] is turned off.
+    /// With [`AgentBuilder::autosave`], the conversation is saved once the
+    /// agent has answered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the API call fails, tool execution fails,
+    /// the maximum number of iterations is exceeded, or the conversation
+    /// cannot be saved.
+    pub async fn run_conversation(&self, conversation: &mut Conversation) -> Result<String> {
+        self.run_conversation_with_metadata(conversation)
+            .await
+            .map(|response| response.content)
+    }
+
+    /// Starts a run over `conversation` that the caller drives one step at a
+    /// time; see [`AgentRun`].
+    ///
+    /// ```no_run
+    /// use aiform::prelude::*;
+    /// use aiform::AgentStep;
+    ///
+    /// # async fn example(agent: Agent) -> Result<()> {
+    /// let mut conversation = Conversation::new();
+    /// conversation.add_user_message("Find me a flight to Lisbon");
+    /// let mut run = agent.start(&mut conversation);
+    /// let answer = loop {
+    ///     match run.step().await? {
+    ///         AgentStep::ToolCalls(calls) => {
+    ///             for call in &calls {
+    ///                 println!("{} {}", call.name, call.arguments);
+    ///             }
+    ///             run.execute_tools().await?;
+    ///         }
+    ///         AgentStep::Final(answer) => break answer,
+    ///         _ => unreachable!(),
+    ///     }
+    /// };
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn start<'a>(&'a self, conversation: &'a mut Conversation) -> AgentRun<'a> {
+        AgentRun {
+            agent: self,
+            conversation,
+            state: LoopState::new(LoopMode::Text),
+            ctx: RunContext::default(),
+            finished: false,
+        }
+    }
+
+    /// Calls this agent as if it were a tool.
+    ///
+    /// This creates a fresh, isolated conversation for the request and returns
+    /// only the final response. All intermediate tool calls and reasoning are
+    /// hidden from the caller.
+    ///
+    /// This is used when one agent calls another agent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the agent execution fails.
+    pub async fn call_as_tool(&self, message: impl Into<String>) -> Result<String> {
+        let mut private_conversation = self.new_conversation();
+
+        private_conversation.add_user_message(message);
+        self.execute_text_loop(
+            &mut private_conversation,
+            LoopMode::Text,
+            &mut RunContext::default(),
+        )
+        .await
+    }
+
+    /// Estimates the tokens a request for `conversation` would use, including
+    /// the agent's tool definitions, with the agent's
+    /// [token counter](AgentBuilder::token_counter).
+    ///
+    /// ```no_run
+    /// use aiform::prelude::*;
+    ///
+    /// # fn example(agent: Agent, conversation: Conversation) {
+    /// if agent.estimate_request_tokens(&conversation) > 120_000 {
+    ///     println!("The conversation no longer fits the context window");
+    /// }
+    /// # }
+    /// ```
+    pub fn estimate_request_tokens(&self, conversation: &Conversation) -> usize {
+        conversation.estimate_tokens(&*self.token_counter) + self.tool_tokens()
+    }
+
+    /// Starts a conversation with the agent's system prompt, if any.
+    pub(crate) fn new_conversation(&self) -> Conversation {
+        match self.system_prompt {
+            Some(ref prompt) => Conversation::with_system(prompt.clone()),
+            None => Conversation::new(),
+        }
+    }
+
+    /// Whether runs add the final answer to the conversation themselves.
+    pub(crate) fn appends_final_response(&self) -> bool {
+        self.append_final_response
+    }
+
+    /// Executes the agent loop, then collects unreferenced attachments and
+    /// saves the conversation if configured.
+    async fn execute_loop(
+        &self,
+        conversation: &mut Conversation,
+        mode: LoopMode,
+        ctx: &mut RunContext,
+    ) -> Result<RunOutcome> {
+        let before = match self.attachments {
+            Some(ref attachments) => attachments.snapshot().await?,
+            None => None,
+        };
+
+        let outcome = self.run_loop(conversation, mode, ctx).await?;
+        if let (Some(ref attachments), Some(before)) = (&self.attachments, before) {
+            let mut referenced = serde_json::to_string(conversation.messages())?;
+            if let RunOutcome::Answer(ref answer) = outcome {
+                referenced.push_str(answer);
+            }
+            attachments
+                .collect_unreferenced(&before, &referenced)
+                .await?;
+        }
+        self.autosave(conversation).await?;
+        Ok(outcome)
+    }
+
+    /// Saves the conversation to the [`autosave`](AgentBuilder::autosave)
+    /// store, if any.
+    async fn autosave(&self, conversation: &Conversation) -> Result<()> {
+        if let Some((ref store, ref id)) = self.autosave {
+            store.save(id, conversation).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs the agent loop: LLM call -> tool execution -> repeat.
+    ///
+    /// In [`LoopMode::Rendered`], a call to a terminal tool ends the loop with
+    /// the tool's rendered output. In [`LoopMode::Outcome`], the `ask_user`
+    /// and handoff pseudo-tools end the loop, and content-filtered responses
+    /// are reported as refusals.
+    async fn run_loop(
+        &self,
+        conversation: &mut Conversation,
+        mode: LoopMode,
+        ctx: &mut RunContext,
+    ) -> Result<RunOutcome> {
+        let span = trace::run_span(self.name.as_deref(), &self.model);
+        trace::instrument(
+            async {
+                let mut state = LoopState::new(mode);
+                let result = loop {
+                    // Tool calls are executed when the loop advances again.
+                    match self.advance(&mut state, conversation, ctx).await {
+                        Ok(Progress::Done(outcome)) => break Ok(outcome),
+                        Ok(Progress::ToolCalls(_)) => {}
+                        Err(error) => break Err(error),
+                    }
+                };
+                trace::record_iterations(ctx.iterations);
+                trace::record_usage(&ctx.usage);
+                if let Err(ref error) = result {
+                    self.metrics.record_error(error);
+                    trace::error(error);
+                }
+                result
+            },
+            span,
+        )
+        .await
+    }
+
+    /// Advances the agent loop by one step: finishes the pending tool calls,
+    /// then sends the next completion request.
+    ///
+    /// Returns the response's tool calls, left pending in `state`, or how the
+    /// run ended.
+    async fn advance(
+        &self,
+        state: &mut LoopState,
+        conversation: &mut Conversation,
+        ctx: &mut RunContext,
+    ) -> Result<Progress> {
+        let mode = state.mode;
+        if !state.started {
+            state.started = true;
+            self.notify("on_run_start", |hook| {
+                hook.on_run_start(self.name.as_deref())
+            })
+            .await?;
+            if let Some(ref prompt) = self.system_prompt {
+                if self.inherit_system_prompt
+                    && conversation.system_prompt() != Some(prompt.as_str())
+                {
+                    conversation.set_system_prompt(prompt.clone());
+                }
+            }
+            state.pseudo_tools = self.builtin_tools();
+            if mode == LoopMode::Outcome {
+                state.pseudo_tools.extend(self.pseudo_tools());
+            }
+
+            if mode == LoopMode::Outcome {
+                if let Some(ref gate) = self.ambiguity_gate {
+                    let report = self.run_gate(gate, conversation, ctx).await;
+                    let question = report
+                        .classification
+                        .as_ref()
+                        .filter(|_| report.verdict == GateVerdict::Clarify)
+                        .map(|classification| classification.question.clone());
+                    ctx.gate = Some(report);
+                    if let Some(question) = question {
+                        conversation.add_assistant_message(question.clone());
+                        self.sign(conversation);
+                        return Ok(Progress::Done(RunOutcome::NeedsUserInput { question }));
+                    }
+                }
+            }
+
+            state.tool_choice = match ctx.tool_choice.take() {
+                Some(choice) => {
+                    self.check_tool_choice(&choice)?;
+                    Some(choice)
+                }
+                None => self.tool_choice.clone(),
+            };
+        }
+
+        if let Some(mut round) = state.round.take() {
+            self.execute_round(&mut round, conversation, ctx, mode)
+                .await?;
+            if let Some(outcome) = round.terminal {
+                return outcome.map(Progress::Done);
+            }
+        }
+
+        if state.iterations == self.max_iterations {
+            return Err(Error::MaxIterationsExceeded {
+                max: self.max_iterations,
+                agent: self.name.clone(),
+            });
+        }
+        state.iterations += 1;
+
+        if ctx.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        ctx.iterations += 1;
+        let iteration = ctx.iterations;
+        let span = trace::iteration_span(iteration);
+        trace::instrument(self.iterate(state, conversation, ctx, iteration), span).await
+    }
+
+    /// Sends the next completion request of the loop and handles its
+    /// response; the second half of [`advance`](Self::advance).
+    async fn iterate(
+        &self,
+        state: &mut LoopState,
+        conversation: &mut Conversation,
+        ctx: &mut RunContext,
+        iteration: usize,
+    ) -> Result<Progress> {
+        let mode = state.mode;
+        self.notify("on_iteration", |hook| hook.on_iteration(iteration))
+            .await?;
+        ctx.emit(AgentEvent::IterationStarted {
+            iteration,
+            max_iterations: self.max_iterations,
+        });
+        let reshaped = self.apply_context_strategy(conversation, ctx).await?;
+        let mut messages = std::mem::take(&mut state.messages);
+        let history = conversation.messages();
+        // Comparing is cheap next to copying, and catches edits anywhere in
+        // the history, not only at its end.
+        if reshaped || !history.starts_with(&messages) {
+            messages.clear();
+        }
+        messages.extend_from_slice(&history[messages.len()..]);
+        let history_len = history.len();
+        let mut request = CreateChatCompletionRequestArgs::default();
+        request.model(&self.model);
+        request.messages(messages);
+
+        let tools = state.tools.take().unwrap_or_else(|| {
+            let mut tools = self
+                .tools
+                .as_ref()
+                .map(|toolset| toolset.tools().to_vec())
+                .unwrap_or_default();
+            tools.extend(state.pseudo_tools.iter().cloned());
+            tools
+        });
+        if !tools.is_empty() {
+            request.tools(tools);
+            if let Some(ref choice) = state.tool_choice {
+                request.tool_choice(choice.to_option());
+            }
+        }
+        // A forced tool is called once; then the model may answer.
+        if let Some(ToolChoice::Named(_)) = state.tool_choice {
+            state.tool_choice = Some(ToolChoice::Auto);
+        }
+
+        let mut request = request.build().map_err(|e| {
+            Error::InvalidConfiguration(format!("Failed to build chat request: {}", e))
+        })?;
+        self.sampling.apply(&mut request);
+        if self.json_mode {
+            request.response_format = Some(ResponseFormat::JsonObject);
+        }
+        if let Some(ref json_schema) = ctx.output_schema {
+            request.response_format = Some(ResponseFormat::JsonSchema {
+                json_schema: json_schema.clone(),
+            });
+            // Without `response_format` the schema has to be in the prompt.
+            if !self.provider.capabilities().supports("response_format") {
+                let mut instruction = Conversation::new();
+                instruction.add_system_message(format!(
+                    "Answer with a JSON object that matches this JSON schema: {}",
+                    json_schema.schema.clone().unwrap_or_default()
+                ));
+                request.messages.extend_from_slice(instruction.messages());
+            }
+        }
+
+        self.notify("on_request", |hook| hook.on_request(conversation))
+            .await?;
+        let response = self.complete_cancellable(&mut request, ctx).await?;
+        state.messages = request.messages;
+        state.messages.truncate(history_len);
+        state.tools = request.tools;
+
+        let choice = response
+            .choices
+            .first()
+            .ok_or_else(|| Error::Other("No response from API".into()))?;
+
+        let message = &choice.message;
+        let mut usage = Usage::default();
+        if let Some(ref response_usage) = response.usage {
+            usage.add(response_usage);
+        }
+        trace::record_usage(&usage);
+        if !self.hooks.is_empty() {
+            self.notify("on_response", |hook| hook.on_response(message, &usage))
+                .await?;
+        }
+        ctx.emit(AgentEvent::ResponseReceived {
+            iteration,
+            tool_calls: message.tool_calls.as_ref().map_or(0, Vec::len),
+        });
+
+        if mode == LoopMode::Outcome && choice.finish_reason == Some(FinishReason::ContentFilter) {
+            return Ok(Progress::Done(RunOutcome::Refused {
+                reason: message
+                    .content
+                    .clone()
+                    .filter(|content| !content.is_empty())
+                    .unwrap_or_else(|| "The response was blocked by a content filter".into()),
+            }));
+        }
+
+        // Check if there are tool calls
+        if let Some(ref tool_calls) = message.tool_calls {
+            ctx.tool_calls_made += tool_calls.len();
+            let mut tool_calls = tool_calls.clone();
+            if choice.finish_reason == Some(FinishReason::Length) {
+                self.continue_truncated_arguments(conversation, &mut tool_calls, ctx)
+                    .await?;
+            }
+
+            let arguments = tool_calls
+                .iter()
+                .map(parse_tool_arguments)
+                .collect::<Result<Vec<_>>>()?;
+
+            // Add assistant message with tool calls
+            let round_start = conversation.len();
+            conversation
+                .add_assistant_message_with_tools(message.content.clone(), tool_calls.clone());
+            self.sign(conversation);
+
+            let round = ToolRound {
+                results: vec![None; tool_calls.len()],
+                started: vec![None; tool_calls.len()],
+                durations: vec![Duration::ZERO; tool_calls.len()],
+                calls: tool_calls,
+                arguments,
+                round_start,
+                added: 0,
+                terminal: None,
+            };
+            let pending = round.pending();
+            state.round = Some(round);
+            return Ok(Progress::ToolCalls(pending));
+        }
+
+        // No tool calls, this is the final response
+        if let Some(content) = &message.content {
+            // Post-processing could break structured output.
+            let answer = if ctx.output_schema.is_some() {
+                content.clone()
+            } else {
+                self.post_process(content.clone())
+            };
+            if self.append_final_response {
+                conversation.add_assistant_message(answer.clone());
+                self.sign(conversation);
+            }
+            return Ok(Progress::Done(RunOutcome::Answer(answer)));
+        }
+
+        Err(Error::Other(
+            "Agent returned no content or tool calls".into(),
+        ))
+    }
+
+    /// Executes the tool calls of a round that have no result yet, adding
+    /// their results to the conversation.
+    ///
+    /// A call that ends the run, such as a terminal tool, is kept as the
+    /// round's outcome.
+    async fn execute_round(
+        &self,
+        round: &mut ToolRound,
+        conversation: &mut Conversation,
+        ctx: &mut RunContext,
+        mode: LoopMode,
+    ) -> Result<()> {
+        let unanswered: Vec<usize> = (0..round.calls.len())
+            .filter(|&index| round.results[index].is_none())
+            .collect();
+
+        let mut denied = HashMap::new();
+        for &index in &unanswered {
+            let tool_name = &round.calls[index].function.name;
+            let Some(approver) = self.approvals.get(tool_name) else {
+                continue;
+            };
+            match approver(tool_name, &round.arguments[index]).await {
+                ApprovalDecision::Approve => {}
+                ApprovalDecision::Deny { reason } => {
+                    denied.insert(index, reason);
+                }
+                ApprovalDecision::Modify(arguments) => round.arguments[index] = arguments,
+            }
+        }
+
+        let parallel = self.tool_concurrency > 1 && unanswered.len() > 1;
+        let mut dispatched = HashMap::new();
+        if parallel {
+            if ctx.is_cancelled() {
+                conversation.truncate(round.round_start);
+                return Err(Error::Cancelled);
+            }
+            for &index in &unanswered {
+                round.start(index, ctx);
+            }
+            let approved: Vec<usize> = unanswered
+                .iter()
+                .copied()
+                .filter(|index| !denied.contains_key(index))
+                .collect();
+            let tool_calls: Vec<_> = approved
+                .iter()
+                .map(|&index| round.calls[index].clone())
+                .collect();
+            let arguments: Vec<_> = approved
+                .iter()
+                .map(|&index| round.arguments[index].clone())
+                .collect();
+            let results = self
+                .dispatch_concurrently(&tool_calls, &arguments, mode)
+                .await;
+            dispatched = approved.into_iter().zip(results).collect();
+        }
+
+        for &index in &unanswered {
+            let tool_call = round.calls[index].clone();
+            let args = round.arguments[index].clone();
+            let tool_name = &tool_call.function.name;
+            if !parallel {
+                if ctx.is_cancelled() {
+                    // Keep tool calls and their results together.
+                    conversation.truncate(round.round_start);
+                    return Err(Error::Cancelled);
+                }
+                round.start(index, ctx);
+            }
+
+            if let Some(ref attachments) = self.attachments {
+                if tool_name == READ_ATTACHMENT_TOOL {
+                    let result = attachments.read_tool(&args).await;
+                    round.answer(index, result, conversation, ctx);
+                    continue;
+                }
+            }
+
+            if mode == LoopMode::Outcome {
+                if let Some(outcome) = self.pseudo_tool_outcome(tool_name, &args) {
+                    round.answer(index, pseudo_tool_result(&outcome), conversation, ctx);
+                    round.terminal.get_or_insert(Ok(outcome));
+                    continue;
+                }
+            }
+
+            if let Some(reason) = denied.remove(&index) {
+                let denial = ToolError::permission_denied(format!(
+                    "The call to '{}' was not approved: {}",
+                    tool_name, reason
+                ));
+                round.answer(index, denial.to_model_json(), conversation, ctx);
+                continue;
+            }
+
+            // Execute tools
+            let toolset = self.tools.as_ref().ok_or_else(|| {
+                Error::InvalidConfiguration(
+                    "Agent received tool calls but has no tools configured".to_string(),
+                )
+            })?;
+
+            let result = match dispatched.remove(&index).flatten() {
+                Some((result, duration)) => {
+                    // Concurrent calls may have waited for a free slot.
+                    round.started[index] = None;
+                    round.durations[index] = duration;
+                    result
+                }
+                None => self.dispatch_observed(toolset, tool_name, args).await,
+            };
+            let mut result = match result? {
+                Ok(result) => result,
+                Err(error) => {
+                    round.answer(index, error.to_model_json(), conversation, ctx);
+                    continue;
+                }
+            };
+
+            if self.plain_text_tools.applies_to(tool_name) {
+                result = plain_text::normalize_tool_result(&result);
+            }
+
+            if mode == LoopMode::Rendered && round.terminal.is_none() {
+                if let Some(template) = self.terminal_tools.get(tool_name) {
+                    round.terminal = Some(
+                        template
+                            .render_str(&result)
+                            .map(|answer| RunOutcome::Answer(self.post_process(answer)))
+                            .map_err(|e| match e {
+                                Error::Render(msg) => {
+                                    Error::Render(format!("tool '{}': {}", tool_name, msg))
+                                }
+                                other => other,
+                            }),
+                    );
+                }
+            }
+
+            if let Some(ref attachments) = self.attachments {
+                result = attachments.expand_refs(&result).await?;
+            }
+
+            let limit = self.tool_output_limits.get(tool_name).copied();
+            if let Some(limit) = limit.or(self.max_tool_output_bytes) {
+                truncate_tool_output(&mut result, limit);
+            }
+
+            round.answer(index, result, conversation, ctx);
+        }
+        Ok(())
+    }
+
+    /// Classifies whether the conversation's latest request is specific
+    /// enough to act on. Fails open: classification errors let the run
+    /// proceed.
+    async fn run_gate(
+        &self,
+        gate: &AmbiguityGate,
+        conversation: &Conversation,
+        ctx: &mut RunContext,
+    ) -> GateReport {
+        let started = Instant::now();
+        let report = |verdict, classification| GateReport {
+            verdict,
+            classification,
+            latency: started.elapsed(),
+        };
+        let Some((transcript, latest)) = gate::transcript(conversation) else {
+            return report(GateVerdict::Proceed, None);
+        };
+        if gate.is_allowed(&latest) {
+            return report(GateVerdict::Allowed, None);
+        }
+
+        match self.classify(gate, transcript, ctx).await {
+            Ok(classification) => {
+                let verdict = match gate.verdict(&classification) {
+                    // Without a question there is nothing to ask.
+                    GateVerdict::Clarify if classification.question.trim().is_empty() => {
+                        GateVerdict::Proceed
+                    }
+                    verdict => verdict,
+                };
+                report(verdict, Some(classification))
+            }
+            Err(error) => {
+                self.warn(Warning::AmbiguityGateFailed {
+                    error: error.clone(),
+                });
+                report(GateVerdict::Failed(error), None)
+            }
+        }
+    }
+
+    /// Sends the gate's classification request.
+    async fn classify(
+        &self,
+        gate: &AmbiguityGate,
+        transcript: String,
+        ctx: &mut RunContext,
+    ) -> std::result::Result<GateClassification, String> {
+        let mut messages = Conversation::with_system(gate::GATE_PROMPT);
+        messages.add_user_message(transcript);
+        let mut request = CreateChatCompletionRequestArgs::default()
+            .model(gate.model.as_deref().unwrap_or(&self.model))
+            .messages(messages.messages().to_vec())
+            .build()
+            .map_err(|e| e.to_string())?;
+        request.response_format = Some(ResponseFormat::JsonObject);
+        let response = self
+            .complete(&mut request, ctx)
+            .await
+            .map_err(|e| e.to_string())?;
+        let content = response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.as_deref())
+            .ok_or("the classification was empty")?;
+        gate::parse_classification(content)
+    }
+
+    /// Checks that a per-run tool choice names one of the agent's tools.
+    fn check_tool_choice(&self, choice: &ToolChoice) -> Result<()> {
+        check_tool_choice(self.tools.as_deref(), choice)
+    }
+
+    /// Runs the loop in a mode that can only end with an answer.
+    async fn execute_text_loop(
+        &self,
+        conversation: &mut Conversation,
+        mode: LoopMode,
+        ctx: &mut RunContext,
+    ) -> Result<String> {
+        match self.execute_loop(conversation, mode, ctx).await? {
+            RunOutcome::Answer(answer) => Ok(answer),
+            other => Err(Error::Other(
+                format!("Unexpected run outcome: {:?}", other).into(),
+            )),
+        }
+    }
+
+    /// Sends a completion request, retrying retryable failures according
+    /// to the retry policy.
+    ///
+    /// Parameters the provider does not support are dropped first. With
+    /// [`AgentBuilder::strip_rejected_parameters`], a request rejected for
+    /// one of its parameters is sent once more without it.
+    async fn complete(
+        &self,
+        request: &mut CreateChatCompletionRequest,
+        ctx: &mut RunContext,
+    ) -> Result<CreateChatCompletionResponse> {
+        let capabilities = self.provider.capabilities();
+        for parameter in provider::strip_unsupported(request, &capabilities)? {
+            if ctx.dropped_parameters.insert(parameter.clone()) {
+                self.warn(Warning::ParameterDropped { parameter });
+            }
+        }
+        if ctx.stream {
+            request.stream = Some(true);
+        }
+        if !self.allow_extra_body_overrides {
+            let extra_body = ctx.extra_body.as_ref().unwrap_or(&self.extra_body);
+            let conflicts = backend::conflicting_fields(request, extra_body)?;
+            if !conflicts.is_empty() {
+                return Err(Error::InvalidConfiguration(format!(
+                    "Extra body fields conflict with request parameters: {}",
+                    conflicts.join(", ")
+                )));
+            }
+        }
+
+        let max_retries = self.retry.map_or(0, |policy| policy.max_retries);
+        let mut retry = 0;
+        let mut stripped_rejected = false;
+        loop {
+            let error = match self.complete_once(request, ctx).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+
+            if retry < max_retries && error.is_retryable() {
+                retry += 1;
+                let backoff = self.retry.unwrap_or_default().backoff(retry);
+                self.warn(Warning::RequestRetried {
+                    attempt: retry,
+                    backoff,
+                    error: error.to_string(),
+                });
+                tokio::time::sleep(backoff).await;
+                continue;
+            }
+
+            if self.strip_rejected_parameters && !stripped_rejected {
+                if let Some(parameter) = provider::rejected_parameter(&error, request) {
+                    provider::learn_rejected(&request.model, &parameter);
+                    self.warn(Warning::ParameterRejected {
+                        parameter: parameter.clone(),
+                        model: request.model.clone(),
+                    });
+                    ctx.dropped_parameters.insert(parameter);
+                    provider::strip_unsupported(request, &capabilities)?;
+                    stripped_rejected = true;
+                    continue;
+                }
+            }
+
+            return Err(error);
+        }
+    }
+
+    /// Sends a completion request like [`complete`](Self::complete),
+    /// returning [`Error::Cancelled`] as soon as the run is cancelled.
+    async fn complete_cancellable(
+        &self,
+        request: &mut CreateChatCompletionRequest,
+        ctx: &mut RunContext,
+    ) -> Result<CreateChatCompletionResponse> {
+        match ctx.cancel.clone() {
+            Some(cancel) => tokio::select! {
+                biased;
+                _ = cancel.cancelled() => Err(Error::Cancelled),
+                response = self.complete(request, ctx) => response,
+            },
+            None => self.complete(request, ctx).await,
+        }
+    }
+
+    /// Sends a completion request once, bounded by the request timeout if
+    /// set.
+    async fn complete_once(
+        &self,
+        request: &CreateChatCompletionRequest,
+        ctx: &mut RunContext,
+    ) -> Result<CreateChatCompletionResponse> {
+        let Some(limit) = self.request_timeout else {
+            return self.send_request(request, ctx).await;
+        };
+        let started = Instant::now();
+        tokio::time::timeout(limit, self.send_request(request, ctx))
+            .await
+            .map_err(|_| Error::Timeout {
+                elapsed: started.elapsed(),
+            })?
+    }
+
+    /// Sends a completion request, streaming it when the run is streamed.
+    async fn send_request(
+        &self,
+        request: &CreateChatCompletionRequest,
+        ctx: &mut RunContext,
+    ) -> Result<CreateChatCompletionResponse> {
+        let _permit = self.acquire_permit(ctx).await;
+        let request = self.chat_request(request, ctx);
+        let started = Instant::now();
+        let response = if !ctx.stream {
+            self.client
+                .create_chat_completion(request)
+                .await
+                .map_err(Error::from)
+        } else {
+            match self.client.create_chat_completion_stream(request).await {
+                Ok(chunks) => {
+                    stream::collect_response(chunks, |text| {
+                        ctx.emit(AgentEvent::TextDelta(text.to_string()))
+                    })
+                    .await
+                }
+                Err(error) => Err(error.into()),
+            }
+        };
+        self.metrics.record_request(
+            started.elapsed(),
+            response
+                .as_ref()
+                .ok()
+                .and_then(|response| response.usage.as_ref()),
+        );
+        let response = response?;
+        ctx.record_usage(&response);
+        Ok(response)
+    }
+
+    /// Adds the run's extra body fields to a request.
+    fn chat_request<'a>(
+        &'a self,
+        request: &'a CreateChatCompletionRequest,
+        ctx: &'a RunContext,
+    ) -> ChatRequest<'a> {
+        ChatRequest {
+            request,
+            extra_body: Some(ctx.extra_body.as_ref().unwrap_or(&self.extra_body)),
+        }
+    }
+
+    /// Waits for a permit from the agent's limiter, if it has one.
+    async fn acquire_permit(&self, ctx: &mut RunContext) -> Option<LimiterPermit> {
+        let limiter = self.limiter.as_ref()?;
+        let permit = limiter.acquire(ctx.priority).await;
+        ctx.timings.scheduling_wait += permit.waited();
+        Some(permit)
+    }
+
+    /// Definitions of the built-in tools offered in every mode.
+    fn builtin_tools(&self) -> Vec<ChatCompletionTool> {
+        let mut tools = Vec::new();
+        if let Some(ref attachments) = self.attachments {
+            tools.push(function_tool(
+                READ_ATTACHMENT_TOOL,
+                "Read part of an attachment by id. Tool results show only a preview of \
+                 attachments; use this to read the parts you need.",
+                attachments.read_tool_parameters(),
+            ));
+        }
+        tools
+    }
+
+    /// Definitions of the enabled pseudo-tools.
+    fn pseudo_tools(&self) -> Vec<ChatCompletionTool> {
+        let mut tools = Vec::new();
+        if self.ask_user {
+            tools.push(function_tool(
+                ASK_USER_TOOL,
+                "Ask the user a clarifying question when you cannot proceed without \
+                 their input. The conversation pauses until they reply.",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "question": { "type": "string", "description": "The question to ask" }
+                    },
+                    "required": ["question"]
+                }),
+            ));
+        }
+        for target in &self.handoff_targets {
+            tools.push(function_tool(
+                &handoff_tool_name(target),
+                &handoff_description(target),
+                json!({ "type": "object", "properties": {} }),
+            ));
+        }
+        tools
+    }
+
+    /// Returns the outcome a pseudo-tool call ends the run with, if
+    /// `tool_name` is an enabled pseudo-tool.
+    fn pseudo_tool_outcome(&self, tool_name: &str, args: &serde_json::Value) -> Option<RunOutcome> {
+        if self.ask_user && tool_name == ASK_USER_TOOL {
+            let question = args["question"].as_str().unwrap_or_default().to_string();
+            return Some(RunOutcome::NeedsUserInput { question });
+        }
+        self.handoff_targets
+            .iter()
+            .find(|target| handoff_tool_name(target) == tool_name)
+            .or_else(|| self.tools.as_ref()?.handoffs.get(tool_name))
+            .map(|target| RunOutcome::Handoff {
+                target: target.clone(),
+            })
+    }
+
+    /// Returns whether a tool call is answered by the agent itself rather
+    /// than dispatched to the tool set.
+    fn is_builtin_call(&self, tool_name: &str, args: &serde_json::Value, mode: LoopMode) -> bool {
+        (self.attachments.is_some() && tool_name == READ_ATTACHMENT_TOOL)
+            || (mode == LoopMode::Outcome && self.pseudo_tool_outcome(tool_name, args).is_some())
+    }
+
+    /// Dispatches the tool calls of one response concurrently, up to the
+    /// configured limit, returning each call's result at its position.
+    ///
+    /// Calls answered by the agent itself are left as `None`.
+    async fn dispatch_concurrently(
+        &self,
+        tool_calls: &[ChatCompletionMessageToolCall],
+        arguments: &[serde_json::Value],
+        mode: LoopMode,
+    ) -> Vec<Option<(Result<std::result::Result<String, ToolError>>, Duration)>> {
+        let Some(ref toolset) = self.tools else {
+            return Vec::new();
+        };
+        let mut dispatches = Vec::with_capacity(tool_calls.len());
+        for (tool_call, args) in tool_calls.iter().zip(arguments) {
+            let tool_name = &tool_call.function.name;
+            let builtin = self.is_builtin_call(tool_name, args, mode);
+            let dispatch = self.dispatch_observed(toolset, tool_name, args.clone());
+            dispatches.push(async move {
+                if builtin {
+                    None
+                } else {
+                    let started = Instant::now();
+                    let result = dispatch.await;
+                    Some((result, started.elapsed()))
+                }
+            });
+        }
+        futures::stream::iter(dispatches)
+            .buffered(self.tool_concurrency)
+            .collect()
+            .await
+    }
+
+    /// Dispatches a tool call in its own trace span, telling the agent's
+    /// hooks when it starts and ends.
+    async fn dispatch_observed(
+        &self,
+        toolset: &ToolSet,
+        tool_name: &str,
+        args: serde_json::Value,
+    ) -> Result<std::result::Result<String, ToolError>> {
+        let span = trace::tool_span(tool_name, &args);
+        trace::instrument(self.dispatch_hooked(toolset, tool_name, args), span).await
+    }
+
+    async fn dispatch_hooked(
+        &self,
+        toolset: &ToolSet,
+        tool_name: &str,
+        args: serde_json::Value,
+    ) -> Result<std::result::Result<String, ToolError>> {
+        if !self.hooks.is_empty() {
+            self.notify("on_tool_start", |hook| hook.on_tool_start(tool_name, &args))
+                .await?;
+        }
+        let started = Instant::now();
+        let result = self.dispatch_tool(toolset, tool_name, args).await;
+        let duration = started.elapsed();
+        self.metrics
+            .record_tool_call(tool_name, duration, matches!(result, Ok(Err(_))));
+        match result {
+            Ok(Ok(ref output)) => trace::record_tool_result(output.len(), duration),
+            Ok(Err(ref error)) => {
+                trace::record_tool_result(0, duration);
+                trace::error(error);
+            }
+            Err(ref error) => trace::error(error),
+        }
+        if self.hooks.is_empty() {
+            return result;
+        }
+        // Hooks also see the failures that abort the run.
+        let observed = match result {
+            Ok(ref result) => result.clone(),
+            Err(ref error) => Err(ToolError::fatal(error.to_string())),
+        };
+        self.notify("on_tool_end", |hook| {
+            hook.on_tool_end(tool_name, &observed, duration)
+        })
+        .await?;
+        result
+    }
+
+    /// Calls every hook in turn, stopping the run if one aborts it. A hook
+    /// that panics is reported as a warning and skipped.
+    async fn notify<'a>(
+        &'a self,
+        event: &str,
+        call: impl Fn(&'a dyn AgentHook) -> BoxFuture<'a, HookAction>,
+    ) -> Result<()> {
+        for hook in &self.hooks {
+            let called = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| call(&**hook)));
+            let action = match called {
+                Ok(future) => std::panic::AssertUnwindSafe(future).catch_unwind().await,
+                Err(panic) => Err(panic),
+            };
+            match action {
+                Ok(HookAction::Continue) => {}
+                Ok(HookAction::Abort { reason }) => {
+                    return Err(Error::HookAborted {
+                        event: event.to_string(),
+                        reason,
+                    })
+                }
+                Err(panic) => {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string());
+                    self.warn(Warning::HookPanicked {
+                        event: event.to_string(),
+                        message,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Dispatches a tool call, applying the tool error policy.
+    ///
+    /// Returns the tool's output, or the error to report to the model.
+    async fn dispatch_tool(
+        &self,
+        toolset: &ToolSet,
+        tool_name: &str,
+        args: serde_json::Value,
+    ) -> Result<std::result::Result<String, ToolError>> {
+        let mut attempt = 0;
+        loop {
+            let dispatch = toolset.dispatch(tool_name.to_string(), args.clone());
+            let timeout = self.tool_timeouts.get(tool_name).copied();
+            let result = match timeout.or(self.tool_timeout) {
+                None => dispatch.await,
+                Some(limit) => tokio::time::timeout(limit, dispatch)
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(ToolError::timeout(format!(
+                            "the tool did not finish within {}",
+                            crate::duration::format(limit)
+                        ))
+                        .into())
+                    }),
+            };
+            let mut error = match result {
+                Ok(result) => return Ok(Ok(result)),
+                Err(e) => {
+                    if let Some(Error::InvalidToolArguments { errors, .. }) = e.downcast_ref() {
+                        // The same arguments would fail again; no retry.
+                        let invalid = Error::InvalidToolArguments {
+                            tool_name: tool_name.to_string(),
+                            errors: errors.clone(),
+                            raw: args,
+                        };
+                        let error = ToolError::from_boxed(e);
+                        return match self.tool_error_policy.action_for(&error) {
+                            ToolErrorAction::ReturnToModel => Ok(Err(error)),
+                            ToolErrorAction::Abort => Err(invalid),
+                        };
+                    }
+                    ToolError::from_boxed(e)
+                }
+            };
+            if error.code == ToolError::UNKNOWN_TOOL {
+                let available: Vec<&str> = toolset
+                    .tools()
+                    .iter()
+                    .map(|tool| tool.function.name.as_str())
+                    .collect();
+                error.message = format!(
+                    "no such tool '{}', available tools are: {}",
+                    tool_name,
+                    available.join(", ")
+                );
+            }
+
+            if error.retryable && attempt < self.tool_error_policy.retries() {
+                attempt += 1;
+                self.warn(Warning::ToolRetried {
+                    tool_name: tool_name.to_string(),
+                    attempt,
+                    code: error.code.clone(),
+                });
+                continue;
+            }
+
+            return match self.tool_error_policy.action_for(&error) {
+                ToolErrorAction::ReturnToModel => Ok(Err(error)),
+                ToolErrorAction::Abort if error.code == ToolError::UNKNOWN_TOOL => {
+                    Err(Error::ToolNotFound(tool_name.to_string()))
+                }
+                ToolErrorAction::Abort => Err(Error::ToolExecution {
+                    tool_name: tool_name.to_string(),
+                    message: if error.code == ToolError::TOOL_FAILED {
+                        error.message
+                    } else {
+                        error.to_string()
+                    },
+                    agent: self.name.clone(),
+                }),
+            };
+        }
+    }
+
+    /// Requests continuations for the last tool call's arguments while they
+    /// are unterminated JSON, up to the configured limit.
+    async fn continue_truncated_arguments(
+        &self,
+        conversation: &Conversation,
+        tool_calls: &mut [ChatCompletionMessageToolCall],
+        ctx: &mut RunContext,
+    ) -> Result<()> {
+        let Some(tool_call) = tool_calls.last_mut() else {
+            return Ok(());
+        };
+        let tool_name = tool_call.function.name.clone();
+        let mut arguments = std::mem::take(&mut tool_call.function.arguments);
+
+        for continuation in 1..=self.argument_continuation.max_continuations {
+            match serde_json::from_str::<serde_json::Value>(&arguments) {
+                Err(e) if e.is_eof() => {}
+                // Complete, or invalid for a reason more text cannot fix.
+                _ => break,
+            }
+
+            self.warn(Warning::TruncatedToolArguments {
+                tool_name: tool_name.clone(),
+                continuation,
+                received_bytes: arguments.len(),
+            });
+
+            let mut request = self.continuation_request(conversation, &tool_name, &arguments)?;
+            // The fragments are JSON for the tool call, not text to stream.
+            let stream = std::mem::replace(&mut ctx.stream, false);
+            let response = self.complete_cancellable(&mut request, ctx).await;
+            ctx.stream = stream;
+            let response = response?;
+            let fragment = response
+                .choices
+                .into_iter()
+                .next()
+                .and_then(|choice| choice.message.content)
+                .unwrap_or_default();
+            if fragment.is_empty() {
+                break;
+            }
+            arguments = stitch_fragment(&arguments, &fragment);
+        }
+
+        tool_call.function.arguments = arguments;
+        Ok(())
+    }
+
+    /// Builds a request asking the model to continue truncated arguments.
+    fn continuation_request(
+        &self,
+        conversation: &Conversation,
+        tool_name: &str,
+        partial: &str,
+    ) -> Result<CreateChatCompletionRequest> {
+        let mut continuation = conversation.clone();
+        continuation.add_assistant_message(partial);
+
+        if !self.argument_continuation.assistant_prefill {
+            continuation.add_user_message(format!(
+                "Your previous message was cut off while writing the JSON arguments for the \
+                 `{}` tool call; it is shown above. Continue the JSON exactly where it stopped. \
+                 Reply with only the remaining characters, without repeating anything already \
+                 written and without code fences.",
+                tool_name
+            ));
+        }
+
+        let mut request = CreateChatCompletionRequestArgs::default()
+            .model(&self.model)
+            .messages(continuation.messages().to_vec())
+            .build()
+            .map_err(|e| {
+                Error::InvalidConfiguration(format!("Failed to build chat request: {}", e))
+            })?;
+        self.sampling.apply(&mut request);
+        Ok(request)
+    }
+
+    /// Names the assistant message just added after the agent, if it has a
+    /// name, so multi-agent conversations show who said what.
+    fn sign(&self, conversation: &mut Conversation) {
+        let Some(ref name) = self.name else {
+            return;
+        };
+        if let Some(ChatCompletionRequestMessage::Assistant(message)) =
+            conversation.messages_mut().last_mut()
+        {
+            message.name = Some(participant_name(name));
+        }
+    }
+
+    /// Runs the final answer through the post-processor chain.
+    fn post_process(&self, answer: String) -> String {
+        self.post_processors
+            .iter()
+            .fold(answer, |answer, processor| processor(answer))
+    }
+
+    /// Fits the conversation to the context window, if configured.
+    ///
+    /// Returns whether any messages were dropped or summarized.
+    async fn apply_context_strategy(
+        &self,
+        conversation: &mut Conversation,
+        ctx: &mut RunContext,
+    ) -> Result<bool> {
+        let Some(ref strategy) = self.context_strategy else {
+            return Ok(false);
+        };
+        match *strategy {
+            ContextStrategy::SlidingWindow { max_tokens } => {
+                let budget = max_tokens.saturating_sub(self.tool_tokens());
+                let dropped = conversation.truncate_to_tokens(budget, &*self.token_counter);
+                if dropped > 0 {
+                    self.warn(Warning::ContextTruncated { dropped });
+                }
+                Ok(dropped > 0)
+            }
+            ContextStrategy::Summarize {
+                trigger_tokens,
+                keep_recent,
+            } => {
+                if self.estimate_request_tokens(conversation) <= trigger_tokens {
+                    return Ok(false);
+                }
+                let summarized = self.compact_with(conversation, keep_recent, ctx).await?;
+                if summarized > 0 {
+                    self.warn(Warning::ContextCompacted { summarized });
+                }
+                Ok(summarized > 0)
+            }
+        }
+    }
+
+    /// Estimates the tokens of the agent's tool definitions.
+    fn tool_tokens(&self) -> usize {
+        self.tools
+            .as_ref()
+            .map_or(0, |tools| tools.estimate_tokens(&*self.token_counter))
+    }
+
+    /// Replaces all but the last `keep_recent` messages with a summary; see
+    /// [`Conversation::compact`].
+    pub(crate) async fn compact(
+        &self,
+        conversation: &mut Conversation,
+        keep_recent: usize,
+    ) -> Result<usize> {
+        self.compact_with(conversation, keep_recent, &mut RunContext::default())
+            .await
+    }
+
+    async fn compact_with(
+        &self,
+        conversation: &mut Conversation,
+        keep_recent: usize,
+        ctx: &mut RunContext,
+    ) -> Result<usize> {
+        let Some(range) = context::compaction_range(conversation.messages(), keep_recent) else {
+            return Ok(0);
+        };
+        let mut messages = Conversation::with_system(context::SUMMARY_PROMPT);
+        messages.add_user_message(context::transcript(&conversation.messages()[range.clone()]));
+        let mut request = CreateChatCompletionRequestArgs::default()
+            .model(&self.model)
+            .messages(messages.messages().to_vec())
+            .build()
+            .map_err(|e| {
+                Error::InvalidConfiguration(format!("Failed to build chat request: {}", e))
+            })?;
+        // The summary is not part of the answer, so it is never streamed.
+        let stream = std::mem::take(&mut ctx.stream);
+        let response = self.complete(&mut request, ctx).await;
+        ctx.stream = stream;
+        let summary = response?
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .filter(|summary| !summary.trim().is_empty())
+            .ok_or_else(|| Error::Other("The summary was empty".into()))?;
+        let summarized = range.len();
+        context::replace_with_summary(conversation, range, &summary);
+        Ok(summarized)
+    }
+
+    /// Reports a warning to the configured handler, if any.
+    fn warn(&self, warning: Warning) {
+        if let Some(ref handler) = self.warning_handler {
+            handler(&warning);
+        } else if self.verbose_warnings {
+            eprintln!("aiform warning: {}", warning);
+        }
+    }
+}
+
+/// A run of an agent driven one step at a time, as started by
+/// [`Agent::start`].
+///
+/// Each [`step`](Self::step) sends one completion request. When the model
+/// calls tools, the run waits for their results: run them with
+/// [`execute_tools`](Self::execute_tools), supply your own with
+/// [`provide_tool_result`](Self::provide_tool_result), or mix both. Calls
+/// still without a result are executed by the next step.
+pub struct AgentRun<'a> {
+    agent: &'a Agent,
+    conversation: &'a mut Conversation,
+    state: LoopState,
+    ctx: RunContext,
+    finished: bool,
+}
+
+impl AgentRun<'_> {
+    /// Executes pending tool calls, then asks the model for its next
+    /// response.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfiguration`] once the run has answered,
+    /// plus any error [`Agent::run`] can return.
+    pub async fn step(&mut self) -> Result<AgentStep> {
+        if self.finished {
+            return Err(Error::InvalidConfiguration(
+                "The agent run has already finished".to_string(),
+            ));
+        }
+        let progress = self
+            .agent
+            .advance(&mut self.state, self.conversation, &mut self.ctx)
+            .await;
+        match progress {
+            Ok(Progress::ToolCalls(calls)) => Ok(AgentStep::ToolCalls(calls)),
+            Ok(Progress::Done(outcome)) => {
+                self.finished = true;
+                match outcome {
+                    RunOutcome::Answer(answer) => {
+                        self.agent.autosave(self.conversation).await?;
+                        Ok(AgentStep::Final(answer))
+                    }
+                    other => Err(Error::Other(
+                        format!("Unexpected run outcome: {:?}", other).into(),
+                    )),
+                }
+            }
+            Err(error) => {
+                self.finished = true;
+                Err(error)
+            }
+        }
+    }
+
+    /// Executes the pending tool calls the way [`Agent::run`] would and adds
+    /// their results to the conversation.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error executing tools can return during [`Agent::run`].
+    pub async fn execute_tools(&mut self) -> Result<()> {
+        let Some(ref mut round) = self.state.round else {
+            return Ok(());
+        };
+        self.agent
+            .execute_round(round, self.conversation, &mut self.ctx, self.state.mode)
+            .await
+    }
+
+    /// Answers the pending tool call `id` with `content` instead of
+    /// executing the tool.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfiguration`] if no pending call has this
+    /// id.
+    pub fn provide_tool_result(&mut self, id: &str, content: impl Into<String>) -> Result<()> {
+        let round = self.state.round.as_mut();
+        let index = round.as_ref().and_then(|round| {
+            round
+                .calls
+                .iter()
+                .zip(&round.results)
+                .position(|(call, result)| call.id == id && result.is_none())
+        });
+        match (round, index) {
+            (Some(round), Some(index)) => {
+                round.answer(index, content.into(), self.conversation, &self.ctx);
+                Ok(())
+            }
+            _ => Err(Error::InvalidConfiguration(format!(
+                "No pending tool call with id '{}'",
+                id
+            ))),
+        }
+    }
+
+    /// The tool calls of the last step that have no result yet.
+    pub fn pending_tool_calls(&self) -> Vec<PendingToolCall> {
+        self.state
+            .round
+            .as_ref()
+            .map(ToolRound::pending)
+            .unwrap_or_default()
+    }
+
+    /// The conversation of the run so far.
+    pub fn conversation(&self) -> &Conversation {
+        self.conversation
+    }
+}
+
+/// Turns an agent's name into one the API accepts as a message or tool
+/// name, which may only contain ASCII letters, digits, `_` and `-`.
+pub(crate) fn participant_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(64)
+        .collect()
+}
+
+/// Builds a function tool definition.
+pub(crate) fn function_tool(
+    name: &str,
+    description: &str,
+    parameters: serde_json::Value,
+) -> ChatCompletionTool {
+    serde_json::from_value(json!({
+        "type": "function",
+        "function": { "name": name, "description": description, "parameters": parameters },
+    }))
+    .expect("valid tool definition")
+}
+
+/// Cuts a tool result to at most `limit` bytes, ending it with a note of how
+/// many bytes were cut. The cut falls on a character boundary.
+fn truncate_tool_output(output: &mut String, limit: usize) {
+    if output.len() <= limit {
+        return;
+    }
+    let mut end = limit;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = output.len() - end;
+    output.truncate(end);
+    output.push_str(&format!("\n[truncated {} bytes]", cut));
+}
+
+/// Adds a tool result to the conversation and reports it to the caller.
+fn add_tool_result(
+    conversation: &mut Conversation,
+    ctx: &RunContext,
+    tool_call: &ChatCompletionMessageToolCall,
+    result: String,
+    duration: Duration,
+) {
+    ctx.emit(AgentEvent::ToolCallFinished {
+        id: tool_call.id.clone(),
+        name: tool_call.function.name.clone(),
+        result: result.clone(),
+        duration,
+    });
+    conversation.add_tool_message(&tool_call.id, result);
+}
+
+/// Builds the strict `json_schema` response format for an output type,
+/// named by [`StructuredOutput::schema_name`] and described by the schema's
+/// own description.
+pub(crate) fn output_schema<T: StructuredOutput>() -> ResponseFormatJsonSchema {
+    let schema = crate::schema::strict(T::schema());
+    ResponseFormatJsonSchema {
+        name: T::schema_name(),
+        description: schema["description"].as_str().map(String::from),
+        schema: Some(schema),
+        strict: Some(true),
+    }
+}
+
+/// Checks that a [`ToolChoice::Named`] tool is in `tools`.
+fn check_tool_choice(tools: Option<&ToolSet>, choice: &ToolChoice) -> Result<()> {
+    let ToolChoice::Named(name) = choice else {
+        return Ok(());
+    };
+    let known = tools.is_some_and(|tools| tools.tools().iter().any(|t| &t.function.name == name));
+    if !known {
+        return Err(Error::InvalidConfiguration(format!(
+            "Tool choice '{}' is not in the agent's tools",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Name of the pseudo-tool that hands off to `target`.
+fn handoff_tool_name(target: &str) -> String {
+    format!("transfer_to_{}", target)
+}
+
+/// Description of the pseudo-tool that hands off to `target`.
+fn handoff_description(target: &str) -> String {
+    format!(
+        "Transfer the conversation to the '{}' agent when it is better suited to continue.",
+        target
+    )
+}
+
+/// A tool created by [`Agent::handoff_tools`]. Runs that report their
+/// outcome end when it is called instead of calling it.
+struct HandoffTool {
+    name: String,
+    description: String,
+    target: String,
+}
+
+impl ErasedTool for HandoffTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({ "type": "object", "properties": {} })
+    }
+
+    fn effects(&self) -> ToolEffects {
+        ToolEffects::ReadOnly
+    }
+
+    fn call(
+        &self,
+        _args: serde_json::Value,
+    ) -> BoxFuture<'_, std::result::Result<String, Box<dyn std::error::Error + Send + Sync>>> {
+        Box::pin(async move {
+            Err(format!(
+                "The conversation can only be transferred to '{}' in runs that report \
+                 their outcome, such as Agent::run_with_handoffs",
+                self.target
+            )
+            .into())
+        })
+    }
+}
+
+/// The tool result recorded for a pseudo-tool call, so the conversation
+/// stays valid when it is continued.
+fn pseudo_tool_result(outcome: &RunOutcome) -> String {
+    match outcome {
+        RunOutcome::NeedsUserInput { .. } => {
+            "The question was shown to the user; their reply follows.".to_string()
+        }
+        RunOutcome::Handoff { target } => format!("Transferred to {}.", target),
+        _ => String::new(),
+    }
+}
+
+/// Parses a tool call's JSON arguments, reporting where they became invalid.
+/// Empty arguments are read as `{}`.
+pub(crate) fn parse_tool_arguments(
+    tool_call: &ChatCompletionMessageToolCall,
+) -> Result<serde_json::Value> {
+    let arguments = &tool_call.function.arguments;
+    // Models may send no arguments at all to a tool without parameters.
+    if arguments.trim().is_empty() {
+        return Ok(serde_json::json!({}));
+    }
+    serde_json::from_str(arguments).map_err(|e| Error::MalformedToolArguments {
+        tool_name: tool_call.function.name.clone(),
+        offset: json_error_offset(arguments, &e),
+        message: e.to_string(),
+    })
+}
+
+/// Converts a serde_json line/column position into a byte offset.
+fn json_error_offset(text: &str, error: &serde_json::Error) -> usize {
+    if error.is_eof() {
+        return text.len();
+    }
+
+    let mut offset = 0;
+    for (index, line) in text.split('\n').enumerate() {
+        if index + 1 == error.line() {
+            return (offset + error.column().saturating_sub(1)).min(text.len());
+        }
+        offset += line.len() + 1;
+    }
+    text.len()
+}
+
+/// Appends a continuation fragment, dropping code fences and any text the
+/// model repeated from the end of the partial arguments.
+fn stitch_fragment(partial: &str, fragment: &str) -> String {
+    const MIN_OVERLAP: usize = 16;
+
+    let mut fragment = fragment;
+    if let Some(rest) = fragment.trim_start().strip_prefix("```") {
+        fragment = rest.trim_start_matches("json");
+        fragment = fragment.strip_prefix('\n').unwrap_or(fragment);
+        fragment = fragment.trim_end().strip_suffix("```").unwrap_or(fragment);
+    }
+
+    let max_overlap = partial.len().min(fragment.len());
+    for len in (MIN_OVERLAP..=max_overlap).rev() {
+        if partial.is_char_boundary(partial.len() - len)
+            && fragment.is_char_boundary(len)
+            && partial.ends_with(&fragment[..len])
+        {
+            return format!("{}{}", partial, &fragment[len..]);
+        }
+    }
+    format!("{}{}", partial, fragment)
+}
+
+/// Builder for creating agents.
+///
+/// # Example
+///
+/// ```no_run
+/// use aiform::prelude::*;
+/// # use async_openai::Client;
+///
+/// # async fn example() -> Result<()> {
+/// let agent = Agent::builder()
+///     .model("gpt-4")
+///     .system_prompt("You are a helpful coding assistant")
+///     .max_iterations(15)
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AgentBuilder {
+    client: Option<Arc<dyn ChatBackend>>,
+    model: Option<String>,
+    system_prompt: Option<String>,
+    tools: Option<Arc<ToolSet>>,
+    max_iterations: Option<usize>,
+    terminal_tools: HashMap<String, Template>,
+    argument_continuation: ArgumentContinuation,
+    warning_handler: Option<WarningHandler>,
+    post_processors: Vec<PostProcessor>,
+    plain_text_tools: PlainTextTools,
+    read_only: bool,
+    tool_error_policy: Option<ToolErrorPolicy>,
+    ask_user: bool,
+    ambiguity_gate: Option<AmbiguityGate>,
+    context_strategy: Option<ContextStrategy>,
+    token_counter: Arc<dyn TokenCounter>,
+    handoff_targets: Vec<String>,
+    max_handoffs: usize,
+    attachments: Option<Attachments>,
+    limiter: Option<Limiter>,
+    profile: Option<Profile>,
+    request_timeout: Option<Duration>,
+    tool_timeout: Option<Duration>,
+    tool_timeouts: HashMap<String, Duration>,
+    max_tool_output_bytes: Option<usize>,
+    tool_output_limits: HashMap<String, usize>,
+    retry: Option<RetryPolicy>,
+    sampling: Sampling,
+    json_mode: bool,
+    structured_output_retry: bool,
+    tool_choice: Option<ToolChoice>,
+    tool_concurrency: usize,
+    provider: Provider,
+    strip_rejected_parameters: bool,
+    append_final_response: bool,
+    inherit_system_prompt: bool,
+    hooks: Vec<Arc<dyn AgentHook>>,
+    approvals: HashMap<String, Approver>,
+    autosave: Option<(Arc<dyn ConversationStore>, String)>,
+    extra_body: serde_json::Map<String, serde_json::Value>,
+    allow_extra_body_overrides: bool,
+    name: Option<String>,
+    #[cfg(feature = "jsonschema")]
+    validate_tool_arguments: bool,
+    /// Whether `tools` already validate their arguments, as those of an
+    /// agent built with `validate_tool_arguments` do.
+    #[cfg(feature = "jsonschema")]
+    tools_validated: bool,
+    openrouter: Option<OpenRouterOptions>,
+    record_to: Option<PathBuf>,
+    replay_from: Option<PathBuf>,
+    replay_rules: MatchRules,
+}
+
+impl AgentBuilder {
+    /// Creates a new agent builder with default settings.
+    pub fn new() -> Self {
+        Self {
+            client: None,
+            model: None,
+            system_prompt: None,
+            tools: None,
+            max_iterations: None,
+            terminal_tools: HashMap::new(),
+            argument_continuation: ArgumentContinuation::default(),
+            warning_handler: None,
+            post_processors: Vec::new(),
+            plain_text_tools: PlainTextTools::default(),
+            read_only: false,
+            tool_error_policy: None,
+            ask_user: false,
+            ambiguity_gate: None,
+            context_strategy: None,
+            token_counter: Arc::new(ApproxTokenCounter),
+            handoff_targets: Vec::new(),
+            max_handoffs: DEFAULT_MAX_HANDOFFS,
+            attachments: None,
+            limiter: None,
+            profile: None,
+            request_timeout: None,
+            tool_timeout: None,
+            tool_timeouts: HashMap::new(),
+            max_tool_output_bytes: None,
+            tool_output_limits: HashMap::new(),
+            retry: None,
+            sampling: Sampling::default(),
+            json_mode: false,
+            structured_output_retry: true,
+            tool_choice: None,
+            tool_concurrency: 1,
+            provider: Provider::default(),
+            strip_rejected_parameters: false,
+            append_final_response: true,
+            inherit_system_prompt: true,
+            hooks: Vec::new(),
+            approvals: HashMap::new(),
+            autosave: None,
+            extra_body: serde_json::Map::new(),
+            allow_extra_body_overrides: false,
+            name: None,
+            #[cfg(feature = "jsonschema")]
+            validate_tool_arguments: false,
+            #[cfg(feature = "jsonschema")]
+            tools_validated: false,
+            openrouter: None,
+            record_to: None,
+            replay_from: None,
+            replay_rules: MatchRules::default(),
+        }
+    }
+
+    /// Sets the OpenAI client to use.
+    ///
+    /// Any `async_openai` configuration works, e.g. Azure OpenAI:
+    ///
+    /// ```no_run
+    /// use aiform::prelude::*;
+    /// use async_openai::{config::AzureConfig, Client};
+    ///
+    /// # fn example() -> Result<()> {
+    /// let config = AzureConfig::new()
+    ///     .with_api_base("https://my-resource.openai.azure.com")
+    ///     .with_deployment_id("gpt-4o")
+    ///     .with_api_version("2024-08-01-preview");
+    /// let agent = Agent::builder()
+    ///     .client(Client::with_config(config))
+    ///     .model("gpt-4o")
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// If not set, the shared [`default_client`](crate::default_client) is
+    /// used.
+    pub fn client<C>(mut self, client: Client<C>) -> Self
+    where
+        C: Config + Send + Sync + 'static,
+    {
+        self.client = Some(Arc::new(client));
+        self
+    }
+
+    /// Sends completions through `backend` instead of an OpenAI client,
+    /// e.g. a `MockChatBackend` from the `testing` module in tests.
+    pub fn backend(mut self, backend: Arc<dyn ChatBackend>) -> Self {
+        self.client = Some(backend);
+        self
+    }
+
+    /// Sets the model to use (e.g., "gpt-4", "gpt-3.5-turbo").
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    /// Names the agent, for telling agents apart in traces, hooks and
+    /// errors.
+    ///
+    /// The name is also set on the assistant messages the agent adds to a
+    /// conversation, with characters the API does not accept in names
+    /// replaced by `_`, and names [`AgentTool::from_agent`](crate::agent_tool::AgentTool::from_agent)
+    /// tools.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the system prompt for the agent.
+    pub fn system_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.system_prompt = Some(prompt.into());
+        self
+    }
+
+    /// Sets the tools available to the agent, replacing any set before.
+    pub fn tools(mut self, tools: ToolSet) -> Self {
+        self.tools = Some(Arc::new(tools));
+        #[cfg(feature = "jsonschema")]
+        {
+            self.tools_validated = false;
+        }
+        self
+    }
+
+    /// Sets a tool set shared with other agents, replacing any set before.
+    pub fn shared_tools(mut self, tools: Arc<ToolSet>) -> Self {
+        self.tools = Some(tools);
+        #[cfg(feature = "jsonschema")]
+        {
+            self.tools_validated = false;
+        }
+        self
+    }
+
+    /// Adds tools on top of the ones already set.
+    ///
+    /// The existing tool set is shared, not rebuilt. If a name appears in
+    /// both, calls go to the tool from `tools`. When the existing tools
+    /// already validate their arguments, as those of an agent derived from
+    /// one with [`validate_tool_arguments`](Self::validate_tool_arguments)
+    /// do, the added tools are validated too.
+    pub fn extend_tools(mut self, tools: ToolSet) -> Self {
+        #[cfg(feature = "jsonschema")]
+        let tools = if self.tools_validated {
+            tools.with_middleware(crate::middleware::ValidateArguments::new())
+        } else {
+            tools
+        };
+        self.tools = Some(Arc::new(match self.tools.take() {
+            Some(base) => ToolSet::layered(base, tools),
+            None => tools,
+        }));
+        self
+    }
+
+    /// Sets the maximum number of iterations for the agent loop.
+    ///
+    /// Default is 10 iterations.
+    pub fn max_iterations(mut self, max: usize) -> Self {
+        self.max_iterations = Some(max);
+        self
+    }
+
+    /// Sets the sampling temperature, between 0.0 and 2.0.
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.sampling.temperature = Some(temperature);
+        self
+    }
+
+    /// Sets nucleus sampling's probability mass, between 0.0 and 1.0.
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.sampling.top_p = Some(top_p);
+        self
+    }
+
+    /// Sets the maximum number of tokens generated per completion.
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.sampling.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Sets the frequency penalty, between -2.0 and 2.0.
+    pub fn frequency_penalty(mut self, penalty: f32) -> Self {
+        self.sampling.frequency_penalty = Some(penalty);
+        self
+    }
+
+    /// Sets the presence penalty, between -2.0 and 2.0.
+    pub fn presence_penalty(mut self, penalty: f32) -> Self {
+        self.sampling.presence_penalty = Some(penalty);
+        self
+    }
+
+    /// Marks a tool as terminal and sets the template used to render its output.
+    ///
+    /// Only affects [`Agent::run_rendered`]; see the [`render`](crate::render)
+    /// module for the template syntax.
+    pub fn terminal_tool(mut self, tool_name: impl Into<String>, template: Template) -> Self {
+        self.terminal_tools.insert(tool_name.into(), template);
+        self
+    }
+
+    /// Sets how truncated tool-call arguments are recovered.
+    ///
+    /// Defaults to two continuations without assistant prefill.
+    pub fn argument_continuation(mut self, policy: ArgumentContinuation) -> Self {
+        self.argument_continuation = policy;
+        self
+    }
+
+    /// Sets a callback for non-fatal [`Warning`]s raised during runs.
+    ///
+    /// Warnings are dropped if no handler is set.
+    pub fn on_warning(mut self, handler: impl Fn(&Warning) + Send + Sync + 'static) -> Self {
+        self.warning_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Adds a hook that observes requests, responses and tool calls; see
+    /// [`AgentHook`].
+    ///
+    /// Hooks are called in the order they are added.
+    pub fn hook(mut self, hook: impl AgentHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Asks `approver` before every call of the named tools; see the
+    /// [`approval`](crate::approval) module.
+    ///
+    /// Calling this again for a tool replaces its approver.
+    pub fn require_approval(
+        mut self,
+        names: &[&str],
+        approver: impl Fn(&str, &serde_json::Value) -> BoxFuture<'static, ApprovalDecision>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        let approver: Approver = Arc::new(approver);
+        for name in names {
+            self.approvals.insert(name.to_string(), approver.clone());
+        }
+        self
+    }
+
+    /// Saves the conversation to `store` under `id` after every turn the
+    /// agent completes, whichever method ran it.
+    ///
+    /// A turn that fails is not saved. Use
+    /// [`Sessions`](crate::store::Sessions) instead to run one agent over
+    /// many stored conversations.
+    pub fn autosave(mut self, store: Arc<dyn ConversationStore>, id: impl Into<String>) -> Self {
+        self.autosave = Some((store, id.into()));
+        self
+    }
+
+    /// Adds a transformation for final answers.
+    ///
+    /// Processors run in the order they are added, after the model (or a
+    /// terminal tool template) has produced the answer. Intermediate messages
+    /// in the conversation are not affected.
+    pub fn post_process(
+        mut self,
+        processor: impl Fn(String) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.post_processors.push(Arc::new(processor));
+        self
+    }
+
+    /// Guarantees plain-text final answers.
+    ///
+    /// Adds [`plain_text::to_plain_text`] to the post-processor chain, removing
+    /// emoji, markdown syntax and terminal escape codes. Processors added
+    /// later still run after it.
+    pub fn plain_text(self) -> Self {
+        self.post_process(|answer| plain_text::to_plain_text(&answer))
+    }
+
+    /// Converts every tool result to plain text before the model sees it.
+    ///
+    /// JSON results keep their structure; only string values are converted.
+    pub fn plain_text_tool_results(mut self) -> Self {
+        self.plain_text_tools = PlainTextTools::All;
+        self
+    }
+
+    /// Converts results of the named tool to plain text before the model sees
+    /// them. Can be called once per tool.
+    pub fn plain_text_tool(mut self, tool_name: impl Into<String>) -> Self {
+        match &mut self.plain_text_tools {
+            PlainTextTools::All => {}
+            PlainTextTools::Only(names) => {
+                names.insert(tool_name.into());
+            }
+            PlainTextTools::None => {
+                self.plain_text_tools = PlainTextTools::Only(HashSet::from([tool_name.into()]));
+            }
+        }
+        self
+    }
+
+    /// Sets how tool failures are handled.
+    ///
+    /// Defaults to aborting the run on the first failure; see
+    /// [`ToolErrorPolicy`] for retries and code-specific actions. Calls to
+    /// tools the agent does not have fail with the code
+    /// [`ToolError::UNKNOWN_TOOL`]: aborting ends the run with
+    /// [`Error::ToolNotFound`], returning them to the model lists the
+    /// available tools.
+    pub fn tool_error_policy(mut self, policy: ToolErrorPolicy) -> Self {
+        self.tool_error_policy = Some(policy);
+        self
+    }
+
+    /// Checks the arguments of every tool call against the tool's schema
+    /// before the tool runs; see
+    /// [`ValidateArguments`](crate::middleware::ValidateArguments).
+    ///
+    /// Calls that do not match fail with
+    /// [`Error::InvalidToolArguments`], or are returned to the model as an
+    /// [`INVALID_INPUT`](ToolError::INVALID_INPUT) tool error if the
+    /// [`tool_error_policy`](Self::tool_error_policy) says so. Off by
+    /// default.
+    #[cfg(feature = "jsonschema")]
+    pub fn validate_tool_arguments(mut self, validate: bool) -> Self {
+        self.validate_tool_arguments = validate;
+        self
+    }
+
+    /// Applies a bundle of reliability settings.
+    ///
+    /// Settings made explicitly with [`max_iterations`](Self::max_iterations),
+    /// [`request_timeout`](Self::request_timeout), [`retry`](Self::retry),
+    /// [`tool_error_policy`](Self::tool_error_policy) or
+    /// [`on_warning`](Self::on_warning) take precedence over the profile.
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Sets a time limit for each completion request.
+    ///
+    /// A request that takes longer fails the run with [`Error::Timeout`].
+    /// Messages added to the conversation before the request are kept. No
+    /// limit is applied by default.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets a time limit for each tool call.
+    ///
+    /// A tool that takes longer is cancelled and fails with a retryable
+    /// [`ToolError::TIMEOUT`] error, which is handled by the
+    /// [`tool_error_policy`](Self::tool_error_policy) like any other tool
+    /// failure. No limit is applied by default.
+    pub fn tool_timeout(mut self, timeout: Duration) -> Self {
+        self.tool_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets a time limit for calls to one tool, replacing
+    /// [`tool_timeout`](Self::tool_timeout) for it.
+    pub fn tool_timeout_for(mut self, tool_name: impl Into<String>, timeout: Duration) -> Self {
+        self.tool_timeouts.insert(tool_name.into(), timeout);
+        self
+    }
+
+    /// Cuts tool results longer than `limit` bytes before they are added to
+    /// the conversation, so a tool cannot flood the context window.
+    ///
+    /// The result ends with `[truncated N bytes]` saying how much was cut.
+    /// No limit is applied by default.
+    pub fn max_tool_output_bytes(mut self, limit: usize) -> Self {
+        self.max_tool_output_bytes = Some(limit);
+        self
+    }
+
+    /// Sets the result size limit for one tool, replacing
+    /// [`max_tool_output_bytes`](Self::max_tool_output_bytes) for it.
+    pub fn max_tool_output_bytes_for(mut self, tool_name: impl Into<String>, limit: usize) -> Self {
+        self.tool_output_limits.insert(tool_name.into(), limit);
+        self
+    }
+
+    /// Retries completion requests that fail with a retryable error, such
+    /// as a rate limit or a server error, with exponential backoff.
+    ///
+    /// Each attempt gets the full [`request_timeout`](Self::request_timeout).
+    /// See [`Error::is_retryable`] for what is retried. Requests are not
+    /// retried by default.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Offers the model an `ask_user(question)` pseudo-tool.
+    ///
+    /// Only used by [`Agent::run_outcome`] and
+    /// [`Agent::run_conversation_outcome`]: when the model calls it, the run
+    /// ends with [`RunOutcome::NeedsUserInput`] instead of dispatching a tool.
+    pub fn ask_user(mut self, enabled: bool) -> Self {
+        self.ask_user = enabled;
+        self
+    }
+
+    /// Checks that outcome runs act on specific requests; see the
+    /// [`gate`](crate::gate) module.
+    ///
+    /// Only used by [`Agent::run_outcome`] and its variants: when the gate
+    /// finds the request too vague, the run ends with
+    /// [`RunOutcome::NeedsUserInput`] before any tool is called.
+    pub fn ambiguity_gate(mut self, gate: AmbiguityGate) -> Self {
+        self.ambiguity_gate = Some(gate);
+        self
+    }
+
+    /// Keeps the conversation within the context window; see the
+    /// [`context`](crate::context) module.
+    ///
+    /// The strategy is applied to the conversation before every request,
+    /// so dropped messages are also gone from the caller's conversation.
+    pub fn context_strategy(mut self, strategy: ContextStrategy) -> Self {
+        self.context_strategy = Some(strategy);
+        self
+    }
+
+    /// Sets how tokens are counted for the context strategy and
+    /// [`Agent::estimate_request_tokens`]. Defaults to
+    /// [`ApproxTokenCounter`].
+    pub fn token_counter(mut self, counter: impl TokenCounter + 'static) -> Self {
+        self.token_counter = Arc::new(counter);
+        self
+    }
+
+    /// Offers the model a `transfer_to_<target>` pseudo-tool per target.
+    ///
+    /// Only used by [`Agent::run_outcome`] and
+    /// [`Agent::run_conversation_outcome`]: when the model calls one, the run
+    /// ends with [`RunOutcome::Handoff`].
+    pub fn handoff_targets<I, S>(mut self, targets: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.handoff_targets = targets.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets how many times [`Agent::run_with_handoffs`] may transfer the
+    /// conversation before failing, which stops agents from handing it back
+    /// and forth forever.
+    ///
+    /// Default is 5 transfers.
+    pub fn max_handoffs(mut self, max: usize) -> Self {
+        self.max_handoffs = max;
+        self
+    }
+
+    /// Enables attachment handling.
+    ///
+    /// Attachment ids in tool results are replaced with previews, and the
+    /// model is offered a `read_attachment` tool to read ranges on demand.
+    /// See the [`attachment`](crate::attachment) module.
+    pub fn attachments(mut self, attachments: Attachments) -> Self {
+        self.attachments = Some(attachments);
+        self
+    }
+
+    /// Shares a [`Limiter`] that every completion request must get a permit
+    /// from.
+    ///
+    /// Attach the same limiter to several agents to share one quota between
+    /// them; use [`Agent::run_with_options`] to set a run's priority.
+    pub fn limiter(mut self, limiter: Limiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Asks the model to answer with a JSON object, by sending
+    /// `response_format: {"type": "json_object"}`.
+    ///
+    /// Most providers also require the prompt to mention JSON.
+    pub fn json_mode(mut self, enabled: bool) -> Self {
+        self.json_mode = enabled;
+        self
+    }
+
+    /// Sets whether a [structured run](Agent::run_structured) whose answer
+    /// does not deserialize asks the model once more, showing it the parse
+    /// error. Enabled by default.
+    pub fn structured_output_retry(mut self, enabled: bool) -> Self {
+        self.structured_output_retry = enabled;
+        self
+    }
+
+    /// Sets whether and which tools the model must call.
+    ///
+    /// A [`ToolChoice::Named`] tool is only forced on the first iteration;
+    /// later iterations use [`ToolChoice::Auto`] so the model can answer.
+    /// Override it per run with [`RunOptions::tool_choice`].
+    pub fn tool_choice(mut self, choice: ToolChoice) -> Self {
+        self.tool_choice = Some(choice);
+        self
+    }
+
+    /// Runs the tool calls of one model response concurrently instead of one
+    /// after another.
+    ///
+    /// Results are still added to the conversation in the order the model
+    /// made the calls. Off by default, since tools sharing mutable state
+    /// may not expect concurrent calls.
+    pub fn parallel_tools(mut self, enabled: bool) -> Self {
+        self.tool_concurrency = if enabled { usize::MAX } else { 1 };
+        self
+    }
+
+    /// Runs up to `max` tool calls of one model response concurrently.
+    ///
+    /// `1` runs them one after another, as without
+    /// [`parallel_tools`](Self::parallel_tools).
+    pub fn max_parallel_tools(mut self, max: usize) -> Self {
+        self.tool_concurrency = max.max(1);
+        self
+    }
+
+    /// Declares the kind of server the agent talks to.
+    ///
+    /// Configured request parameters the provider does not support are
+    /// dropped with a [`Warning::ParameterDropped`] instead of sent. Defaults
+    /// to [`Provider::OpenAI`], which supports every parameter. See the
+    /// [`provider`](crate::provider) module.
+    pub fn provider(mut self, provider: Provider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    /// Adds top-level fields to the body of every chat request, for
+    /// vendor-specific parameters such as `top_k`, `min_p` or
+    /// `repetition_penalty` that `async_openai` does not model.
+    ///
+    /// Fields added later replace earlier ones of the same name. Per-run
+    /// fields can be added with [`RunOptions::extra_body`]. A request whose
+    /// typed parameters already set one of the fields fails with
+    /// [`Error::InvalidConfiguration`], unless
+    /// [`allow_extra_body_overrides`](Self::allow_extra_body_overrides) is
+    /// turned on.
+    pub fn extra_body(mut self, fields: serde_json::Map<String, serde_json::Value>) -> Self {
+        self.extra_body.extend(fields);
+        self
+    }
+
+    /// Lets [extra body fields](Self::extra_body) replace request
+    /// parameters of the same name, e.g. to send a `temperature` outside the
+    /// range [`temperature`](Self::temperature) accepts. Off by default.
+    pub fn allow_extra_body_overrides(mut self, allow: bool) -> Self {
+        self.allow_extra_body_overrides = allow;
+        self
+    }
+
+    /// Records every completion request and response of the agent's runs
+    /// to a fixture file at `path`; see the [`fixture`](crate::fixture)
+    /// module.
+    ///
+    /// The file is rewritten after every response.
+    pub fn record_to(mut self, path: impl Into<PathBuf>) -> Self {
+        self.record_to = Some(path.into());
+        self
+    }
+
+    /// Answers completion requests from the fixture file at `path` instead
+    /// of the client; see the [`fixture`](crate::fixture) module.
+    ///
+    /// [`build`](Self::build) fails if the file cannot be read or was
+    /// written with another format version.
+    pub fn replay_from(mut self, path: impl Into<PathBuf>) -> Self {
+        self.replay_from = Some(path.into());
+        self
+    }
+
+    /// Sets which parts of each request must match the recorded one during
+    /// a [replay](Self::replay_from).
+    pub fn replay_rules(mut self, rules: MatchRules) -> Self {
+        self.replay_rules = rules;
+        self
+    }
+
+    /// Talks to OpenRouter with the given attribution headers and routing;
+    /// see the [`openrouter`](crate::openrouter) module.
+    ///
+    /// Replaces any client set with [`client`](Self::client), and sets the
+    /// provider to [`Provider::OpenRouter`].
+    pub fn openrouter(mut self, options: OpenRouterOptions) -> Self {
+        self.extra_body.extend(options.body_fields());
+        self.provider = Provider::OpenRouter;
+        self.openrouter = Some(options);
+        self
+    }
+
+    /// When a request is rejected with an error naming one of its optional
+    /// parameters, sends it once more without that parameter.
+    ///
+    /// The retry is reported with a [`Warning::ParameterRejected`], and the
+    /// parameter is dropped from later requests for the same model for the
+    /// rest of the process. Off by default.
+    pub fn strip_rejected_parameters(mut self, enabled: bool) -> Self {
+        self.strip_rejected_parameters = enabled;
+        self
+    }
+
+    /// Sets whether the model's final answer is added to the conversation
+    /// as an assistant message before a run returns. On by default.
+    pub fn append_final_response(mut self, enabled: bool) -> Self {
+        self.append_final_response = enabled;
+        self
+    }
+
+    /// Sets whether runs over an existing conversation make the agent's
+    /// system prompt its first message, replacing a different one. On by
+    /// default; turn it off to manage the system message yourself.
+    pub fn inherit_system_prompt(mut self, enabled: bool) -> Self {
+        self.inherit_system_prompt = enabled;
+        self
+    }
+
+    /// Requires every tool to be classified as read-only.
+    ///
+    /// With this set, [`build`](Self::build) fails if any tool is
+    /// [`ToolEffects::Mutating`](crate::ToolEffects::Mutating), including
+    /// tools that were never classified.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Builds the agent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if required fields (model) are not set, if a
+    /// sampling parameter is out of range, if two tools share a name, if a
+    /// terminal tool is not part of the configured tools, if the
+    /// [tool choice](Self::tool_choice) names a tool that is not, or if a
+    /// read-only agent has mutating tools.
+    pub fn build(self) -> Result<Agent> {
+        let model = self
+            .model
+            .ok_or_else(|| Error::InvalidConfiguration("Model must be specified".to_string()))?;
+        self.sampling.validate()?;
+        if let Some(ref tools) = self.tools {
+            tools.validate()?;
+        }
+
+        if self.read_only {
+            if let Some(ref tools) = self.tools {
+                let mutating = tools.mutating_tools();
+                if !mutating.is_empty() {
+                    return Err(Error::InvalidConfiguration(format!(
+                        "Read-only agent cannot use mutating tools: {}",
+                        mutating.join(", ")
+                    )));
+                }
+            }
+        }
+
+        for name in self.terminal_tools.keys() {
+            let known = self
+                .tools
+                .as_ref()
+                .is_some_and(|tools| tools.tools().iter().any(|t| &t.function.name == name));
+            if !known {
+                return Err(Error::InvalidConfiguration(format!(
+                    "Terminal tool '{}' is not in the agent's tools",
+                    name
+                )));
+            }
+        }
+
+        for name in self.approvals.keys() {
+            let known = self
+                .tools
+                .as_ref()
+                .is_some_and(|tools| tools.tools().iter().any(|t| &t.function.name == name));
+            if !known {
+                return Err(Error::InvalidConfiguration(format!(
+                    "Tool '{}' requires approval but is not in the agent's tools",
+                    name
+                )));
+            }
+        }
+
+        let limited = self
+            .tool_timeouts
+            .keys()
+            .chain(self.tool_output_limits.keys());
+        for name in limited {
+            let known = self
+                .tools
+                .as_ref()
+                .is_some_and(|tools| tools.tools().iter().any(|t| &t.function.name == name));
+            if !known {
+                return Err(Error::InvalidConfiguration(format!(
+                    "Tool '{}' has limits but is not in the agent's tools",
+                    name
+                )));
+            }
+        }
+
+        if let Some(ref choice) = self.tool_choice {
+            check_tool_choice(self.tools.as_deref(), choice)?;
+        }
+
+        let pseudo_tools = self
+            .ask_user
+            .then(|| ASK_USER_TOOL.to_string())
+            .into_iter()
+            .chain(self.handoff_targets.iter().map(|t| handoff_tool_name(t)))
+            .chain(
+                self.attachments
+                    .is_some()
+                    .then(|| READ_ATTACHMENT_TOOL.to_string()),
+            );
+        for name in pseudo_tools {
+            let taken = self
+                .tools
+                .as_ref()
+                .is_some_and(|tools| tools.tools().iter().any(|t| t.function.name == name));
+            if taken {
+                return Err(Error::InvalidConfiguration(format!(
+                    "Tool '{}' conflicts with a built-in pseudo-tool",
+                    name
+                )));
+            }
+        }
+
+        let client = match self.openrouter {
+            Some(ref options) => Arc::new(Client::with_config(options.config()?)),
+            None => self
+                .client
+                .unwrap_or_else(|| crate::client::default_client() as Arc<dyn ChatBackend>),
+        };
+        let client: Arc<dyn ChatBackend> = match (self.record_to, self.replay_from) {
+            (Some(_), Some(_)) => {
+                return Err(Error::InvalidConfiguration(
+                    "An agent cannot record and replay a fixture at once".into(),
+                ))
+            }
+            (Some(path), None) => Arc::new(fixture::Recorder::new(client, path)),
+            (None, Some(path)) => Arc::new(fixture::Replayer::new(
+                Fixture::load(path)?,
+                self.replay_rules,
+            )),
+            (None, None) => client,
+        };
+
+        let profile = self.profile.as_ref();
+        let max_iterations = self
+            .max_iterations
+            .or(profile.map(|p| p.max_iterations))
+            .unwrap_or(DEFAULT_MAX_ITERATIONS);
+        let request_timeout = self
+            .request_timeout
+            .or(profile.and_then(|p| p.request_timeout));
+        let retry = self.retry.or(profile.and_then(|p| p.retry));
+        let tool_error_policy = self.tool_error_policy.unwrap_or_else(|| {
+            ToolErrorPolicy::default().max_retries(profile.map_or(0, |p| p.tool_retries))
+        });
+        let verbose_warnings =
+            self.warning_handler.is_none() && profile.is_some_and(|p| p.verbose_warnings);
+
+        #[cfg(feature = "jsonschema")]
+        let tools = match self.tools {
+            Some(tools) if self.validate_tool_arguments && !self.tools_validated => Some(Arc::new(
+                (*tools)
+                    .clone()
+                    .with_middleware(crate::middleware::ValidateArguments::new()),
+            )),
+            tools => tools,
+        };
+        #[cfg(not(feature = "jsonschema"))]
+        let tools = self.tools;
+
+        let metrics = AgentMetrics::new(
+            tools
+                .iter()
+                .flat_map(|tools| tools.tools())
+                .map(|tool| tool.function.name.clone()),
+        );
+
+        Ok(Agent {
+            client,
+            model,
+            system_prompt: self.system_prompt,
+            tools,
+            max_iterations,
+            terminal_tools: self.terminal_tools,
+            argument_continuation: self.argument_continuation,
+            warning_handler: self.warning_handler,
+            post_processors: self.post_processors,
+            plain_text_tools: self.plain_text_tools,
+            tool_error_policy,
+            read_only: self.read_only,
+            ask_user: self.ask_user,
+            ambiguity_gate: self.ambiguity_gate,
+            context_strategy: self.context_strategy,
+            token_counter: self.token_counter,
+            handoff_targets: self.handoff_targets,
+            max_handoffs: self.max_handoffs,
+            attachments: self.attachments,
+            limiter: self.limiter,
+            profile: self.profile,
+            request_timeout,
+            tool_timeout: self.tool_timeout,
+            tool_timeouts: self.tool_timeouts,
+            max_tool_output_bytes: self.max_tool_output_bytes,
+            tool_output_limits: self.tool_output_limits,
+            retry,
+            verbose_warnings,
+            sampling: self.sampling,
+            json_mode: self.json_mode,
+            structured_output_retry: self.structured_output_retry,
+            tool_choice: self.tool_choice.clone(),
+            tool_concurrency: self.tool_concurrency,
+            provider: self.provider,
+            strip_rejected_parameters: self.strip_rejected_parameters,
+            append_final_response: self.append_final_response,
+            inherit_system_prompt: self.inherit_system_prompt,
+            hooks: self.hooks,
+            approvals: self.approvals,
+            autosave: self.autosave,
+            extra_body: self.extra_body,
+            allow_extra_body_overrides: self.allow_extra_body_overrides,
+            name: self.name,
+            #[cfg(feature = "jsonschema")]
+            validate_tool_arguments: self.validate_tool_arguments,
+            metrics: Arc::new(metrics),
+        })
+    }
+}
+
+impl Default for AgentBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::render::ToolOutput;
+    use crate::testing::MockChatBackend;
+    use crate::tokens::count_messages;
+    use serde_json::{json, Value};
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct SearchArgs {
+        to: String,
+    }
+
+    #[tool("Search flights")]
+    async fn search_flights(args: SearchArgs) -> Result<ToolOutput> {
+        ToolOutput::json(json!({
+            "count": 2,
+            "to": args.to,
+            "cheapest": { "price": 129.5 }
+        }))
+    }
+
+    fn agent(backend: Arc<MockChatBackend>, builder: AgentBuilder) -> Agent {
+        builder
+            .model("mock-model")
+            .backend(backend)
+            .tools(tools![SearchFlightsTool])
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_run_rendered_returns_template_output() {
+        let backend = Arc::new(
+            MockChatBackend::new().respond_with_tool_call("search_flights", json!({ "to": "LIS" })),
+        );
+        let template =
+            Template::parse("Found {count} flights to {to}, cheapest is {cheapest.price}").unwrap();
+        let agent = agent(
+            backend.clone(),
+            Agent::builder().terminal_tool("search_flights", template),
+        );
+
+        let answer = agent.run_rendered("Find flights to Lisbon").await.unwrap();
+        assert_eq!(answer, "Found 2 flights to LIS, cheapest is 129.5");
+        // The model is never asked to phrase the result.
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_ignores_terminal_tools() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("There are two flights."),
+        );
+        let template = Template::parse("{count}").unwrap();
+        let agent = agent(
+            backend.clone(),
+            Agent::builder().terminal_tool("search_flights", template),
+        );
+
+        let answer = agent.run("Find flights to Lisbon").await.unwrap();
+        assert_eq!(answer, "There are two flights.");
+        assert_eq!(backend.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_run_rendered_missing_field() {
+        let backend = Arc::new(
+            MockChatBackend::new().respond_with_tool_call("search_flights", json!({ "to": "LIS" })),
+        );
+        let template = Template::parse("{seats}").unwrap();
+        let agent = agent(
+            backend,
+            Agent::builder().terminal_tool("search_flights", template),
+        );
+
+        let err = agent.run_rendered("Find flights").await.unwrap_err();
+        assert!(matches!(err, Error::Render(ref msg) if msg.contains("search_flights")));
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct WriteArgs {
+        path: String,
+        content: String,
+    }
+
+    #[tool("Write a file")]
+    async fn write_file(args: WriteArgs) -> Result<String> {
+        Ok(format!(
+            "wrote {} bytes to {}",
+            args.content.len(),
+            args.path
+        ))
+    }
+
+    fn writer(backend: Arc<MockChatBackend>, builder: AgentBuilder) -> Agent {
+        builder
+            .model("mock-model")
+            .backend(backend)
+            .tools(tools![WriteFileTool])
+            .build()
+            .unwrap()
+    }
+
+    fn collect_warnings(
+        builder: AgentBuilder,
+    ) -> (AgentBuilder, Arc<std::sync::Mutex<Vec<Warning>>>) {
+        let warnings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = warnings.clone();
+        let builder = builder.on_warning(move |w| sink.lock().unwrap().push(w.clone()));
+        (builder, warnings)
+    }
+
+    const FULL_ARGS: &str =
+        r#"{"path":"notes.txt","content":"the quick brown fox jumps over the lazy dog"}"#;
+
+    #[tokio::test]
+    async fn test_truncated_arguments_are_continued() {
+        let (head, tail) = FULL_ARGS.split_at(40);
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_raw_tool_call("write_file", head, "length")
+                .respond_with_text(tail)
+                .respond_with_text("Saved."),
+        );
+        let (builder, warnings) = collect_warnings(Agent::builder());
+        let agent = writer(backend.clone(), builder);
+
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Save a note");
+        let answer = agent.run_conversation(&mut conversation).await.unwrap();
+        assert_eq!(answer, "Saved.");
+
+        // The stitched arguments were dispatched and recorded in the conversation.
+        let tool_result = serde_json::to_value(&conversation.messages()[2]).unwrap();
+        assert_eq!(tool_result["content"], "wrote 43 bytes to notes.txt");
+        let assistant = serde_json::to_value(&conversation.messages()[1]).unwrap();
+        assert_eq!(
+            assistant["tool_calls"][0]["function"]["arguments"],
+            FULL_ARGS
+        );
+
+        // The continuation request shows the partial arguments and asks to continue.
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 3);
+        let continuation = serde_json::to_value(&requests[1].messages).unwrap();
+        let messages = continuation.as_array().unwrap();
+        assert_eq!(messages[messages.len() - 2]["content"], head);
+        assert_eq!(messages[messages.len() - 1]["role"], "user");
+        assert!(requests[1].tools.is_none());
+
+        let warnings = warnings.lock().unwrap();
+        assert_eq!(
+            *warnings,
+            vec![Warning::TruncatedToolArguments {
+                tool_name: "write_file".into(),
+                continuation: 1,
+                received_bytes: 40,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_continuation_with_prefill_and_repeated_overlap() {
+        let (head, tail) = FULL_ARGS.split_at(40);
+        // The model repeats the end of what it already wrote.
+        let repeated = format!("{}{}", &head[20..], tail);
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_raw_tool_call("write_file", head, "length")
+                .respond_with_text(&repeated)
+                .respond_with_text("Saved."),
+        );
+        let agent = writer(
+            backend.clone(),
+            Agent::builder().argument_continuation(ArgumentContinuation {
+                max_continuations: 1,
+                assistant_prefill: true,
+            }),
+        );
+
+        assert_eq!(agent.run("Save a note").await.unwrap(), "Saved.");
+
+        let continuation = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        let last = continuation.as_array().unwrap().last().unwrap().clone();
+        assert_eq!(last["role"], "assistant");
+        assert_eq!(last["content"], head);
+    }
+
+    #[tokio::test]
+    async fn test_continuations_are_bounded() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_raw_tool_call("write_file", &FULL_ARGS[..20], "length")
+                .respond_with_text(&FULL_ARGS[20..30]),
+        );
+        let (builder, warnings) = collect_warnings(Agent::builder().argument_continuation(
+            ArgumentContinuation {
+                max_continuations: 1,
+                ..Default::default()
+            },
+        ));
+        let agent = writer(backend.clone(), builder);
+
+        let err = agent.run("Save a note").await.unwrap_err();
+        match err {
+            Error::MalformedToolArguments {
+                tool_name, offset, ..
+            } => {
+                assert_eq!(tool_name, "write_file");
+                assert_eq!(offset, 30);
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+        assert_eq!(backend.requests().len(), 2);
+        assert_eq!(warnings.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_continuation_request_times_out() {
+        let (head, tail) = FULL_ARGS.split_at(40);
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_raw_tool_call("write_file", head, "length")
+                .respond_with_text(tail)
+                .delayed(Duration::from_secs(30)),
+        );
+        let agent = writer(
+            backend.clone(),
+            Agent::builder().request_timeout(Duration::from_secs(5)),
+        );
+
+        match agent.run("Save a note").await {
+            Err(Error::Timeout { elapsed }) => assert_eq!(elapsed, Duration::from_secs(5)),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        assert_eq!(backend.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_arguments_report_offset() {
+        let backend = Arc::new(MockChatBackend::new().respond_with_raw_tool_call(
+            "write_file",
+            r#"{"path": "a.txt", "content": oops}"#,
+            "tool_calls",
+        ));
+        let agent = writer(backend.clone(), Agent::builder());
+
+        let err = agent.run("Save a note").await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::MalformedToolArguments { ref tool_name, offset: 29, .. } if tool_name == "write_file"
+        ));
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_unknown_terminal_tool_is_rejected() {
+        let result = Agent::builder()
+            .model("mock-model")
+            .tools(tools![SearchFlightsTool])
+            .terminal_tool("book_flight", Template::parse("{id}").unwrap())
+            .build();
+        assert!(matches!(result, Err(Error::InvalidConfiguration(_))));
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct ReadArgs {
+        page: String,
+    }
+
+    #[tool("Fetch a page")]
+    async fn fetch_page(args: ReadArgs) -> Result<String> {
+        Ok(format!(
+            "\u{1b}[1m# {}\u{1b}[0m\n\n**Welcome** 👋 to the page",
+            args.page
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_plain_text_post_processing() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("fetch_page", json!({ "page": "Home" }))
+                .respond_with_text(
+                    "## Summary ✨\n\nThe page says **welcome** 👋:\n```\nhello_world()\n```",
+                ),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(tools![FetchPageTool])
+            .plain_text()
+            .post_process(|answer| answer.to_uppercase())
+            .plain_text_tool("fetch_page")
+            .build()
+            .unwrap();
+
+        let answer = agent.run("What does the home page say?").await.unwrap();
+        assert_eq!(answer, "SUMMARY\n\nTHE PAGE SAYS WELCOME:\nHELLO_WORLD()");
+
+        // The tool result was normalized before it reached the model.
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        assert_eq!(messages[2]["content"], "Home\n\nWelcome to the page");
+    }
+
+    #[tokio::test]
+    async fn test_run_with_metadata_sums_usage() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_calls(&[
+                    ("fetch_page", json!({ "page": "Home" })),
+                    ("fetch_page", json!({ "page": "About" })),
+                ])
+                .respond_with_tool_call("fetch_page", json!({ "page": "Contact" }))
+                .respond_with_text("Done"),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend)
+            .tools(tools![FetchPageTool])
+            .build()
+            .unwrap();
+
+        let response = agent.run_with_metadata("Fetch the site").await.unwrap();
+        assert_eq!(response.content, "Done");
+        assert_eq!(response.iterations, 3);
+        assert_eq!(response.tool_calls_made, 3);
+        assert_eq!(
+            response.usage,
+            Usage {
+                prompt_tokens: 30,
+                completion_tokens: 15,
+                total_tokens: 45,
+            }
+        );
+    }
+
+    #[test]
+    fn test_usage_saturates() {
+        let huge: CompletionUsage = serde_json::from_value(json!({
+            "prompt_tokens": u32::MAX,
+            "completion_tokens": 10,
+            "total_tokens": u32::MAX
+        }))
+        .unwrap();
+        let mut usage = Usage::default();
+        usage.add(&huge);
+        usage.add(&huge);
+        assert_eq!(usage.prompt_tokens, u32::MAX);
+        assert_eq!(usage.completion_tokens, 20);
+        assert_eq!(usage.total_tokens, u32::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_tool_results_untouched_by_default() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("fetch_page", json!({ "page": "Home" }))
+                .respond_with_text("**Done** 🎉"),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(tools![FetchPageTool])
+            .build()
+            .unwrap();
+
+        assert_eq!(agent.run("Fetch").await.unwrap(), "**Done** 🎉");
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        assert!(messages[2]["content"]
+            .as_str()
+            .unwrap()
+            .contains("\u{1b}[1m"));
+    }
+
+    #[tool("Look up a page", effects = "read_only")]
+    async fn lookup_page(args: ReadArgs) -> Result<String> {
+        Ok(args.page)
+    }
+
+    #[test]
+    fn test_read_only_rejects_mutating_tools() {
+        let result = Agent::builder()
+            .model("mock-model")
+            .tools(tools![LookupPageTool, WriteFileTool, FetchPageTool])
+            .read_only(true)
+            .build();
+        match result {
+            Err(Error::InvalidConfiguration(msg)) => {
+                assert!(msg.contains("write_file, fetch_page"), "{}", msg);
+                assert!(!msg.contains("lookup_page"));
+            }
+            _ => panic!("read-only agent accepted mutating tools"),
+        }
+
+        let agent = Agent::builder()
+            .model("mock-model")
+            .tools(tools![LookupPageTool])
+            .read_only(true)
+            .build();
+        assert!(agent.is_ok());
+    }
+
+    static FLAKY_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct OrderArgs {
+        id: String,
+    }
+
+    #[tool("Fetch a quote, rate limited twice before succeeding")]
+    async fn flaky_quote(args: OrderArgs) -> Result<String> {
+        if FLAKY_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < 2 {
+            return Err(ToolError::rate_limited("try again").into());
+        }
+        Ok(format!("quote for {}", args.id))
+    }
+
+    #[tool("Look up an order")]
+    async fn get_order(args: OrderArgs) -> std::result::Result<String, ToolError> {
+        match args.id.as_str() {
+            "locked" => Err(ToolError::permission_denied("order is locked")),
+            "broken" => Err(ToolError::fatal("database unavailable")),
+            id => Err(ToolError::not_found(format!("no order {}", id))),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retryable_tool_errors_are_retried() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("flaky_quote", json!({ "id": "7" }))
+                .respond_with_text("Done."),
+        );
+        let (builder, warnings) = collect_warnings(
+            Agent::builder().tool_error_policy(ToolErrorPolicy::default().max_retries(2)),
+        );
+        let agent = builder
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(tools![FlakyQuoteTool])
+            .build()
+            .unwrap();
+
+        assert_eq!(agent.run("Quote order 7").await.unwrap(), "Done.");
+        // The model never saw the failures.
+        assert_eq!(backend.requests().len(), 2);
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        assert_eq!(messages[2]["content"], "quote for 7");
+        assert_eq!(warnings.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_tool_error_policy_by_code() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("get_order", json!({ "id": "9" }))
+                .respond_with_text("That order does not exist."),
+        );
+        let policy = ToolErrorPolicy::return_to_model()
+            .on_code(ToolError::PERMISSION_DENIED, ToolErrorAction::Abort);
+        let build = |backend: Arc<MockChatBackend>| {
+            Agent::builder()
+                .model("mock-model")
+                .backend(backend)
+                .tools(tools![GetOrderTool])
+                .tool_error_policy(policy.clone())
+                .build()
+                .unwrap()
+        };
+
+        // not_found is reported to the model as structured JSON.
+        let agent = build(backend.clone());
+        assert_eq!(
+            agent.run("Find order 9").await.unwrap(),
+            "That order does not exist."
+        );
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        let reported: serde_json::Value =
+            serde_json::from_str(messages[2]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            reported,
+            json!({ "error": { "code": "not_found", "message": "no order 9", "retryable": false } })
+        );
+
+        // permission_denied is configured to abort.
+        let backend = Arc::new(
+            MockChatBackend::new().respond_with_tool_call("get_order", json!({ "id": "locked" })),
+        );
+        let err = build(backend).run("Find order").await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ToolExecution { ref message, .. } if message == "permission_denied: order is locked"
+        ));
+
+        // fatal always aborts.
+        let backend = Arc::new(
+            MockChatBackend::new().respond_with_tool_call("get_order", json!({ "id": "broken" })),
+        );
+        let err = build(backend.clone()).run("Find order").await.unwrap_err();
+        assert!(matches!(err, Error::ToolExecution { .. }));
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_tools() {
+        // Aborting reports the hallucinated name.
+        let backend =
+            Arc::new(MockChatBackend::new().respond_with_tool_call("book_hotel", json!({})));
+        let err = agent(backend, Agent::builder())
+            .run("Book a hotel")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::ToolNotFound(ref name) if name == "book_hotel"));
+
+        // Returned to the model, the error lists the available tools.
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("book_hotel", json!({}))
+                .respond_with_text("I can only search flights."),
+        );
+        let policy = ToolErrorPolicy::abort()
+            .on_code(ToolError::UNKNOWN_TOOL, ToolErrorAction::ReturnToModel);
+        let answer = agent(backend.clone(), Agent::builder().tool_error_policy(policy))
+            .run("Book a hotel")
+            .await
+            .unwrap();
+        assert_eq!(answer, "I can only search flights.");
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        let reported: serde_json::Value =
+            serde_json::from_str(messages[2]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(reported["error"]["code"], "unknown_tool");
+        assert_eq!(
+            reported["error"]["message"],
+            "no such tool 'book_hotel', available tools are: search_flights"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sliding_window_drops_oldest_messages() {
+        let backend = Arc::new(MockChatBackend::new().respond_with_text("Lisbon again?"));
+        let (builder, warnings) = collect_warnings(
+            Agent::builder().context_strategy(ContextStrategy::SlidingWindow { max_tokens: 40 }),
+        );
+        let agent = agent(backend.clone(), builder);
+
+        let mut conversation = Conversation::with_system("You book flights");
+        for trip in ["Paris", "Rome", "Oslo"] {
+            conversation.add_user_message(format!("Find me a flight to {} next week", trip));
+            conversation.add_assistant_message(format!("Here are three flights to {}", trip));
+        }
+        conversation.add_user_message("And Lisbon?");
+        agent.run_conversation(&mut conversation).await.unwrap();
+
+        let request = &backend.requests()[0];
+        assert!(count_messages(&ApproxTokenCounter, &request.messages) <= 40);
+        let messages = serde_json::to_value(&request.messages).unwrap();
+        assert_eq!(messages[0]["content"], "You book flights");
+        assert_eq!(
+            messages[messages.as_array().unwrap().len() - 1]["content"],
+            "And Lisbon?"
+        );
+        // Plus the answer.
+        assert_eq!(conversation.len(), request.messages.len() + 1);
+        assert_eq!(
+            *warnings.lock().unwrap(),
+            vec![Warning::ContextTruncated {
+                dropped: 8 - request.messages.len()
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requests_follow_the_conversation_between_iterations() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_tool_call("search_flights", json!({ "to": "OPO" }))
+                .respond_with_text("Both are cheap"),
+        );
+        let (builder, warnings) = collect_warnings(
+            Agent::builder().context_strategy(ContextStrategy::SlidingWindow { max_tokens: 90 }),
+        );
+        let agent = agent(backend.clone(), builder);
+
+        let mut conversation = Conversation::with_system("You book flights");
+        for trip in ["Paris", "Rome"] {
+            conversation.add_user_message(format!("Find me a flight to {} next week", trip));
+            conversation.add_assistant_message(format!("Here are three flights to {}", trip));
+        }
+        conversation.add_user_message("Lisbon or Porto?");
+        agent.run_conversation(&mut conversation).await.unwrap();
+
+        // Every iteration drops old messages.
+        assert_eq!(warnings.lock().unwrap().len(), 3);
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 3);
+        // Without the answer, the conversation is what the last request sent.
+        assert_eq!(
+            requests[2].messages,
+            conversation.messages()[..conversation.len() - 1]
+        );
+        for request in &requests {
+            assert!(count_messages(&ApproxTokenCounter, &request.messages) <= 90);
+        }
+    }
+
+    #[test]
+    fn test_estimate_request_tokens_includes_tools() {
+        let agent = agent(Arc::new(MockChatBackend::new()), Agent::builder());
+        let conversation = Conversation::with_system("You book flights");
+        let tools = tools![SearchFlightsTool].estimate_tokens(&ApproxTokenCounter);
+        assert!(tools > 0);
+        assert_eq!(
+            agent.estimate_request_tokens(&conversation),
+            conversation.estimate_tokens(&ApproxTokenCounter) + tools
+        );
+    }
+
+    #[tokio::test]
+    async fn test_summarize_compacts_older_messages() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text("The user looked at flights to Paris, Rome and Oslo.")
+                .respond_with_text("Lisbon again?"),
+        );
+        let (builder, warnings) = collect_warnings(Agent::builder().context_strategy(
+            ContextStrategy::Summarize {
+                trigger_tokens: 40,
+                keep_recent: 2,
+            },
+        ));
+        let agent = agent(backend.clone(), builder);
+
+        let mut conversation = Conversation::with_system("You book flights");
+        for trip in ["Paris", "Rome", "Oslo"] {
+            conversation.add_user_message(format!("Find me a flight to {} next week", trip));
+            conversation.add_assistant_message(format!("Here are three flights to {}", trip));
+        }
+        conversation.add_user_message("And Lisbon?");
+        agent.run_conversation(&mut conversation).await.unwrap();
+
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].tools.is_none());
+        let summary_request = serde_json::to_value(&requests[0].messages).unwrap();
+        assert!(summary_request[1]["content"]
+            .as_str()
+            .unwrap()
+            .starts_with("User: Find me a flight to Paris next week\n"));
+
+        let messages = serde_json::to_value(&requests[1].messages).unwrap();
+        assert_eq!(requests[1].messages.len(), 4);
+        assert_eq!(messages[0]["content"], "You book flights");
+        assert_eq!(
+            messages[1]["content"],
+            "Summary of earlier conversation: The user looked at flights to Paris, Rome and Oslo."
+        );
+        assert_eq!(messages[2]["content"], "Here are three flights to Oslo");
+        assert_eq!(messages[3]["content"], "And Lisbon?");
+        assert_eq!(
+            *warnings.lock().unwrap(),
+            vec![Warning::ContextCompacted { summarized: 5 }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_with_overrides_shares_client_and_tools() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text("Base answer.")
+                .respond_with_text("Formal answer."),
+        );
+        let base = Agent::builder()
+            .model("mock-model")
+            .system_prompt("Be casual")
+            .backend(backend.clone())
+            .tools(tools![SearchFlightsTool])
+            .max_iterations(4)
+            .build()
+            .unwrap();
+
+        let formal = base
+            .with_overrides()
+            .system_prompt("Be formal")
+            .model("other-model")
+            .build()
+            .unwrap();
+
+        assert!(Arc::ptr_eq(&base.client, &formal.client));
+        assert!(Arc::ptr_eq(
+            base.tools.as_ref().unwrap(),
+            formal.tools.as_ref().unwrap()
+        ));
+        assert_eq!(formal.max_iterations, 4);
+
+        // Overrides only apply to the derived agent.
+        assert_eq!(base.run("Hi").await.unwrap(), "Base answer.");
+        assert_eq!(formal.run("Hi").await.unwrap(), "Formal answer.");
+        let requests = serde_json::to_value(backend.requests()).unwrap();
+        assert_eq!(requests[0]["model"], "mock-model");
+        assert_eq!(requests[0]["messages"][0]["content"], "Be casual");
+        assert_eq!(requests[1]["model"], "other-model");
+        assert_eq!(requests[1]["messages"][0]["content"], "Be formal");
+    }
+
+    #[tokio::test]
+    async fn test_with_overrides_tools() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("write_file", json!({ "path": "a", "content": "b" }))
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("Done."),
+        );
+        let base = agent(backend.clone(), Agent::builder());
+
+        let extended = base
+            .with_overrides()
+            .extend_tools(tools![WriteFileTool])
+            .build()
+            .unwrap();
+        let names: Vec<_> = extended
+            .tools
+            .as_ref()
+            .unwrap()
+            .tools()
+            .iter()
+            .map(|t| t.function.name.clone())
+            .collect();
+        assert_eq!(names, vec!["search_flights", "write_file"]);
+        assert_eq!(base.tools.as_ref().unwrap().tools().len(), 1);
+
+        // Both the inherited and the added tool dispatch.
+        assert_eq!(extended.run("Go").await.unwrap(), "Done.");
+        let messages = serde_json::to_value(&backend.requests()[2].messages).unwrap();
+        assert_eq!(messages[2]["content"], "wrote 1 bytes to a");
+        assert!(messages[4]["content"].as_str().unwrap().contains("LIS"));
+
+        let replaced = base
+            .with_overrides()
+            .tools(tools![WriteFileTool])
+            .build()
+            .unwrap();
+        assert_eq!(replaced.tools.as_ref().unwrap().tools().len(), 1);
+        assert_eq!(
+            replaced.tools.as_ref().unwrap().tools()[0].function.name,
+            "write_file"
+        );
+    }
+
+    #[tool("Ask something")]
+    async fn ask_user(args: OrderArgs) -> Result<String> {
+        Ok(args.id)
+    }
+
+    fn outcome_agent(backend: Arc<MockChatBackend>) -> Agent {
+        Agent::builder()
+            .model("mock-model")
+            .backend(backend)
+            .tools(tools![SearchFlightsTool])
+            .ask_user(true)
+            .handoff_targets(["billing"])
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_run_outcome_answer() {
+        let backend = Arc::new(MockChatBackend::new().respond_with_text("Hello!"));
+        let outcome = outcome_agent(backend.clone())
+            .run_outcome("Hi")
+            .await
+            .unwrap();
+        assert_eq!(outcome, RunOutcome::Answer("Hello!".into()));
+
+        let tools = serde_json::to_value(&backend.requests()[0].tools).unwrap();
+        let names: Vec<_> = tools
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["function"]["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["search_flights", "ask_user", "transfer_to_billing"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ask_user_ends_the_loop_and_resumes() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("ask_user", json!({ "question": "Where to?" }))
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("Two flights to Lisbon."),
+        );
+        let agent = outcome_agent(backend.clone());
+
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Find me a flight");
+        let outcome = agent
+            .run_conversation_outcome(&mut conversation)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome,
+            RunOutcome::NeedsUserInput {
+                question: "Where to?".into()
+            }
+        );
+        // The pseudo-tool was not dispatched and the loop stopped.
+        assert_eq!(backend.requests().len(), 1);
+        assert_eq!(conversation.len(), 3);
+
+        conversation.add_user_message("Lisbon");
+        let outcome = agent
+            .run_conversation_outcome(&mut conversation)
+            .await
+            .unwrap();
+        assert_eq!(outcome, RunOutcome::Answer("Two flights to Lisbon.".into()));
+    }
+
+    fn gated_agent(backend: Arc<MockChatBackend>) -> (Agent, Arc<std::sync::Mutex<Vec<Warning>>>) {
+        let gate = AmbiguityGate::new()
+            .model("gate-model")
+            .always_proceed("list *");
+        let (builder, warnings) = collect_warnings(Agent::builder().ambiguity_gate(gate));
+        let agent = builder
+            .model("mock-model")
+            .backend(backend)
+            .tools(tools![SearchFlightsTool])
+            .build()
+            .unwrap();
+        (agent, warnings)
+    }
+
+    fn classification(specific_enough: bool, question: &str) -> String {
+        json!({
+            "specific_enough": specific_enough,
+            "confidence": 0.9,
+            "missing_information": if specific_enough { vec![] } else { vec!["destination"] },
+            "question": question,
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_ambiguity_gate_clarifies() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text(&classification(false, "Where would you like to fly?"))
+                .respond_with_text(&classification(true, ""))
+                .respond_with_text("Two flights to Lisbon."),
+        );
+        let (agent, _) = gated_agent(backend.clone());
+
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Book me a flight");
+        let response = agent
+            .run_conversation_outcome_with_metadata(&mut conversation)
+            .await
+            .unwrap();
+        let question = "Where would you like to fly?".to_string();
+        assert_eq!(
+            response.outcome,
+            RunOutcome::NeedsUserInput {
+                question: question.clone()
+            }
+        );
+        let gate = response.gate.unwrap();
+        assert_eq!(gate.verdict, GateVerdict::Clarify);
+        assert_eq!(
+            gate.classification.unwrap().missing_information,
+            ["destination"]
+        );
+        assert_eq!(response.iterations, 0);
+        // Only the gate model was asked, and the question joins the
+        // conversation.
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].model, "gate-model");
+        assert_eq!(conversation.len(), 2);
+
+        conversation.add_user_message("To Lisbon, tomorrow");
+        let response = agent
+            .run_conversation_outcome_with_metadata(&mut conversation)
+            .await
+            .unwrap();
+        assert_eq!(
+            response.outcome,
+            RunOutcome::Answer("Two flights to Lisbon.".into())
+        );
+        assert_eq!(response.gate.unwrap().verdict, GateVerdict::Proceed);
+        let transcript = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        assert_eq!(
+            transcript[1]["content"],
+            format!(
+                "User: Book me a flight\nAssistant: {}\nUser: To Lisbon, tomorrow\n",
+                question
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ambiguity_gate_proceeds() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text(&classification(true, ""))
+                .respond_with_text("Two flights to Lisbon.")
+                .respond_with_text("You have no bookings."),
+        );
+        let (agent, warnings) = gated_agent(backend.clone());
+
+        let response = agent
+            .run_outcome_with_metadata("Find flights to Lisbon tomorrow")
+            .await
+            .unwrap();
+        assert_eq!(
+            response.outcome,
+            RunOutcome::Answer("Two flights to Lisbon.".into())
+        );
+        assert_eq!(response.gate.unwrap().verdict, GateVerdict::Proceed);
+        assert_eq!(backend.requests()[1].model, "mock-model");
+
+        // Allow-listed requests skip classification.
+        let response = agent
+            .run_outcome_with_metadata("List my bookings")
+            .await
+            .unwrap();
+        assert_eq!(response.gate.unwrap().verdict, GateVerdict::Allowed);
+        assert_eq!(backend.requests().len(), 3);
+        assert!(warnings.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ambiguity_gate_fails_open() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_error(api_error("400"))
+                .respond_with_text("Two flights to Lisbon.")
+                .respond_with_text("not json")
+                .respond_with_text("Two flights to Porto."),
+        );
+        let (agent, warnings) = gated_agent(backend.clone());
+
+        for answer in ["Two flights to Lisbon.", "Two flights to Porto."] {
+            let response = agent.run_outcome_with_metadata("Book it").await.unwrap();
+            assert_eq!(response.outcome, RunOutcome::Answer(answer.into()));
+            let gate = response.gate.unwrap();
+            assert!(matches!(gate.verdict, GateVerdict::Failed(_)));
+            assert_eq!(gate.classification, None);
+        }
+        let warnings = warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(matches!(
+            warnings[1],
+            Warning::AmbiguityGateFailed { ref error } if error.starts_with("classification is not JSON")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_run_outcome_handoff() {
+        let backend = Arc::new(
+            MockChatBackend::new().respond_with_tool_call("transfer_to_billing", json!({})),
+        );
+        let outcome = outcome_agent(backend)
+            .run_outcome("Refund me")
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome,
+            RunOutcome::Handoff {
+                target: "billing".into()
+            }
+        );
+    }
+
+    fn specialist(backend: Arc<MockChatBackend>, prompt: &str) -> Arc<Agent> {
+        Arc::new(
+            Agent::builder()
+                .model("mock-model")
+                .backend(backend)
+                .system_prompt(prompt)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_run_with_handoffs_transfers_the_conversation() {
+        let registry = AgentRegistry::new();
+        let billing_backend =
+            Arc::new(MockChatBackend::new().respond_with_text("Your refund is on its way."));
+        registry
+            .register(
+                "billing",
+                specialist(billing_backend.clone(), "You handle billing"),
+            )
+            .unwrap();
+        assert!(matches!(
+            Agent::handoff_tools(&registry, &["shipping"]),
+            Err(Error::AgentNotFound(ref name)) if name == "shipping"
+        ));
+
+        let triage_backend = Arc::new(
+            MockChatBackend::new().respond_with_tool_call("transfer_to_billing", json!({})),
+        );
+        let triage = Agent::builder()
+            .model("mock-model")
+            .backend(triage_backend.clone())
+            .system_prompt("You route requests")
+            .tools(Agent::handoff_tools(&registry, &["billing"]).unwrap())
+            .build()
+            .unwrap();
+
+        let response = triage
+            .run_with_handoffs(&registry, "I was charged twice")
+            .await
+            .unwrap();
+        assert_eq!(
+            response.outcome,
+            RunOutcome::Answer("Your refund is on its way.".into())
+        );
+        assert_eq!(response.transfers, ["billing"]);
+        let offered = serde_json::to_value(&triage_backend.requests()[0].tools).unwrap();
+        assert_eq!(offered[0]["function"]["name"], "transfer_to_billing");
+
+        // Billing continues the same conversation under its own prompt.
+        let messages = serde_json::to_value(&billing_backend.requests()[0].messages).unwrap();
+        assert_eq!(messages[0]["content"], "You handle billing");
+        assert_eq!(messages[1]["content"], "I was charged twice");
+        assert_eq!(messages[3]["content"], "Transferred to billing.");
+        assert_eq!(response.conversation.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_run_with_handoffs_caps_transfers() {
+        let registry = AgentRegistry::new();
+        let ping_pong = |target: &str, calls: usize| {
+            let backend = (0..calls).fold(MockChatBackend::new(), |backend, _| {
+                backend.respond_with_tool_call(&format!("transfer_to_{}", target), json!({}))
+            });
+            Arc::new(
+                Agent::builder()
+                    .model("mock-model")
+                    .backend(Arc::new(backend))
+                    .handoff_targets([target])
+                    .max_handoffs(2)
+                    .build()
+                    .unwrap(),
+            )
+        };
+        registry.register("sales", ping_pong("support", 2)).unwrap();
+        registry.register("support", ping_pong("sales", 1)).unwrap();
+
+        let err = registry
+            .get("sales")
+            .unwrap()
+            .run_with_handoffs(&registry, "Which plan do I need?")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::MaxHandoffsExceeded { max: 2, ref transfers }
+                if transfers == &["support", "sales", "support"]
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Conversation exceeded maximum handoffs: 2 (support -> sales -> support)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handoff_tools_outside_outcome_runs() {
+        let registry = AgentRegistry::new();
+        registry
+            .register(
+                "billing",
+                specialist(Arc::new(MockChatBackend::new()), "You handle billing"),
+            )
+            .unwrap();
+        let backend = Arc::new(
+            MockChatBackend::new().respond_with_tool_call("transfer_to_billing", json!({})),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend)
+            .tools(Agent::handoff_tools(&registry, &["billing"]).unwrap())
+            .build()
+            .unwrap();
+
+        let err = agent.run("I was charged twice").await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ToolExecution { ref tool_name, ref message, .. }
+                if tool_name == "transfer_to_billing" && message.contains("run_with_handoffs")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_run_outcome_refused() {
+        let backend = Arc::new(MockChatBackend::new().respond_with_message(
+            json!({ "role": "assistant", "content": null }),
+            "content_filter",
+        ));
+        let outcome = outcome_agent(backend)
+            .run_outcome("Something bad")
+            .await
+            .unwrap();
+        assert!(matches!(outcome, RunOutcome::Refused { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_run_does_not_offer_pseudo_tools() {
+        let backend = Arc::new(MockChatBackend::new().respond_with_text("Hello!"));
+        outcome_agent(backend.clone()).run("Hi").await.unwrap();
+        let tools = backend.requests()[0].tools.clone().unwrap();
+        assert_eq!(tools.len(), 1);
+
+        let conflict = Agent::builder()
+            .model("mock-model")
+            .tools(tools![AskUserTool])
+            .ask_user(true)
+            .build();
+        assert!(matches!(conflict, Err(Error::InvalidConfiguration(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_timings_report_scheduling_wait() {
+        let limiter = Limiter::new(1);
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("Two flights."),
+        );
+        let agent = agent(backend, Agent::builder().limiter(limiter.clone()));
+
+        let held = limiter.acquire(Priority::High).await;
+        let (report, _) = tokio::join!(
+            agent.run_with_options("Flights?", RunOptions::new().priority(Priority::Low)),
+            async move {
+                tokio::time::sleep(Duration::from_millis(1500)).await;
+                drop(held);
+            }
+        );
+        let report = report.unwrap();
+        assert_eq!(report.content, "Two flights.");
+        assert_eq!(report.timings.scheduling_wait, Duration::from_millis(1500));
+        assert!(report.timings.total >= report.timings.scheduling_wait);
+        assert_eq!(limiter.stats(Priority::Low).granted, 2);
+        assert_eq!(limiter.available(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_stream_events() {
+        use futures::StreamExt;
+
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("Two flights found."),
+        );
+        let agent = agent(backend.clone(), Agent::builder());
+
+        let events: Vec<_> = agent.run_stream("Flights to Lisbon?").collect().await;
+        let events: Vec<_> = events.into_iter().map(|event| event.unwrap()).collect();
+        assert_eq!(
+            events[..5],
+            [
+                AgentEvent::IterationStarted {
+                    iteration: 1,
+                    max_iterations: DEFAULT_MAX_ITERATIONS
+                },
+                AgentEvent::ResponseReceived {
+                    iteration: 1,
+                    tool_calls: 1
+                },
+                AgentEvent::ToolCallStarted {
+                    id: "call_0_0".into(),
+                    name: "search_flights".into()
+                },
+                AgentEvent::ToolCallFinished {
+                    id: "call_0_0".into(),
+                    name: "search_flights".into(),
+                    result: r#"{"cheapest":{"price":129.5},"count":2,"to":"LIS"}"#.into(),
+                    duration: Duration::ZERO,
+                },
+                AgentEvent::IterationStarted {
+                    iteration: 2,
+                    max_iterations: DEFAULT_MAX_ITERATIONS
+                },
+            ]
+        );
+        assert_eq!(
+            events[5..],
+            [
+                AgentEvent::TextDelta("Two ".into()),
+                AgentEvent::TextDelta("flights ".into()),
+                AgentEvent::TextDelta("found.".into()),
+                AgentEvent::ResponseReceived {
+                    iteration: 2,
+                    tool_calls: 0
+                },
+                AgentEvent::Completed("Two flights found.".into()),
+            ]
+        );
+        assert_eq!(backend.requests()[0].stream, Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_run_conversation_stream_updates_conversation() {
+        use futures::StreamExt;
+
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_error(async_openai::error::OpenAIError::StreamError(
+                    "connection lost".into(),
+                )),
+        );
+        let agent = agent(backend, Agent::builder());
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Flights to Lisbon?");
+
+        let events: Vec<_> = agent
+            .run_conversation_stream(&mut conversation)
+            .collect()
+            .await;
+        assert_eq!(events.len(), 6);
+        assert!(matches!(events[5], Err(Error::OpenAI(_))));
+        // The tool call and its result were recorded before the failure.
+        assert_eq!(conversation.len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_with_events() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("check_seats", json!({ "to": "Lisbon" }))
+                .respond_with_text("Seats are available."),
+        );
+        let seats = Agent::builder()
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(tools![CheckSeatsTool])
+            .max_iterations(3)
+            .build()
+            .unwrap();
+
+        let (run, mut events) = seats.run_with_events("Any seats to Lisbon?");
+        assert_eq!(run.await.unwrap(), "Seats are available.");
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        assert_eq!(
+            received,
+            [
+                AgentEvent::IterationStarted {
+                    iteration: 1,
+                    max_iterations: 3
+                },
+                AgentEvent::ResponseReceived {
+                    iteration: 1,
+                    tool_calls: 1
+                },
+                AgentEvent::ToolCallStarted {
+                    id: "call_0_0".into(),
+                    name: "check_seats".into()
+                },
+                AgentEvent::ToolCallFinished {
+                    id: "call_0_0".into(),
+                    name: "check_seats".into(),
+                    result: "seats to Lisbon".into(),
+                    duration: Duration::from_secs(60),
+                },
+                AgentEvent::IterationStarted {
+                    iteration: 2,
+                    max_iterations: 3
+                },
+                AgentEvent::ResponseReceived {
+                    iteration: 2,
+                    tool_calls: 0
+                },
+                AgentEvent::Completed("Seats are available.".into()),
+            ]
+        );
+        // Completions are not streamed.
+        assert_eq!(backend.requests()[0].stream, None);
+    }
+
+    #[test]
+    fn test_profile_effective_policy() {
+        let policy = |builder: AgentBuilder| {
+            builder
+                .model("mock-model")
+                .build()
+                .unwrap()
+                .effective_policy()
+        };
+
+        assert_eq!(
+            policy(Agent::builder().profile(Profile::interactive())),
+            EffectivePolicy {
+                profile: Some("interactive".into()),
+                max_iterations: 5,
+                request_timeout: Some(Duration::from_secs(20)),
+                tool_timeout: None,
+                retry: Profile::interactive().retry,
+                tool_retries: 1,
+                verbose_warnings: false,
+            }
+        );
+        let batch = policy(Agent::builder().profile(Profile::batch()));
+        assert_eq!(batch.max_iterations, 20);
+        assert_eq!(batch.request_timeout, Some(Duration::from_secs(120)));
+        assert_eq!(batch.tool_retries, 3);
+        assert_eq!(batch.retry.unwrap().max_retries, 5);
+        let dev = policy(Agent::builder().profile(Profile::dev()));
+        assert_eq!(dev.retry, None);
+        assert_eq!(dev.tool_retries, 0);
+        assert!(dev.verbose_warnings);
+
+        let defaults = policy(Agent::builder());
+        assert_eq!(defaults.profile, None);
+        assert_eq!(defaults.max_iterations, DEFAULT_MAX_ITERATIONS);
+        assert_eq!(defaults.request_timeout, None);
+    }
+
+    #[test]
+    fn test_explicit_settings_override_profile() {
+        let agent = Agent::builder()
+            .model("mock-model")
+            .max_iterations(8)
+            .tool_error_policy(ToolErrorPolicy::return_to_model())
+            .profile(Profile::interactive())
+            .request_timeout(Duration::from_secs(3))
+            .on_warning(|_| {})
+            .build()
+            .unwrap();
+        let policy = agent.effective_policy();
+        assert_eq!(policy.max_iterations, 8);
+        assert_eq!(policy.request_timeout, Some(Duration::from_secs(3)));
+        assert_eq!(policy.tool_retries, 0);
+
+        let dev = Agent::builder()
+            .model("mock-model")
+            .profile(Profile::dev())
+            .on_warning(|_| {})
+            .build()
+            .unwrap();
+        assert!(!dev.effective_policy().verbose_warnings);
+
+        // Derived agents keep the resolved settings.
+        let derived = agent.with_overrides().build().unwrap();
+        assert_eq!(derived.effective_policy(), policy);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_timeout() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text("Too late.")
+                .latency(Duration::from_secs(30)),
+        );
+        let agent = agent(
+            backend,
+            Agent::builder().request_timeout(Duration::from_secs(5)),
+        );
+        match agent.run("Hi").await {
+            Err(Error::Timeout { elapsed }) => assert_eq!(elapsed, Duration::from_secs(5)),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_timeout_keeps_earlier_messages() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "Lisbon" }))
+                .respond_with_text("Too late.")
+                .delayed(Duration::from_secs(30)),
+        );
+        let agent = agent(
+            backend,
+            Agent::builder().request_timeout(Duration::from_secs(5)),
+        );
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Flights to Lisbon?");
+
+        let result = agent.run_conversation(&mut conversation).await;
+        assert!(matches!(result, Err(Error::Timeout { .. })));
+        // The tool round before the timeout is still there and complete.
+        let messages = serde_json::to_value(conversation.messages()).unwrap();
+        assert_eq!(conversation.len(), 3);
+        assert_eq!(messages[2]["role"], "tool");
+        assert_eq!(
+            messages[2]["tool_call_id"],
+            messages[1]["tool_calls"][0]["id"]
+        );
+    }
+
+    #[tool("Check seat availability, slowly")]
+    async fn check_seats(args: SearchArgs) -> Result<String> {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Ok(format!("seats to {}", args.to))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tool_timeout() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("check_seats", json!({ "to": "Lisbon" }))
+                .respond_with_text("Seat availability is unknown right now."),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(tools![CheckSeatsTool])
+            .tool_timeout(Duration::from_secs(2))
+            .tool_error_policy(ToolErrorPolicy::return_to_model().max_retries(1))
+            .build()
+            .unwrap();
+
+        let started = Instant::now();
+        let answer = agent.run("Any seats to Lisbon?").await.unwrap();
+        assert_eq!(answer, "Seat availability is unknown right now.");
+        // One attempt plus one retry, each cut off after two seconds.
+        assert_eq!(started.elapsed(), Duration::from_secs(4));
+
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        let result: Value = serde_json::from_str(messages[2]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(result["error"]["code"], "timeout");
+        assert_eq!(
+            result["error"]["message"],
+            "the tool did not finish within 2s"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tool_timeout_for_one_tool() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("check_seats", json!({ "to": "Lisbon" }))
+                .respond_with_text("Seat availability is unknown right now."),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(tools![CheckSeatsTool, SearchFlightsTool])
+            .tool_timeout(Duration::from_secs(120))
+            .tool_timeout_for("check_seats", Duration::from_secs(5))
+            .tool_error_policy(ToolErrorPolicy::return_to_model())
+            .build()
+            .unwrap();
+
+        let started = Instant::now();
+        agent.run("Any seats to Lisbon?").await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        let result: Value = serde_json::from_str(messages[2]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            result["error"]["message"],
+            "the tool did not finish within 5s"
+        );
+
+        let unknown = Agent::builder()
+            .model("mock-model")
+            .tools(tools![CheckSeatsTool])
+            .tool_timeout_for("book_hotel", Duration::from_secs(5))
+            .build();
+        assert!(matches!(unknown, Err(Error::InvalidConfiguration(_))));
+    }
+
+    #[cfg(feature = "jsonschema")]
+    #[tokio::test]
+    async fn test_invalid_tool_arguments_are_returned_to_the_model() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "destination": "LIS" }))
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("Found 2 flights"),
+        );
+        let flights = agent(
+            backend.clone(),
+            Agent::builder()
+                .validate_tool_arguments(true)
+                .tool_error_policy(ToolErrorPolicy::return_to_model()),
+        );
+        assert_eq!(
+            flights.run("Flights to Lisbon?").await.unwrap(),
+            "Found 2 flights"
+        );
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        let result: Value = serde_json::from_str(messages[2]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(result["error"]["code"], "invalid_input");
+        assert_eq!(
+            result["error"]["details"]["errors"][0],
+            "missing required field `to`"
+        );
+
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "destination": "LIS" })),
+        );
+        let strict = agent(backend, Agent::builder().validate_tool_arguments(true));
+        let error = strict.run("Flights to Lisbon?").await.unwrap_err();
+        assert!(matches!(
+            error,
+            Error::InvalidToolArguments { ref tool_name, ref raw, .. }
+                if tool_name == "search_flights" && raw == &json!({ "destination": "LIS" })
+        ));
+    }
+
+    #[cfg(feature = "jsonschema")]
+    #[tokio::test]
+    async fn test_derived_agents_validate_new_tools() {
+        let base = agent(
+            Arc::new(MockChatBackend::new()),
+            Agent::builder().validate_tool_arguments(true),
+        );
+        let invalid_call = || {
+            Arc::new(
+                MockChatBackend::new()
+                    .respond_with_tool_call("dump_schedule", json!({ "destination": "LIS" })),
+            )
+        };
+        let replaced = base
+            .with_overrides()
+            .backend(invalid_call())
+            .tools(tools![DumpScheduleTool])
+            .build()
+            .unwrap();
+        let extended = base
+            .with_overrides()
+            .backend(invalid_call())
+            .extend_tools(tools![DumpScheduleTool])
+            .build()
+            .unwrap();
+
+        for derived in [replaced, extended] {
+            let error = derived.run("Show me the schedule").await.unwrap_err();
+            assert!(matches!(
+                error,
+                Error::InvalidToolArguments { ref tool_name, .. } if tool_name == "dump_schedule"
+            ));
+        }
+    }
+
+    #[tool("Dump the whole flight schedule")]
+    async fn dump_schedule(args: SearchArgs) -> Result<String> {
+        Ok(format!("{}:{}", args.to, "é".repeat(100_000)))
+    }
+
+    #[tokio::test]
+    async fn test_tool_output_is_truncated() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("dump_schedule", json!({ "to": "LIS" }))
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("Done"),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend)
+            .tools(tools![DumpScheduleTool, SearchFlightsTool])
+            .max_tool_output_bytes(1001)
+            .max_tool_output_bytes_for("search_flights", 20)
+            .build()
+            .unwrap();
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Show me the schedule");
+        agent.run_conversation(&mut conversation).await.unwrap();
+
+        let messages = serde_json::to_value(conversation.messages()).unwrap();
+        let schedule = messages[2]["content"].as_str().unwrap();
+        // The cut moves back to the start of the 'é' crossing the limit.
+        assert_eq!(
+            schedule,
+            format!("LIS:{}\n[truncated 199004 bytes]", "é".repeat(498))
+        );
+        let search = messages[4]["content"].as_str().unwrap();
+        assert_eq!(search, "{\"cheapest\":{\"price\"\n[truncated 29 bytes]");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_parallel_tools_keep_result_order() {
+        let calls = [
+            ("check_seats", json!({ "to": "Lisbon" })),
+            ("check_seats", json!({ "to": "Porto" })),
+            ("check_seats", json!({ "to": "Faro" })),
+        ];
+        for (builder, expected) in [
+            (Agent::builder().parallel_tools(true), 60),
+            (Agent::builder().max_parallel_tools(2), 120),
+            (Agent::builder(), 180),
+        ] {
+            let backend = Arc::new(
+                MockChatBackend::new()
+                    .respond_with_tool_calls(&calls)
+                    .respond_with_text("All checked."),
+            );
+            let agent = builder
+                .model("mock-model")
+                .backend(backend.clone())
+                .tools(tools![CheckSeatsTool])
+                .build()
+                .unwrap();
+
+            let started = Instant::now();
+            agent.run("Seats to Lisbon, Porto and Faro?").await.unwrap();
+            assert_eq!(started.elapsed(), Duration::from_secs(expected));
+
+            let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+            for (index, to) in ["Lisbon", "Porto", "Faro"].into_iter().enumerate() {
+                let message = &messages[index + 2];
+                assert_eq!(
+                    message["tool_call_id"],
+                    messages[1]["tool_calls"][index]["id"]
+                );
+                assert_eq!(message["content"], format!("seats to {}", to));
+            }
+        }
+    }
+
+    /// Cancelled by [`hold_seat`], so the tool can stop its own run.
+    static HOLD_SEAT_CANCEL: std::sync::OnceLock<CancelHandle> = std::sync::OnceLock::new();
+
+    #[tool("Hold a seat, then cancel the run")]
+    async fn hold_seat(args: SearchArgs) -> Result<String> {
+        HOLD_SEAT_CANCEL.get_or_init(CancelHandle::new).cancel();
+        Ok(format!("seat held to {}", args.to))
+    }
+
+    #[tokio::test]
+    async fn test_cancel_after_first_tool_call_drops_the_round() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_calls(&[
+                    ("hold_seat", json!({ "to": "Lisbon" })),
+                    ("hold_seat", json!({ "to": "Porto" })),
+                ])
+                .respond_with_text("Both seats are held."),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(tools![HoldSeatTool])
+            .build()
+            .unwrap();
+        let cancel = HOLD_SEAT_CANCEL.get_or_init(CancelHandle::new).clone();
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Hold seats to Lisbon and Porto");
+
+        let result = agent
+            .run_conversation_cancellable(&mut conversation, cancel)
+            .await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+        // The second call never ran, so the round is removed entirely.
+        assert_eq!(conversation.len(), 1);
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_abandons_request_in_flight() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "Lisbon" }))
+                .respond_with_text("Too late.")
+                .delayed(Duration::from_secs(30)),
+        );
+        let agent = agent(backend, Agent::builder());
+        let cancel = CancelHandle::new();
+        tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                cancel.cancel();
+            }
+        });
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Flights to Lisbon?");
+
+        let started = Instant::now();
+        let result = agent
+            .run_conversation_cancellable(&mut conversation, cancel.clone())
+            .await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+        // The completed tool round is kept.
+        assert_eq!(conversation.len(), 3);
+
+        // A cancelled handle stops the next run before any request.
+        let result = agent.run_cancellable("Hi", cancel).await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn test_default_built_agents_share_the_default_client() {
+        let first = Agent::builder().model("mock-model").build().unwrap();
+        let second = Agent::builder().model("mock-model").build().unwrap();
+        let derived = first.with_overrides().build().unwrap();
+
+        let default = crate::client::default_client() as Arc<dyn ChatBackend>;
+        for agent in [&first, &second, &derived] {
+            assert!(std::ptr::addr_eq(
+                Arc::as_ptr(&agent.client),
+                Arc::as_ptr(&default)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_client_accepts_any_config() {
+        let config = async_openai::config::AzureConfig::new()
+            .with_api_base("https://example.openai.azure.com")
+            .with_deployment_id("gpt-4o");
+        let azure = Agent::builder()
+            .client(Client::with_config(config))
+            .model("gpt-4o")
+            .build()
+            .unwrap();
+        let derived = azure.with_overrides().build().unwrap();
+        assert!(Arc::ptr_eq(&azure.client, &derived.client));
+
+        let default = crate::client::default_client() as Arc<dyn ChatBackend>;
+        assert!(!std::ptr::addr_eq(
+            Arc::as_ptr(&azure.client),
+            Arc::as_ptr(&default)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_parameters_are_dropped() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "Lisbon" }))
+                .respond_with_text("Done."),
+        );
+        let (builder, warnings) = collect_warnings(
+            Agent::builder()
+                .provider(Provider::Custom(
+                    crate::provider::Capabilities::all().without("temperature"),
+                ))
+                .temperature(0.3)
+                .top_p(0.9),
+        );
+        let agent = agent(backend.clone(), builder);
+
+        agent.run("Flights?").await.unwrap();
+        for request in backend.requests() {
+            assert_eq!(request.temperature, None);
+            assert_eq!(request.top_p, Some(0.9));
+        }
+        // Reported once per run, not once per request.
+        assert_eq!(
+            *warnings.lock().unwrap(),
+            vec![Warning::ParameterDropped {
+                parameter: "temperature".into()
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejected_parameters_are_stripped_and_remembered() {
+        let rejection = async_openai::error::OpenAIError::ApiError(
+            serde_json::from_value(json!({
+                "message": "Invalid parameter: 'response_format' of type 'json_object' is not \
+                            supported with this model.",
+                "type": "invalid_request_error",
+                "param": "response_format",
+                "code": null,
+            }))
+            .unwrap(),
+        );
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_error(rejection)
+                .respond_with_text("{\"ok\": true}")
+                .respond_with_text("{\"ok\": true}"),
+        );
+        let (builder, warnings) = collect_warnings(
+            Agent::builder()
+                .model("strict-model")
+                .backend(backend.clone())
+                .json_mode(true)
+                .strip_rejected_parameters(true),
+        );
+        let agent = builder.build().unwrap();
+
+        assert_eq!(agent.run("Reply in JSON").await.unwrap(), "{\"ok\": true}");
+        let requests = backend.requests();
+        assert!(requests[0].response_format.is_some());
+        assert!(requests[1].response_format.is_none());
+        assert_eq!(
+            warnings.lock().unwrap()[0],
+            Warning::ParameterRejected {
+                parameter: "response_format".into(),
+                model: "strict-model".into(),
+            }
+        );
+
+        // Later runs leave the parameter out from the start.
+        agent.run("Reply in JSON").await.unwrap();
+        assert!(backend.requests()[2].response_format.is_none());
+        assert_eq!(
+            warnings.lock().unwrap()[1],
+            Warning::ParameterDropped {
+                parameter: "response_format".into()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejected_parameters_fail_without_opt_in() {
+        let rejection = async_openai::error::OpenAIError::ApiError(
+            serde_json::from_value(json!({
+                "message": "Unsupported parameter: 'response_format'",
+                "code": "400",
+            }))
+            .unwrap(),
+        );
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_error(rejection)
+                .respond_with_text("unreachable"),
+        );
+        let agent = agent(backend.clone(), Agent::builder().json_mode(true));
+        assert!(matches!(agent.run("Hi").await, Err(Error::OpenAI(_))));
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[derive(Debug, PartialEq, StructuredOutput, ToolArg, Serialize, serde::Deserialize)]
+    struct FlightSummary {
+        destination: String,
+        flights: u32,
+    }
+
+    #[tokio::test]
+    async fn test_run_structured_as_renders_the_typed_answer() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "Lisbon" }))
+                .respond_with_text(r#"{"destination": "Lisbon", "flights": 2}"#),
+        );
+        let agent = agent(backend.clone(), Agent::builder().plain_text());
+
+        let rendered = agent
+            .run_structured_as::<FlightSummary>("Flights to Lisbon?", Format::Yaml)
+            .await
+            .unwrap();
+        assert_eq!(
+            rendered.value,
+            FlightSummary {
+                destination: "Lisbon".into(),
+                flights: 2
+            }
+        );
+        assert_eq!(rendered.text, "destination: Lisbon\nflights: 2\n");
+
+        let request = serde_json::to_value(&backend.requests()[0]).unwrap();
+        assert_eq!(request["response_format"]["type"], "json_schema");
+        let json_schema = &request["response_format"]["json_schema"];
+        assert_eq!(json_schema["name"], "FlightSummary");
+        assert_eq!(json_schema["strict"], true);
+        assert_eq!(
+            json_schema["schema"]["properties"]["destination"],
+            json!({ "type": "string" })
+        );
+        assert_eq!(json_schema["schema"]["additionalProperties"], false);
+    }
+
+    #[tokio::test]
+    async fn test_run_structured_retries_once_with_the_parse_error() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text(r#"{"destination": "Lisbon"}"#)
+                .respond_with_text(r#"{"destination": "Lisbon", "flights": 2}"#),
+        );
+        let retrying = agent(backend.clone(), Agent::builder());
+        let summary: FlightSummary = retrying.run_structured("Flights to Lisbon?").await.unwrap();
+        assert_eq!(summary.flights, 2);
+
+        let retry = serde_json::to_value(&backend.requests()[1]).unwrap();
+        let messages = retry["messages"].as_array().unwrap();
+        assert_eq!(
+            messages[messages.len() - 2]["content"],
+            r#"{"destination": "Lisbon"}"#
+        );
+        let correction = messages[messages.len() - 1]["content"].as_str().unwrap();
+        assert!(correction.contains("missing field `flights`"));
+
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text("Two flights.")
+                .respond_with_text("Still two flights."),
+        );
+        let error = agent(backend.clone(), Agent::builder())
+            .run_structured::<FlightSummary>("Flights to Lisbon?")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, Error::StructuredOutputParse { ref raw, .. } if raw == "Still two flights.")
+        );
+
+        let backend = Arc::new(MockChatBackend::new().respond_with_text("Two flights."));
+        let agent = agent(
+            backend.clone(),
+            Agent::builder().structured_output_retry(false),
+        );
+        let error = agent
+            .run_structured::<FlightSummary>("Flights to Lisbon?")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::StructuredOutputParse { .. }));
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_named_tool_choice_is_forced_once() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "Lisbon" }))
+                .respond_with_text("Two flights found."),
+        );
+        let builder = Agent::builder().tool_choice(ToolChoice::Named("search_flights".into()));
+        let agent = agent(backend.clone(), builder);
+        agent.run("Flights to Lisbon?").await.unwrap();
+
+        let requests = backend.requests();
+        let first = serde_json::to_value(&requests[0]).unwrap();
+        assert_eq!(
+            first["tool_choice"],
+            json!({ "type": "function", "function": { "name": "search_flights" } })
+        );
+        let second = serde_json::to_value(&requests[1]).unwrap();
+        assert_eq!(second["tool_choice"], "auto");
+    }
+
+    #[tokio::test]
+    async fn test_tool_choice_run_override() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text("Summary.")
+                .respond_with_text("Summary."),
+        );
+        let agent = agent(
+            backend.clone(),
+            Agent::builder().tool_choice(ToolChoice::Required),
+        );
+        let options = RunOptions::new().tool_choice(ToolChoice::None);
+        agent.run_with_options("Summarize", options).await.unwrap();
+        agent.run("Summarize").await.unwrap();
+
+        let requests = backend.requests();
+        let tool_choices: Vec<_> = requests
+            .iter()
+            .map(|request| serde_json::to_value(request).unwrap()["tool_choice"].clone())
+            .collect();
+        assert_eq!(tool_choices, [json!("none"), json!("required")]);
+
+        let options = RunOptions::new().tool_choice(ToolChoice::Named("book".into()));
+        let result = agent.run_with_options("Book it", options).await;
+        assert!(matches!(result, Err(Error::InvalidConfiguration(_))));
+    }
+
+    #[test]
+    fn test_named_tool_choice_must_be_a_tool() {
+        let result = Agent::builder()
+            .model("mock-model")
+            .tools(tools![SearchFlightsTool])
+            .tool_choice(ToolChoice::Named("book_flight".into()))
+            .build();
+        match result {
+            Err(Error::InvalidConfiguration(message)) => assert_eq!(
+                message,
+                "Tool choice 'book_flight' is not in the agent's tools"
+            ),
+            other => panic!("expected a configuration error, got {:?}", other.err()),
+        }
+    }
+
+    fn api_error(code: &str) -> async_openai::error::OpenAIError {
+        async_openai::error::OpenAIError::ApiError(
+            serde_json::from_value(json!({
+                "message": "Provider returned error",
+                "type": null,
+                "param": null,
+                "code": code,
+            }))
+            .unwrap(),
+        )
+    }
+
+    fn retrying(backend: Arc<MockChatBackend>) -> (Agent, Arc<std::sync::Mutex<Vec<Warning>>>) {
+        let (builder, warnings) = collect_warnings(Agent::builder().retry(RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10),
+            jitter: false,
+        }));
+        (agent(backend, builder), warnings)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transient_errors_are_retried() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "Lisbon" }))
+                .respond_with_error(api_error("429"))
+                .respond_with_error(api_error("server_error"))
+                .respond_with_text("Two flights found."),
+        );
+        let (agent, warnings) = retrying(backend.clone());
+
+        let started = Instant::now();
+        assert_eq!(agent.run("Flights?").await.unwrap(), "Two flights found.");
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+
+        // The retries resent the conversation including the tool result.
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[3].messages.len(), 3);
+        let backoffs: Vec<_> = warnings
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|warning| match warning {
+                Warning::RequestRetried { backoff, .. } => *backoff,
+                other => panic!("unexpected warning {:?}", other),
+            })
+            .collect();
+        assert_eq!(backoffs, [1, 2].map(Duration::from_secs));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_non_retryable_errors_fail_immediately() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_error(api_error("400"))
+                .respond_with_text("unreachable"),
+        );
+        let (agent, warnings) = retrying(backend.clone());
+
+        assert!(matches!(agent.run("Hi").await, Err(Error::OpenAI(_))));
+        assert_eq!(backend.requests().len(), 1);
+        assert!(warnings.lock().unwrap().is_empty());
+
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_error(api_error("503"))
+                .respond_with_error(api_error("503"))
+                .respond_with_error(api_error("503"))
+                .respond_with_error(api_error("503")),
+        );
+        let (agent, _) = retrying(backend.clone());
+        assert!(agent.run("Hi").await.unwrap_err().is_retryable());
+        assert_eq!(backend.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_sampling_parameters_are_sent() {
+        let backend = Arc::new(MockChatBackend::new().respond_with_text("Hi!"));
+        let tuned = agent(
+            backend.clone(),
+            Agent::builder()
+                .temperature(0.2)
+                .top_p(0.9)
+                .max_tokens(256)
+                .frequency_penalty(0.5)
+                .presence_penalty(-0.5),
+        );
+        tuned.run("Hello").await.unwrap();
+
+        let request = serde_json::to_value(&backend.requests()[0]).unwrap();
+        assert_eq!(request["temperature"], json!(0.2f32));
+        assert_eq!(request["top_p"], json!(0.9f32));
+        assert_eq!(request["max_tokens"], 256);
+        assert_eq!(request["frequency_penalty"], 0.5);
+        assert_eq!(request["presence_penalty"], -0.5);
+
+        let backend = Arc::new(MockChatBackend::new().respond_with_text("Hi!"));
+        agent(backend.clone(), Agent::builder())
+            .run("Hello")
+            .await
+            .unwrap();
+        let request = serde_json::to_value(&backend.requests()[0]).unwrap();
+        assert!(request.get("temperature").is_none());
+    }
+
+    #[test]
+    fn test_sampling_parameters_are_validated() {
+        let invalid = [
+            Agent::builder().temperature(2.5),
+            Agent::builder().top_p(-0.1),
+            Agent::builder().max_tokens(0),
+            Agent::builder().frequency_penalty(3.0),
+            Agent::builder().presence_penalty(f32::NAN),
+        ];
+        for builder in invalid {
+            let result = builder.model("mock-model").build();
+            assert!(matches!(result, Err(Error::InvalidConfiguration(_))));
+        }
+        assert!(Agent::builder()
+            .model("mock-model")
+            .temperature(0.0)
+            .top_p(1.0)
+            .build()
+            .is_ok());
+    }
+
+    static REPORT_ATTACHMENTS: std::sync::OnceLock<Attachments> = std::sync::OnceLock::new();
+
+    fn report_attachments() -> &'static Attachments {
+        REPORT_ATTACHMENTS.get_or_init(|| {
+            Attachments::new(Arc::new(crate::attachment::MemoryAttachmentStore::new()))
+                .preview_chars(9)
+                .collect_garbage(true)
+        })
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct ReportArgs {}
+
+    #[tool("Export the quarterly report")]
+    async fn export_report(_args: ReportArgs) -> Result<String> {
+        let attachments = report_attachments();
+        attachments.register("scratch data").await?;
+        let id = attachments
+            .register("Quarterly report: revenue grew 12%.")
+            .await?;
+        Ok(id.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_attachments_are_previewed_read_and_collected() {
+        let id =
+            crate::attachment::AttachmentRef::for_content(b"Quarterly report: revenue grew 12%.");
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("export_report", json!({}))
+                .respond_with_tool_call(
+                    "read_attachment",
+                    json!({ "id": id.as_str(), "offset": 18 }),
+                )
+                .respond_with_text("Revenue grew 12%."),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(tools![ExportReportTool])
+            .attachments(report_attachments().clone())
+            .build()
+            .unwrap();
+
+        let answer = agent.run("How did the quarter go?").await.unwrap();
+        assert_eq!(answer, "Revenue grew 12%.");
+
+        let requests = serde_json::to_value(backend.requests()).unwrap();
+        let names: Vec<_> = requests[0]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["function"]["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["export_report", "read_attachment"]);
+
+        let preview: Value =
+            serde_json::from_str(requests[1]["messages"][2]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(preview["attachment_id"], id.as_str());
+        assert_eq!(preview["preview"], "Quarterly…");
+
+        let range: Value =
+            serde_json::from_str(requests[2]["messages"][4]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(range["content"], "revenue grew 12%.");
+
+        // The scratch attachment was never referenced, so it was collected.
+        let ids = report_attachments().store().ids().await.unwrap();
+        assert_eq!(ids, vec![id]);
+    }
+
+    /// Records every hook call as a line of text.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        events: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl Recorder {
+        fn record(&self, event: String) -> BoxFuture<'_, HookAction> {
+            self.events.lock().unwrap().push(event);
+            Box::pin(async { HookAction::Continue })
+        }
+
+        fn events(&self) -> Vec<String> {
+            self.events.lock().unwrap().clone()
+        }
+    }
+
+    impl AgentHook for Recorder {
+        fn on_run_start<'a>(&'a self, agent: Option<&'a str>) -> BoxFuture<'a, HookAction> {
+            self.record(format!("run of {}", agent.unwrap_or("an unnamed agent")))
+        }
+
+        fn on_iteration(&self, iteration: usize) -> BoxFuture<'_, HookAction> {
+            self.record(format!("iteration {}", iteration))
+        }
+
+        fn on_request<'a>(&'a self, conversation: &'a Conversation) -> BoxFuture<'a, HookAction> {
+            self.record(format!("request with {} messages", conversation.len()))
+        }
+
+        fn on_response<'a>(
+            &'a self,
+            message: &'a async_openai::types::ChatCompletionResponseMessage,
+            usage: &'a Usage,
+        ) -> BoxFuture<'a, HookAction> {
+            let calls = message.tool_calls.as_ref().map_or(0, Vec::len);
+            self.record(format!(
+                "response with {} tool calls, {} tokens",
+                calls, usage.total_tokens
+            ))
+        }
+
+        fn on_tool_start<'a>(
+            &'a self,
+            name: &'a str,
+            args: &'a Value,
+        ) -> BoxFuture<'a, HookAction> {
+            self.record(format!("start {} {}", name, args))
+        }
+
+        fn on_tool_end<'a>(
+            &'a self,
+            name: &'a str,
+            result: &'a std::result::Result<String, ToolError>,
+            _duration: Duration,
+        ) -> BoxFuture<'a, HookAction> {
+            let size = result.as_ref().map_or(0, String::len);
+            self.record(format!("end {} with {} bytes", name, size))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hooks_observe_requests_and_tool_calls() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("There are two flights."),
+        );
+        let first = Recorder::default();
+        let second = Recorder::default();
+        let agent = agent(
+            backend,
+            Agent::builder().hook(first.clone()).hook(second.clone()),
+        );
+
+        agent.run("Find flights to Lisbon").await.unwrap();
+        let expected = [
+            "run of an unnamed agent",
+            "iteration 1",
+            "request with 1 messages",
+            "response with 1 tool calls, 15 tokens",
+            "start search_flights {\"to\":\"LIS\"}",
+            "end search_flights with 49 bytes",
+            "iteration 2",
+            "request with 3 messages",
+            "response with 0 tool calls, 15 tokens",
+        ];
+        assert_eq!(first.events(), expected);
+        assert_eq!(second.events(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_named_agents_sign_messages_and_errors() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("There are two flights.")
+                .respond_with_tool_call("search_flights", json!({ "to": "OPO" }))
+                .respond_with_tool_call("search_flights", json!({ "to": "FAO" })),
+        );
+        let recorder = Recorder::default();
+        let agent = agent(
+            backend,
+            Agent::builder()
+                .name("travel desk")
+                .max_iterations(2)
+                .hook(recorder.clone()),
+        );
+        assert_eq!(agent.name(), Some("travel desk"));
+
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Find flights to Lisbon");
+        agent.run_conversation(&mut conversation).await.unwrap();
+        assert_eq!(recorder.events()[0], "run of travel desk");
+        let messages = serde_json::to_value(conversation.messages()).unwrap();
+        assert_eq!(messages[0].get("name"), None);
+        assert_eq!(messages[1]["name"], "travel_desk");
+        assert_eq!(messages[3]["name"], "travel_desk");
+
+        let err = agent.run("And to Porto?").await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::MaxIterationsExceeded { max: 2, agent: Some(ref name) } if name == "travel desk"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Agent 'travel desk' exceeded maximum iterations: 2"
+        );
+    }
+
+    struct Panicking;
+
+    impl AgentHook for Panicking {
+        fn on_tool_start<'a>(
+            &'a self,
+            _name: &'a str,
+            _args: &'a Value,
+        ) -> BoxFuture<'a, HookAction> {
+            Box::pin(async { panic!("audit log unavailable") })
+        }
+    }
+
+    struct Blocker;
+
+    impl AgentHook for Blocker {
+        fn on_tool_start<'a>(
+            &'a self,
+            name: &'a str,
+            _args: &'a Value,
+        ) -> BoxFuture<'a, HookAction> {
+            Box::pin(async move {
+                HookAction::Abort {
+                    reason: format!("{} is not allowed", name),
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hook_panics_warn_and_aborts_stop_the_run() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("There are two flights."),
+        );
+        let warnings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = warnings.clone();
+        let recorder = Recorder::default();
+        let observed = agent(
+            backend,
+            Agent::builder()
+                .hook(Panicking)
+                .hook(recorder.clone())
+                .on_warning(move |w| sink.lock().unwrap().push(w.clone())),
+        );
+        let answer = observed.run("Find flights to Lisbon").await.unwrap();
+        assert_eq!(answer, "There are two flights.");
+        assert_eq!(
+            *warnings.lock().unwrap(),
+            [Warning::HookPanicked {
+                event: "on_tool_start".into(),
+                message: "audit log unavailable".into(),
+            }]
+        );
+        // Later hooks still run.
+        assert!(recorder
+            .events()
+            .contains(&"end search_flights with 49 bytes".to_string()));
+
+        let backend = Arc::new(
+            MockChatBackend::new().respond_with_tool_call("search_flights", json!({ "to": "LIS" })),
+        );
+        let blocked = agent(backend.clone(), Agent::builder().hook(Blocker));
+        let error = blocked.run("Find flights to Lisbon").await.unwrap_err();
+        assert!(matches!(
+            error,
+            Error::HookAborted { ref event, ref reason }
+                if event == "on_tool_start" && reason == "search_flights is not allowed"
+        ));
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_step_wise_run() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_calls(&[
+                    ("search_flights", json!({ "to": "LIS" })),
+                    ("search_flights", json!({ "to": "OPO" })),
+                ])
+                .respond_with_text("Porto is cheaper."),
+        );
+        let agent = agent(backend.clone(), Agent::builder());
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Lisbon or Porto?");
+        let mut run = agent.start(&mut conversation);
+
+        let AgentStep::ToolCalls(calls) = run.step().await.unwrap() else {
+            panic!("expected tool calls");
+        };
+        assert_eq!(
+            calls,
+            [
+                PendingToolCall {
+                    id: "call_0_0".into(),
+                    name: "search_flights".into(),
+                    arguments: json!({ "to": "LIS" }),
+                },
+                PendingToolCall {
+                    id: "call_0_1".into(),
+                    name: "search_flights".into(),
+                    arguments: json!({ "to": "OPO" }),
+                },
+            ]
+        );
+
+        run.provide_tool_result("call_0_1", "No flights to Porto today")
+            .unwrap();
+        assert!(run.provide_tool_result("call_0_1", "again").is_err());
+        assert!(run.provide_tool_result("call_9_9", "unknown").is_err());
+        assert_eq!(run.pending_tool_calls(), calls[..1]);
+        // Results are added in the order of the calls.
+        assert_eq!(run.conversation().len(), 2);
+
+        // Stepping executes the calls that are still pending.
+        let step = run.step().await.unwrap();
+        assert_eq!(step, AgentStep::Final("Porto is cheaper.".into()));
+        assert!(matches!(
+            run.step().await,
+            Err(Error::InvalidConfiguration(_))
+        ));
+
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        assert_eq!(messages[2]["tool_call_id"], "call_0_0");
+        assert_eq!(messages[2]["content"].as_str().unwrap().len(), 49);
+        assert_eq!(messages[3]["tool_call_id"], "call_0_1");
+        assert_eq!(messages[3]["content"], "No flights to Porto today");
+    }
+
+    #[tokio::test]
+    async fn test_requests_follow_edits_in_the_middle_of_the_history() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("There are two flights."),
+        );
+        let agent = agent(backend.clone(), Agent::builder());
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Find me a flight to Paris");
+        conversation.add_assistant_message("Here are three flights to Paris");
+        conversation.add_user_message("And to Lisbon?");
+
+        let mut state = LoopState::new(LoopMode::Text);
+        let mut ctx = RunContext::default();
+        let progress = agent
+            .advance(&mut state, &mut conversation, &mut ctx)
+            .await
+            .unwrap();
+        assert!(matches!(progress, Progress::ToolCalls(_)));
+
+        // Same length and last message, different middle.
+        let mut edited = Conversation::new();
+        edited.add_assistant_message("There are no flights to Paris");
+        conversation.messages_mut()[1] = edited.messages()[0].clone();
+        agent
+            .advance(&mut state, &mut conversation, &mut ctx)
+            .await
+            .unwrap();
+
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[1].messages,
+            conversation.messages()[..conversation.len() - 1]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_step_wise_run_executes_tools() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("There are two flights."),
+        );
+        let agent = agent(backend.clone(), Agent::builder());
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Find flights to Lisbon");
+        let mut run = agent.start(&mut conversation);
+
+        assert!(matches!(
+            run.step().await.unwrap(),
+            AgentStep::ToolCalls(ref calls) if calls.len() == 1
+        ));
+        run.execute_tools().await.unwrap();
+        assert!(run.pending_tool_calls().is_empty());
+        assert_eq!(run.conversation().len(), 3);
+        assert_eq!(
+            run.step().await.unwrap(),
+            AgentStep::Final("There are two flights.".into())
+        );
+        assert_eq!(backend.requests().len(), 2);
+        assert_eq!(conversation.len(), 4);
+    }
+
+    #[tool("Book the cheapest flight")]
+    async fn book_flight(args: SearchArgs) -> Result<String> {
+        Ok(format!("booked a flight to {}", args.to))
+    }
+
+    #[tokio::test]
+    async fn test_denied_tool_call_is_reported_to_the_model() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("book_flight", json!({ "to": "LIS" }))
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("There are two flights; tell me which one to book."),
+        );
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = asked.clone();
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(tools![SearchFlightsTool, BookFlightTool])
+            .require_approval(&["book_flight"], move |name, args| {
+                log.lock().unwrap().push(format!("{} {}", name, args));
+                Box::pin(async {
+                    ApprovalDecision::Deny {
+                        reason: "Ask before booking".into(),
+                    }
+                })
+            })
+            .build()
+            .unwrap();
+
+        let answer = agent.run("Book me a flight to Lisbon").await.unwrap();
+        assert_eq!(answer, "There are two flights; tell me which one to book.");
+        // Only the gated tool needs approval.
+        assert_eq!(*asked.lock().unwrap(), ["book_flight {\"to\":\"LIS\"}"]);
+
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        let denial: Value = serde_json::from_str(messages[2]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(denial["error"]["code"], ToolError::PERMISSION_DENIED);
+        assert_eq!(
+            denial["error"]["message"],
+            "The call to 'book_flight' was not approved: Ask before booking"
+        );
+        let messages = serde_json::to_value(&backend.requests()[2].messages).unwrap();
+        assert_eq!(messages[4]["content"].as_str().unwrap().len(), 49);
+    }
+
+    #[tokio::test]
+    async fn test_approver_can_modify_arguments() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("book_flight", json!({ "to": "LIS" }))
+                .respond_with_text("Booked."),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(tools![BookFlightTool])
+            .require_approval(&["book_flight"], |_, _| {
+                Box::pin(async { ApprovalDecision::Modify(json!({ "to": "OPO" })) })
+            })
+            .build()
+            .unwrap();
+
+        agent.run("Book me a flight to Lisbon").await.unwrap();
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        assert_eq!(messages[2]["content"], "booked a flight to OPO");
+
+        let unknown = Agent::builder()
+            .model("mock-model")
+            .tools(tools![BookFlightTool])
+            .require_approval(&["send_email"], |_, _| {
+                Box::pin(async { ApprovalDecision::Approve })
+            })
+            .build();
+        assert!(matches!(unknown, Err(Error::InvalidConfiguration(_))));
+    }
+
+    #[tokio::test]
+    async fn test_extra_body_is_merged_into_requests() {
+        use crate::openrouter::{OpenRouterOptions, ProviderRouting};
+
+        let fields = |value: serde_json::Value| value.as_object().unwrap().clone();
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text("Hi")
+                .respond_with_text("Hi again"),
+        );
+        let chat = agent(
+            backend.clone(),
+            Agent::builder().extra_body(fields(json!({
+                "provider": { "order": ["together"] },
+                "transforms": ["middle-out"],
+            }))),
+        );
+        chat.run("Hello").await.unwrap();
+        let body = &backend.bodies()[0];
+        assert_eq!(body["model"], "mock-model");
+        assert_eq!(body["provider"], json!({ "order": ["together"] }));
+        assert_eq!(body["transforms"], json!(["middle-out"]));
+
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Hello");
+        let options = RunOptions::new().extra_body(fields(json!({ "top_k": 40 })));
+        chat.run_conversation_with_options(&mut conversation, options)
+            .await
+            .unwrap();
+        let body = &backend.bodies()[1];
+        assert_eq!(body["top_k"], 40);
+        assert_eq!(body["transforms"], json!(["middle-out"]));
+
+        let routed = Agent::builder()
+            .model("meta-llama/llama-3.1-70b-instruct:nitro")
+            .openrouter(
+                OpenRouterOptions::new()
+                    .api_key("sk-or-test")
+                    .routing(ProviderRouting::new().allow_fallbacks(false)),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(routed.provider, Provider::OpenRouter);
+        assert_eq!(
+            routed.extra_body["provider"],
+            json!({ "allow_fallbacks": false })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extra_body_conflicts_are_rejected() {
+        let fields = json!({ "temperature": 1.5, "min_p": 0.05 });
+        let fields = fields.as_object().unwrap().clone();
+        let backend = Arc::new(MockChatBackend::new().respond_with_text("Hi"));
+        let strict = agent(
+            backend.clone(),
+            Agent::builder().temperature(0.2).extra_body(fields.clone()),
+        );
+        let error = strict.run("Hello").await.unwrap_err();
+        assert!(
+            matches!(error, Error::InvalidConfiguration(ref message) if message.ends_with(": temperature"))
+        );
+        assert!(backend.requests().is_empty());
+
+        let lenient = agent(
+            backend.clone(),
+            Agent::builder()
+                .temperature(0.2)
+                .extra_body(fields)
+                .allow_extra_body_overrides(true),
+        );
+        lenient.run("Hello").await.unwrap();
+        let body = &backend.bodies()[0];
+        assert_eq!(body["temperature"], 1.5);
+        assert_eq!(body["min_p"], 0.05);
+    }
+
+    #[tokio::test]
+    async fn test_autosave_after_each_turn() {
+        use crate::store::{ConversationStore, MemoryStore};
+
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text("Where to?")
+                .respond_with_error(api_error("400")),
+        );
+        let store = Arc::new(MemoryStore::new());
+        let chat = agent(backend, Agent::builder().autosave(store.clone(), "trip"));
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Find me a flight");
+        chat.run_conversation(&mut conversation).await.unwrap();
+
+        let saved = store.load("trip").await.unwrap().unwrap();
+        assert_eq!(saved.messages(), conversation.messages());
+
+        // A failed turn leaves the last saved version.
+        conversation.add_user_message("To Lisbon");
+        assert!(chat.run_conversation(&mut conversation).await.is_err());
+        assert_eq!(store.load("trip").await.unwrap().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_autosave_from_every_entry_point() {
+        use crate::store::{ConversationStore, MemoryStore};
+
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text("Cancellable")
+                .respond_with_text("With options")
+                .respond_with_text("Outcome")
+                .respond_with_text("Streamed")
+                .respond_with_text("Step-wise"),
+        );
+        let store = Arc::new(MemoryStore::new());
+        let chat = agent(backend, Agent::builder().autosave(store.clone(), "trip"));
+        let saved = || async { store.load("trip").await.unwrap().unwrap() };
+        let new_conversation = || {
+            let mut conversation = Conversation::new();
+            conversation.add_user_message("Find me a flight");
+            conversation
+        };
+
+        let mut conversation = new_conversation();
+        chat.run_conversation_cancellable(&mut conversation, CancelHandle::new())
+            .await
+            .unwrap();
+        assert_eq!(saved().await.last_assistant_text(), Some("Cancellable"));
+
+        let mut conversation = new_conversation();
+        chat.run_conversation_with_options(&mut conversation, RunOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(saved().await.last_assistant_text(), Some("With options"));
+
+        let mut conversation = new_conversation();
+        chat.run_conversation_outcome(&mut conversation)
+            .await
+            .unwrap();
+        assert_eq!(saved().await.last_assistant_text(), Some("Outcome"));
+
+        let mut conversation = new_conversation();
+        let events: Vec<_> = chat
+            .run_conversation_stream(&mut conversation)
+            .collect()
+            .await;
+        assert!(events.iter().all(Result::is_ok));
+        assert_eq!(saved().await.last_assistant_text(), Some("Streamed"));
+
+        let mut conversation = new_conversation();
+        let mut run = chat.start(&mut conversation);
+        run.step().await.unwrap();
+        assert_eq!(saved().await.messages(), conversation.messages());
+        assert_eq!(conversation.last_assistant_text(), Some("Step-wise"));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_accumulate_across_runs() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("Flight TP1234 is the cheapest")
+                .respond_with_tool_call("book_hotel", json!({})),
+        );
+        let flights = agent(backend, Agent::builder());
+        assert_eq!(flights.metrics(), MetricsSnapshot::default());
+
+        flights.run("Find flights to Lisbon").await.unwrap();
+        assert!(flights.run("Book a hotel").await.is_err());
+
+        let metrics = flights.metrics();
+        assert_eq!(metrics.requests, 3);
+        assert_eq!((metrics.prompt_tokens, metrics.completion_tokens), (30, 15));
+        assert_eq!(metrics.total_tokens(), 45);
+        assert_eq!(
+            metrics.tool_calls,
+            [
+                ("book_hotel".to_string(), 1),
+                ("search_flights".to_string(), 1)
+            ]
+            .into()
+        );
+        assert_eq!(metrics.errors, [("tool_not_found".to_string(), 1)].into());
+        assert_eq!(metrics.request_latency.count, 3);
+        assert!(metrics.request_latency.min <= metrics.request_latency.mean);
+        assert!(metrics.request_latency.mean <= metrics.request_latency.max);
+        assert_eq!(metrics.tool_latency.count, 2);
+        let serialized = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(serialized["tool_calls"]["search_flights"], 1);
+
+        // Derived agents count on their own.
+        assert_eq!(
+            flights.with_overrides().build().unwrap().metrics(),
+            MetricsSnapshot::default()
+        );
+        flights.reset_metrics();
+        assert_eq!(flights.metrics(), MetricsSnapshot::default());
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn test_tracing_spans() {
+        use std::sync::Mutex;
+        use tracing_subscriber::fmt::format::FmtSpan;
+
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Buffer {
+            fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(bytes)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_span_events(FmtSpan::CLOSE)
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_tool_call("book_hotel", json!({})),
+        );
+        let flights = agent(backend, Agent::builder().name("flights"));
+        let result = flights.run("Find flights to Lisbon").await;
+        assert!(matches!(result, Err(Error::ToolNotFound(_))));
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        // The innermost span of each span's closing line.
+        let closed = |name: &str| {
+            output
+                .lines()
+                .filter_map(|line| line.split_once(": aiform::trace: close"))
+                .map(|(spans, _)| spans.rsplit("}:").next().unwrap().to_string())
+                .filter(|span| span.contains(&format!("{}{{", name)))
+                .collect::<Vec<_>>()
+        };
+        let runs = closed("agent.run");
+        assert_eq!(runs.len(), 1, "{}", output);
+        assert!(runs[0].contains(r#"agent.name="flights" agent.model="mock-model""#));
+        assert!(runs[0].contains("iterations=2"));
+        assert_eq!(closed("agent.iteration").len(), 2, "{}", output);
+        assert!(closed("agent.iteration")[0].contains("iteration=1"));
+
+        let tools = closed("agent.tool");
+        assert_eq!(tools.len(), 2, "{}", output);
+        assert!(tools[0].contains(r#"tool.name="search_flights" tool.argument_bytes=12"#));
+        assert!(tools[0].contains("tool.result_bytes="));
+        assert!(tools[0].contains("tool.latency_ms="));
+        assert!(output.contains(r#"unknown tool tool.name="book_hotel""#));
+        assert!(output.contains("ERROR"));
+    }
+
+    #[tokio::test]
+    async fn test_final_response_is_appended() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("search_flights", json!({ "to": "LIS" }))
+                .respond_with_text("There are two flights.")
+                .respond_with_text("The cheapest is 129.50."),
+        );
+        let chat = agent(backend, Agent::builder());
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Find flights to Lisbon");
+        chat.run_conversation(&mut conversation).await.unwrap();
+        conversation.add_user_message("Which is cheapest?");
+        chat.run_conversation(&mut conversation).await.unwrap();
+
+        let messages = serde_json::to_value(conversation.messages()).unwrap();
+        let roles: Vec<_> = messages
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            roles,
+            [
+                "user",
+                "assistant",
+                "tool",
+                "assistant",
+                "user",
+                "assistant"
+            ]
+        );
+        assert_eq!(messages[3]["content"], "There are two flights.");
+        assert_eq!(messages[5]["content"], "The cheapest is 129.50.");
+
+        let backend = Arc::new(MockChatBackend::new().respond_with_text("Hello."));
+        let opted_out = agent(backend, Agent::builder().append_final_response(false));
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Hi");
+        opted_out.run_conversation(&mut conversation).await.unwrap();
+        assert_eq!(conversation.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_conversation_sets_the_system_prompt() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text("Hi.")
+                .respond_with_text("Hi.")
+                .respond_with_text("Hi."),
+        );
+        let flights = agent(
+            backend.clone(),
+            Agent::builder().system_prompt("You book flights"),
+        );
+        for mut conversation in [
+            Conversation::new(),
+            Conversation::with_system("You book trains"),
+            Conversation::with_system("You book flights"),
+        ] {
+            conversation.add_user_message("Hi");
+            flights.run_conversation(&mut conversation).await.unwrap();
+            assert_eq!(conversation.system_prompt(), Some("You book flights"));
+            assert_eq!(
+                conversation
+                    .iter_role(crate::conversation::Role::System)
+                    .count(),
+                1
+            );
+            assert_eq!(conversation.len(), 3);
+        }
+        for request in backend.requests() {
+            let messages = serde_json::to_value(&request.messages).unwrap();
+            assert_eq!(messages[0]["content"], "You book flights");
+            assert_eq!(messages[1]["role"], "user");
+        }
+
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text("Hi.")
+                .respond_with_text("Hi."),
+        );
+        let managed = agent(
+            backend,
+            Agent::builder()
+                .system_prompt("You book flights")
+                .inherit_system_prompt(false),
+        );
+        let mut conversation = Conversation::with_system("You book trains");
+        conversation.add_user_message("Hi");
+        managed.run_conversation(&mut conversation).await.unwrap();
+        assert_eq!(conversation.system_prompt(), Some("You book trains"));
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("Hi");
+        managed.run_conversation(&mut conversation).await.unwrap();
+        assert!(conversation.system_prompt().is_none());
     }
 }
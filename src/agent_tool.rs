@@ -1,9 +1,13 @@
 //! Utilities for using agents as tools.
 
-use crate::{Agent, Tool, ToolArg};
+use crate::{
+    agent::{function_tool, participant_name},
+    error::{Error, Result},
+    Agent, ErasedTool, ToolArg,
+};
+use async_openai::types::ChatCompletionTool;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
 /// Arguments for calling an agent as a tool.
 #[derive(Debug, Clone, Serialize, Deserialize, ToolArg)]
@@ -18,13 +22,18 @@ pub struct AgentCallArgs {
 /// to another specialized agent. The calling agent only sees the final
 /// response, not the intermediate tool calls or reasoning.
 ///
+/// The model sees the tool under the name and description given to
+/// [`AgentTool::new`], or under the agent's own name with
+/// [`AgentTool::from_agent`], so one tool set can hold several agents. Calls share
+/// the agent without locking, so parallel tool calls to the same agent run
+/// concurrently.
+///
 /// # Example
 ///
-/// ```ignore
+/// ```no_run
 /// use aiform::prelude::*;
 /// use aiform::agent_tool::AgentTool;
 /// use std::sync::Arc;
-/// use tokio::sync::Mutex;
 ///
 /// # async fn example() -> Result<()> {
 /// // Create a specialized analyst agent
@@ -34,22 +43,24 @@ pub struct AgentCallArgs {
 ///     .build()?;
 ///
 /// let analyst_tool = AgentTool::new(
-///     "analyst",
+///     "ask_analyst",
 ///     "Ask the analyst agent to analyze data",
-///     Arc::new(Mutex::new(analyst)),
+///     Arc::new(analyst),
 /// );
 ///
-/// // In the future, you'll be able to use agents as tools directly
-/// // For now, you can call the analyst manually
-/// let response = analyst_tool.call_agent("Analyze this data").await?;
+/// // The researcher can now delegate to the analyst
+/// let researcher = Agent::builder()
+///     .model("gpt-4")
+///     .tools(tools![analyst_tool])
+///     .build()?;
+/// let response = researcher.run("Analyze this data: ...").await?;
 /// # Ok(())
 /// # }
 /// ```
-#[allow(dead_code)]
 pub struct AgentTool {
     name: String,
     description: String,
-    agent: Arc<Mutex<Agent>>,
+    agent: Arc<Agent>,
 }
 
 impl AgentTool {
@@ -60,42 +71,199 @@ impl AgentTool {
     /// * `name` - The name of the tool (how it appears to the calling agent)
     /// * `description` - Description of what the agent does
     /// * `agent` - The agent to wrap
-    pub fn new(
-        name: impl Into<String>,
-        description: impl Into<String>,
-        agent: Arc<Mutex<Agent>>,
-    ) -> Self {
+    pub fn new(name: impl Into<String>, description: impl Into<String>, agent: Arc<Agent>) -> Self {
         Self {
             name: name.into(),
             description: description.into(),
             agent,
         }
     }
-}
 
-impl Tool for AgentTool {
-    const NAME: &'static str = "agent_call";
-    const DESCRIPTION: &'static str = "Call another agent";
+    /// Creates a tool named after the agent's [name](crate::AgentBuilder::name),
+    /// with characters tool names cannot contain replaced by `_`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfiguration`] if the agent has no name.
+    pub fn from_agent(description: impl Into<String>, agent: Arc<Agent>) -> Result<Self> {
+        let name = agent.name().map(participant_name).ok_or_else(|| {
+            Error::InvalidConfiguration(
+                "AgentTool::from_agent needs a named agent; name it with \
+                 AgentBuilder::name or use AgentTool::new"
+                    .into(),
+            )
+        })?;
+        Ok(Self::new(name, description, agent))
+    }
+
+    /// Returns the tool definition the calling agent's model sees, named
+    /// and described as given to [`AgentTool::new`].
+    pub fn definition(&self) -> ChatCompletionTool {
+        function_tool(&self.name, &self.description, AgentCallArgs::schema())
+    }
+
+    /// Sends the message in `args`, an [`AgentCallArgs`] object, to the
+    /// wrapped agent and returns its answer.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Json`](crate::Error::Json) if `args` has no message,
+    /// or any error the agent's run fails with.
+    pub async fn call(&self, args: serde_json::Value) -> Result<String> {
+        let args: AgentCallArgs = serde_json::from_value(args)?;
+        self.agent.call_as_tool(args.message).await
+    }
+}
 
-    fn name() -> &'static str {
-        Self::NAME
+impl ErasedTool for AgentTool {
+    fn name(&self) -> &str {
+        &self.name
     }
 
-    fn description() -> &'static str {
-        Self::DESCRIPTION
+    fn description(&self) -> &str {
+        &self.description
     }
 
-    fn parameters() -> serde_json::Value {
+    fn parameters(&self) -> serde_json::Value {
         AgentCallArgs::schema()
     }
 
-    async fn call(
+    fn call(
         &self,
         args: serde_json::Value,
-    ) -> std::result::Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let args: AgentCallArgs = serde_json::from_value(args)?;
-        let agent = self.agent.lock().await;
-        let response = agent.call_as_tool(args.message).await?;
-        Ok(response)
+    ) -> futures::future::BoxFuture<
+        '_,
+        std::result::Result<String, Box<dyn std::error::Error + Send + Sync>>,
+    > {
+        Box::pin(async move { Ok(AgentTool::call(self, args).await?) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockChatBackend;
+    use crate::tools;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn agent_tool(name: &str, answer: &str) -> AgentTool {
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(Arc::new(MockChatBackend::new().respond_with_text(answer)))
+            .build()
+            .unwrap();
+        AgentTool::new(name, format!("Ask the {}", name), Arc::new(agent))
+    }
+
+    #[tokio::test]
+    async fn test_agent_tools_are_named_by_instance() {
+        let lawyer = agent_tool("lawyer", "It is legal.");
+        let definition = serde_json::to_value(lawyer.definition()).unwrap();
+        assert_eq!(definition["function"]["name"], "lawyer");
+        assert_eq!(definition["function"]["description"], "Ask the lawyer");
+        assert_eq!(
+            definition["function"]["parameters"],
+            AgentCallArgs::schema()
+        );
+
+        let tools = tools![lawyer, agent_tool("accountant", "It is deductible.")];
+        tools.validate().unwrap();
+        let names: Vec<_> = tools.tools().iter().map(|t| &t.function.name).collect();
+        assert_eq!(names, ["lawyer", "accountant"]);
+        let args = json!({ "message": "Can I expense my boat?" });
+        assert_eq!(
+            tools.dispatch("lawyer".into(), args.clone()).await.unwrap(),
+            "It is legal."
+        );
+        assert_eq!(
+            tools.dispatch("accountant".into(), args).await.unwrap(),
+            "It is deductible."
+        );
+    }
+
+    #[test]
+    fn test_agent_tools_default_to_the_agent_name() {
+        let agent = |name: Option<&str>| {
+            let mut builder = Agent::builder()
+                .model("mock-model")
+                .backend(Arc::new(MockChatBackend::new()));
+            if let Some(name) = name {
+                builder = builder.name(name);
+            }
+            Arc::new(builder.build().unwrap())
+        };
+        let tool = AgentTool::from_agent("Ask about trips", agent(Some("travel agent"))).unwrap();
+        assert_eq!(tool.definition().function.name, "travel_agent");
+        assert!(matches!(
+            AgentTool::from_agent("Ask about trips", agent(None)),
+            Err(Error::InvalidConfiguration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_researcher_delegates_to_analyst() {
+        let analyst_backend =
+            Arc::new(MockChatBackend::new().respond_with_text("The trend is strongly positive."));
+        let analyst = Agent::builder()
+            .model("analyst-model")
+            .backend(analyst_backend.clone())
+            .build()
+            .unwrap();
+        let analyst_tool = AgentTool::new(
+            "ask_analyst",
+            "Ask the analyst to analyze data",
+            Arc::new(analyst),
+        );
+
+        let researcher_backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("ask_analyst", json!({ "message": "Adoption grew 23%" }))
+                .respond_with_text("The analyst sees a strongly positive trend."),
+        );
+        let researcher = Agent::builder()
+            .model("researcher-model")
+            .backend(researcher_backend.clone())
+            .tools(tools![analyst_tool])
+            .build()
+            .unwrap();
+        let answer = researcher.run("Analyze Rust adoption").await.unwrap();
+        assert_eq!(answer, "The analyst sees a strongly positive trend.");
+
+        let definitions = serde_json::to_value(&researcher_backend.requests()[0].tools).unwrap();
+        assert_eq!(definitions[0]["function"]["name"], "ask_analyst");
+        assert_eq!(
+            definitions[0]["function"]["description"],
+            "Ask the analyst to analyze data"
+        );
+        let asked = serde_json::to_value(&analyst_backend.requests()[0].messages).unwrap();
+        assert_eq!(asked[0]["content"], "Adoption grew 23%");
+        let answered = serde_json::to_value(&researcher_backend.requests()[1].messages).unwrap();
+        assert_eq!(answered[2]["content"], "The trend is strongly positive.");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_calls_run_in_parallel() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text("First.")
+                .respond_with_text("Second.")
+                .latency(Duration::from_millis(100)),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend)
+            .build()
+            .unwrap();
+        let tool = AgentTool::new("helper", "Ask the helper", Arc::new(agent));
+
+        let started = tokio::time::Instant::now();
+        let (first, second) = tokio::join!(
+            tool.call(json!({ "message": "one" })),
+            tool.call(json!({ "message": "two" })),
+        );
+        first.unwrap();
+        second.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_millis(100));
     }
 }
@@ -0,0 +1,59 @@
+//! Human-in-the-loop approval of tool calls.
+//!
+//! Tools registered with
+//! [`AgentBuilder::require_approval`](crate::AgentBuilder::require_approval)
+//! are only called once an approver agrees. The approver sees the tool name
+//! and the parsed arguments, and can let the call through, change its
+//! arguments, or deny it. A denied call is reported to the model as a
+//! [`PERMISSION_DENIED`](crate::tool_error::ToolError::PERMISSION_DENIED)
+//! tool error carrying the reason, so the model can try something else.
+//!
+//! ```no_run
+//! use aiform::approval::ApprovalDecision;
+//! use aiform::prelude::*;
+//!
+//! # fn example(tools: ToolSet) -> Result<()> {
+//! let agent = Agent::builder()
+//!     .model("gpt-4o")
+//!     .tools(tools)
+//!     .require_approval(&["delete_file", "send_email"], |name, args| {
+//!         println!("Allow {} with {}? [y/N]", name, args);
+//!         let mut answer = String::new();
+//!         let _ = std::io::stdin().read_line(&mut answer);
+//!         Box::pin(async move {
+//!             if answer.trim() == "y" {
+//!                 ApprovalDecision::Approve
+//!             } else {
+//!                 ApprovalDecision::Deny {
+//!                     reason: "The user declined".into(),
+//!                 }
+//!             }
+//!         })
+//!     })
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// What an approver decided about a tool call.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ApprovalDecision {
+    /// Call the tool as the model asked.
+    Approve,
+    /// Do not call the tool; the model is told why.
+    Deny {
+        /// Why the call was denied, shown to the model.
+        reason: String,
+    },
+    /// Call the tool with these arguments instead.
+    Modify(Value),
+}
+
+/// Decides whether a tool call may run, given the tool name and its
+/// arguments.
+pub type Approver = Arc<dyn Fn(&str, &Value) -> BoxFuture<'static, ApprovalDecision> + Send + Sync>;
@@ -0,0 +1,698 @@
+//! Content-addressed storage for payloads too large for the conversation.
+//!
+//! Large documents and files are kept in an [`AttachmentStore`] and referred
+//! to by an [`AttachmentRef`] id derived from their content. Tools accept and
+//! return ids instead of the payload itself:
+//!
+//! - Use `AttachmentRef` as a tool argument type to receive an id.
+//! - Return an id (on its own, or as a string anywhere in a JSON result) to
+//!   hand a payload back. When the agent is configured with
+//!   [`AgentBuilder::attachments`](crate::AgentBuilder::attachments), the id
+//!   is expanded into a short preview before the result enters the
+//!   conversation, and the model can fetch specific ranges with the built-in
+//!   `read_attachment` tool.
+//!
+//! ```no_run
+//! use aiform::attachment::{Attachments, MemoryAttachmentStore};
+//! use aiform::prelude::*;
+//! use std::sync::Arc;
+//!
+//! # async fn example() -> Result<()> {
+//! let attachments = Attachments::new(Arc::new(MemoryAttachmentStore::new()));
+//! # let report_text = String::new();
+//! let report = attachments.register(report_text).await?;
+//!
+//! let agent = Agent::builder()
+//!     .model("gpt-4o")
+//!     .attachments(attachments.clone())
+//!     .build()?;
+//! let answer = agent
+//!     .run(format!("Summarize attachment {}", report))
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    error::{Error, Result},
+    store::StoreFuture,
+    tool_error::ToolError,
+    ToolArg,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// Name of the built-in tool that reads attachment ranges.
+pub const READ_ATTACHMENT_TOOL: &str = "read_attachment";
+
+const ID_PREFIX: &str = "att_";
+const ID_HEX_LEN: usize = 24;
+
+/// The id of an attachment, derived from a hash of its content.
+///
+/// Serializes as a plain string such as `"att_3f9a0c..."`. As a tool
+/// argument, it is advertised to the model as an attachment id.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AttachmentRef(String);
+
+impl AttachmentRef {
+    /// Computes the id for the given content.
+    pub fn for_content(data: &[u8]) -> Self {
+        let digest = Sha256::digest(data);
+        let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        AttachmentRef(format!("{}{}", ID_PREFIX, &hex[..ID_HEX_LEN]))
+    }
+
+    /// Parses an id, returning `None` if it is not well-formed.
+    pub fn parse(id: &str) -> Option<Self> {
+        let hex = id.strip_prefix(ID_PREFIX)?;
+        let valid = hex.len() == ID_HEX_LEN
+            && hex
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+        valid.then(|| AttachmentRef(id.to_string()))
+    }
+
+    /// Returns the id as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for AttachmentRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<String> for AttachmentRef {
+    type Error = String;
+
+    fn try_from(id: String) -> std::result::Result<Self, Self::Error> {
+        AttachmentRef::parse(&id).ok_or_else(|| format!("invalid attachment id {:?}", id))
+    }
+}
+
+impl From<AttachmentRef> for String {
+    fn from(id: AttachmentRef) -> Self {
+        id.0
+    }
+}
+
+impl ToolArg for AttachmentRef {
+    fn schema() -> Value {
+        json!({
+            "type": "string",
+            "description": "attachment id, like 'att_3f9a0c...'",
+            "pattern": format!("^{}[0-9a-f]{{{}}}$", ID_PREFIX, ID_HEX_LEN),
+        })
+    }
+}
+
+/// Storage for attachment content.
+pub trait AttachmentStore: Send + Sync {
+    /// Stores content under its id. Storing the same content twice is a no-op.
+    fn insert<'a>(&'a self, id: &'a AttachmentRef, data: &'a [u8]) -> StoreFuture<'a, ()>;
+
+    /// Loads content, or `None` if the id is unknown.
+    fn get<'a>(&'a self, id: &'a AttachmentRef) -> StoreFuture<'a, Option<Arc<[u8]>>>;
+
+    /// Deletes content, returning whether it existed.
+    fn remove<'a>(&'a self, id: &'a AttachmentRef) -> StoreFuture<'a, bool>;
+
+    /// Lists all stored ids.
+    fn ids(&self) -> StoreFuture<'_, Vec<AttachmentRef>>;
+}
+
+/// An in-process [`AttachmentStore`].
+#[derive(Default)]
+pub struct MemoryAttachmentStore {
+    attachments: RwLock<HashMap<AttachmentRef, Arc<[u8]>>>,
+}
+
+impl MemoryAttachmentStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AttachmentStore for MemoryAttachmentStore {
+    fn insert<'a>(&'a self, id: &'a AttachmentRef, data: &'a [u8]) -> StoreFuture<'a, ()> {
+        self.attachments
+            .write()
+            .unwrap()
+            .entry(id.clone())
+            .or_insert_with(|| data.into());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn get<'a>(&'a self, id: &'a AttachmentRef) -> StoreFuture<'a, Option<Arc<[u8]>>> {
+        let data = self.attachments.read().unwrap().get(id).cloned();
+        Box::pin(async { Ok(data) })
+    }
+
+    fn remove<'a>(&'a self, id: &'a AttachmentRef) -> StoreFuture<'a, bool> {
+        let existed = self.attachments.write().unwrap().remove(id).is_some();
+        Box::pin(async move { Ok(existed) })
+    }
+
+    fn ids(&self) -> StoreFuture<'_, Vec<AttachmentRef>> {
+        let ids = self.attachments.read().unwrap().keys().cloned().collect();
+        Box::pin(async { Ok(ids) })
+    }
+}
+
+/// An [`AttachmentStore`] keeping one file per attachment in a directory.
+pub struct FileAttachmentStore {
+    dir: PathBuf,
+}
+
+impl FileAttachmentStore {
+    /// Uses `dir` for storage, creating it on first write.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, id: &AttachmentRef) -> PathBuf {
+        self.dir.join(id.as_str())
+    }
+}
+
+fn io_error(action: &str, id: &AttachmentRef, e: std::io::Error) -> Error {
+    Error::Other(format!("Failed to {} attachment {}: {}", action, id, e).into())
+}
+
+impl AttachmentStore for FileAttachmentStore {
+    fn insert<'a>(&'a self, id: &'a AttachmentRef, data: &'a [u8]) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.path(id);
+            if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+                return Ok(());
+            }
+            tokio::fs::create_dir_all(&self.dir)
+                .await
+                .map_err(|e| io_error("store", id, e))?;
+            // Write then rename so readers never see a partial file.
+            let partial = path.with_extension("partial");
+            tokio::fs::write(&partial, data)
+                .await
+                .map_err(|e| io_error("store", id, e))?;
+            tokio::fs::rename(&partial, &path)
+                .await
+                .map_err(|e| io_error("store", id, e))
+        })
+    }
+
+    fn get<'a>(&'a self, id: &'a AttachmentRef) -> StoreFuture<'a, Option<Arc<[u8]>>> {
+        Box::pin(async move {
+            match tokio::fs::read(self.path(id)).await {
+                Ok(data) => Ok(Some(data.into())),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(io_error("read", id, e)),
+            }
+        })
+    }
+
+    fn remove<'a>(&'a self, id: &'a AttachmentRef) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.path(id)).await {
+                Ok(()) => Ok(true),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+                Err(e) => Err(io_error("remove", id, e)),
+            }
+        })
+    }
+
+    fn ids(&self) -> StoreFuture<'_, Vec<AttachmentRef>> {
+        Box::pin(async move {
+            let mut entries = match tokio::fs::read_dir(&self.dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => {
+                    return Err(Error::Other(
+                        format!("Failed to list attachments: {}", e).into(),
+                    ))
+                }
+            };
+            let mut ids = Vec::new();
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .map_err(|e| Error::Other(format!("Failed to list attachments: {}", e).into()))?
+            {
+                if let Some(id) = entry.file_name().to_str().and_then(AttachmentRef::parse) {
+                    ids.push(id);
+                }
+            }
+            Ok(ids)
+        })
+    }
+}
+
+/// A range of an attachment returned by [`Attachments::read`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AttachmentRange {
+    /// The attachment read from.
+    pub id: AttachmentRef,
+    /// Byte offset where the range starts.
+    pub offset: usize,
+    /// Byte offset just past the end of the range.
+    pub end: usize,
+    /// Total size of the attachment in bytes.
+    pub total_bytes: usize,
+    /// The content of the range, decoded as UTF-8.
+    pub content: String,
+}
+
+/// An attachment store plus the settings agents use with it.
+///
+/// Cheap to clone; clones share the store.
+#[derive(Clone)]
+pub struct Attachments {
+    store: Arc<dyn AttachmentStore>,
+    preview_chars: usize,
+    max_read_bytes: usize,
+    collect_garbage: bool,
+}
+
+impl Attachments {
+    /// Wraps a store with default settings: 200-character previews, reads of
+    /// at most 8000 bytes, and no garbage collection.
+    pub fn new(store: Arc<dyn AttachmentStore>) -> Self {
+        Self {
+            store,
+            preview_chars: 200,
+            max_read_bytes: 8000,
+            collect_garbage: false,
+        }
+    }
+
+    /// Sets how many characters of an attachment are shown in previews.
+    pub fn preview_chars(mut self, chars: usize) -> Self {
+        self.preview_chars = chars;
+        self
+    }
+
+    /// Sets the largest range the model can read in one call.
+    pub fn max_read_bytes(mut self, bytes: usize) -> Self {
+        self.max_read_bytes = bytes;
+        self
+    }
+
+    /// Deletes attachments created during a run that the conversation does
+    /// not reference once the run completes.
+    ///
+    /// Attachments count as created during a run if they appeared in the
+    /// store while it was in progress, so only enable this when the store is
+    /// not shared with concurrent runs.
+    pub fn collect_garbage(mut self, enabled: bool) -> Self {
+        self.collect_garbage = enabled;
+        self
+    }
+
+    /// Returns the underlying store.
+    pub fn store(&self) -> &Arc<dyn AttachmentStore> {
+        &self.store
+    }
+
+    /// Stores content and returns its id.
+    pub async fn register(&self, data: impl Into<Vec<u8>>) -> Result<AttachmentRef> {
+        let data = data.into();
+        let id = AttachmentRef::for_content(&data);
+        self.store.insert(&id, &data).await?;
+        Ok(id)
+    }
+
+    /// Reads up to `length` bytes starting at `offset`.
+    ///
+    /// The range is narrowed to UTF-8 character boundaries and capped at the
+    /// configured maximum, so the returned offsets may differ from the
+    /// requested ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Tool`] with code `not_found` for unknown ids and
+    /// `invalid_input` for offsets past the end.
+    pub async fn read(
+        &self,
+        id: &AttachmentRef,
+        offset: usize,
+        length: Option<usize>,
+    ) -> Result<AttachmentRange> {
+        let data = self
+            .store
+            .get(id)
+            .await?
+            .ok_or_else(|| ToolError::not_found(format!("no attachment with id {}", id)))?;
+
+        if offset > data.len() {
+            return Err(ToolError::invalid_input(format!(
+                "offset {} is past the end of attachment {} ({} bytes)",
+                offset,
+                id,
+                data.len()
+            ))
+            .into());
+        }
+
+        let length = length
+            .unwrap_or(self.max_read_bytes)
+            .min(self.max_read_bytes);
+        let start = char_boundary_at_or_after(&data, offset);
+        let end = char_boundary_at_or_before(&data, start.saturating_add(length).min(data.len()));
+
+        Ok(AttachmentRange {
+            id: id.clone(),
+            offset: start,
+            end,
+            total_bytes: data.len(),
+            content: String::from_utf8_lossy(&data[start..end]).into_owned(),
+        })
+    }
+
+    /// Describes an attachment with its id, size, and a short preview.
+    ///
+    /// Returns `None` if the id is unknown.
+    pub async fn preview(&self, id: &AttachmentRef) -> Result<Option<Value>> {
+        let Some(data) = self.store.get(id).await? else {
+            return Ok(None);
+        };
+
+        let preview = match std::str::from_utf8(&data) {
+            Ok(text) => {
+                let mut preview: String = text.chars().take(self.preview_chars).collect();
+                if preview.len() < text.len() {
+                    preview.push('…');
+                }
+                preview
+            }
+            Err(_) => format!("<binary data, {} bytes>", data.len()),
+        };
+
+        Ok(Some(json!({
+            "attachment_id": id,
+            "size_bytes": data.len(),
+            "preview": preview,
+            "hint": format!(
+                "Call {} with this id to read more of the attachment.",
+                READ_ATTACHMENT_TOOL
+            ),
+        })))
+    }
+
+    /// Replaces attachment ids in a tool result with previews.
+    ///
+    /// A result that is only an id, or JSON containing ids as string values,
+    /// has each known id replaced by its [`preview`](Self::preview). Other
+    /// results are returned unchanged.
+    pub async fn expand_refs(&self, result: &str) -> Result<String> {
+        if let Some(id) = AttachmentRef::parse(result.trim()) {
+            return Ok(match self.preview(&id).await? {
+                Some(preview) => preview.to_string(),
+                None => result.to_string(),
+            });
+        }
+
+        let Ok(mut value) = serde_json::from_str::<Value>(result) else {
+            return Ok(result.to_string());
+        };
+        let mut ids = Vec::new();
+        collect_refs(&value, &mut ids);
+        if ids.is_empty() {
+            return Ok(result.to_string());
+        }
+
+        let mut previews = HashMap::new();
+        for id in ids {
+            if let Some(preview) = self.preview(&id).await? {
+                previews.insert(id, preview);
+            }
+        }
+        replace_refs(&mut value, &previews);
+        Ok(value.to_string())
+    }
+
+    /// Handles a `read_attachment` call, returning the tool result.
+    pub(crate) async fn read_tool(&self, args: &Value) -> String {
+        let id = args["id"].as_str().and_then(AttachmentRef::parse);
+        let offset = args["offset"].as_u64().unwrap_or(0) as usize;
+        let length = args["length"].as_u64().map(|n| n as usize);
+
+        let result = match id {
+            Some(id) => self.read(&id, offset, length).await,
+            None => Err(ToolError::invalid_input("'id' must be an attachment id").into()),
+        };
+        match result {
+            Ok(range) => serde_json::to_string(&range).unwrap_or_default(),
+            Err(Error::Tool(error)) => error.to_model_json(),
+            Err(other) => ToolError::new(ToolError::TOOL_FAILED, other.to_string()).to_model_json(),
+        }
+    }
+
+    /// Definition of the built-in `read_attachment` tool.
+    pub(crate) fn read_tool_parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "id": AttachmentRef::schema(),
+                "offset": { "type": "integer", "description": "byte offset to start reading at, default 0" },
+                "length": {
+                    "type": "integer",
+                    "description": format!("number of bytes to read, at most {}", self.max_read_bytes),
+                },
+            },
+            "required": ["id"]
+        })
+    }
+
+    /// Lists the stored ids when garbage collection is enabled.
+    pub(crate) async fn snapshot(&self) -> Result<Option<HashSet<AttachmentRef>>> {
+        if !self.collect_garbage {
+            return Ok(None);
+        }
+        Ok(Some(self.store.ids().await?.into_iter().collect()))
+    }
+
+    /// Deletes attachments that are not in `before` and not mentioned in
+    /// `referenced`, returning the deleted ids.
+    pub(crate) async fn collect_unreferenced(
+        &self,
+        before: &HashSet<AttachmentRef>,
+        referenced: &str,
+    ) -> Result<Vec<AttachmentRef>> {
+        let mut removed = Vec::new();
+        for id in self.store.ids().await? {
+            if !before.contains(&id) && !referenced.contains(id.as_str()) {
+                self.store.remove(&id).await?;
+                removed.push(id);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+fn collect_refs(value: &Value, ids: &mut Vec<AttachmentRef>) {
+    match value {
+        Value::String(s) => ids.extend(AttachmentRef::parse(s)),
+        Value::Array(items) => items.iter().for_each(|item| collect_refs(item, ids)),
+        Value::Object(map) => map.values().for_each(|item| collect_refs(item, ids)),
+        _ => {}
+    }
+}
+
+fn replace_refs(value: &mut Value, previews: &HashMap<AttachmentRef, Value>) {
+    match value {
+        Value::String(s) => {
+            if let Some(preview) = AttachmentRef::parse(s).and_then(|id| previews.get(&id)) {
+                *value = preview.clone();
+            }
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| replace_refs(item, previews)),
+        Value::Object(map) => map
+            .values_mut()
+            .for_each(|item| replace_refs(item, previews)),
+        _ => {}
+    }
+}
+
+fn is_char_boundary(data: &[u8], index: usize) -> bool {
+    // Continuation bytes have the form 0b10xxxxxx.
+    index >= data.len() || (data[index] & 0xC0) != 0x80
+}
+
+fn char_boundary_at_or_after(data: &[u8], mut index: usize) -> usize {
+    while !is_char_boundary(data, index) {
+        index += 1;
+    }
+    index
+}
+
+fn char_boundary_at_or_before(data: &[u8], mut index: usize) -> usize {
+    while index > 0 && !is_char_boundary(data, index) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachments() -> Attachments {
+        Attachments::new(Arc::new(MemoryAttachmentStore::new()))
+    }
+
+    #[tokio::test]
+    async fn test_ids_are_content_addressed() {
+        let attachments = attachments();
+        let a = attachments.register("hello").await.unwrap();
+        let b = attachments.register("hello").await.unwrap();
+        let c = attachments.register("world").await.unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(AttachmentRef::parse(a.as_str()), Some(a.clone()));
+        assert_eq!(attachments.store().ids().await.unwrap().len(), 2);
+
+        assert!(AttachmentRef::parse("att_xyz").is_none());
+        assert!(serde_json::from_value::<AttachmentRef>(json!("nope")).is_err());
+        assert_eq!(serde_json::to_value(&a).unwrap(), json!(a.as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_preview_generation() {
+        let attachments = attachments().preview_chars(10);
+        let id = attachments.register("é".repeat(50)).await.unwrap();
+        let preview = attachments.preview(&id).await.unwrap().unwrap();
+        assert_eq!(preview["attachment_id"], id.as_str());
+        assert_eq!(preview["size_bytes"], 100);
+        assert_eq!(preview["preview"], format!("{}…", "é".repeat(10)));
+
+        let short = attachments.register("short").await.unwrap();
+        let preview = attachments.preview(&short).await.unwrap().unwrap();
+        assert_eq!(preview["preview"], "short");
+
+        let binary = attachments.register(vec![0xff, 0xfe, 0x00]).await.unwrap();
+        let preview = attachments.preview(&binary).await.unwrap().unwrap();
+        assert_eq!(preview["preview"], "<binary data, 3 bytes>");
+    }
+
+    #[tokio::test]
+    async fn test_expand_refs() {
+        let attachments = attachments().preview_chars(5);
+        let id = attachments.register("a long document").await.unwrap();
+        let unknown = AttachmentRef::for_content(b"never stored");
+
+        let expanded: Value =
+            serde_json::from_str(&attachments.expand_refs(id.as_str()).await.unwrap()).unwrap();
+        assert_eq!(expanded["preview"], "a lon…");
+
+        let result = json!({ "files": [id.as_str(), unknown.as_str()], "count": 2 }).to_string();
+        let expanded: Value =
+            serde_json::from_str(&attachments.expand_refs(&result).await.unwrap()).unwrap();
+        assert_eq!(expanded["files"][0]["attachment_id"], id.as_str());
+        assert_eq!(expanded["files"][1], unknown.as_str());
+        assert_eq!(expanded["count"], 2);
+
+        assert_eq!(
+            attachments.expand_refs("plain text").await.unwrap(),
+            "plain text"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ranged_reads() {
+        let attachments = attachments().max_read_bytes(8);
+        let id = attachments.register("0123456789abcdef").await.unwrap();
+
+        let range = attachments.read(&id, 4, Some(4)).await.unwrap();
+        assert_eq!(
+            (range.offset, range.end, range.content.as_str()),
+            (4, 8, "4567")
+        );
+        assert_eq!(range.total_bytes, 16);
+
+        // Capped at the maximum read size and at the end of the content.
+        let range = attachments.read(&id, 4, Some(100)).await.unwrap();
+        assert_eq!(range.content, "456789ab");
+        let range = attachments.read(&id, 12, None).await.unwrap();
+        assert_eq!(range.content, "cdef");
+
+        assert!(attachments.read(&id, 17, None).await.is_err());
+        let missing = AttachmentRef::for_content(b"missing");
+        assert!(matches!(
+            attachments.read(&missing, 0, None).await,
+            Err(Error::Tool(ref e)) if e.code == "not_found"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_ranged_reads_respect_char_boundaries() {
+        let attachments = attachments();
+        // Each "日" is three bytes.
+        let id = attachments.register("日本語").await.unwrap();
+        let range = attachments.read(&id, 1, Some(4)).await.unwrap();
+        assert_eq!(
+            (range.offset, range.end, range.content.as_str()),
+            (3, 6, "本")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_tool_reports_errors_to_model() {
+        let attachments = attachments();
+        let result: Value =
+            serde_json::from_str(&attachments.read_tool(&json!({ "id": "bogus" })).await).unwrap();
+        assert_eq!(result["error"]["code"], "invalid_input");
+    }
+
+    #[tokio::test]
+    async fn test_collect_unreferenced() {
+        let attachments = attachments().collect_garbage(true);
+        let existing = attachments.register("from before the run").await.unwrap();
+        let before = attachments.snapshot().await.unwrap().unwrap();
+
+        let kept = attachments.register("referenced").await.unwrap();
+        let dropped = attachments.register("scratch").await.unwrap();
+        let conversation = format!("the tool returned {}", kept);
+
+        let removed = attachments
+            .collect_unreferenced(&before, &conversation)
+            .await
+            .unwrap();
+        assert_eq!(removed, vec![dropped]);
+        let mut remaining = attachments.store().ids().await.unwrap();
+        remaining.sort();
+        let mut expected = vec![existing, kept];
+        expected.sort();
+        assert_eq!(remaining, expected);
+    }
+
+    #[tokio::test]
+    async fn test_file_store() {
+        let dir = std::env::temp_dir().join(format!("aiform-attachments-{}", std::process::id()));
+        let attachments = Attachments::new(Arc::new(FileAttachmentStore::new(&dir)));
+
+        let id = attachments.register("on disk").await.unwrap();
+        assert_eq!(attachments.register("on disk").await.unwrap(), id);
+        assert_eq!(attachments.store().ids().await.unwrap(), vec![id.clone()]);
+        assert_eq!(
+            attachments.read(&id, 3, None).await.unwrap().content,
+            "disk"
+        );
+
+        assert!(attachments.store().remove(&id).await.unwrap());
+        assert!(!attachments.store().remove(&id).await.unwrap());
+        assert!(attachments.store().get(&id).await.unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+//! The transport agents use to request chat completions.
+//!
+//! Agents send every completion through a [`ChatBackend`]. It is implemented
+//! for every `async_openai::Client`; other implementations can be passed to
+//! [`AgentBuilder::backend`](crate::AgentBuilder::backend), e.g. the scripted
+//! `MockChatBackend` of the `testing` module, enabled by the `test-util`
+//! feature.
+
+use async_openai::{
+    config::Config,
+    error::OpenAIError,
+    types::{
+        ChatCompletionResponseStream, CreateChatCompletionRequest, CreateChatCompletionResponse,
+    },
+    Client,
+};
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::future::Future;
+use std::pin::Pin;
+
+/// Boxed future returned by [`ChatBackend`] methods.
+pub type BackendFuture<'a, T> =
+    Pin<Box<dyn Future<Output = std::result::Result<T, OpenAIError>> + Send + 'a>>;
+
+/// A chat completion request and the body fields sent along with it.
+///
+/// Serializes to the JSON body to send.
+#[derive(Debug, Clone, Copy)]
+pub struct ChatRequest<'a> {
+    pub(crate) request: &'a CreateChatCompletionRequest,
+    /// Top-level body fields `async_openai` does not model, such as
+    /// OpenRouter's provider routing. They replace request fields of the
+    /// same name.
+    pub(crate) extra_body: Option<&'a Map<String, Value>>,
+}
+
+impl<'a> ChatRequest<'a> {
+    /// Returns the typed request.
+    pub fn request(&self) -> &'a CreateChatCompletionRequest {
+        self.request
+    }
+
+    /// Returns the body fields added to the typed request, if any.
+    pub fn extra_body(&self) -> Option<&'a Map<String, Value>> {
+        self.extra_body
+    }
+}
+
+impl<'a> From<&'a CreateChatCompletionRequest> for ChatRequest<'a> {
+    fn from(request: &'a CreateChatCompletionRequest) -> Self {
+        Self {
+            request,
+            extra_body: None,
+        }
+    }
+}
+
+impl Serialize for ChatRequest<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let Some(extra_body) = self.extra_body.filter(|fields| !fields.is_empty()) else {
+            return self.request.serialize(serializer);
+        };
+        let mut body = serde_json::to_value(self.request).map_err(serde::ser::Error::custom)?;
+        if let Some(fields) = body.as_object_mut() {
+            fields.extend(extra_body.clone());
+        }
+        body.serialize(serializer)
+    }
+}
+
+/// Returns the fields of `extra_body` that the typed request sets too.
+pub(crate) fn conflicting_fields(
+    request: &mut CreateChatCompletionRequest,
+    extra_body: &Map<String, Value>,
+) -> serde_json::Result<Vec<String>> {
+    if extra_body.is_empty() {
+        return Ok(Vec::new());
+    }
+    // Only the names matter, so the history is not serialized.
+    let messages = std::mem::take(&mut request.messages);
+    let typed = serde_json::to_value(&*request);
+    request.messages = messages;
+    let typed = typed?;
+    Ok(extra_body
+        .keys()
+        .filter(|name| {
+            typed
+                .get(name.as_str())
+                .is_some_and(|value| !value.is_null())
+        })
+        .cloned()
+        .collect())
+}
+
+/// Sends chat completion requests on behalf of an agent.
+///
+/// Requests are borrowed, so the agent loop and retries never copy the
+/// message history. Implemented for every `async_openai::Client`.
+pub trait ChatBackend: Send + Sync {
+    /// Performs a single (non-streaming) chat completion.
+    fn create_chat_completion<'a>(
+        &'a self,
+        request: ChatRequest<'a>,
+    ) -> BackendFuture<'a, CreateChatCompletionResponse>;
+
+    /// Performs a streaming chat completion. The request has `stream` set.
+    fn create_chat_completion_stream<'a>(
+        &'a self,
+        request: ChatRequest<'a>,
+    ) -> BackendFuture<'a, ChatCompletionResponseStream>;
+
+    /// Lists the ids of the models available to the caller.
+    fn list_models(&self) -> BackendFuture<'_, Vec<String>>;
+}
+
+impl<C: Config + Send + Sync> ChatBackend for Client<C> {
+    fn create_chat_completion<'a>(
+        &'a self,
+        request: ChatRequest<'a>,
+    ) -> BackendFuture<'a, CreateChatCompletionResponse> {
+        Box::pin(async move { self.chat().create_byot(request).await })
+    }
+
+    fn create_chat_completion_stream<'a>(
+        &'a self,
+        request: ChatRequest<'a>,
+    ) -> BackendFuture<'a, ChatCompletionResponseStream> {
+        Box::pin(async move { self.chat().create_stream_byot(request).await })
+    }
+
+    fn list_models(&self) -> BackendFuture<'_, Vec<String>> {
+        Box::pin(async move {
+            let models = self.models().list().await?;
+            Ok(models.data.into_iter().map(|model| model.id).collect())
+        })
+    }
+}
@@ -0,0 +1,378 @@
+//! A blocking agent, for callers that are not async.
+//!
+//! [`blocking::Agent`](Agent) wraps an [`Agent`](crate::Agent) with its own
+//! single-threaded Tokio runtime, so runs are plain function calls:
+//!
+//! ```no_run
+//! use aiform::blocking;
+//!
+//! fn main() -> aiform::Result<()> {
+//!     let agent = blocking::Agent::builder()
+//!         .model("gpt-4o-mini")
+//!         .system_prompt("You are a concise assistant.")
+//!         .build()?;
+//!     println!("{}", agent.run("What is the capital of Norway?")?);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The blocking agent must not be used from async code: building or
+//! calling it inside a Tokio runtime panics, since blocking there would
+//! stall the runtime's other tasks. Async code should use
+//! [`aiform::Agent`](crate::Agent) and await its methods.
+
+use crate::agent::{ArgumentContinuation, ToolChoice};
+use crate::approval::ApprovalDecision;
+use crate::attachment::Attachments;
+use crate::backend::ChatBackend;
+use crate::context::ContextStrategy;
+use crate::conversation::Conversation;
+use crate::error::{Error, Result};
+use crate::fixture::MatchRules;
+use crate::gate::AmbiguityGate;
+use crate::hook::AgentHook;
+use crate::limiter::Limiter;
+use crate::metrics::MetricsSnapshot;
+use crate::openrouter::OpenRouterOptions;
+use crate::profile::Profile;
+use crate::provider::Provider;
+use crate::render::Template;
+use crate::retry::RetryPolicy;
+use crate::store::ConversationStore;
+use crate::tokens::TokenCounter;
+use crate::tool_error::ToolErrorPolicy;
+use crate::warning::Warning;
+use crate::{StructuredOutput, ToolSet};
+use async_openai::{config::Config, Client};
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+/// An agent whose runs block the calling thread.
+///
+/// Built with [`Agent::builder`], which has the same methods as
+/// [`AgentBuilder`](crate::AgentBuilder), or wrapped around an async agent
+/// with [`Agent::new`].
+pub struct Agent {
+    inner: crate::Agent,
+    /// Taken when the agent is dropped.
+    runtime: Option<Runtime>,
+}
+
+impl Agent {
+    /// Creates a builder for a blocking agent.
+    pub fn builder() -> AgentBuilder {
+        AgentBuilder::new()
+    }
+
+    /// Wraps an async agent, starting the runtime its runs block on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called inside a Tokio runtime.
+    pub fn new(agent: crate::Agent) -> Result<Self> {
+        assert_not_async();
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| Error::Other(Box::new(e)))?;
+        Ok(Self {
+            inner: agent,
+            runtime: Some(runtime),
+        })
+    }
+
+    /// The wrapped async agent.
+    pub fn as_async(&self) -> &crate::Agent {
+        &self.inner
+    }
+
+    /// Runs the agent with a single user message; see
+    /// [`Agent::run`](crate::Agent::run).
+    pub fn run(&self, message: impl Into<String>) -> Result<String> {
+        self.block_on(self.inner.run(message))
+    }
+
+    /// Runs the agent with an existing conversation; see
+    /// [`Agent::run_conversation`](crate::Agent::run_conversation).
+    pub fn run_conversation(&self, conversation: &mut Conversation) -> Result<String> {
+        self.block_on(self.inner.run_conversation(conversation))
+    }
+
+    /// Runs the agent and deserializes its answer into `T`; see
+    /// [`Agent::run_structured`](crate::Agent::run_structured).
+    pub fn run_structured<T>(&self, message: impl Into<String>) -> Result<T>
+    where
+        T: StructuredOutput + DeserializeOwned,
+    {
+        self.block_on(self.inner.run_structured(message))
+    }
+
+    /// Calls one of the agent's tools directly, returning its output.
+    ///
+    /// The call goes through the tool set's middleware and cache, as calls
+    /// made by the model do. A tool that fails gives
+    /// [`Error::ToolExecution`], unless it failed with an [`Error`].
+    pub fn call_tool(&self, name: &str, arguments: serde_json::Value) -> Result<String> {
+        let Some(tools) = self.inner.tool_set() else {
+            return Err(Error::ToolNotFound(name.to_string()));
+        };
+        self.block_on(tools.dispatch(name.to_string(), arguments))
+            .map_err(|e| match e.downcast::<Error>() {
+                Ok(error) => *error,
+                Err(e) => Error::ToolExecution {
+                    tool_name: name.to_string(),
+                    message: e.to_string(),
+                    agent: self.inner.name().map(String::from),
+                },
+            })
+    }
+
+    /// The agent's counters; see [`Agent::metrics`](crate::Agent::metrics).
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.inner.metrics()
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        assert_not_async();
+        self.runtime
+            .as_ref()
+            .expect("the runtime is only taken on drop")
+            .block_on(future)
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        // Dropping a runtime blocks until its tasks stop, which panics in
+        // async code; an agent moved into a task may be dropped there.
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl std::fmt::Debug for Agent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Agent").finish_non_exhaustive()
+    }
+}
+
+/// Panics with an explanation if the current thread is running async code.
+fn assert_not_async() {
+    if Handle::try_current().is_ok() {
+        panic!(
+            "aiform::blocking::Agent cannot be used inside a Tokio runtime, where \
+             blocking would stall other tasks; use aiform::Agent and await its methods"
+        );
+    }
+}
+
+/// Builder for a blocking [`Agent`].
+///
+/// Each method configures the agent as the
+/// [`AgentBuilder`](crate::AgentBuilder) method of the same name does.
+#[derive(Default)]
+pub struct AgentBuilder {
+    inner: crate::AgentBuilder,
+}
+
+/// Defines builder methods that pass their arguments on to the async
+/// builder's method of the same name.
+macro_rules! forward {
+    ($($(#[$attr:meta])* fn $name:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            $(#[$attr])*
+            #[doc = concat!(
+                "See [`AgentBuilder::", stringify!($name),
+                "`](crate::AgentBuilder::", stringify!($name), ")."
+            )]
+            pub fn $name(self, $($arg: $ty),*) -> Self {
+                Self {
+                    inner: self.inner.$name($($arg),*),
+                }
+            }
+        )*
+    };
+}
+
+impl AgentBuilder {
+    /// Creates a builder with the same defaults as
+    /// [`AgentBuilder::new`](crate::AgentBuilder::new).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See [`AgentBuilder::client`](crate::AgentBuilder::client).
+    pub fn client<C>(self, client: Client<C>) -> Self
+    where
+        C: Config + Send + Sync + 'static,
+    {
+        Self {
+            inner: self.inner.client(client),
+        }
+    }
+
+    /// See [`AgentBuilder::handoff_targets`](crate::AgentBuilder::handoff_targets).
+    pub fn handoff_targets<I, S>(self, targets: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            inner: self.inner.handoff_targets(targets),
+        }
+    }
+
+    forward! {
+        fn backend(backend: Arc<dyn ChatBackend>);
+        fn model(model: impl Into<String>);
+        fn name(name: impl Into<String>);
+        fn system_prompt(prompt: impl Into<String>);
+        fn tools(tools: ToolSet);
+        fn shared_tools(tools: Arc<ToolSet>);
+        fn extend_tools(tools: ToolSet);
+        fn max_iterations(max: usize);
+        fn max_handoffs(max: usize);
+        fn temperature(temperature: f32);
+        fn top_p(top_p: f32);
+        fn max_tokens(max_tokens: u32);
+        fn frequency_penalty(penalty: f32);
+        fn presence_penalty(penalty: f32);
+        fn terminal_tool(tool_name: impl Into<String>, template: Template);
+        fn argument_continuation(policy: ArgumentContinuation);
+        fn on_warning(handler: impl Fn(&Warning) + Send + Sync + 'static);
+        fn hook(hook: impl AgentHook + 'static);
+        fn require_approval(
+            names: &[&str],
+            approver: impl Fn(&str, &serde_json::Value) -> BoxFuture<'static, ApprovalDecision>
+                + Send
+                + Sync
+                + 'static
+        );
+        fn autosave(store: Arc<dyn ConversationStore>, id: impl Into<String>);
+        fn post_process(processor: impl Fn(String) -> String + Send + Sync + 'static);
+        fn plain_text();
+        fn plain_text_tool_results();
+        fn plain_text_tool(tool_name: impl Into<String>);
+        fn tool_error_policy(policy: ToolErrorPolicy);
+        #[cfg(feature = "jsonschema")]
+        fn validate_tool_arguments(validate: bool);
+        fn profile(profile: Profile);
+        fn request_timeout(timeout: Duration);
+        fn tool_timeout(timeout: Duration);
+        fn tool_timeout_for(tool_name: impl Into<String>, timeout: Duration);
+        fn max_tool_output_bytes(limit: usize);
+        fn max_tool_output_bytes_for(tool_name: impl Into<String>, limit: usize);
+        fn retry(policy: RetryPolicy);
+        fn ask_user(enabled: bool);
+        fn ambiguity_gate(gate: AmbiguityGate);
+        fn context_strategy(strategy: ContextStrategy);
+        fn token_counter(counter: impl TokenCounter + 'static);
+        fn attachments(attachments: Attachments);
+        fn limiter(limiter: Limiter);
+        fn json_mode(enabled: bool);
+        fn structured_output_retry(enabled: bool);
+        fn tool_choice(choice: ToolChoice);
+        fn parallel_tools(enabled: bool);
+        fn max_parallel_tools(max: usize);
+        fn provider(provider: Provider);
+        fn extra_body(fields: serde_json::Map<String, serde_json::Value>);
+        fn allow_extra_body_overrides(allow: bool);
+        fn record_to(path: impl Into<PathBuf>);
+        fn replay_from(path: impl Into<PathBuf>);
+        fn replay_rules(rules: MatchRules);
+        fn openrouter(options: OpenRouterOptions);
+        fn strip_rejected_parameters(enabled: bool);
+        fn append_final_response(enabled: bool);
+        fn inherit_system_prompt(enabled: bool);
+        fn read_only(read_only: bool);
+    }
+
+    /// Builds the agent and starts its runtime; fails as
+    /// [`AgentBuilder::build`](crate::AgentBuilder::build) does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called inside a Tokio runtime.
+    pub fn build(self) -> Result<Agent> {
+        assert_not_async();
+        Agent::new(self.inner.build()?)
+    }
+}
+
+impl From<crate::AgentBuilder> for AgentBuilder {
+    fn from(inner: crate::AgentBuilder) -> Self {
+        Self { inner }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{tool, tools, ToolArg};
+    use crate::testing::MockChatBackend;
+    use serde_json::json;
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct ForecastArgs {
+        city: String,
+    }
+
+    #[tool("Get the forecast for a city")]
+    async fn get_forecast(args: ForecastArgs) -> Result<String> {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        Ok(format!("Sunny in {}", args.city))
+    }
+
+    fn forecaster(backend: Arc<MockChatBackend>) -> Agent {
+        Agent::builder()
+            .model("mock-model")
+            .backend(backend)
+            .tools(tools![GetForecastTool])
+            .max_iterations(3)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_runs_block_without_a_runtime() {
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("get_forecast", json!({ "city": "Oslo" }))
+                .respond_with_text("It will be sunny in Oslo."),
+        );
+        let forecaster = forecaster(backend.clone());
+
+        let answer = forecaster.run("Weather in Oslo?").unwrap();
+        assert_eq!(answer, "It will be sunny in Oslo.");
+        assert_eq!(backend.requests().len(), 2);
+        assert_eq!(forecaster.metrics().tool_calls["get_forecast"], 1);
+
+        let output = forecaster
+            .call_tool("get_forecast", json!({ "city": "Bergen" }))
+            .unwrap();
+        assert_eq!(output, "Sunny in Bergen");
+        assert!(matches!(
+            forecaster.call_tool("get_tides", json!({})),
+            Err(Error::ToolNotFound(ref name)) if name == "get_tides"
+        ));
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "cannot be used inside a Tokio runtime")]
+    async fn test_panics_inside_a_runtime() {
+        forecaster(Arc::new(MockChatBackend::new()));
+    }
+
+    #[test]
+    fn test_can_be_dropped_inside_a_runtime() {
+        let forecaster = forecaster(Arc::new(MockChatBackend::new()));
+        let runtime = Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async move { drop(forecaster) });
+    }
+}
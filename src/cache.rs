@@ -0,0 +1,293 @@
+//! Memoized tool results.
+//!
+//! Agents often call the same lookup tool with the same arguments several
+//! times in a run, and again in later runs.
+//! [`ToolSet::with_cache`](crate::ToolSet::with_cache) remembers the results
+//! of the tools named in the [`CachePolicy`] and answers repeated calls
+//! without running the tool:
+//!
+//! ```
+//! use aiform::cache::CachePolicy;
+//! use aiform::prelude::*;
+//! use std::time::Duration;
+//!
+//! # fn example(tools: ToolSet) -> ToolSet {
+//! tools.with_cache(CachePolicy {
+//!     ttl: Some(Duration::from_secs(300)),
+//!     ..CachePolicy::for_tools(["get_weather", "lookup_airport"])
+//! })
+//! # }
+//! ```
+//!
+//! Only tools named in the policy are cached, since calling a side-effecting
+//! tool twice is not the same as calling it once. Failed calls are not
+//! cached.
+
+use crate::{Dispatcher, ToolFuture};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Which tool results are cached, and for how long.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachePolicy {
+    /// How long a result is reused. `None` keeps results until they are
+    /// evicted.
+    pub ttl: Option<Duration>,
+    /// How many results are kept; the least recently used is evicted to
+    /// make room.
+    pub max_entries: usize,
+    /// The tools whose results are cached.
+    pub per_tool: HashSet<String>,
+}
+
+impl CachePolicy {
+    /// Caches up to 1024 results of the named tools, without expiry.
+    pub fn for_tools<S: Into<String>>(tools: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            per_tool: tools.into_iter().map(Into::into).collect(),
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            ttl: None,
+            max_entries: 1024,
+            per_tool: HashSet::new(),
+        }
+    }
+}
+
+/// How often a cache answered calls, as returned by
+/// [`ToolSet::cache_stats`](crate::ToolSet::cache_stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Calls answered from the cache.
+    pub hits: u64,
+    /// Calls of cached tools that ran the tool.
+    pub misses: u64,
+}
+
+/// A cached result.
+#[derive(Debug)]
+struct Entry {
+    output: String,
+    stored: Instant,
+    /// When the entry was last used, as a tick of [`Entries::clock`].
+    used: u64,
+}
+
+/// Cached results by tool name and canonical arguments.
+#[derive(Debug, Default)]
+struct Entries {
+    by_key: HashMap<(String, String), Entry>,
+    clock: u64,
+}
+
+/// The results cached for a tool set, shared by its clones; see
+/// [`ToolSet::with_cache`](crate::ToolSet::with_cache).
+#[derive(Debug, Clone)]
+pub struct ToolCache {
+    policy: Arc<CachePolicy>,
+    entries: Arc<Mutex<Entries>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl ToolCache {
+    pub(crate) fn new(policy: CachePolicy) -> Self {
+        Self {
+            policy: Arc::new(policy),
+            entries: Arc::default(),
+            hits: Arc::default(),
+            misses: Arc::default(),
+        }
+    }
+
+    /// How often the cache answered calls.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// A dispatcher answering cached calls and caching the results of
+    /// `dispatcher`.
+    pub(crate) fn wrap(&self, dispatcher: Dispatcher) -> Dispatcher {
+        let cache = self.clone();
+        Arc::new(move |name: String, args: Value| {
+            if !cache.policy.per_tool.contains(&name) {
+                return dispatcher(name, args);
+            }
+            let key = (name, canonical_json(&args));
+            if let Some(output) = cache.get(&key) {
+                cache.hits.fetch_add(1, Ordering::Relaxed);
+                return Box::pin(async move { Ok(output) }) as ToolFuture;
+            }
+            cache.misses.fetch_add(1, Ordering::Relaxed);
+            let call = dispatcher(key.0.clone(), args);
+            let cache = cache.clone();
+            Box::pin(async move {
+                let output = call.await?;
+                cache.insert(key, output.clone());
+                Ok(output)
+            })
+        })
+    }
+
+    /// The unexpired result cached under `key`, marking it used.
+    fn get(&self, key: &(String, String)) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        let tick = entries.clock + 1;
+        let entry = entries.by_key.get_mut(key)?;
+        if self
+            .policy
+            .ttl
+            .is_some_and(|ttl| entry.stored.elapsed() >= ttl)
+        {
+            entries.by_key.remove(key);
+            return None;
+        }
+        entry.used = tick;
+        let output = entry.output.clone();
+        entries.clock = tick;
+        Some(output)
+    }
+
+    /// Caches a result, evicting the least recently used one if full.
+    fn insert(&self, key: (String, String), output: String) {
+        if self.policy.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.by_key.contains_key(&key) && entries.by_key.len() >= self.policy.max_entries {
+            let oldest = entries
+                .by_key
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.by_key.remove(&oldest);
+            }
+        }
+        entries.clock += 1;
+        let used = entries.clock;
+        entries.by_key.insert(
+            key,
+            Entry {
+                output,
+                stored: Instant::now(),
+                used,
+            },
+        );
+    }
+}
+
+/// Serializes a value with object keys sorted, so equal arguments give
+/// equal keys whatever order the model wrote them in.
+fn canonical_json(value: &Value) -> String {
+    fn sorted(value: &Value) -> Value {
+        match value {
+            Value::Object(map) => {
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                Value::Object(
+                    keys.into_iter()
+                        .map(|key| (key.clone(), sorted(&map[key])))
+                        .collect(),
+                )
+            }
+            Value::Array(items) => Value::Array(items.iter().map(sorted).collect()),
+            other => other.clone(),
+        }
+    }
+    sorted(value).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ToolSet;
+    use serde_json::json;
+
+    /// A set of `lookup` and `book` tools answering with their call count.
+    fn counting_tools() -> (ToolSet, Arc<AtomicU64>) {
+        let calls = Arc::new(AtomicU64::new(0));
+        let counter = calls.clone();
+        let tools = serde_json::from_value::<Vec<_>>(json!([
+            { "type": "function", "function": { "name": "lookup", "parameters": {} } },
+            { "type": "function", "function": { "name": "book", "parameters": {} } },
+        ]))
+        .unwrap();
+        let tools = ToolSet {
+            tools,
+            dispatcher: Arc::new(move |name, _| {
+                let call = counter.fetch_add(1, Ordering::Relaxed) + 1;
+                Box::pin(async move { Ok(format!("{} #{}", name, call)) })
+            }),
+            effects: Default::default(),
+            middleware: Vec::new(),
+            cache: None,
+            handoffs: Default::default(),
+        };
+        (tools, calls)
+    }
+
+    #[tokio::test]
+    async fn test_identical_calls_hit_the_cache() {
+        let (tools, calls) = counting_tools();
+        let tools = tools.with_cache(CachePolicy::for_tools(["lookup"]));
+        let dispatch = |name: &str, args| tools.dispatch(name.into(), args);
+
+        let first = dispatch("lookup", json!({ "city": "Paris", "days": 2 }))
+            .await
+            .unwrap();
+        assert_eq!(first, "lookup #1");
+        // Argument order does not matter.
+        let second = dispatch("lookup", json!({ "days": 2, "city": "Paris" }))
+            .await
+            .unwrap();
+        assert_eq!(second, "lookup #1");
+        let other = dispatch("lookup", json!({ "city": "Lyon", "days": 2 }))
+            .await
+            .unwrap();
+        assert_eq!(other, "lookup #2");
+        // Tools outside the policy always run.
+        dispatch("book", json!({})).await.unwrap();
+        assert_eq!(dispatch("book", json!({})).await.unwrap(), "book #4");
+
+        assert_eq!(calls.load(Ordering::Relaxed), 4);
+        assert_eq!(tools.cache_stats(), Some(CacheStats { hits: 1, misses: 2 }));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expired_and_evicted_results_miss() {
+        let (tools, _) = counting_tools();
+        let tools = tools.with_cache(CachePolicy {
+            ttl: Some(Duration::from_secs(60)),
+            max_entries: 2,
+            ..CachePolicy::for_tools(["lookup"])
+        });
+        let lookup = |city: &str| tools.dispatch("lookup".into(), json!({ "city": city }));
+
+        assert_eq!(lookup("Paris").await.unwrap(), "lookup #1");
+        tokio::time::advance(Duration::from_secs(59)).await;
+        assert_eq!(lookup("Paris").await.unwrap(), "lookup #1");
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(lookup("Paris").await.unwrap(), "lookup #2");
+
+        // Lyon was used less recently than Paris, so Nice evicts it.
+        assert_eq!(lookup("Lyon").await.unwrap(), "lookup #3");
+        assert_eq!(lookup("Paris").await.unwrap(), "lookup #2");
+        assert_eq!(lookup("Nice").await.unwrap(), "lookup #4");
+        assert_eq!(lookup("Paris").await.unwrap(), "lookup #2");
+        assert_eq!(lookup("Lyon").await.unwrap(), "lookup #5");
+    }
+}
@@ -0,0 +1,100 @@
+//! Cooperative cancellation of running agents.
+//!
+//! A [`CancelHandle`] is passed to [`Agent::run_cancellable`] or
+//! [`RunOptions::cancel`]; calling [`cancel`](CancelHandle::cancel) on any of
+//! its clones stops the run at the next safe point with
+//! [`Error::Cancelled`](crate::Error::Cancelled).
+//!
+//! ```no_run
+//! use aiform::cancel::CancelHandle;
+//! use aiform::prelude::*;
+//!
+//! # async fn example(agent: Agent) -> Result<()> {
+//! let cancel = CancelHandle::new();
+//! let stop = cancel.clone();
+//! tokio::spawn(async move {
+//!     tokio::signal::ctrl_c().await.ok();
+//!     stop.cancel();
+//! });
+//!
+//! match agent.run_cancellable("Plan my trip", cancel).await {
+//!     Err(Error::Cancelled) => println!("stopped"),
+//!     result => println!("{}", result?),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Agent::run_cancellable`]: crate::Agent::run_cancellable
+//! [`RunOptions::cancel`]: crate::RunOptions::cancel
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// A shared flag that asks a run to stop.
+///
+/// Clones share the flag, so one clone can be handed to the run while
+/// another is kept to cancel it.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelHandle {
+    /// Creates a handle that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels every run using this handle or one of its clones.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Returns whether [`cancel`](Self::cancel) was called.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Waits until the handle is cancelled.
+    pub async fn cancelled(&self) {
+        let notified = self.inner.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_wakes_waiters() {
+        let handle = CancelHandle::new();
+        assert!(!handle.is_cancelled());
+
+        let waiter = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.cancelled().await }
+        });
+        tokio::task::yield_now().await;
+        handle.cancel();
+        waiter.await.unwrap();
+
+        assert!(handle.is_cancelled());
+        // Waiting on an already cancelled handle returns immediately.
+        handle.cancelled().await;
+    }
+}
@@ -0,0 +1,71 @@
+//! The process-wide client used by agents built without one.
+//!
+//! [`AgentBuilder`](crate::AgentBuilder) falls back to [`default_client`]
+//! when no client is set, so every such agent shares one client and its
+//! connection pool instead of reading the environment and opening new
+//! connections per agent. The client is created from the environment
+//! (`OPENAI_API_KEY` and friends) on first use.
+//!
+//! To configure it once, e.g. with a custom base URL, call
+//! [`set_default_client`] before building any agent:
+//!
+//! ```no_run
+//! use async_openai::{config::OpenAIConfig, Client};
+//!
+//! # fn example() -> aiform::Result<()> {
+//! let config = OpenAIConfig::new().with_api_base("https://openrouter.ai/api/v1");
+//! aiform::set_default_client(Client::with_config(config))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Agents given a client with [`AgentBuilder::client`](crate::AgentBuilder::client)
+//! use that one instead. Clones of a client share its connection pool, so
+//! passing clones of one configured client to many agents is as cheap as the
+//! default.
+
+use crate::error::{Error, Result};
+use async_openai::{config::OpenAIConfig, Client};
+use std::sync::{Arc, OnceLock};
+
+static DEFAULT_CLIENT: OnceLock<Arc<Client<OpenAIConfig>>> = OnceLock::new();
+
+/// Returns the shared default client, creating it from the environment on
+/// first use.
+pub fn default_client() -> Arc<Client<OpenAIConfig>> {
+    DEFAULT_CLIENT
+        .get_or_init(|| Arc::new(Client::new()))
+        .clone()
+}
+
+/// Sets the client used by agents built without one.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidConfiguration`] if the default client was already
+/// set or used, e.g. by building an agent without a client. Call this
+/// during startup, before any agent is built.
+pub fn set_default_client(client: Client<OpenAIConfig>) -> Result<()> {
+    DEFAULT_CLIENT.set(Arc::new(client)).map_err(|_| {
+        Error::InvalidConfiguration(
+            "the default client is already in use; call set_default_client before building \
+             any agent without a client"
+                .into(),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_after_first_use_fails() {
+        let first = default_client();
+        assert!(Arc::ptr_eq(&first, &default_client()));
+
+        let error = set_default_client(Client::new()).unwrap_err();
+        assert!(error.to_string().contains("already in use"));
+        assert!(Arc::ptr_eq(&first, &default_client()));
+    }
+}
@@ -0,0 +1,347 @@
+//! Keeping conversations within the model's context window.
+//!
+//! Long conversations eventually exceed the context window and requests
+//! fail. [`Conversation::truncate_to_messages`] and
+//! [`Conversation::truncate_to_tokens`] drop the oldest messages,
+//! [`Conversation::compact`] replaces them with a summary, and an agent with
+//! a [`ContextStrategy`] does either before every request:
+//!
+//! ```no_run
+//! use aiform::context::ContextStrategy;
+//! use aiform::prelude::*;
+//!
+//! # fn example() -> Result<()> {
+//! let agent = Agent::builder()
+//!     .model("gpt-4o")
+//!     .context_strategy(ContextStrategy::SlidingWindow { max_tokens: 100_000 })
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! System messages are always kept. An assistant message with tool calls
+//! and the tool results answering it are dropped together, so the
+//! conversation stays valid for the API. The most recent message, together
+//! with its tool call group, is always kept.
+
+use crate::agent::Agent;
+use crate::conversation::Conversation;
+use crate::error::Result;
+use crate::tokens::{self, TokenCounter};
+use async_openai::types::ChatCompletionRequestMessage;
+use serde_json::Value;
+use std::ops::Range;
+
+/// Instructions for the summarization request.
+pub(crate) const SUMMARY_PROMPT: &str = "Summarize the following conversation between a user \
+and an assistant, so the assistant can continue it without the original messages. Keep every \
+fact, decision, open question and tool result that may matter later. Answer with the summary \
+only.";
+
+/// Prefix of the system message that replaces compacted messages.
+pub(crate) const SUMMARY_PREFIX: &str = "Summary of earlier conversation: ";
+
+/// How an agent keeps its conversation within the context window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ContextStrategy {
+    /// Drops the oldest messages before each request until the request,
+    /// including tool definitions, is estimated to use at most `max_tokens`
+    /// tokens.
+    SlidingWindow {
+        /// The token budget for the request.
+        max_tokens: usize,
+    },
+    /// Summarizes older messages before a request once the request is
+    /// estimated to use more than `trigger_tokens` tokens; see
+    /// [`Conversation::compact`].
+    Summarize {
+        /// The estimated request size that triggers summarization.
+        trigger_tokens: usize,
+        /// How many recent messages are kept verbatim.
+        keep_recent: usize,
+    },
+}
+
+impl Conversation {
+    /// Drops the oldest messages until at most `n` non-system messages
+    /// remain, and returns how many were dropped.
+    ///
+    /// Fewer than `n` messages remain if keeping `n` would split a tool
+    /// call from its results.
+    pub fn truncate_to_messages(&mut self, n: usize) -> usize {
+        let groups = message_groups(self.messages());
+        let mut remaining: usize = groups.iter().map(Vec::len).sum();
+        let mut drop = 0;
+        while remaining > n && drop + 1 < groups.len() {
+            remaining -= groups[drop].len();
+            drop += 1;
+        }
+        self.drop_groups(&groups[..drop])
+    }
+
+    /// Drops the oldest messages until a request with this conversation is
+    /// estimated to use at most `budget` tokens, and returns how many were
+    /// dropped.
+    pub fn truncate_to_tokens(&mut self, budget: usize, counter: &dyn TokenCounter) -> usize {
+        let groups = message_groups(self.messages());
+        let mut remaining = self.estimate_tokens(counter);
+        let mut drop = 0;
+        while remaining > budget && drop + 1 < groups.len() {
+            remaining -= groups[drop]
+                .iter()
+                .map(|&index| tokens::count_message(counter, &self.messages()[index]))
+                .sum::<usize>();
+            drop += 1;
+        }
+        self.drop_groups(&groups[..drop])
+    }
+
+    /// Replaces all but the last `keep_recent` messages with a summary
+    /// written by `agent`, and returns how many messages were replaced.
+    ///
+    /// The summary is added as a system message after the leading system
+    /// messages, and is itself summarized by the next compaction. More than
+    /// `keep_recent` messages are kept if needed to keep a tool call
+    /// together with its results. The summary is requested directly, without
+    /// tools or the agent's own [`ContextStrategy`].
+    ///
+    /// # Errors
+    ///
+    /// Returns any error the summarization request fails with; the
+    /// conversation is left unchanged.
+    pub async fn compact(&mut self, agent: &Agent, keep_recent: usize) -> Result<usize> {
+        agent.compact(self, keep_recent).await
+    }
+
+    /// Removes the messages of `groups` and returns how many were removed.
+    fn drop_groups(&mut self, groups: &[Vec<usize>]) -> usize {
+        let mut dropped: Vec<usize> = groups.iter().flatten().copied().collect();
+        dropped.sort_unstable();
+        self.retain_positions(|index| dropped.binary_search(&index).is_err());
+        dropped.len()
+    }
+}
+
+/// Returns the range of messages [`Conversation::compact`] replaces: from
+/// the end of the leading system messages up to the kept recent groups.
+pub(crate) fn compaction_range(
+    messages: &[ChatCompletionRequestMessage],
+    keep_recent: usize,
+) -> Option<Range<usize>> {
+    let start = messages
+        .iter()
+        .take_while(|message| matches!(message, ChatCompletionRequestMessage::System(_)))
+        .count();
+    let mut kept = 0;
+    let mut end = messages.len();
+    for group in message_groups(messages).iter().rev() {
+        if kept >= keep_recent {
+            break;
+        }
+        kept += group.len();
+        end = group[0];
+    }
+    (start < end).then_some(start..end)
+}
+
+/// Renders messages as a plain-text transcript for summarization.
+pub(crate) fn transcript(messages: &[ChatCompletionRequestMessage]) -> String {
+    let messages = serde_json::to_value(messages).unwrap_or_default();
+    let mut transcript = String::new();
+    for message in messages.as_array().into_iter().flatten() {
+        let speaker = match message["role"].as_str().unwrap_or_default() {
+            "system" => "Earlier context",
+            "user" => "User",
+            "assistant" => "Assistant",
+            "tool" => "Tool result",
+            _ => continue,
+        };
+        let content = match message["content"] {
+            Value::String(ref content) => content.clone(),
+            Value::Array(ref parts) => parts
+                .iter()
+                .map(|part| part["text"].as_str().unwrap_or("[image]"))
+                .collect::<Vec<_>>()
+                .join(" "),
+            _ => String::new(),
+        };
+        if !content.is_empty() {
+            transcript.push_str(&format!("{}: {}\n", speaker, content));
+        }
+        for call in message["tool_calls"].as_array().into_iter().flatten() {
+            let function = &call["function"];
+            transcript.push_str(&format!(
+                "Assistant called {}({})\n",
+                function["name"].as_str().unwrap_or_default(),
+                function["arguments"].as_str().unwrap_or_default()
+            ));
+        }
+    }
+    transcript
+}
+
+/// Replaces the messages in `range` with a summary message.
+pub(crate) fn replace_with_summary(
+    conversation: &mut Conversation,
+    range: Range<usize>,
+    summary: &str,
+) {
+    let mut message = Conversation::new();
+    message.add_system_message(format!("{}{}", SUMMARY_PREFIX, summary.trim()));
+    conversation.replace_range(range, message.messages()[0].clone());
+}
+
+/// Splits the non-system messages into groups that must be kept or dropped
+/// together, oldest first: a message, plus the tool results that follow it.
+fn message_groups(messages: &[ChatCompletionRequestMessage]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (index, message) in messages.iter().enumerate() {
+        match message {
+            ChatCompletionRequestMessage::System(_) => {}
+            ChatCompletionRequestMessage::Tool(_) if !groups.is_empty() => {
+                groups.last_mut().unwrap().push(index);
+            }
+            _ => groups.push(vec![index]),
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::ApproxTokenCounter;
+    use async_openai::types::ChatCompletionMessageToolCall;
+    use serde_json::json;
+
+    fn tool_call(id: &str) -> ChatCompletionMessageToolCall {
+        serde_json::from_value(json!({
+            "id": id,
+            "type": "function",
+            "function": { "name": "search", "arguments": "{}" },
+        }))
+        .unwrap()
+    }
+
+    /// A conversation with two tool call rounds of two calls each.
+    fn conversation() -> Conversation {
+        let mut conversation = Conversation::with_system("You are helpful");
+        for round in 0..2 {
+            conversation.add_user_message(format!("Question {}", round));
+            let ids = [format!("call_{}a", round), format!("call_{}b", round)];
+            conversation.add_assistant_message_with_tools(
+                None,
+                ids.iter().map(|id| tool_call(id)).collect(),
+            );
+            for id in &ids {
+                conversation.add_tool_message(id, "result");
+            }
+            conversation.add_assistant_message(format!("Answer {}", round));
+        }
+        conversation
+    }
+
+    /// Asserts that every tool result follows the tool call it answers.
+    fn assert_paired(conversation: &Conversation) {
+        let value = serde_json::to_value(conversation.messages()).unwrap();
+        let mut open: Vec<String> = Vec::new();
+        for message in value.as_array().unwrap() {
+            match message["role"].as_str().unwrap() {
+                "tool" => {
+                    let id = message["tool_call_id"].as_str().unwrap();
+                    assert!(open.contains(&id.to_string()), "orphaned result {}", id);
+                    open.retain(|open| open != id);
+                }
+                _ => {
+                    assert!(open.is_empty(), "unanswered calls {:?}", open);
+                    for call in message["tool_calls"].as_array().into_iter().flatten() {
+                        open.push(call["id"].as_str().unwrap().to_string());
+                    }
+                }
+            }
+        }
+        assert!(open.is_empty(), "unanswered calls {:?}", open);
+    }
+
+    #[test]
+    fn test_truncation_never_splits_tool_calls() {
+        let full = conversation();
+        for n in 0..=full.len() {
+            let mut truncated = full.clone();
+            let dropped = truncated.truncate_to_messages(n);
+            assert_eq!(truncated.len() + dropped, full.len());
+            assert_paired(&truncated);
+            assert!(matches!(
+                truncated.messages()[0],
+                ChatCompletionRequestMessage::System(_)
+            ));
+            assert!(truncated.len() > 1);
+        }
+
+        // Keeping three messages would split the second round's tool calls.
+        let mut truncated = full.clone();
+        assert_eq!(truncated.truncate_to_messages(3), 9);
+        let value = serde_json::to_value(truncated.messages()).unwrap();
+        assert_eq!(truncated.len(), 2);
+        assert_eq!(value[1]["content"], "Answer 1");
+
+        let mut truncated = full.clone();
+        assert_eq!(truncated.truncate_to_messages(100), 0);
+    }
+
+    #[test]
+    fn test_compaction_range_keeps_tool_calls_together() {
+        let full = conversation();
+        // The last three messages would split the second round's tool calls.
+        assert_eq!(compaction_range(full.messages(), 3), Some(1..7));
+        assert_eq!(compaction_range(full.messages(), 1), Some(1..10));
+        assert_eq!(compaction_range(full.messages(), 10), None);
+        assert_eq!(compaction_range(full.messages(), 0), Some(1..11));
+
+        let mut compacted = full.clone();
+        replace_with_summary(&mut compacted, 1..6, " The user asked question 0. ");
+        assert_eq!(compacted.len(), 7);
+        assert_paired(&compacted);
+        let value = serde_json::to_value(&compacted.messages()[1]).unwrap();
+        assert_eq!(value["role"], "system");
+        assert_eq!(
+            value["content"],
+            "Summary of earlier conversation: The user asked question 0."
+        );
+    }
+
+    #[test]
+    fn test_transcript() {
+        let full = conversation();
+        let transcript = transcript(&full.messages()[1..6]);
+        assert_eq!(
+            transcript,
+            "User: Question 0\n\
+             Assistant called search({})\n\
+             Assistant called search({})\n\
+             Tool result: result\n\
+             Tool result: result\n\
+             Assistant: Answer 0\n"
+        );
+    }
+
+    #[test]
+    fn test_truncate_to_tokens() {
+        let full = conversation();
+        let total = full.token_count(&ApproxTokenCounter);
+        for budget in 0..=total {
+            let mut truncated = full.clone();
+            truncated.truncate_to_tokens(budget, &ApproxTokenCounter);
+            assert_paired(&truncated);
+            assert!(truncated.token_count(&ApproxTokenCounter) <= budget || truncated.len() == 2);
+        }
+
+        let mut truncated = full.clone();
+        assert_eq!(truncated.truncate_to_tokens(total, &ApproxTokenCounter), 0);
+        assert_eq!(
+            truncated.truncate_to_tokens(total - 1, &ApproxTokenCounter),
+            1
+        );
+    }
+}
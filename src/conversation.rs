@@ -1,18 +1,160 @@
 //! Conversation and message management for agents.
 
+use crate::error::Result;
 use async_openai::types::{
-    ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage,
-    ChatCompletionRequestToolMessage, ChatCompletionRequestUserMessage,
-    ChatCompletionRequestUserMessageContent, Role,
+    ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessage,
+    ChatCompletionRequestAssistantMessageContent, ChatCompletionRequestAssistantMessageContentPart,
+    ChatCompletionRequestDeveloperMessageContent, ChatCompletionRequestMessage,
+    ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestMessageContentPartText,
+    ChatCompletionRequestSystemMessage, ChatCompletionRequestSystemMessageContent,
+    ChatCompletionRequestSystemMessageContentPart, ChatCompletionRequestToolMessage,
+    ChatCompletionRequestToolMessageContent, ChatCompletionRequestToolMessageContentPart,
+    ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent,
+    ChatCompletionRequestUserMessageContentPart, ImageUrl,
 };
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use async_openai::types::{ImageDetail, Role};
+
+/// An image attached to a user message, for vision models.
+///
+/// ```
+/// use aiform::conversation::{ImageDetail, ImageInput};
+/// use aiform::prelude::*;
+///
+/// let mut conversation = Conversation::new();
+/// conversation.add_user_message_with_images(
+///     "What is on this receipt?",
+///     vec![
+///         ImageInput::url("https://example.com/receipt.jpg").detail(ImageDetail::High),
+///         ImageInput::bytes(b"\x89PNG...", "image/png"),
+///     ],
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageInput {
+    url: String,
+    detail: ImageDetail,
+}
+
+impl ImageInput {
+    /// An image the provider downloads from `url`.
+    pub fn url(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            detail: ImageDetail::Auto,
+        }
+    }
+
+    /// An image sent inline, base64-encoded as a data URL of type
+    /// `mime_type`, e.g. `"image/png"`.
+    pub fn bytes(data: impl AsRef<[u8]>, mime_type: &str) -> Self {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(data);
+        Self::url(format!("data:{};base64,{}", mime_type, encoded))
+    }
+
+    /// Sets the detail level the model sees the image at. Defaults to
+    /// [`ImageDetail::Auto`].
+    pub fn detail(mut self, detail: ImageDetail) -> Self {
+        self.detail = detail;
+        self
+    }
+}
+
+impl From<ImageInput> for ChatCompletionRequestUserMessageContentPart {
+    fn from(image: ImageInput) -> Self {
+        ChatCompletionRequestUserMessageContentPart::ImageUrl(
+            ChatCompletionRequestMessageContentPartImage {
+                image_url: ImageUrl {
+                    url: image.url,
+                    detail: Some(image.detail),
+                },
+            },
+        )
+    }
+}
+
+/// How [`Conversation::to_markdown`] and [`Conversation::to_text`] render a
+/// conversation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptOptions {
+    /// Whether system and developer messages are shown. Defaults to `true`.
+    pub include_system: bool,
+    /// Tool results longer than this many characters are cut off. Defaults
+    /// to no limit.
+    pub max_tool_output_len: Option<usize>,
+    /// Whether tool calls are shown with their ids. Defaults to `false`.
+    pub include_tool_call_ids: bool,
+}
+
+impl Default for TranscriptOptions {
+    fn default() -> Self {
+        Self {
+            include_system: true,
+            max_tool_output_len: None,
+            include_tool_call_ids: false,
+        }
+    }
+}
 
 /// A conversation consisting of multiple messages.
 ///
 /// Manages the message history for an agent, including user messages,
 /// assistant responses, and tool call results.
-#[derive(Debug, Clone, Default)]
+///
+/// Every message carries [`MessageMetadata`] that is never sent to the API;
+/// see [`entries`](Self::entries).
+///
+/// Serializes as `{"messages": [...], "metadata": [...]}`, with messages in
+/// the chat completions format, e.g. for persisting with
+/// [`to_json`](Self::to_json) and [`from_json`](Self::from_json).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Conversation {
     messages: Vec<ChatCompletionRequestMessage>,
+    /// Metadata by message position. Messages added through `messages_mut`
+    /// have none until the next change made through a method.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    metadata: Vec<MessageMetadata>,
+}
+
+/// Information about a message that is not sent to the API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageMetadata {
+    /// When the message was added, or [`UNIX_EPOCH`] if unknown.
+    pub created_at: SystemTime,
+    /// Key/value tags, e.g. where the message came from.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tags: HashMap<String, String>,
+}
+
+impl MessageMetadata {
+    fn now() -> Self {
+        Self {
+            created_at: SystemTime::now(),
+            tags: HashMap::new(),
+        }
+    }
+
+    fn unknown() -> Self {
+        Self {
+            created_at: UNIX_EPOCH,
+            tags: HashMap::new(),
+        }
+    }
+}
+
+/// A message together with its metadata, as returned by
+/// [`Conversation::entries`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConversationEntry<'a> {
+    /// The message as sent to the API.
+    pub message: &'a ChatCompletionRequestMessage,
+    /// The message's metadata.
+    pub metadata: &'a MessageMetadata,
 }
 
 impl Conversation {
@@ -20,6 +162,7 @@ impl Conversation {
     pub fn new() -> Self {
         Self {
             messages: Vec::new(),
+            metadata: Vec::new(),
         }
     }
 
@@ -32,10 +175,9 @@ impl Conversation {
 
     /// Adds a system message to the conversation.
     pub fn add_system_message(&mut self, content: impl Into<String>) {
-        self.messages.push(ChatCompletionRequestMessage::System(
-            async_openai::types::ChatCompletionRequestSystemMessage {
-                content: content.into(),
-                role: Role::System,
+        self.push(ChatCompletionRequestMessage::System(
+            ChatCompletionRequestSystemMessage {
+                content: ChatCompletionRequestSystemMessageContent::Text(content.into()),
                 name: None,
             },
         ));
@@ -43,10 +185,56 @@ impl Conversation {
 
     /// Adds a user message to the conversation.
     pub fn add_user_message(&mut self, content: impl Into<String>) {
-        self.messages.push(ChatCompletionRequestMessage::User(
+        self.push(ChatCompletionRequestMessage::User(
             ChatCompletionRequestUserMessage {
                 content: ChatCompletionRequestUserMessageContent::Text(content.into()),
-                role: Role::User,
+                name: None,
+            },
+        ));
+    }
+
+    /// Adds a user message with tags, e.g. where the message came from.
+    ///
+    /// ```
+    /// use aiform::prelude::*;
+    ///
+    /// let mut conversation = Conversation::new();
+    /// conversation.add_user_message_tagged("Flights to Lisbon?", [("source", "web")]);
+    /// assert_eq!(conversation.find_by_tag("source", "web").count(), 1);
+    /// ```
+    pub fn add_user_message_tagged<K, V>(
+        &mut self,
+        content: impl Into<String>,
+        tags: impl IntoIterator<Item = (K, V)>,
+    ) where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.add_user_message(content);
+        let metadata = self.metadata.last_mut().expect("message was just added");
+        metadata.tags = tags
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect();
+    }
+
+    /// Adds a user message with images, for vision models.
+    ///
+    /// The message's content is the text followed by one part per image.
+    pub fn add_user_message_with_images(
+        &mut self,
+        content: impl Into<String>,
+        images: Vec<ImageInput>,
+    ) {
+        let mut parts = vec![ChatCompletionRequestUserMessageContentPart::Text(
+            ChatCompletionRequestMessageContentPartText {
+                text: content.into(),
+            },
+        )];
+        parts.extend(images.into_iter().map(Into::into));
+        self.push(ChatCompletionRequestMessage::User(
+            ChatCompletionRequestUserMessage {
+                content: ChatCompletionRequestUserMessageContent::Array(parts),
                 name: None,
             },
         ));
@@ -54,9 +242,11 @@ impl Conversation {
 
     /// Adds an assistant message to the conversation.
     pub fn add_assistant_message(&mut self, content: impl Into<String>) {
-        self.messages.push(ChatCompletionRequestMessage::Assistant(
+        self.push(ChatCompletionRequestMessage::Assistant(
             ChatCompletionRequestAssistantMessage {
-                content: Some(content.into()),
+                content: Some(ChatCompletionRequestAssistantMessageContent::Text(
+                    content.into(),
+                )),
                 tool_calls: None,
                 ..Default::default()
             },
@@ -69,9 +259,9 @@ impl Conversation {
         content: Option<String>,
         tool_calls: Vec<async_openai::types::ChatCompletionMessageToolCall>,
     ) {
-        self.messages.push(ChatCompletionRequestMessage::Assistant(
+        self.push(ChatCompletionRequestMessage::Assistant(
             ChatCompletionRequestAssistantMessage {
-                content,
+                content: content.map(ChatCompletionRequestAssistantMessageContent::Text),
                 tool_calls: Some(tool_calls),
                 ..Default::default()
             },
@@ -84,11 +274,10 @@ impl Conversation {
         tool_call_id: impl Into<String>,
         content: impl Into<String>,
     ) {
-        self.messages.push(ChatCompletionRequestMessage::Tool(
+        self.push(ChatCompletionRequestMessage::Tool(
             ChatCompletionRequestToolMessage {
-                role: Role::Tool,
                 tool_call_id: tool_call_id.into(),
-                content: content.into(),
+                content: ChatCompletionRequestToolMessageContent::Text(content.into()),
             },
         ));
     }
@@ -99,10 +288,79 @@ impl Conversation {
     }
 
     /// Returns a mutable reference to all messages in the conversation.
+    ///
+    /// Metadata is kept by position: messages added here have an unknown
+    /// creation time and no tags, and removing or reordering messages here
+    /// leaves the metadata where it was.
     pub fn messages_mut(&mut self) -> &mut Vec<ChatCompletionRequestMessage> {
         &mut self.messages
     }
 
+    /// Iterates over the messages together with their metadata.
+    pub fn entries(&self) -> impl Iterator<Item = ConversationEntry<'_>> {
+        static UNKNOWN: OnceLock<MessageMetadata> = OnceLock::new();
+        let unknown = UNKNOWN.get_or_init(MessageMetadata::unknown);
+        self.messages
+            .iter()
+            .enumerate()
+            .map(move |(index, message)| ConversationEntry {
+                message,
+                metadata: self.metadata.get(index).unwrap_or(unknown),
+            })
+    }
+
+    /// Iterates over the messages tagged with `key` set to `value`.
+    pub fn find_by_tag<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a str,
+    ) -> impl Iterator<Item = ConversationEntry<'a>> {
+        self.entries()
+            .filter(move |entry| entry.metadata.tags.get(key).is_some_and(|tag| tag == value))
+    }
+
+    /// Adds a message, stamped with the current time.
+    fn push(&mut self, message: ChatCompletionRequestMessage) {
+        self.sync_metadata();
+        self.messages.push(message);
+        self.metadata.push(MessageMetadata::now());
+    }
+
+    /// Gives every message metadata again after changes made through
+    /// `messages_mut`.
+    fn sync_metadata(&mut self) {
+        self.metadata
+            .resize_with(self.messages.len(), MessageMetadata::unknown);
+    }
+
+    /// Shortens the conversation to its first `len` messages.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.sync_metadata();
+        self.messages.truncate(len);
+        self.metadata.truncate(len);
+    }
+
+    /// Keeps the messages for which `keep` returns true, by position.
+    pub(crate) fn retain_positions(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        self.sync_metadata();
+        let kept: Vec<bool> = (0..self.messages.len()).map(&mut keep).collect();
+        let mut flags = kept.iter();
+        self.messages.retain(|_| *flags.next().unwrap());
+        let mut flags = kept.iter();
+        self.metadata.retain(|_| *flags.next().unwrap());
+    }
+
+    /// Replaces the messages in `range` with `message`, added now.
+    pub(crate) fn replace_range(
+        &mut self,
+        range: std::ops::Range<usize>,
+        message: ChatCompletionRequestMessage,
+    ) {
+        self.sync_metadata();
+        self.messages.splice(range.clone(), [message]);
+        self.metadata.splice(range, [MessageMetadata::now()]);
+    }
+
     /// Returns the number of messages in the conversation.
     pub fn len(&self) -> usize {
         self.messages.len()
@@ -116,9 +374,394 @@ impl Conversation {
     /// Clears all messages from the conversation.
     pub fn clear(&mut self) {
         self.messages.clear();
+        self.metadata.clear();
+    }
+
+    /// Returns an independent copy of the conversation, to continue it in
+    /// a different direction.
+    pub fn fork(&self) -> Conversation {
+        self.clone()
+    }
+
+    /// Returns a new conversation with the messages before `index`, e.g. to
+    /// ask for an alternative to message `index`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SplitToolRound`](crate::Error::SplitToolRound) if
+    /// message `index` is a tool result, since the branch would have tool
+    /// calls without all of their results.
+    pub fn branch_at(&self, index: usize) -> Result<Conversation> {
+        let end = self.cut_point(index)?;
+        let mut branch = Conversation {
+            messages: self.messages[..end].to_vec(),
+            metadata: self.metadata.clone(),
+        };
+        branch.truncate(end);
+        Ok(branch)
+    }
+
+    /// Removes every message after `index`, keeping message `index`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SplitToolRound`](crate::Error::SplitToolRound) if
+    /// the message after `index` is a tool result of a kept tool call. The
+    /// conversation is left unchanged.
+    pub fn truncate_after(&mut self, index: usize) -> Result<()> {
+        let end = self.cut_point(index.saturating_add(1))?;
+        self.truncate(end);
+        Ok(())
+    }
+
+    /// Checks that keeping the messages before `end` keeps tool calls and
+    /// their results together.
+    fn cut_point(&self, end: usize) -> Result<usize> {
+        match self.messages.get(end) {
+            Some(ChatCompletionRequestMessage::Tool(_)) => {
+                Err(crate::Error::SplitToolRound { index: end })
+            }
+            _ => Ok(end.min(self.messages.len())),
+        }
+    }
+
+    /// Returns the latest message, if any.
+    pub fn last_message(&self) -> Option<&ChatCompletionRequestMessage> {
+        self.messages.last()
+    }
+
+    /// Returns the text of the latest assistant message that has text,
+    /// skipping tool-call messages without content.
+    pub fn last_assistant_text(&self) -> Option<&str> {
+        self.iter_role(Role::Assistant).rev().find_map(message_text)
+    }
+
+    /// Returns the text of the latest user message.
+    pub fn last_user_text(&self) -> Option<&str> {
+        self.iter_role(Role::User).rev().find_map(message_text)
+    }
+
+    /// Iterates over the messages with `role`, in order.
+    ///
+    /// Developer messages count as [`Role::System`].
+    pub fn iter_role(
+        &self,
+        role: Role,
+    ) -> impl DoubleEndedIterator<Item = &ChatCompletionRequestMessage> {
+        self.messages
+            .iter()
+            .filter(move |message| message_role(message) == role)
+    }
+
+    /// Returns the system prompt: the text of the system message the
+    /// conversation starts with, if any.
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.messages
+            .first()
+            .filter(|message| matches!(message, ChatCompletionRequestMessage::System(_)))
+            .and_then(message_text)
+    }
+
+    /// Replaces the system prompt, or inserts it at the start if the
+    /// conversation has none.
+    ///
+    /// Unlike [`add_system_message`](Self::add_system_message), this never
+    /// leaves two system prompts, which some providers reject.
+    pub fn set_system_prompt(&mut self, content: impl Into<String>) {
+        let message = ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+            content: ChatCompletionRequestSystemMessageContent::Text(content.into()),
+            name: None,
+        });
+        self.sync_metadata();
+        match self.messages.first_mut() {
+            Some(first @ ChatCompletionRequestMessage::System(_)) => *first = message,
+            _ => {
+                self.messages.insert(0, message);
+                self.metadata.insert(0, MessageMetadata::now());
+            }
+        }
+    }
+
+    /// Removes the system prompt, if the conversation starts with one.
+    pub fn remove_system_prompt(&mut self) {
+        if let Some(ChatCompletionRequestMessage::System(_)) = self.messages.first() {
+            self.sync_metadata();
+            self.messages.remove(0);
+            self.metadata.remove(0);
+        }
+    }
+
+    /// Pairs every tool call the assistant made with its result, matched by
+    /// tool call id, in the order of the calls.
+    ///
+    /// Calls that have not been answered yet are paired with `None`.
+    pub fn tool_interactions(&self) -> Vec<(&ChatCompletionMessageToolCall, Option<&str>)> {
+        let calls = self.messages.iter().flat_map(|message| match message {
+            ChatCompletionRequestMessage::Assistant(message) => {
+                message.tool_calls.as_deref().unwrap_or_default()
+            }
+            _ => &[],
+        });
+        calls
+            .map(|call| {
+                let result = self.messages.iter().find_map(|message| match message {
+                    ChatCompletionRequestMessage::Tool(tool) if tool.tool_call_id == call.id => {
+                        message_text(message)
+                    }
+                    _ => None,
+                });
+                (call, result)
+            })
+            .collect()
+    }
+
+    /// Renders the conversation as Markdown, e.g. for showing chat history.
+    ///
+    /// Every message is shown with its role. Tool calls are shown with the
+    /// tool name, the pretty-printed arguments and the matching result.
+    pub fn to_markdown(&self, options: &TranscriptOptions) -> String {
+        self.transcript(options, true)
+    }
+
+    /// Renders the conversation as plain text, like
+    /// [`to_markdown`](Self::to_markdown) without the formatting.
+    pub fn to_text(&self, options: &TranscriptOptions) -> String {
+        self.transcript(options, false)
+    }
+
+    fn transcript(&self, options: &TranscriptOptions, markdown: bool) -> String {
+        let calls: HashSet<&str> = self
+            .tool_interactions()
+            .into_iter()
+            .map(|(call, _)| call.id.as_str())
+            .collect();
+        let results: HashMap<&str, &str> = self
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                ChatCompletionRequestMessage::Tool(tool) => {
+                    Some((tool.tool_call_id.as_str(), message_text(message)?))
+                }
+                _ => None,
+            })
+            .rev()
+            .collect();
+        let tool_output = |output: &str| match options.max_tool_output_len {
+            Some(max) if output.chars().count() > max => {
+                let kept: String = output.chars().take(max).collect();
+                let total = output.chars().count();
+                format!("{}… [truncated from {} characters]", kept, total)
+            }
+            _ => output.to_string(),
+        };
+
+        let mut blocks = Vec::new();
+        for message in &self.messages {
+            let speaker = match message {
+                ChatCompletionRequestMessage::Developer(_) => "Developer",
+                ChatCompletionRequestMessage::System(_) => "System",
+                ChatCompletionRequestMessage::User(_) => "User",
+                ChatCompletionRequestMessage::Assistant(_) => "Assistant",
+                ChatCompletionRequestMessage::Tool(tool) => {
+                    // Results are shown with their calls.
+                    if calls.contains(tool.tool_call_id.as_str()) {
+                        continue;
+                    }
+                    "Tool"
+                }
+                ChatCompletionRequestMessage::Function(_) => "Function",
+            };
+            if message_role(message) == Role::System && !options.include_system {
+                continue;
+            }
+
+            let mut content = content_text(message);
+            match message {
+                ChatCompletionRequestMessage::Tool(tool) => {
+                    content = tool_output(&content);
+                    if options.include_tool_call_ids {
+                        content = format!("({}) {}", tool.tool_call_id, content);
+                    }
+                }
+                ChatCompletionRequestMessage::Function(function) => {
+                    content = format!("{} returned {}", function.name, content);
+                }
+                _ => {}
+            }
+            if !content.is_empty() {
+                blocks.push(match markdown {
+                    true => format!("**{}:** {}", speaker, content),
+                    false => format!("{}: {}", speaker, content),
+                });
+            }
+
+            let ChatCompletionRequestMessage::Assistant(assistant) = message else {
+                continue;
+            };
+            for call in assistant.tool_calls.iter().flatten() {
+                let function = &call.function;
+                let arguments = serde_json::from_str::<serde_json::Value>(&function.arguments)
+                    .and_then(|arguments| serde_json::to_string_pretty(&arguments))
+                    .unwrap_or_else(|_| function.arguments.clone());
+                let result = results
+                    .get(call.id.as_str())
+                    .map(|result| tool_output(result));
+                let block = if markdown {
+                    let mut block = format!("**Assistant** called `{}`", function.name);
+                    if options.include_tool_call_ids {
+                        block.push_str(&format!(" (`{}`)", call.id));
+                    }
+                    block.push_str(&format!(":\n{}", fenced(&arguments, "json")));
+                    match result {
+                        Some(result) => {
+                            block.push_str(&format!("\n**Result:**\n{}", fenced(&result, "")))
+                        }
+                        None => block.push_str("\n*No result yet.*"),
+                    }
+                    block
+                } else {
+                    let mut block = format!("Assistant called {}", function.name);
+                    if options.include_tool_call_ids {
+                        block.push_str(&format!(" ({})", call.id));
+                    }
+                    block.push_str(&format!(":\n{}", arguments));
+                    match result {
+                        Some(result) => block.push_str(&format!("\nResult: {}", result)),
+                        None => block.push_str("\nNo result yet."),
+                    }
+                    block
+                };
+                blocks.push(block);
+            }
+        }
+        blocks.join("\n\n")
+    }
+
+    /// Serializes the conversation as JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Json`](crate::Error::Json) if a message cannot be serialized.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Restores a conversation serialized with [`to_json`](Self::to_json).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Json`](crate::Error::Json) if `json` is not a serialized conversation.
+    pub fn from_json(json: &str) -> Result<Conversation> {
+        Ok(serde_json::from_str(json)?)
     }
 }
 
+/// The role a message was sent with.
+fn message_role(message: &ChatCompletionRequestMessage) -> Role {
+    match message {
+        ChatCompletionRequestMessage::Developer(_) | ChatCompletionRequestMessage::System(_) => {
+            Role::System
+        }
+        ChatCompletionRequestMessage::User(_) => Role::User,
+        ChatCompletionRequestMessage::Assistant(_) => Role::Assistant,
+        ChatCompletionRequestMessage::Tool(_) => Role::Tool,
+        ChatCompletionRequestMessage::Function(_) => Role::Function,
+    }
+}
+
+/// The text of a message: its content, or the first text part of content
+/// made of parts.
+fn message_text(message: &ChatCompletionRequestMessage) -> Option<&str> {
+    match message {
+        ChatCompletionRequestMessage::Developer(message) => match message.content {
+            ChatCompletionRequestDeveloperMessageContent::Text(ref text) => Some(text),
+            ChatCompletionRequestDeveloperMessageContent::Array(ref parts) => {
+                parts.first().map(|part| part.text.as_str())
+            }
+        },
+        ChatCompletionRequestMessage::System(message) => match message.content {
+            ChatCompletionRequestSystemMessageContent::Text(ref text) => Some(text),
+            ChatCompletionRequestSystemMessageContent::Array(ref parts) => {
+                parts.first().map(|part| match part {
+                    ChatCompletionRequestSystemMessageContentPart::Text(part) => &*part.text,
+                })
+            }
+        },
+        ChatCompletionRequestMessage::User(message) => match message.content {
+            ChatCompletionRequestUserMessageContent::Text(ref text) => Some(text),
+            ChatCompletionRequestUserMessageContent::Array(ref parts) => {
+                parts.iter().find_map(|part| match part {
+                    ChatCompletionRequestUserMessageContentPart::Text(part) => Some(&*part.text),
+                    _ => None,
+                })
+            }
+        },
+        ChatCompletionRequestMessage::Assistant(message) => match message.content {
+            Some(ChatCompletionRequestAssistantMessageContent::Text(ref text)) => Some(text),
+            Some(ChatCompletionRequestAssistantMessageContent::Array(ref parts)) => {
+                parts.iter().find_map(|part| match part {
+                    ChatCompletionRequestAssistantMessageContentPart::Text(part) => {
+                        Some(&*part.text)
+                    }
+                    _ => None,
+                })
+            }
+            None => None,
+        },
+        ChatCompletionRequestMessage::Tool(message) => match message.content {
+            ChatCompletionRequestToolMessageContent::Text(ref text) => Some(text),
+            ChatCompletionRequestToolMessageContent::Array(ref parts) => {
+                parts.first().map(|part| match part {
+                    ChatCompletionRequestToolMessageContentPart::Text(part) => &*part.text,
+                })
+            }
+        },
+        ChatCompletionRequestMessage::Function(message) => message.content.as_deref(),
+    }
+}
+
+/// All of a message's content as text, with placeholders for images and
+/// audio.
+fn content_text(message: &ChatCompletionRequestMessage) -> String {
+    let parts: Vec<&str> = match message {
+        ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+            content: ChatCompletionRequestUserMessageContent::Array(parts),
+            ..
+        }) => parts
+            .iter()
+            .map(|part| match part {
+                ChatCompletionRequestUserMessageContentPart::Text(part) => &*part.text,
+                ChatCompletionRequestUserMessageContentPart::ImageUrl(_) => "[image]",
+                ChatCompletionRequestUserMessageContentPart::InputAudio(_) => "[audio]",
+            })
+            .collect(),
+        ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
+            content: Some(ChatCompletionRequestAssistantMessageContent::Array(parts)),
+            ..
+        }) => parts
+            .iter()
+            .map(|part| match part {
+                ChatCompletionRequestAssistantMessageContentPart::Text(part) => &*part.text,
+                ChatCompletionRequestAssistantMessageContentPart::Refusal(part) => &*part.refusal,
+            })
+            .collect(),
+        // Other messages rarely split their content into parts.
+        _ => message_text(message).into_iter().collect(),
+    };
+    parts.join(" ")
+}
+
+/// Wraps `text` in a Markdown code fence longer than any backtick run in it.
+fn fenced(text: &str, language: &str) -> String {
+    let longest = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{}{}\n{}\n{}", fence, language, text, fence)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -146,6 +789,53 @@ mod tests {
         assert!(!conv.is_empty());
     }
 
+    #[test]
+    fn test_assistant_messages_have_assistant_role() {
+        let mut conv = Conversation::new();
+        conv.add_assistant_message("Hi there!");
+        conv.add_assistant_message_with_tools(None, vec![]);
+
+        for message in conv.messages() {
+            let value = serde_json::to_value(message).unwrap();
+            assert_eq!(value["role"], "assistant");
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut conv = Conversation::with_system("You book flights");
+        conv.add_user_message("Flights to Lisbon?");
+        let tool_call: async_openai::types::ChatCompletionMessageToolCall =
+            serde_json::from_value(serde_json::json!({
+                "id": "call_1",
+                "type": "function",
+                "function": { "name": "search_flights", "arguments": "{\"to\":\"Lisbon\"}" },
+            }))
+            .unwrap();
+        conv.add_assistant_message_with_tools(None, vec![tool_call]);
+        conv.add_tool_message("call_1", "2 flights");
+        conv.add_assistant_message("There are 2 flights.");
+
+        let json = conv.to_json().unwrap();
+        let restored = Conversation::from_json(&json).unwrap();
+        assert_eq!(restored, conv);
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let messages = &value["messages"];
+        assert_eq!(messages[2]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(
+            messages[2]["tool_calls"][0]["function"]["name"],
+            "search_flights"
+        );
+        assert_eq!(messages[3]["role"], "tool");
+        assert_eq!(messages[3]["tool_call_id"], "call_1");
+
+        assert!(matches!(
+            Conversation::from_json("[1, 2]"),
+            Err(crate::Error::Json(_))
+        ));
+    }
+
     #[test]
     fn test_clear() {
         let mut conv = Conversation::new();
@@ -155,4 +845,341 @@ mod tests {
         assert_eq!(conv.len(), 0);
         assert!(conv.is_empty());
     }
+
+    #[test]
+    fn test_user_message_with_images() {
+        let mut conv = Conversation::new();
+        conv.add_user_message_with_images(
+            "What is this?",
+            vec![
+                ImageInput::url("https://example.com/cat.jpg").detail(ImageDetail::Low),
+                ImageInput::bytes(b"png", "image/png"),
+            ],
+        );
+
+        let value = serde_json::to_value(&conv.messages()[0]).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": "What is this?" },
+                    {
+                        "type": "image_url",
+                        "image_url": { "url": "https://example.com/cat.jpg", "detail": "low" },
+                    },
+                    {
+                        "type": "image_url",
+                        "image_url": { "url": "data:image/png;base64,cG5n", "detail": "auto" },
+                    },
+                ],
+            })
+        );
+        let message = crate::msg!(user "What is this?", images: [
+            ImageInput::url("https://example.com/cat.jpg").detail(ImageDetail::Low),
+            ImageInput::bytes(b"png", "image/png"),
+        ]);
+        assert_eq!(message, conv.messages()[0]);
+    }
+
+    #[test]
+    fn test_accessors() {
+        let empty = Conversation::new();
+        assert!(empty.last_message().is_none());
+        assert!(empty.last_assistant_text().is_none());
+        assert!(empty.system_prompt().is_none());
+        assert!(empty.tool_interactions().is_empty());
+
+        let mut conv = Conversation::with_system("You book flights");
+        conv.add_user_message("Flights to Lisbon or Porto?");
+        let tool_calls: Vec<ChatCompletionMessageToolCall> =
+            serde_json::from_value(serde_json::json!([
+                {
+                    "id": "call_1",
+                    "type": "function",
+                    "function": { "name": "search_flights", "arguments": "{\"to\":\"LIS\"}" },
+                },
+                {
+                    "id": "call_2",
+                    "type": "function",
+                    "function": { "name": "search_flights", "arguments": "{\"to\":\"OPO\"}" },
+                },
+            ]))
+            .unwrap();
+        conv.add_assistant_message_with_tools(None, tool_calls);
+        conv.add_tool_message("call_2", "1 flight");
+        conv.add_tool_message("call_1", "2 flights");
+        conv.add_assistant_message("Lisbon has more flights.");
+        conv.add_user_message_with_images(
+            "And this one?",
+            vec![ImageInput::url("https://example.com/ticket.jpg")],
+        );
+        let tool_call: ChatCompletionMessageToolCall = serde_json::from_value(serde_json::json!({
+            "id": "call_3",
+            "type": "function",
+            "function": { "name": "read_ticket", "arguments": "{}" },
+        }))
+        .unwrap();
+        conv.add_assistant_message_with_tools(None, vec![tool_call]);
+
+        assert_eq!(conv.system_prompt(), Some("You book flights"));
+        assert_eq!(conv.last_user_text(), Some("And this one?"));
+        // The pending tool call has no text.
+        assert_eq!(conv.last_assistant_text(), Some("Lisbon has more flights."));
+        assert_eq!(conv.last_message(), conv.messages().last());
+        assert_eq!(conv.iter_role(Role::User).count(), 2);
+        assert_eq!(conv.iter_role(Role::Assistant).count(), 3);
+        assert_eq!(conv.iter_role(Role::Tool).count(), 2);
+
+        let interactions: Vec<_> = conv
+            .tool_interactions()
+            .into_iter()
+            .map(|(call, result)| (call.id.as_str(), result))
+            .collect();
+        assert_eq!(
+            interactions,
+            [
+                ("call_1", Some("2 flights")),
+                ("call_2", Some("1 flight")),
+                ("call_3", None),
+            ]
+        );
+    }
+
+    /// A conversation with every kind of message.
+    fn transcript_conversation() -> Conversation {
+        let mut conv = Conversation::with_system("You book flights");
+        conv.messages_mut().push(
+            serde_json::from_value(serde_json::json!({
+                "role": "developer",
+                "content": "Prefer direct flights",
+            }))
+            .unwrap(),
+        );
+        conv.add_user_message_with_images(
+            "Where does this ticket go?",
+            vec![ImageInput::url("https://example.com/ticket.jpg")],
+        );
+        let tool_calls: Vec<ChatCompletionMessageToolCall> =
+            serde_json::from_value(serde_json::json!([
+                {
+                    "id": "call_1",
+                    "type": "function",
+                    "function": { "name": "search_flights", "arguments": "{\"to\":\"LIS\"}" },
+                },
+                {
+                    "id": "call_2",
+                    "type": "function",
+                    "function": { "name": "get_prices", "arguments": "{}" },
+                },
+            ]))
+            .unwrap();
+        conv.add_assistant_message_with_tools(Some("Let me look.".into()), tool_calls);
+        conv.add_tool_message("call_1", "TP1350 08:15, TP1360 19:40");
+        conv.add_tool_message("call_2", "```€129.50```");
+        conv.add_tool_message("call_9", "stale result");
+        conv.messages_mut().push(
+            serde_json::from_value(serde_json::json!({
+                "role": "function",
+                "name": "legacy_lookup",
+                "content": "ok",
+            }))
+            .unwrap(),
+        );
+        conv.add_assistant_message("There are two direct flights to Lisbon.");
+        let tool_call: ChatCompletionMessageToolCall = serde_json::from_value(serde_json::json!({
+            "id": "call_3",
+            "type": "function",
+            "function": { "name": "hold_seat", "arguments": "not json" },
+        }))
+        .unwrap();
+        conv.add_assistant_message_with_tools(None, vec![tool_call]);
+        conv
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        let markdown = transcript_conversation().to_markdown(&TranscriptOptions::default());
+        let expected = r##"**System:** You book flights
+
+**Developer:** Prefer direct flights
+
+**User:** Where does this ticket go? [image]
+
+**Assistant:** Let me look.
+
+**Assistant** called `search_flights`:
+```json
+{
+  "to": "LIS"
+}
+```
+**Result:**
+```
+TP1350 08:15, TP1360 19:40
+```
+
+**Assistant** called `get_prices`:
+```json
+{}
+```
+**Result:**
+````
+```€129.50```
+````
+
+**Tool:** stale result
+
+**Function:** legacy_lookup returned ok
+
+**Assistant:** There are two direct flights to Lisbon.
+
+**Assistant** called `hold_seat`:
+```json
+not json
+```
+*No result yet.*"##;
+        assert_eq!(markdown, expected);
+    }
+
+    #[test]
+    fn test_to_text() {
+        let options = TranscriptOptions {
+            include_system: false,
+            max_tool_output_len: Some(12),
+            include_tool_call_ids: true,
+        };
+        let text = transcript_conversation().to_text(&options);
+        let expected = r##"User: Where does this ticket go? [image]
+
+Assistant: Let me look.
+
+Assistant called search_flights (call_1):
+{
+  "to": "LIS"
+}
+Result: TP1350 08:15… [truncated from 26 characters]
+
+Assistant called get_prices (call_2):
+{}
+Result: ```€129.50``… [truncated from 13 characters]
+
+Tool: (call_9) stale result
+
+Function: legacy_lookup returned ok
+
+Assistant: There are two direct flights to Lisbon.
+
+Assistant called hold_seat (call_3):
+not json
+No result yet."##;
+        assert_eq!(text, expected);
+    }
+
+    #[test]
+    fn test_set_system_prompt() {
+        let mut conv = Conversation::new();
+        conv.add_user_message("Flights to Lisbon?");
+        conv.set_system_prompt("You book flights");
+        assert_eq!(conv.len(), 2);
+        assert_eq!(conv.system_prompt(), Some("You book flights"));
+
+        conv.set_system_prompt("You book flights. Today is 2024-05-01.");
+        assert_eq!(conv.len(), 2);
+        assert_eq!(conv.iter_role(Role::System).count(), 1);
+        assert_eq!(
+            conv.system_prompt(),
+            Some("You book flights. Today is 2024-05-01.")
+        );
+        assert_eq!(conv.last_user_text(), Some("Flights to Lisbon?"));
+
+        conv.remove_system_prompt();
+        assert_eq!(conv.len(), 1);
+        assert!(conv.system_prompt().is_none());
+        conv.remove_system_prompt();
+        assert_eq!(conv.len(), 1);
+    }
+
+    #[test]
+    fn test_fork_and_branch() {
+        let mut conv = Conversation::with_system("You book flights");
+        conv.add_user_message("Flights to Lisbon?");
+        let tool_call: ChatCompletionMessageToolCall = serde_json::from_value(serde_json::json!({
+            "id": "call_1",
+            "type": "function",
+            "function": { "name": "search_flights", "arguments": "{\"to\":\"LIS\"}" },
+        }))
+        .unwrap();
+        conv.add_assistant_message_with_tools(None, vec![tool_call]);
+        conv.add_tool_message("call_1", "2 flights");
+        conv.add_assistant_message("There are 2 flights.");
+
+        let mut fork = conv.fork();
+        fork.add_user_message("And Porto?");
+        assert_eq!(conv.len(), 5);
+        assert_eq!(fork.len(), 6);
+
+        // Right before the tool interaction.
+        let before = conv.branch_at(2).unwrap();
+        assert_eq!(before.last_user_text(), Some("Flights to Lisbon?"));
+        assert_eq!(before.len(), 2);
+        // Right after it, to regenerate the answer.
+        let after = conv.branch_at(4).unwrap();
+        assert_eq!(after.len(), 4);
+        assert_eq!(after.tool_interactions()[0].1, Some("2 flights"));
+        // Between the call and its result.
+        assert!(matches!(
+            conv.branch_at(3),
+            Err(crate::Error::SplitToolRound { index: 3 })
+        ));
+        assert_eq!(conv.branch_at(10).unwrap(), conv);
+
+        let mut truncated = conv.fork();
+        assert!(truncated.truncate_after(2).is_err());
+        assert_eq!(truncated, conv);
+        truncated.truncate_after(3).unwrap();
+        assert_eq!(truncated, after);
+        truncated.truncate_after(1).unwrap();
+        assert_eq!(truncated, before);
+    }
+
+    #[test]
+    fn test_message_metadata() {
+        let started = SystemTime::now();
+        let mut conv = Conversation::with_system("You book flights");
+        conv.add_user_message_tagged("Flights to Lisbon?", [("source", "web"), ("user", "42")]);
+        conv.add_assistant_message("There are 2 flights.");
+        conv.add_user_message_tagged("And Porto?", [("source", "app")]);
+        conv.messages_mut().push(
+            serde_json::from_value(serde_json::json!({ "role": "user", "content": "Hi" })).unwrap(),
+        );
+
+        let entries: Vec<_> = conv.entries().collect();
+        assert_eq!(entries.len(), 5);
+        assert!(entries[..4]
+            .iter()
+            .all(|entry| entry.metadata.created_at >= started));
+        assert_eq!(entries[4].metadata.created_at, UNIX_EPOCH);
+        assert_eq!(entries[1].metadata.tags["user"], "42");
+        assert!(entries[2].metadata.tags.is_empty());
+
+        let web: Vec<_> = conv.find_by_tag("source", "web").collect();
+        assert_eq!(web.len(), 1);
+        assert_eq!(web[0].message, &conv.messages()[1]);
+        assert_eq!(conv.find_by_tag("source", "email").count(), 0);
+
+        // Metadata is persisted, but never sent.
+        let restored = Conversation::from_json(&conv.to_json().unwrap()).unwrap();
+        assert_eq!(restored, conv);
+        let message = serde_json::to_value(&conv.messages()[1]).unwrap();
+        assert!(message.get("tags").is_none());
+
+        // Conversations saved without metadata still load.
+        let old = Conversation::from_json(r#"{"messages": [{ "role": "user", "content": "Hi" }]}"#)
+            .unwrap();
+        assert_eq!(
+            old.entries().next().unwrap().metadata.created_at,
+            UNIX_EPOCH
+        );
+    }
 }
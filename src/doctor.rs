@@ -0,0 +1,712 @@
+//! Setup diagnostics for new installations.
+//!
+//! [`doctor`] runs a sequence of cheap checks against a client and reports
+//! which part of the setup is broken, with a hint on how to fix it:
+//!
+//! 1. [`Check::Environment`]: the API key environment variable is set.
+//! 2. [`Check::Reachability`]: the base URL accepts TCP connections.
+//! 3. [`Check::Models`]: the models list can be fetched with the key, and
+//!    the configured model is on it.
+//! 4. [`Check::Completion`]: a one-token completion succeeds.
+//! 5. [`Check::ToolCalling`]: the model calls a trivial tool when asked to.
+//!
+//! Every check is bounded by a timeout and can be skipped. When the base URL
+//! is unreachable, the checks that need the network are skipped.
+//!
+//! ```no_run
+//! use aiform::doctor::{Check, Doctor};
+//! use async_openai::{config::OpenAIConfig, Client};
+//!
+//! # async fn example() {
+//! let client = Client::with_config(
+//!     OpenAIConfig::new().with_api_base("https://openrouter.ai/api/v1"),
+//! );
+//! let report = Doctor::new(client)
+//!     .model("openai/gpt-4o-mini")
+//!     .api_key_env("OPENROUTER_API_KEY")
+//!     .skip(Check::ToolCalling)
+//!     .run()
+//!     .await;
+//! print!("{}", report);
+//! # }
+//! ```
+
+use crate::{agent::function_tool, backend::ChatBackend};
+use async_openai::{config::Config, error::OpenAIError, Client};
+use serde_json::json;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Environment variables that route HTTP traffic through a proxy.
+const PROXY_VARS: [&str; 6] = [
+    "HTTPS_PROXY",
+    "https_proxy",
+    "HTTP_PROXY",
+    "http_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+];
+
+/// Diagnoses a client's setup with the default checks.
+///
+/// Shorthand for `Doctor::new(client).run()`.
+pub async fn doctor<C: Config + Send + Sync + 'static>(client: Client<C>) -> DoctorReport {
+    Doctor::new(client).run().await
+}
+
+/// A setup check performed by [`Doctor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Check {
+    /// The API key environment variable is set.
+    Environment,
+    /// The base URL accepts TCP connections.
+    Reachability,
+    /// The models list can be fetched and includes the configured model.
+    Models,
+    /// A one-token completion succeeds.
+    Completion,
+    /// The model calls a trivial tool when asked to.
+    ToolCalling,
+}
+
+impl Check {
+    /// Every check, in the order they run.
+    pub const ALL: [Check; 5] = [
+        Check::Environment,
+        Check::Reachability,
+        Check::Models,
+        Check::Completion,
+        Check::ToolCalling,
+    ];
+
+    /// Returns a short display name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::Environment => "environment",
+            Check::Reachability => "reachability",
+            Check::Models => "models",
+            Check::Completion => "completion",
+            Check::ToolCalling => "tool calling",
+        }
+    }
+
+    fn needs_network(self) -> bool {
+        !matches!(self, Check::Environment | Check::Reachability)
+    }
+}
+
+/// Whether a check passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    /// The check succeeded.
+    Passed,
+    /// The check found a problem.
+    Failed,
+    /// The check did not run.
+    Skipped,
+}
+
+/// The result of one check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    /// Which check this is.
+    pub check: Check,
+    /// Whether the check passed.
+    pub status: CheckStatus,
+    /// What the check found.
+    pub message: String,
+    /// How to fix a failure, when there is a likely fix.
+    pub hint: Option<String>,
+    /// How long the check took.
+    pub elapsed: Duration,
+}
+
+impl CheckResult {
+    fn passed(check: Check, message: impl Into<String>) -> Self {
+        Self {
+            check,
+            status: CheckStatus::Passed,
+            message: message.into(),
+            hint: None,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    fn failed(check: Check, message: impl Into<String>, hint: Option<String>) -> Self {
+        Self {
+            check,
+            status: CheckStatus::Failed,
+            message: message.into(),
+            hint,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    fn skipped(check: Check, message: impl Into<String>) -> Self {
+        Self {
+            check,
+            status: CheckStatus::Skipped,
+            message: message.into(),
+            hint: None,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+/// The results of a [`Doctor`] run.
+///
+/// Its `Display` implementation renders one line per check, suitable for
+/// printing to a console.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DoctorReport {
+    /// Results in the order the checks ran.
+    pub checks: Vec<CheckResult>,
+}
+
+impl DoctorReport {
+    /// Returns true if no check failed.
+    pub fn is_healthy(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// Returns the failed checks.
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.checks
+            .iter()
+            .filter(|result| result.status == CheckStatus::Failed)
+    }
+
+    /// Returns the result of a check.
+    pub fn get(&self, check: Check) -> Option<&CheckResult> {
+        self.checks.iter().find(|result| result.check == check)
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.checks {
+            let mark = match result.status {
+                CheckStatus::Passed => "✓",
+                CheckStatus::Failed => "✗",
+                CheckStatus::Skipped => "-",
+            };
+            write!(f, "{} {:<13} {}", mark, result.check.name(), result.message)?;
+            if result.status != CheckStatus::Skipped {
+                write!(f, " ({} ms)", result.elapsed.as_millis())?;
+            }
+            writeln!(f)?;
+            if let Some(ref hint) = result.hint {
+                writeln!(f, "  {:<13} hint: {}", "", hint)?;
+            }
+        }
+        match self.failures().count() {
+            0 => writeln!(f, "All checks passed."),
+            1 => writeln!(f, "1 check failed."),
+            n => writeln!(f, "{} checks failed.", n),
+        }
+    }
+}
+
+/// Configurable setup diagnostics.
+pub struct Doctor {
+    backend: Arc<dyn ChatBackend>,
+    api_base: Option<String>,
+    model: String,
+    api_key_env: String,
+    skip: HashSet<Check>,
+    timeout: Duration,
+}
+
+impl Doctor {
+    /// Diagnoses the given client, probing the model `gpt-4o-mini` with the
+    /// key from `OPENAI_API_KEY` and a ten second timeout per check.
+    pub fn new<C: Config + Send + Sync + 'static>(client: Client<C>) -> Self {
+        let api_base = client.config().api_base().to_string();
+        Self {
+            backend: Arc::new(client),
+            api_base: Some(api_base),
+            model: "gpt-4o-mini".to_string(),
+            api_key_env: "OPENAI_API_KEY".to_string(),
+            skip: HashSet::new(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Diagnoses a client built from `config`.
+    pub fn from_config<C: Config + Send + Sync + 'static>(config: C) -> Self {
+        Self::new(Client::with_config(config))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_backend(backend: Arc<dyn ChatBackend>, api_base: Option<String>) -> Self {
+        Self {
+            backend,
+            api_base,
+            ..Self::from_config(async_openai::config::OpenAIConfig::new())
+        }
+    }
+
+    /// Sets the model to look for and probe.
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    /// Sets the environment variable expected to hold the API key.
+    pub fn api_key_env(mut self, name: impl Into<String>) -> Self {
+        self.api_key_env = name.into();
+        self
+    }
+
+    /// Skips a check, e.g. [`Check::Environment`] when the key is passed
+    /// in code.
+    pub fn skip(mut self, check: Check) -> Self {
+        self.skip.insert(check);
+        self
+    }
+
+    /// Sets the time limit for each check.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Runs the checks in order.
+    pub async fn run(&self) -> DoctorReport {
+        let mut checks = Vec::new();
+        let mut unreachable = false;
+
+        for check in Check::ALL {
+            if self.skip.contains(&check) {
+                checks.push(CheckResult::skipped(check, "skipped"));
+                continue;
+            }
+            if unreachable && check.needs_network() {
+                checks.push(CheckResult::skipped(
+                    check,
+                    "skipped because the base URL is unreachable",
+                ));
+                continue;
+            }
+
+            let started = Instant::now();
+            let mut result = match tokio::time::timeout(self.timeout, self.run_check(check)).await {
+                Ok(result) => result,
+                Err(_) => CheckResult::failed(
+                    check,
+                    format!("timed out after {:?}", self.timeout),
+                    self.proxy_hint()
+                        .or_else(|| Some("The provider may be overloaded; try again.".into())),
+                ),
+            };
+            result.elapsed = started.elapsed();
+            unreachable |= check == Check::Reachability && result.status == CheckStatus::Failed;
+            checks.push(result);
+        }
+
+        DoctorReport { checks }
+    }
+
+    async fn run_check(&self, check: Check) -> CheckResult {
+        match check {
+            Check::Environment => self.check_environment(),
+            Check::Reachability => self.check_reachability().await,
+            Check::Models => self.check_models().await,
+            Check::Completion => self.check_completion().await,
+            Check::ToolCalling => self.check_tool_calling().await,
+        }
+    }
+
+    fn check_environment(&self) -> CheckResult {
+        match std::env::var(&self.api_key_env) {
+            Ok(value) if !value.trim().is_empty() => {
+                CheckResult::passed(Check::Environment, format!("{} is set", self.api_key_env))
+            }
+            _ => CheckResult::failed(
+                Check::Environment,
+                format!("{} is not set", self.api_key_env),
+                Some(format!(
+                    "Export {} with your API key, or skip this check if the key is set in code.",
+                    self.api_key_env
+                )),
+            ),
+        }
+    }
+
+    async fn check_reachability(&self) -> CheckResult {
+        let check = Check::Reachability;
+        let Some(ref api_base) = self.api_base else {
+            return CheckResult::skipped(check, "no base URL to probe");
+        };
+        let Some((host, port)) = host_and_port(api_base) else {
+            return CheckResult::failed(
+                check,
+                format!("'{}' is not a valid base URL", api_base),
+                Some("Use a full URL such as https://api.openai.com/v1.".into()),
+            );
+        };
+
+        match tokio::net::TcpStream::connect((host.as_str(), port)).await {
+            Ok(_) => CheckResult::passed(check, format!("connected to {}:{}", host, port)),
+            Err(e) => CheckResult::failed(
+                check,
+                format!("could not connect to {}:{}: {}", host, port, e),
+                self.proxy_hint().or_else(|| {
+                    Some(format!(
+                        "Check the base URL ({}) and your network connection.",
+                        api_base
+                    ))
+                }),
+            ),
+        }
+    }
+
+    async fn check_models(&self) -> CheckResult {
+        let check = Check::Models;
+        let models = match self.backend.list_models().await {
+            Ok(models) => models,
+            Err(e) => return self.api_failure(check, "listing models failed", &e),
+        };
+        if models.is_empty() || models.contains(&self.model) {
+            return CheckResult::passed(check, format!("{} models available", models.len()));
+        }
+
+        let mut similar: Vec<&str> = models
+            .iter()
+            .map(String::as_str)
+            .filter(|id| {
+                let base = self.model.rsplit('/').next().unwrap_or(&self.model);
+                id.contains(base) || base.split('-').next().is_some_and(|p| id.contains(p))
+            })
+            .take(5)
+            .collect();
+        if similar.is_empty() {
+            similar = models.iter().map(String::as_str).take(5).collect();
+        }
+        CheckResult::failed(
+            check,
+            format!("model '{}' is not available with this key", self.model),
+            Some(format!("Available models include: {}.", similar.join(", "))),
+        )
+    }
+
+    async fn check_completion(&self) -> CheckResult {
+        let check = Check::Completion;
+        let request = json!({
+            "model": self.model,
+            "messages": [{ "role": "user", "content": "Reply with OK." }],
+            "max_tokens": 1,
+        });
+        match self.probe(request).await {
+            Ok(_) => CheckResult::passed(check, format!("'{}' answered", self.model)),
+            Err(e) => self.api_failure(check, "completion failed", &e),
+        }
+    }
+
+    async fn check_tool_calling(&self) -> CheckResult {
+        let check = Check::ToolCalling;
+        let tool = function_tool(
+            "ping",
+            "Replies with pong.",
+            json!({ "type": "object", "properties": {} }),
+        );
+        let request = json!({
+            "model": self.model,
+            "messages": [{ "role": "user", "content": "Call the ping tool." }],
+            "tools": [tool],
+            "tool_choice": { "type": "function", "function": { "name": "ping" } },
+            "max_tokens": 20,
+        });
+        let response = match self.probe(request).await {
+            Ok(response) => response,
+            Err(e) => return self.api_failure(check, "tool call request failed", &e),
+        };
+
+        let called = response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.tool_calls.as_ref())
+            .is_some_and(|calls| calls.iter().any(|call| call.function.name == "ping"));
+        if called {
+            CheckResult::passed(check, format!("'{}' called the test tool", self.model))
+        } else {
+            CheckResult::failed(
+                check,
+                format!("'{}' answered without calling the test tool", self.model),
+                Some("Use a model that supports tool calling.".into()),
+            )
+        }
+    }
+
+    async fn probe(
+        &self,
+        request: serde_json::Value,
+    ) -> Result<async_openai::types::CreateChatCompletionResponse, OpenAIError> {
+        let request: async_openai::types::CreateChatCompletionRequest =
+            serde_json::from_value(request).expect("valid probe request");
+        self.backend.create_chat_completion((&request).into()).await
+    }
+
+    /// Builds a failure from an API error, with a hint for the likely cause.
+    fn api_failure(&self, check: Check, action: &str, error: &OpenAIError) -> CheckResult {
+        let hint = match error {
+            OpenAIError::ApiError(api) => {
+                let text = format!("{} {:?}", api.message, api.code).to_lowercase();
+                if ["api key", "api_key", "unauthorized", "authentication"]
+                    .iter()
+                    .any(|needle| text.contains(needle))
+                {
+                    Some(format!(
+                        "Check that {} holds a valid key for this provider.",
+                        self.api_key_env
+                    ))
+                } else if ["model_not_found", "does not exist", "no such model"]
+                    .iter()
+                    .any(|needle| text.contains(needle))
+                {
+                    Some(format!(
+                        "The model '{}' is not available; check its name and your plan.",
+                        self.model
+                    ))
+                } else if check == Check::ToolCalling && text.contains("tool") {
+                    Some("Use a model that supports tool calling.".into())
+                } else {
+                    None
+                }
+            }
+            OpenAIError::JSONDeserialize(_) => Some(
+                "The server did not answer like an OpenAI-compatible API; check the base URL \
+                 (it usually ends in /v1)."
+                    .into(),
+            ),
+            other => transport_hint(&other.to_string()).or_else(|| self.proxy_hint()),
+        };
+        CheckResult::failed(check, format!("{}: {}", action, error), hint)
+    }
+
+    /// Suggests checking the proxy when one is configured.
+    fn proxy_hint(&self) -> Option<String> {
+        let var = PROXY_VARS
+            .iter()
+            .find(|var| std::env::var(var).is_ok_and(|value| !value.is_empty()))?;
+        Some(format!(
+            "{} is set; check that the proxy allows connections to the API.",
+            var
+        ))
+    }
+}
+
+/// Suggests a fix for a connection-level error message.
+fn transport_hint(message: &str) -> Option<String> {
+    let message = message.to_lowercase();
+    if ["certificate", "tls", "ssl", "handshake"]
+        .iter()
+        .any(|needle| message.contains(needle))
+    {
+        Some(
+            "TLS failed. Check that the system clock is correct, since certificate validation \
+             fails when it is skewed, and that no proxy intercepts TLS."
+                .into(),
+        )
+    } else if message.contains("proxy") {
+        Some("The proxy rejected the connection; check the proxy settings.".into())
+    } else if ["dns", "resolve", "lookup"]
+        .iter()
+        .any(|needle| message.contains(needle))
+    {
+        Some("The host name could not be resolved; check the base URL.".into())
+    } else {
+        None
+    }
+}
+
+/// Extracts the host and port from a URL such as `https://host:port/path`.
+fn host_and_port(url: &str) -> Option<(String, u16)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let default_port = match scheme {
+        "https" => 443,
+        "http" => 80,
+        _ => return None,
+    };
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let authority = authority.rsplit('@').next()?;
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, port.parse().ok()?),
+        _ => (authority, default_port),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    (!host.is_empty()).then(|| (host.to_string(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockChatBackend;
+
+    /// A base URL that accepts connections for as long as the listener lives.
+    async fn listening() -> (tokio::net::TcpListener, String) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        (listener, url)
+    }
+
+    fn api_error(message: &str, code: &str) -> OpenAIError {
+        OpenAIError::ApiError(
+            serde_json::from_value(
+                json!({ "message": message, "type": null, "param": null, "code": code }),
+            )
+            .unwrap(),
+        )
+    }
+
+    fn healthy_backend() -> MockChatBackend {
+        MockChatBackend::new()
+            .respond_with_text("OK")
+            .respond_with_tool_call("ping", json!({}))
+    }
+
+    fn doctor(backend: MockChatBackend, api_base: Option<String>) -> Doctor {
+        Doctor::with_backend(Arc::new(backend), api_base)
+            .model("mock-model")
+            .api_key_env("PATH")
+    }
+
+    fn status(report: &DoctorReport, check: Check) -> CheckStatus {
+        report.get(check).unwrap().status
+    }
+
+    #[tokio::test]
+    async fn test_healthy_setup_passes() {
+        let (_listener, url) = listening().await;
+        let report = doctor(healthy_backend(), Some(url)).run().await;
+        assert!(report.is_healthy(), "{}", report);
+        assert_eq!(report.checks.len(), 5);
+        assert!(report.to_string().ends_with("All checks passed.\n"));
+    }
+
+    #[tokio::test]
+    async fn test_missing_api_key() {
+        let report = doctor(healthy_backend(), None)
+            .api_key_env("AIFORM_DOCTOR_TEST_MISSING_KEY")
+            .run()
+            .await;
+        let result = report.get(Check::Environment).unwrap();
+        assert_eq!(result.status, CheckStatus::Failed);
+        assert!(result
+            .hint
+            .as_ref()
+            .unwrap()
+            .contains("AIFORM_DOCTOR_TEST_MISSING_KEY"));
+        assert_eq!(report.failures().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_base_url_skips_network_checks() {
+        let (listener, url) = listening().await;
+        drop(listener);
+        let report = doctor(MockChatBackend::new(), Some(url)).run().await;
+        assert_eq!(status(&report, Check::Reachability), CheckStatus::Failed);
+        for check in [Check::Models, Check::Completion, Check::ToolCalling] {
+            assert_eq!(status(&report, check), CheckStatus::Skipped);
+        }
+
+        let report = doctor(MockChatBackend::new(), Some("api.openai.com".into()))
+            .run()
+            .await;
+        let result = report.get(Check::Reachability).unwrap();
+        assert!(result.message.contains("not a valid base URL"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_key_and_unavailable_model() {
+        let backend = healthy_backend().models(Err(api_error(
+            "Incorrect API key provided",
+            "invalid_api_key",
+        )));
+        let report = doctor(backend, None).run().await;
+        let result = report.get(Check::Models).unwrap();
+        assert_eq!(result.status, CheckStatus::Failed);
+        assert!(result.hint.as_ref().unwrap().contains("valid key"));
+
+        let backend = healthy_backend().models(Ok(vec!["gpt-4o", "gpt-4o-mini", "o3"]));
+        let report = doctor(backend, None).model("gpt-5").run().await;
+        let result = report.get(Check::Models).unwrap();
+        assert_eq!(result.status, CheckStatus::Failed);
+        assert!(result.message.contains("'gpt-5' is not available"));
+        assert_eq!(
+            result.hint.as_deref(),
+            Some("Available models include: gpt-4o, gpt-4o-mini.")
+        );
+
+        let backend = MockChatBackend::new().respond_with_error(api_error(
+            "The model `mock-model` does not exist",
+            "model_not_found",
+        ));
+        let report = doctor(backend, None).skip(Check::ToolCalling).run().await;
+        let result = report.get(Check::Completion).unwrap();
+        assert_eq!(result.status, CheckStatus::Failed);
+        assert!(result
+            .hint
+            .as_ref()
+            .unwrap()
+            .contains("'mock-model' is not available"));
+    }
+
+    #[tokio::test]
+    async fn test_model_without_tool_support() {
+        let backend = MockChatBackend::new()
+            .respond_with_text("OK")
+            .respond_with_text("pong");
+        let report = doctor(backend, None).run().await;
+        assert_eq!(status(&report, Check::Completion), CheckStatus::Passed);
+        let result = report.get(Check::ToolCalling).unwrap();
+        assert_eq!(result.status, CheckStatus::Failed);
+        assert!(report.to_string().contains("✗ tool calling"));
+        assert!(report.to_string().ends_with("1 check failed.\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_checks_are_time_bounded_and_skippable() {
+        let backend = healthy_backend().latency(Duration::from_secs(60));
+        let report = doctor(backend, None)
+            .timeout(Duration::from_secs(1))
+            .skip(Check::Completion)
+            .skip(Check::ToolCalling)
+            .run()
+            .await;
+        let result = report.get(Check::Models).unwrap();
+        assert_eq!(result.status, CheckStatus::Failed);
+        assert_eq!(result.message, "timed out after 1s");
+        assert_eq!(result.elapsed, Duration::from_secs(1));
+        assert_eq!(status(&report, Check::Completion), CheckStatus::Skipped);
+    }
+
+    #[test]
+    fn test_transport_hints() {
+        let hint = transport_hint("error sending request: invalid peer certificate: Expired");
+        assert!(hint.unwrap().contains("system clock"));
+        let hint = transport_hint("dns error: failed to lookup address information");
+        assert!(hint.unwrap().contains("base URL"));
+        assert_eq!(transport_hint("connection reset"), None);
+    }
+
+    #[test]
+    fn test_host_and_port() {
+        assert_eq!(
+            host_and_port("https://api.openai.com/v1"),
+            Some(("api.openai.com".into(), 443))
+        );
+        assert_eq!(
+            host_and_port("http://localhost:11434/v1"),
+            Some(("localhost".into(), 11434))
+        );
+        assert_eq!(
+            host_and_port("http://[::1]:8080"),
+            Some(("::1".into(), 8080))
+        );
+        assert_eq!(host_and_port("ftp://example.com"), None);
+    }
+}
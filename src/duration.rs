@@ -0,0 +1,357 @@
+//! Serde helpers for `std::time::Duration` tool arguments.
+//!
+//! Use this module with serde's `with` attribute on `Duration` fields, and
+//! `#[derive(ToolArg)]` advertises them as human-readable strings such as
+//! `"30s"`, `"5m"` or `"1h30m"`. Without it, a `Duration` field is a compile
+//! error, since serde would expect `{secs, nanos}`:
+//!
+//! ```
+//! use aiform::prelude::*;
+//! use serde::Deserialize;
+//! use std::time::Duration;
+//!
+//! #[derive(ToolArg, Deserialize)]
+//! struct PollArgs {
+//!     #[serde(with = "aiform::duration")]
+//!     interval: Duration,
+//!     #[serde(default, with = "aiform::duration::option")]
+//!     timeout: Option<Duration>,
+//! }
+//! ```
+//!
+//! Plain JSON numbers (and numeric strings) are accepted as seconds, since
+//! models frequently send `45` instead of `"45s"`.
+
+use serde::{de, Deserializer, Serializer};
+use std::fmt;
+use std::time::Duration;
+
+/// The hint shown to the model for duration fields.
+pub const SCHEMA_HINT: &str = "duration like '30s', '5m', '2h'";
+
+/// Error returned when a duration string cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDurationError {
+    input: String,
+    reason: String,
+}
+
+impl ParseDurationError {
+    fn new(input: &str, reason: impl Into<String>) -> Self {
+        Self {
+            input: input.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseDurationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid duration {:?}: {}; expected a number followed by a unit, e.g. '30s', '5m', '2h' or '1h30m'",
+            self.input, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ParseDurationError {}
+
+/// Parses a humantime-style duration such as `"90s"`, `"1h30m"` or `"250ms"`.
+///
+/// A bare number is interpreted as seconds.
+pub fn parse(input: &str) -> std::result::Result<Duration, ParseDurationError> {
+    let trimmed = input.trim();
+    if trimmed.is_empty() {
+        return Err(ParseDurationError::new(input, "empty string"));
+    }
+
+    if let Ok(secs) = trimmed.parse::<f64>() {
+        return from_secs(secs).ok_or_else(|| ParseDurationError::new(input, "out of range"));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = trimmed;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        if number_len == 0 {
+            return Err(ParseDurationError::new(input, "expected a number"));
+        }
+        let value: f64 = rest[..number_len]
+            .parse()
+            .map_err(|_| ParseDurationError::new(input, "malformed number"))?;
+        rest = rest[number_len..].trim_start();
+
+        let unit_len = rest
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(rest.len());
+        if unit_len == 0 {
+            return Err(ParseDurationError::new(input, "missing unit"));
+        }
+        let unit = &rest[..unit_len];
+        let scale = unit_seconds(unit)
+            .ok_or_else(|| ParseDurationError::new(input, format!("unknown unit {:?}", unit)))?;
+        rest = rest[unit_len..].trim_start();
+
+        let part = from_secs(value * scale)
+            .ok_or_else(|| ParseDurationError::new(input, "out of range"))?;
+        total = total
+            .checked_add(part)
+            .ok_or_else(|| ParseDurationError::new(input, "out of range"))?;
+    }
+
+    Ok(total)
+}
+
+/// Formats a duration in the compact form accepted by [`parse`], e.g. `"1h30m"`.
+pub fn format(duration: Duration) -> String {
+    const UNITS: [(&str, u64); 4] = [("d", 86_400), ("h", 3_600), ("m", 60), ("s", 1)];
+
+    let mut out = String::new();
+    let mut secs = duration.as_secs();
+    for (suffix, size) in UNITS {
+        if secs >= size {
+            out.push_str(&format!("{}{}", secs / size, suffix));
+            secs %= size;
+        }
+    }
+
+    let nanos = duration.subsec_nanos();
+    if nanos > 0 {
+        if nanos.is_multiple_of(1_000_000) {
+            out.push_str(&format!("{}ms", nanos / 1_000_000));
+        } else if nanos.is_multiple_of(1_000) {
+            out.push_str(&format!("{}us", nanos / 1_000));
+        } else {
+            out.push_str(&format!("{}ns", nanos));
+        }
+    }
+
+    if out.is_empty() {
+        out.push_str("0s");
+    }
+    out
+}
+
+/// Serializes a duration as a humantime-style string.
+pub fn serialize<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format(*duration))
+}
+
+/// Deserializes a duration from a humantime-style string or a number of seconds.
+pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Duration, D::Error> {
+    deserializer.deserialize_any(DurationVisitor)
+}
+
+/// The same helpers for `Option<Duration>` fields.
+pub mod option {
+    use super::*;
+    use serde::Deserialize;
+
+    /// Serializes an optional duration, writing `null` for `None`.
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        match duration {
+            Some(d) => super::serialize(d, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    /// Deserializes an optional duration, accepting `null`.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Option<Duration>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(deserialize_with = "super::deserialize")] Duration);
+
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|w| w.0))
+    }
+}
+
+struct DurationVisitor;
+
+impl de::Visitor<'_> for DurationVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a {} or a number of seconds", SCHEMA_HINT)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Duration, E> {
+        parse(v).map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> std::result::Result<Duration, E> {
+        Ok(Duration::from_secs(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> std::result::Result<Duration, E> {
+        u64::try_from(v)
+            .map(Duration::from_secs)
+            .map_err(|_| E::custom(format!("invalid duration {}: must not be negative", v)))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> std::result::Result<Duration, E> {
+        from_secs(v).ok_or_else(|| {
+            E::custom(format!(
+                "invalid duration {}: must be a finite, non-negative number of seconds",
+                v
+            ))
+        })
+    }
+}
+
+fn from_secs(secs: f64) -> Option<Duration> {
+    if secs.is_finite() && secs >= 0.0 {
+        Duration::try_from_secs_f64(secs).ok()
+    } else {
+        None
+    }
+}
+
+fn unit_seconds(unit: &str) -> Option<f64> {
+    let scale = match unit {
+        "ns" | "nsec" | "nanos" => 1e-9,
+        "us" | "µs" | "usec" | "micros" => 1e-6,
+        "ms" | "msec" | "millis" => 1e-3,
+        "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 3_600.0,
+        "d" | "day" | "days" => 86_400.0,
+        "w" | "week" | "weeks" => 604_800.0,
+        _ => return None,
+    };
+    Some(scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Args {
+        #[serde(with = "crate::duration")]
+        interval: Duration,
+        #[serde(default, with = "crate::duration::option")]
+        timeout: Option<Duration>,
+    }
+
+    fn interval(value: serde_json::Value) -> std::result::Result<Duration, serde_json::Error> {
+        serde_json::from_value::<Args>(json!({ "interval": value })).map(|a| a.interval)
+    }
+
+    #[test]
+    fn test_parse_units() {
+        assert_eq!(interval(json!("90s")).unwrap(), Duration::from_secs(90));
+        assert_eq!(interval(json!("1h30m")).unwrap(), Duration::from_secs(5400));
+        assert_eq!(
+            interval(json!("1h 30m")).unwrap(),
+            Duration::from_secs(5400)
+        );
+        assert_eq!(
+            interval(json!("250ms")).unwrap(),
+            Duration::from_millis(250)
+        );
+        assert_eq!(interval(json!("1.5m")).unwrap(), Duration::from_secs(90));
+    }
+
+    #[test]
+    fn test_bare_numbers_are_seconds() {
+        assert_eq!(interval(json!(45)).unwrap(), Duration::from_secs(45));
+        assert_eq!(interval(json!("45")).unwrap(), Duration::from_secs(45));
+        assert_eq!(interval(json!(0.5)).unwrap(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_rejects_nonsense() {
+        for bad in [
+            json!("soon"),
+            json!("5 parsecs"),
+            json!(""),
+            json!(-3),
+            json!(true),
+        ] {
+            assert!(interval(bad).is_err());
+        }
+
+        let message = interval(json!("5 parsecs")).unwrap_err().to_string();
+        assert!(message.contains("unknown unit \"parsecs\""));
+        assert!(message.contains("'30s'"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let args = Args {
+            interval: Duration::from_secs(5400),
+            timeout: Some(Duration::from_millis(1500)),
+        };
+        let value = serde_json::to_value(&args).unwrap();
+        assert_eq!(value, json!({ "interval": "1h30m", "timeout": "1s500ms" }));
+        assert_eq!(serde_json::from_value::<Args>(value).unwrap(), args);
+    }
+
+    #[derive(crate::ToolArg, Deserialize)]
+    struct PollArgs {
+        #[serde(with = "crate::duration")]
+        interval: Duration,
+    }
+
+    #[crate::tool("Poll a job until it finishes")]
+    async fn poll_job(args: PollArgs) -> crate::Result<String> {
+        Ok(format!("polling every {}s", args.interval.as_secs()))
+    }
+
+    #[tokio::test]
+    async fn test_nonsense_is_returned_to_the_model() {
+        let backend = std::sync::Arc::new(
+            crate::testing::MockChatBackend::new()
+                .respond_with_tool_call("poll_job", json!({ "interval": "5 parsecs" }))
+                .respond_with_tool_call("poll_job", json!({ "interval": "5m" }))
+                .respond_with_text("Polling every 5 minutes."),
+        );
+        let agent = crate::Agent::builder()
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(crate::tools![PollJobTool])
+            .tool_error_policy(crate::ToolErrorPolicy::return_to_model())
+            .build()
+            .unwrap();
+        assert_eq!(
+            agent.run("Watch the build").await.unwrap(),
+            "Polling every 5 minutes."
+        );
+
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        let result: serde_json::Value =
+            serde_json::from_str(messages[2]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(result["error"]["code"], "invalid_input");
+        let message = result["error"]["message"].as_str().unwrap();
+        assert!(message.contains("unknown unit \"parsecs\""));
+        assert!(message.contains("'30s'"));
+    }
+
+    #[test]
+    fn test_optional_duration() {
+        let args: Args = serde_json::from_value(json!({ "interval": "1m" })).unwrap();
+        assert_eq!(args.timeout, None);
+
+        let args: Args =
+            serde_json::from_value(json!({ "interval": "1m", "timeout": null })).unwrap();
+        assert_eq!(args.timeout, None);
+
+        let args: Args =
+            serde_json::from_value(json!({ "interval": "1m", "timeout": 30 })).unwrap();
+        assert_eq!(args.timeout, Some(Duration::from_secs(30)));
+    }
+}
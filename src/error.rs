@@ -1,5 +1,6 @@
 //! Error types for the aiform library.
 
+use async_openai::error::OpenAIError;
 use std::fmt;
 
 /// Result type alias using [`Error`].
@@ -9,7 +10,7 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug)]
 pub enum Error {
     /// An error occurred while calling the OpenAI API.
-    OpenAI(async_openai::error::OpenAIError),
+    OpenAI(OpenAIError),
 
     /// An error occurred while serializing or deserializing JSON.
     Json(serde_json::Error),
@@ -24,6 +25,17 @@ pub enum Error {
     MaxIterationsExceeded {
         /// The maximum number of iterations allowed.
         max: usize,
+        /// The name of the agent, if it was given one.
+        agent: Option<String>,
+    },
+
+    /// A run with handoffs transferred the conversation more often than
+    /// allowed.
+    MaxHandoffsExceeded {
+        /// The maximum number of transfers allowed.
+        max: usize,
+        /// The agents the conversation was transferred to, in order.
+        transfers: Vec<String>,
     },
 
     /// A tool execution failed.
@@ -32,11 +44,71 @@ pub enum Error {
         tool_name: String,
         /// The underlying error message.
         message: String,
+        /// The name of the agent that called the tool, if it was given one.
+        agent: Option<String>,
+    },
+
+    /// A tool call's arguments were not valid JSON.
+    MalformedToolArguments {
+        /// The name of the tool that was called.
+        tool_name: String,
+        /// Byte offset into the arguments where the JSON became invalid.
+        offset: usize,
+        /// The underlying parse error message.
+        message: String,
+    },
+
+    /// A tool call's arguments did not match the tool's schema.
+    InvalidToolArguments {
+        /// The name of the tool that was called.
+        tool_name: String,
+        /// Each violated constraint, phrased for the model.
+        errors: Vec<String>,
+        /// The arguments as the model sent them.
+        raw: serde_json::Value,
     },
 
     /// An invalid configuration was provided.
     InvalidConfiguration(String),
 
+    /// A tool result could not be rendered through its template.
+    Render(String),
+
+    /// A tool failed with a structured error.
+    Tool(crate::tool_error::ToolError),
+
+    /// A completion request exceeded the configured time limit.
+    Timeout {
+        /// How long the request ran before it was abandoned.
+        elapsed: std::time::Duration,
+    },
+
+    /// The run was cancelled through its
+    /// [`CancelHandle`](crate::cancel::CancelHandle).
+    Cancelled,
+
+    /// The model's structured answer did not match the output type.
+    StructuredOutputParse {
+        /// The answer as returned by the model.
+        raw: String,
+        /// Why it could not be deserialized.
+        source: serde_json::Error,
+    },
+
+    /// An [`AgentHook`](crate::hook::AgentHook) stopped the run.
+    HookAborted {
+        /// The hook method that stopped the run, e.g. `"on_tool_start"`.
+        event: String,
+        /// The reason the hook gave.
+        reason: String,
+    },
+
+    /// A conversation was cut between tool calls and their results.
+    SplitToolRound {
+        /// Where the conversation was to be cut.
+        index: usize,
+    },
+
     /// A generic error occurred.
     Other(Box<dyn std::error::Error + Send + Sync>),
 }
@@ -48,35 +120,148 @@ impl fmt::Display for Error {
             Error::Json(e) => write!(f, "JSON error: {}", e),
             Error::ToolNotFound(name) => write!(f, "Tool not found: {}", name),
             Error::AgentNotFound(name) => write!(f, "Agent not found: {}", name),
-            Error::MaxIterationsExceeded { max } => {
+            Error::MaxIterationsExceeded { max, agent: None } => {
                 write!(f, "Agent exceeded maximum iterations: {}", max)
             }
-            Error::ToolExecution { tool_name, message } => {
-                write!(f, "Tool '{}' failed: {}", tool_name, message)
-            }
+            Error::MaxIterationsExceeded {
+                max,
+                agent: Some(agent),
+            } => write!(f, "Agent '{}' exceeded maximum iterations: {}", agent, max),
+            Error::MaxHandoffsExceeded { max, transfers } => write!(
+                f,
+                "Conversation exceeded maximum handoffs: {} ({})",
+                max,
+                transfers.join(" -> ")
+            ),
+            Error::ToolExecution {
+                tool_name,
+                message,
+                agent: None,
+            } => write!(f, "Tool '{}' failed: {}", tool_name, message),
+            Error::ToolExecution {
+                tool_name,
+                message,
+                agent: Some(agent),
+            } => write!(
+                f,
+                "Tool '{}' of agent '{}' failed: {}",
+                tool_name, agent, message
+            ),
+            Error::MalformedToolArguments {
+                tool_name,
+                offset,
+                message,
+            } => write!(
+                f,
+                "Arguments for tool '{}' are not valid JSON at byte {}: {}",
+                tool_name, offset, message
+            ),
+            Error::InvalidToolArguments {
+                tool_name, errors, ..
+            } => write!(
+                f,
+                "Invalid arguments for tool '{}': {}",
+                tool_name,
+                errors.join("; ")
+            ),
             Error::InvalidConfiguration(msg) => write!(f, "Invalid configuration: {}", msg),
+            Error::Render(msg) => write!(f, "Render error: {}", msg),
+            Error::Tool(e) => write!(f, "Tool error: {}", e),
+            Error::Timeout { elapsed } => write!(f, "Request timed out after {:?}", elapsed),
+            Error::Cancelled => write!(f, "Run was cancelled"),
+            Error::StructuredOutputParse { source, .. } => {
+                write!(
+                    f,
+                    "Structured output does not match the output type: {}",
+                    source
+                )
+            }
+            Error::HookAborted { event, reason } => {
+                write!(f, "Run aborted by a hook in {}: {}", event, reason)
+            }
+            Error::SplitToolRound { index } => write!(
+                f,
+                "Cannot cut the conversation at message {}: it would separate tool calls \
+                 from their results",
+                index
+            ),
             Error::Other(e) => write!(f, "{}", e),
         }
     }
 }
 
+impl Error {
+    /// Returns whether the failed request may succeed if sent again.
+    ///
+    /// Rate limits, server errors (5xx), timeouts and dropped connections
+    /// are retryable. Rejected requests (other 4xx), tool failures and
+    /// local errors are not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Timeout { .. } => true,
+            Error::OpenAI(OpenAIError::Reqwest(e)) => {
+                e.is_timeout()
+                    || e.is_connect()
+                    || e.status()
+                        .is_some_and(|status| retryable_status(status.as_u16()))
+            }
+            Error::OpenAI(OpenAIError::ApiError(api)) => {
+                let code = api.code.as_deref().unwrap_or_default();
+                if let Ok(status) = code.parse::<u16>() {
+                    return retryable_status(status);
+                }
+                let text = format!(
+                    "{} {} {}",
+                    api.r#type.as_deref().unwrap_or_default(),
+                    code,
+                    api.message,
+                )
+                .to_lowercase();
+                [
+                    "rate_limit",
+                    "rate limit",
+                    "server_error",
+                    "overloaded",
+                    "timeout",
+                ]
+                .iter()
+                .any(|needle| text.contains(needle))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns whether an HTTP status is worth retrying.
+fn retryable_status(status: u16) -> bool {
+    matches!(status, 408 | 409 | 429) || (500..600).contains(&status)
+}
+
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Error::OpenAI(e) => Some(e),
             Error::Json(e) => Some(e),
+            Error::StructuredOutputParse { source, .. } => Some(source),
+            Error::Tool(e) => Some(e),
             Error::Other(e) => Some(e.as_ref()),
             _ => None,
         }
     }
 }
 
-impl From<async_openai::error::OpenAIError> for Error {
-    fn from(e: async_openai::error::OpenAIError) -> Self {
+impl From<OpenAIError> for Error {
+    fn from(e: OpenAIError) -> Self {
         Error::OpenAI(e)
     }
 }
 
+impl From<crate::tool_error::ToolError> for Error {
+    fn from(e: crate::tool_error::ToolError) -> Self {
+        Error::Tool(e)
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(e: serde_json::Error) -> Self {
         Error::Json(e)
@@ -88,3 +273,48 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for Error {
         Error::Other(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn api_error(error: serde_json::Value) -> Error {
+        Error::OpenAI(OpenAIError::ApiError(
+            serde_json::from_value(error).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let retryable = [
+            api_error(json!({ "message": "Rate limited", "code": "429" })),
+            api_error(json!({ "message": "Bad gateway", "code": "502" })),
+            api_error(
+                json!({ "message": "Slow down", "type": "requests", "code": "rate_limit_exceeded" }),
+            ),
+            api_error(json!({ "message": "The server had an error", "type": "server_error" })),
+            Error::Timeout {
+                elapsed: std::time::Duration::from_secs(1),
+            },
+        ];
+        for error in retryable {
+            assert!(error.is_retryable(), "{}", error);
+        }
+
+        let permanent = [
+            api_error(json!({ "message": "Invalid model", "code": "400" })),
+            api_error(
+                json!({ "message": "Incorrect API key provided", "code": "invalid_api_key" }),
+            ),
+            Error::ToolNotFound("search".into()),
+            Error::MaxIterationsExceeded {
+                max: 3,
+                agent: None,
+            },
+        ];
+        for error in permanent {
+            assert!(!error.is_retryable(), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Extension traits for the OpenAI client.
+//!
+//! For callers that manage the conversation themselves, [`OpenAIClientExt`]
+//! sends one completion request with a [`ToolSet`] and dispatches the tool
+//! calls the model makes, without running an agent loop:
+//!
+//! ```no_run
+//! use aiform::ext::{OpenAIClientExt, ToolCallResponse};
+//! use aiform::prelude::*;
+//!
+//! # async fn example(tools: ToolSet) -> Result<()> {
+//! let client = async_openai::Client::new();
+//! let messages = vec![msg!(user "What's the weather in Lisbon?")];
+//! match client.call_with_tools("gpt-4o", messages, &tools).await? {
+//!     ToolCallResponse::Content(answer) => println!("{}", answer),
+//!     ToolCallResponse::ToolCalls(calls) => {
+//!         for call in calls {
+//!             println!("{} returned {}", call.name, call.output);
+//!         }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`OpenAIClientExt::structured_output`] does the same for a typed answer:
+//!
+//! ```no_run
+//! use aiform::ext::OpenAIClientExt;
+//! use aiform::prelude::*;
+//! use serde::Deserialize;
+//!
+//! #[derive(StructuredOutput, Deserialize)]
+//! struct Invoice {
+//!     number: String,
+//!     total: f64,
+//! }
+//!
+//! # async fn example() -> Result<()> {
+//! let client = async_openai::Client::new();
+//! let messages = vec![msg!(user "Extract the invoice: ...")];
+//! let invoice: Invoice = client.structured_output("gpt-4o", messages).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::agent::{output_schema, parse_tool_arguments};
+use crate::error::{Error, Result};
+use crate::{StructuredOutput, ToolSet};
+use async_openai::config::Config;
+use async_openai::types::{
+    ChatCompletionRequestMessage, CreateChatCompletionRequestArgs, ResponseFormat,
+};
+use async_openai::Client;
+use serde::de::DeserializeOwned;
+
+/// A tool call the model made, with the tool's output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCallOutput {
+    /// The call's id, to answer it with a tool message.
+    pub id: String,
+    /// The tool that was called.
+    pub name: String,
+    /// The arguments the model passed.
+    pub arguments: serde_json::Value,
+    /// What the tool returned.
+    pub output: String,
+}
+
+/// What the model did in response to [`OpenAIClientExt::call_with_tools`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolCallResponse {
+    /// The model answered with text.
+    Content(String),
+    /// The model called tools, which were dispatched; in call order.
+    ToolCalls(Vec<ToolCallOutput>),
+}
+
+/// Extension methods for the OpenAI client.
+#[allow(async_fn_in_trait)]
+pub trait OpenAIClientExt {
+    /// Sends one chat completion request offering the tools in `tools`, and
+    /// dispatches any tool calls in the response.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OpenAI`] if the request fails,
+    /// [`Error::MalformedToolArguments`] if the model's arguments are not
+    /// JSON, [`Error::ToolNotFound`] for a tool not in `tools`, and
+    /// [`Error::ToolExecution`] if a tool fails.
+    async fn call_with_tools(
+        &self,
+        model: &str,
+        messages: Vec<ChatCompletionRequestMessage>,
+        tools: &ToolSet,
+    ) -> Result<ToolCallResponse>;
+
+    /// Sends one chat completion request whose answer must match the JSON
+    /// schema of `S`, and deserializes it.
+    ///
+    /// The request uses a strict `json_schema` response format named after
+    /// the type.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OpenAI`] if the request fails, and
+    /// [`Error::StructuredOutputParse`] with the raw answer if it does not
+    /// deserialize into `S`.
+    async fn structured_output<S: StructuredOutput + DeserializeOwned>(
+        &self,
+        model: &str,
+        messages: Vec<ChatCompletionRequestMessage>,
+    ) -> Result<S>;
+}
+
+impl<C: Config> OpenAIClientExt for Client<C> {
+    async fn call_with_tools(
+        &self,
+        model: &str,
+        messages: Vec<ChatCompletionRequestMessage>,
+        tools: &ToolSet,
+    ) -> Result<ToolCallResponse> {
+        let mut request = CreateChatCompletionRequestArgs::default();
+        request.model(model).messages(messages);
+        if !tools.tools().is_empty() {
+            request.tools(tools.tools().to_vec());
+        }
+        let request = request.build().map_err(|e| {
+            Error::InvalidConfiguration(format!("Failed to build chat request: {}", e))
+        })?;
+
+        let response = self.chat().create(request).await?;
+        let message = response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message)
+            .ok_or_else(|| Error::Other("The response has no choices".into()))?;
+
+        let Some(tool_calls) = message.tool_calls.filter(|calls| !calls.is_empty()) else {
+            return Ok(ToolCallResponse::Content(
+                message.content.unwrap_or_default(),
+            ));
+        };
+        let mut outputs = Vec::with_capacity(tool_calls.len());
+        for tool_call in tool_calls {
+            let arguments = parse_tool_arguments(&tool_call)?;
+            let name = tool_call.function.name;
+            let output = tools
+                .dispatch(name.clone(), arguments.clone())
+                .await
+                .map_err(|e| match e.downcast::<Error>() {
+                    Ok(error) => *error,
+                    Err(e) => Error::ToolExecution {
+                        tool_name: name.clone(),
+                        message: e.to_string(),
+                        agent: None,
+                    },
+                })?;
+            outputs.push(ToolCallOutput {
+                id: tool_call.id,
+                name,
+                arguments,
+                output,
+            });
+        }
+        Ok(ToolCallResponse::ToolCalls(outputs))
+    }
+
+    async fn structured_output<S: StructuredOutput + DeserializeOwned>(
+        &self,
+        model: &str,
+        messages: Vec<ChatCompletionRequestMessage>,
+    ) -> Result<S> {
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(model)
+            .messages(messages)
+            .response_format(ResponseFormat::JsonSchema {
+                json_schema: output_schema::<S>(),
+            })
+            .build()
+            .map_err(|e| {
+                Error::InvalidConfiguration(format!("Failed to build chat request: {}", e))
+            })?;
+
+        let response = self.chat().create(request).await?;
+        let raw = response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .unwrap_or_default();
+        serde_json::from_str(&raw).map_err(|source| Error::StructuredOutputParse { raw, source })
+    }
+}
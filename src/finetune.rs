@@ -0,0 +1,524 @@
+//! Exporting conversations as fine-tuning data.
+//!
+//! A [`FinetuneExporter`] turns finished conversations into the JSONL chat
+//! format of the OpenAI fine-tuning API: one line per conversation with its
+//! `messages`, including assistant `tool_calls` and `tool` results, and the
+//! `tools` the model could call. Conversations can be filtered by tag and by
+//! whether their output passed a guard, duplicates are dropped, and the rest
+//! is split into training and validation files.
+//!
+//! ```no_run
+//! use aiform::finetune::{FinetuneExample, FinetuneExporter, FinetuneOptions};
+//! use aiform::prelude::*;
+//!
+//! # async fn example(transcripts: Vec<Conversation>, tools: ToolSet) -> Result<()> {
+//! let options = FinetuneOptions::new()
+//!     .require_tag("approved")
+//!     .require_passed_guard(true)
+//!     .validation_fraction(0.1)
+//!     .tools(&tools);
+//! let mut exporter = FinetuneExporter::new(options);
+//! for conversation in transcripts {
+//!     exporter.add(FinetuneExample::new(conversation).tag("approved").passed_guard(true));
+//! }
+//!
+//! let report = exporter.write("datasets/support").await?;
+//! print!("{}", report);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    conversation::Conversation,
+    error::{Error, Result},
+    ToolSet,
+};
+use async_openai::types::ChatCompletionTool;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+/// Name of the training file written by [`FinetuneExporter::write`].
+pub const TRAIN_FILE: &str = "train.jsonl";
+/// Name of the validation file written by [`FinetuneExporter::write`].
+pub const VALIDATION_FILE: &str = "validation.jsonl";
+
+/// Which conversations to export and how to split them.
+#[derive(Debug, Clone, Default)]
+pub struct FinetuneOptions {
+    required_tags: BTreeSet<String>,
+    require_passed_guard: bool,
+    keep_duplicates: bool,
+    validation_fraction: f64,
+    tools: Vec<ChatCompletionTool>,
+}
+
+impl FinetuneOptions {
+    /// Creates options that export every well-formed, distinct conversation
+    /// as training data.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only exports conversations tagged with `tag`. Can be called more than
+    /// once to require several tags.
+    pub fn require_tag(mut self, tag: impl Into<String>) -> Self {
+        self.required_tags.insert(tag.into());
+        self
+    }
+
+    /// Only exports conversations whose output passed a guard.
+    pub fn require_passed_guard(mut self, required: bool) -> Self {
+        self.require_passed_guard = required;
+        self
+    }
+
+    /// Exports conversations with identical content more than once.
+    pub fn keep_duplicates(mut self, keep: bool) -> Self {
+        self.keep_duplicates = keep;
+        self
+    }
+
+    /// Sets the fraction of conversations, between 0 and 1, that go to the
+    /// validation file. Defaults to none.
+    ///
+    /// The split is decided by each conversation's content hash, so the same
+    /// conversation always lands in the same file.
+    pub fn validation_fraction(mut self, fraction: f64) -> Self {
+        self.validation_fraction = fraction;
+        self
+    }
+
+    /// Includes the tool set's definitions with every exported conversation.
+    pub fn tools(mut self, tools: &ToolSet) -> Self {
+        self.tools = tools.tools().to_vec();
+        self
+    }
+}
+
+/// A conversation to export, with what is known about its quality.
+#[derive(Debug, Clone)]
+pub struct FinetuneExample {
+    conversation: Conversation,
+    tags: BTreeSet<String>,
+    passed_guard: Option<bool>,
+}
+
+impl FinetuneExample {
+    /// Wraps a conversation with no tags and no guard result.
+    pub fn new(conversation: Conversation) -> Self {
+        Self {
+            conversation,
+            tags: BTreeSet::new(),
+            passed_guard: None,
+        }
+    }
+
+    /// Tags the conversation, e.g. with `"approved"` after review.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.insert(tag.into());
+        self
+    }
+
+    /// Records whether the run's output passed a guard.
+    pub fn passed_guard(mut self, passed: bool) -> Self {
+        self.passed_guard = Some(passed);
+        self
+    }
+}
+
+impl From<Conversation> for FinetuneExample {
+    fn from(conversation: Conversation) -> Self {
+        Self::new(conversation)
+    }
+}
+
+impl From<&Conversation> for FinetuneExample {
+    fn from(conversation: &Conversation) -> Self {
+        Self::new(conversation.clone())
+    }
+}
+
+/// Why a conversation was left out of the export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SkipReason {
+    /// The conversation lacks a required tag.
+    MissingTag(String),
+    /// A passed guard was required, and the guard failed or never ran.
+    GuardNotPassed,
+    /// The conversation has the same content as an earlier one.
+    Duplicate {
+        /// Position of the earlier conversation, in order of addition.
+        of: usize,
+    },
+    /// The conversation has no assistant message to learn from.
+    NoAssistantMessage,
+    /// An assistant tool call has no tool result.
+    UnansweredToolCall(String),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::MissingTag(tag) => write!(f, "not tagged '{}'", tag),
+            SkipReason::GuardNotPassed => write!(f, "did not pass the output guard"),
+            SkipReason::Duplicate { of } => write!(f, "duplicate of #{}", of),
+            SkipReason::NoAssistantMessage => write!(f, "has no assistant message"),
+            SkipReason::UnansweredToolCall(id) => {
+                write!(f, "tool call '{}' has no result", id)
+            }
+        }
+    }
+}
+
+/// A conversation that was left out of the export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
+    /// Position of the conversation, in order of addition.
+    pub index: usize,
+    /// Why it was left out.
+    pub reason: SkipReason,
+}
+
+/// What an export contained.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportReport {
+    /// Conversations written to the training file.
+    pub train: usize,
+    /// Conversations written to the validation file.
+    pub validation: usize,
+    /// Conversations left out, in order of addition.
+    pub skipped: Vec<Skipped>,
+}
+
+impl fmt::Display for ExportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} training, {} validation, {} skipped",
+            self.train,
+            self.validation,
+            self.skipped.len()
+        )?;
+        for skipped in &self.skipped {
+            writeln!(f, "  #{}: {}", skipped.index, skipped.reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// The exported JSONL, as returned by [`FinetuneExporter::export`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FinetuneExport {
+    /// Training lines, each ending in a newline.
+    pub train: String,
+    /// Validation lines, each ending in a newline.
+    pub validation: String,
+    /// Counts and skipped conversations.
+    pub report: ExportReport,
+}
+
+/// Collects conversations and writes them as fine-tuning JSONL.
+#[derive(Debug, Clone, Default)]
+pub struct FinetuneExporter {
+    options: FinetuneOptions,
+    examples: Vec<FinetuneExample>,
+}
+
+impl FinetuneExporter {
+    /// Creates an exporter with no conversations.
+    pub fn new(options: FinetuneOptions) -> Self {
+        Self {
+            options,
+            examples: Vec::new(),
+        }
+    }
+
+    /// Adds a conversation, or a [`FinetuneExample`] carrying its tags and
+    /// guard result.
+    pub fn add(&mut self, example: impl Into<FinetuneExample>) -> &mut Self {
+        self.examples.push(example.into());
+        self
+    }
+
+    /// Converts the added conversations to JSONL without writing them.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Json`] if a message cannot be serialized.
+    pub fn export(&self) -> Result<FinetuneExport> {
+        let mut export = FinetuneExport::default();
+        let mut seen: HashMap<Vec<u8>, usize> = HashMap::new();
+
+        for (index, example) in self.examples.iter().enumerate() {
+            let line = match self.line(example)? {
+                Ok(line) => line,
+                Err(reason) => {
+                    export.report.skipped.push(Skipped { index, reason });
+                    continue;
+                }
+            };
+            let text = serde_json::to_string(&line)?;
+            let hash = Sha256::digest(text.as_bytes()).to_vec();
+            if !self.options.keep_duplicates {
+                if let Some(&of) = seen.get(&hash) {
+                    let reason = SkipReason::Duplicate { of };
+                    export.report.skipped.push(Skipped { index, reason });
+                    continue;
+                }
+                seen.insert(hash.clone(), index);
+            }
+
+            if is_validation(&hash, self.options.validation_fraction) {
+                export.validation.push_str(&text);
+                export.validation.push('\n');
+                export.report.validation += 1;
+            } else {
+                export.train.push_str(&text);
+                export.train.push('\n');
+                export.report.train += 1;
+            }
+        }
+        Ok(export)
+    }
+
+    /// Writes the training and validation files into the directory `dir`,
+    /// creating it if needed.
+    ///
+    /// The validation file is only written when it has lines.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a message cannot be serialized or a file cannot
+    /// be written.
+    pub async fn write(&self, dir: impl AsRef<Path>) -> Result<ExportReport> {
+        let dir = dir.as_ref();
+        let export = self.export()?;
+        let io_error = |e: std::io::Error| {
+            Error::Other(format!("Failed to write {}: {}", dir.display(), e).into())
+        };
+        tokio::fs::create_dir_all(dir).await.map_err(io_error)?;
+        tokio::fs::write(dir.join(TRAIN_FILE), &export.train)
+            .await
+            .map_err(io_error)?;
+        if !export.validation.is_empty() {
+            tokio::fs::write(dir.join(VALIDATION_FILE), &export.validation)
+                .await
+                .map_err(io_error)?;
+        }
+        Ok(export.report)
+    }
+
+    /// Builds the JSONL record of one conversation, or the reason it is
+    /// left out.
+    fn line(&self, example: &FinetuneExample) -> Result<std::result::Result<Value, SkipReason>> {
+        if let Some(tag) = self
+            .options
+            .required_tags
+            .iter()
+            .find(|tag| !example.tags.contains(*tag))
+        {
+            return Ok(Err(SkipReason::MissingTag(tag.clone())));
+        }
+        if self.options.require_passed_guard && example.passed_guard != Some(true) {
+            return Ok(Err(SkipReason::GuardNotPassed));
+        }
+
+        let messages = example
+            .conversation
+            .messages()
+            .iter()
+            .map(|message| serde_json::to_value(message).map(without_nulls))
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        if let Some(reason) = malformed(&messages) {
+            return Ok(Err(reason));
+        }
+
+        let mut line = json!({ "messages": messages });
+        if !self.options.tools.is_empty() {
+            line["tools"] = without_nulls(serde_json::to_value(&self.options.tools)?);
+        }
+        Ok(Ok(line))
+    }
+}
+
+/// Returns why the messages are not a usable training example, if they
+/// are not.
+fn malformed(messages: &[Value]) -> Option<SkipReason> {
+    if !messages
+        .iter()
+        .any(|message| message["role"] == "assistant")
+    {
+        return Some(SkipReason::NoAssistantMessage);
+    }
+    let answered: HashSet<&str> = messages
+        .iter()
+        .filter_map(|message| message["tool_call_id"].as_str())
+        .collect();
+    messages
+        .iter()
+        .flat_map(|message| message["tool_calls"].as_array().into_iter().flatten())
+        .filter_map(|call| call["id"].as_str())
+        .find(|id| !answered.contains(id))
+        .map(|id| SkipReason::UnansweredToolCall(id.to_string()))
+}
+
+/// Removes null fields, which the fine-tuning API rejects for some roles.
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(name, value)| (name, without_nulls(value)))
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(without_nulls).collect()),
+        other => other,
+    }
+}
+
+/// Decides from a content hash whether a conversation is for validation.
+fn is_validation(hash: &[u8], fraction: f64) -> bool {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    (u64::from_be_bytes(bytes) as f64 / u64::MAX as f64) < fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_openai::types::ChatCompletionMessageToolCall;
+
+    fn tool_call(id: &str, name: &str, arguments: Value) -> ChatCompletionMessageToolCall {
+        serde_json::from_value(json!({
+            "id": id,
+            "type": "function",
+            "function": { "name": name, "arguments": arguments.to_string() },
+        }))
+        .unwrap()
+    }
+
+    fn weather_transcript(city: &str) -> Conversation {
+        let mut conversation = Conversation::with_system("You are a weather assistant");
+        conversation.add_user_message(format!("Weather in {}?", city));
+        conversation.add_assistant_message_with_tools(
+            None,
+            vec![tool_call(
+                "call_1",
+                "get_weather",
+                json!({ "location": city }),
+            )],
+        );
+        conversation.add_tool_message("call_1", r#"{"temp_c":21}"#);
+        conversation.add_assistant_message(format!("It is 21°C in {}.", city));
+        conversation
+    }
+
+    fn weather_tools() -> ToolSet {
+        let tool = serde_json::from_value(json!({
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "description": "Get the current weather",
+                "parameters": {
+                    "type": "object",
+                    "properties": { "location": { "type": "string" } },
+                    "required": ["location"],
+                },
+            },
+        }))
+        .unwrap();
+        ToolSet {
+            tools: vec![tool],
+            dispatcher: std::sync::Arc::new(|_, _| Box::pin(async { Ok(String::new()) })),
+            effects: HashMap::new(),
+            middleware: Vec::new(),
+            cache: None,
+            handoffs: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_tool_use_matches_golden_file() {
+        let options = FinetuneOptions::new().tools(&weather_tools());
+        let mut exporter = FinetuneExporter::new(options);
+        exporter
+            .add(weather_transcript("Lisbon"))
+            .add(weather_transcript("Porto"));
+
+        let export = exporter.export().unwrap();
+        assert_eq!(
+            export.train,
+            include_str!("../tests/golden/finetune_tool_use.jsonl")
+        );
+        assert_eq!(export.validation, "");
+        assert_eq!(export.report.train, 2);
+    }
+
+    #[test]
+    fn test_filters_and_deduplication_are_reported() {
+        let options = FinetuneOptions::new()
+            .require_tag("approved")
+            .require_passed_guard(true);
+        let mut unanswered = Conversation::new();
+        unanswered.add_user_message("Weather?");
+        unanswered.add_assistant_message_with_tools(
+            None,
+            vec![tool_call("call_9", "get_weather", json!({}))],
+        );
+        let mut exporter = FinetuneExporter::new(options);
+        let approved = |conversation| FinetuneExample::new(conversation).tag("approved");
+        exporter
+            .add(approved(weather_transcript("Lisbon")).passed_guard(true))
+            .add(weather_transcript("Porto"))
+            .add(approved(weather_transcript("Porto")).passed_guard(false))
+            .add(approved(weather_transcript("Lisbon")).passed_guard(true))
+            .add(approved(Conversation::with_system("Hi")).passed_guard(true))
+            .add(approved(unanswered).passed_guard(true));
+
+        let report = exporter.export().unwrap().report;
+        let reasons: Vec<_> = report.skipped.iter().map(|s| &s.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                &SkipReason::MissingTag("approved".into()),
+                &SkipReason::GuardNotPassed,
+                &SkipReason::Duplicate { of: 0 },
+                &SkipReason::NoAssistantMessage,
+                &SkipReason::UnansweredToolCall("call_9".into()),
+            ]
+        );
+        assert_eq!(report.train, 1);
+        assert_eq!(
+            report.to_string().lines().take(2).collect::<Vec<_>>(),
+            [
+                "1 training, 0 validation, 5 skipped",
+                "  #1: not tagged 'approved'"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_split_is_stable_and_written_to_files() {
+        let mut exporter = FinetuneExporter::new(FinetuneOptions::new().validation_fraction(0.5));
+        for city in ["Lisbon", "Porto", "Faro", "Braga", "Coimbra", "Évora"] {
+            exporter.add(weather_transcript(city));
+        }
+        let export = exporter.export().unwrap();
+        assert_eq!(export.report.train + export.report.validation, 6);
+        assert!(export.report.validation > 0 && export.report.train > 0);
+        assert_eq!(exporter.export().unwrap(), export);
+
+        let dir = std::env::temp_dir().join(format!("aiform-finetune-{}", std::process::id()));
+        let report = exporter.write(&dir).await.unwrap();
+        assert_eq!(report, export.report);
+        let train = std::fs::read_to_string(dir.join(TRAIN_FILE)).unwrap();
+        let validation = std::fs::read_to_string(dir.join(VALIDATION_FILE)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((train, validation), (export.train, export.validation));
+    }
+}
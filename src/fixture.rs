@@ -0,0 +1,587 @@
+//! Recording agent runs to fixture files and replaying them offline.
+//!
+//! An agent built with [`AgentBuilder::record_to`](crate::AgentBuilder::record_to)
+//! saves every completion request and response of its runs to a JSON
+//! fixture, together with the tool calls made in between. An agent built
+//! with [`AgentBuilder::replay_from`](crate::AgentBuilder::replay_from)
+//! answers from such a fixture instead of the network, so tests and demos
+//! run without an API key:
+//!
+//! ```no_run
+//! use aiform::prelude::*;
+//!
+//! # async fn example(tools: ToolSet) -> Result<()> {
+//! // Once, against the real API:
+//! let agent = Agent::builder()
+//!     .model("gpt-4o")
+//!     .tools(tools.clone())
+//!     .record_to("fixtures/weather.json")
+//!     .build()?;
+//! agent.run("What's the weather in Paris?").await?;
+//!
+//! // In CI:
+//! let agent = Agent::builder()
+//!     .model("gpt-4o")
+//!     .tools(tools)
+//!     .replay_from("fixtures/weather.json")
+//!     .build()?;
+//! agent.run("What's the weather in Paris?").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Tools still run during a replay; only the model's responses come from
+//! the fixture. Each request must match the recorded one under the
+//! [`MatchRules`], or the request fails with an error describing the
+//! difference.
+
+use crate::backend::{BackendFuture, ChatBackend, ChatRequest};
+use crate::error::{Error, Result};
+use async_openai::{
+    error::OpenAIError,
+    types::{ChatCompletionResponseStream, CreateChatCompletionResponse},
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The fixture format version written by this version of the crate.
+pub const FIXTURE_VERSION: u32 = 1;
+
+/// A recorded run: every completion exchange, in order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Fixture {
+    /// The format version the fixture was written with.
+    pub version: u32,
+    /// The recorded exchanges.
+    pub exchanges: Vec<Exchange>,
+}
+
+/// One completion request and its response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    /// The request body as sent.
+    pub request: Value,
+    /// The response, for a non-streaming request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    /// The response chunks, for a streaming request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<Vec<Value>>,
+    /// The tool calls the response asked for, with the results sent back in
+    /// the next request.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolRecord>,
+}
+
+/// A tool call made between two exchanges.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolRecord {
+    /// The id the model gave the call.
+    pub id: String,
+    /// The tool's name.
+    pub name: String,
+    /// The arguments, as the model wrote them.
+    pub arguments: String,
+    /// The result sent back to the model.
+    pub output: String,
+}
+
+impl Fixture {
+    /// Reads a fixture file.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfiguration`] if the file cannot be read,
+    /// is not a fixture, or was written with another format version.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let invalid = |reason: String| {
+            Error::InvalidConfiguration(format!("Fixture {}: {}", path.display(), reason))
+        };
+        let json = std::fs::read(path).map_err(|e| invalid(e.to_string()))?;
+        let value: Value = serde_json::from_slice(&json).map_err(|e| invalid(e.to_string()))?;
+        let version = value.get("version").and_then(Value::as_u64);
+        if version != Some(u64::from(FIXTURE_VERSION)) {
+            return Err(invalid(format!(
+                "format version {} is not supported, expected {}; record it again",
+                version.map_or("missing".to_string(), |v| v.to_string()),
+                FIXTURE_VERSION
+            )));
+        }
+        serde_json::from_value(value).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+/// Which parts of a request must match the recorded one during a replay.
+///
+/// By default the number of messages and the text of the last user message
+/// must match, so timestamps or ids in prompts do not break a replay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchRules {
+    message_count: bool,
+    last_user_message: bool,
+    model: bool,
+    full_body: bool,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self {
+            message_count: true,
+            last_user_message: true,
+            model: false,
+            full_body: false,
+        }
+    }
+}
+
+impl MatchRules {
+    /// The default rules: message count and last user message.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the number of messages must match.
+    pub fn message_count(mut self, enabled: bool) -> Self {
+        self.message_count = enabled;
+        self
+    }
+
+    /// Sets whether the text of the last user message must match.
+    pub fn last_user_message(mut self, enabled: bool) -> Self {
+        self.last_user_message = enabled;
+        self
+    }
+
+    /// Sets whether the model must match.
+    pub fn model(mut self, enabled: bool) -> Self {
+        self.model = enabled;
+        self
+    }
+
+    /// Sets whether the whole request body must match.
+    pub fn full_body(mut self, enabled: bool) -> Self {
+        self.full_body = enabled;
+        self
+    }
+
+    /// Describes how `request` differs from `recorded`, if it does.
+    fn mismatch(&self, recorded: &Value, request: &Value) -> Option<String> {
+        let messages = |body: &Value| body["messages"].as_array().map_or(0, Vec::len);
+        if self.message_count && messages(recorded) != messages(request) {
+            return Some(format!(
+                "{} messages were sent, {} were recorded",
+                messages(request),
+                messages(recorded)
+            ));
+        }
+        if self.last_user_message && last_user_text(recorded) != last_user_text(request) {
+            return Some(format!(
+                "the last user message is {:?}, {:?} was recorded",
+                last_user_text(request),
+                last_user_text(recorded)
+            ));
+        }
+        if self.model && recorded["model"] != request["model"] {
+            return Some(format!(
+                "the model is {}, {} was recorded",
+                request["model"], recorded["model"]
+            ));
+        }
+        if self.full_body && recorded != request {
+            return Some("the request body differs from the recorded one".into());
+        }
+        None
+    }
+}
+
+/// Returns the text of the last user message in a request body.
+fn last_user_text(body: &Value) -> Option<String> {
+    let message = body["messages"]
+        .as_array()?
+        .iter()
+        .rev()
+        .find(|message| message["role"] == "user")?;
+    Some(match &message["content"] {
+        Value::String(text) => text.clone(),
+        content => content
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+    })
+}
+
+fn fixture_error(message: String) -> OpenAIError {
+    OpenAIError::InvalidArgument(message)
+}
+
+/// A backend that passes requests on and records them to a fixture file.
+pub(crate) struct Recorder {
+    inner: Arc<dyn ChatBackend>,
+    path: PathBuf,
+    fixture: Mutex<Fixture>,
+}
+
+impl Recorder {
+    pub(crate) fn new(inner: Arc<dyn ChatBackend>, path: PathBuf) -> Self {
+        Self {
+            inner,
+            path,
+            fixture: Mutex::new(Fixture {
+                version: FIXTURE_VERSION,
+                exchanges: Vec::new(),
+            }),
+        }
+    }
+
+    /// Adds an exchange, fills in the tool calls of the one before from the
+    /// tool results in its request, and rewrites the file.
+    async fn record(&self, exchange: Exchange) -> std::result::Result<(), OpenAIError> {
+        let json = {
+            let mut fixture = self.fixture.lock().unwrap();
+            if let Some(previous) = fixture.exchanges.last_mut() {
+                previous.tools = tool_records(previous, &exchange.request);
+            }
+            fixture.exchanges.push(exchange);
+            serde_json::to_vec_pretty(&*fixture).map_err(OpenAIError::JSONDeserialize)?
+        };
+        let save_error = |e: std::io::Error| {
+            OpenAIError::FileSaveError(format!("{}: {}", self.path.display(), e))
+        };
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(dir).await.map_err(save_error)?;
+        }
+        // Write then rename so an interrupted run leaves the last complete
+        // recording.
+        let partial = self.path.with_extension("partial");
+        tokio::fs::write(&partial, json).await.map_err(save_error)?;
+        tokio::fs::rename(&partial, &self.path)
+            .await
+            .map_err(save_error)
+    }
+}
+
+/// Pairs the tool calls of an exchange's response with the tool results in
+/// the next request.
+fn tool_records(exchange: &Exchange, next_request: &Value) -> Vec<ToolRecord> {
+    let calls = match (&exchange.response, &exchange.chunks) {
+        (Some(response), _) => response["choices"][0]["message"]["tool_calls"].clone(),
+        (None, Some(chunks)) => {
+            // Streamed calls arrive in fragments keyed by index.
+            let mut calls: Vec<Value> = Vec::new();
+            for delta in chunks.iter().map(|chunk| &chunk["choices"][0]["delta"]) {
+                for fragment in delta["tool_calls"].as_array().into_iter().flatten() {
+                    let index = fragment["index"].as_u64().unwrap_or_default() as usize;
+                    if calls.len() <= index {
+                        calls.resize(index + 1, serde_json::json!({ "function": {} }));
+                    }
+                    let call = &mut calls[index];
+                    if let Some(id) = fragment["id"].as_str() {
+                        call["id"] = id.into();
+                    }
+                    for field in ["name", "arguments"] {
+                        if let Some(text) = fragment["function"][field].as_str() {
+                            let joined = format!(
+                                "{}{}",
+                                call["function"][field].as_str().unwrap_or_default(),
+                                text
+                            );
+                            call["function"][field] = joined.into();
+                        }
+                    }
+                }
+            }
+            Value::Array(calls)
+        }
+        (None, None) => Value::Null,
+    };
+    let messages = next_request["messages"].as_array();
+    calls
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|call| {
+            let id = call["id"].as_str()?;
+            let output = messages
+                .into_iter()
+                .flatten()
+                .find(|message| message["role"] == "tool" && message["tool_call_id"] == id)?;
+            Some(ToolRecord {
+                id: id.to_string(),
+                name: call["function"]["name"].as_str()?.to_string(),
+                arguments: call["function"]["arguments"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                output: match &output["content"] {
+                    Value::String(text) => text.clone(),
+                    content => content.to_string(),
+                },
+            })
+        })
+        .collect()
+}
+
+impl ChatBackend for Recorder {
+    fn create_chat_completion<'a>(
+        &'a self,
+        request: ChatRequest<'a>,
+    ) -> BackendFuture<'a, CreateChatCompletionResponse> {
+        Box::pin(async move {
+            let body = serde_json::to_value(request).map_err(OpenAIError::JSONDeserialize)?;
+            let response = self.inner.create_chat_completion(request).await?;
+            self.record(Exchange {
+                request: body,
+                response: Some(
+                    serde_json::to_value(&response).map_err(OpenAIError::JSONDeserialize)?,
+                ),
+                chunks: None,
+                tools: Vec::new(),
+            })
+            .await?;
+            Ok(response)
+        })
+    }
+
+    fn create_chat_completion_stream<'a>(
+        &'a self,
+        request: ChatRequest<'a>,
+    ) -> BackendFuture<'a, ChatCompletionResponseStream> {
+        Box::pin(async move {
+            let body = serde_json::to_value(request).map_err(OpenAIError::JSONDeserialize)?;
+            let mut stream = self.inner.create_chat_completion_stream(request).await?;
+            // The whole response is recorded before it is passed on.
+            let mut chunks = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                chunks.push(chunk?);
+            }
+            self.record(Exchange {
+                request: body,
+                response: None,
+                chunks: Some(
+                    chunks
+                        .iter()
+                        .map(serde_json::to_value)
+                        .collect::<serde_json::Result<_>>()
+                        .map_err(OpenAIError::JSONDeserialize)?,
+                ),
+                tools: Vec::new(),
+            })
+            .await?;
+            let chunks = chunks.into_iter().map(Ok);
+            Ok(Box::pin(futures::stream::iter(chunks)) as ChatCompletionResponseStream)
+        })
+    }
+
+    fn list_models(&self) -> BackendFuture<'_, Vec<String>> {
+        self.inner.list_models()
+    }
+}
+
+/// A backend that answers from a fixture.
+pub(crate) struct Replayer {
+    exchanges: Mutex<std::vec::IntoIter<Exchange>>,
+    models: Vec<String>,
+    rules: MatchRules,
+    sent: Mutex<usize>,
+}
+
+impl Replayer {
+    pub(crate) fn new(fixture: Fixture, rules: MatchRules) -> Self {
+        let mut models: Vec<String> = fixture
+            .exchanges
+            .iter()
+            .filter_map(|exchange| exchange.request["model"].as_str().map(String::from))
+            .collect();
+        models.sort();
+        models.dedup();
+        Self {
+            exchanges: Mutex::new(fixture.exchanges.into_iter()),
+            models,
+            rules,
+            sent: Mutex::new(0),
+        }
+    }
+
+    /// Takes the next exchange if the request matches it.
+    fn next(&self, request: ChatRequest<'_>) -> std::result::Result<Exchange, OpenAIError> {
+        let body = serde_json::to_value(request).map_err(OpenAIError::JSONDeserialize)?;
+        let index = {
+            let mut sent = self.sent.lock().unwrap();
+            *sent += 1;
+            *sent
+        };
+        let exchange = self.exchanges.lock().unwrap().next().ok_or_else(|| {
+            fixture_error(format!(
+                "Replay diverged at request {}: the fixture has no more responses",
+                index
+            ))
+        })?;
+        if let Some(mismatch) = self.rules.mismatch(&exchange.request, &body) {
+            return Err(fixture_error(format!(
+                "Replay diverged at request {}: {}",
+                index, mismatch
+            )));
+        }
+        Ok(exchange)
+    }
+}
+
+impl ChatBackend for Replayer {
+    fn create_chat_completion<'a>(
+        &'a self,
+        request: ChatRequest<'a>,
+    ) -> BackendFuture<'a, CreateChatCompletionResponse> {
+        let result = self.next(request).and_then(|exchange| {
+            let response = exchange.response.ok_or_else(|| {
+                fixture_error("Replay diverged: the recorded request was streamed".into())
+            })?;
+            serde_json::from_value(response).map_err(OpenAIError::JSONDeserialize)
+        });
+        Box::pin(async move { result })
+    }
+
+    fn create_chat_completion_stream<'a>(
+        &'a self,
+        request: ChatRequest<'a>,
+    ) -> BackendFuture<'a, ChatCompletionResponseStream> {
+        let result = self.next(request).and_then(|exchange| {
+            let chunks = exchange.chunks.ok_or_else(|| {
+                fixture_error("Replay diverged: the recorded request was not streamed".into())
+            })?;
+            let chunks = chunks
+                .into_iter()
+                .map(|chunk| serde_json::from_value(chunk).map_err(OpenAIError::JSONDeserialize));
+            Ok(Box::pin(futures::stream::iter(chunks)) as ChatCompletionResponseStream)
+        });
+        Box::pin(async move { result })
+    }
+
+    fn list_models(&self) -> BackendFuture<'_, Vec<String>> {
+        Box::pin(async move { Ok(self.models.clone()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::testing::MockChatBackend;
+    use serde_json::json;
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct GreetArgs {
+        city: String,
+    }
+
+    #[tool("Greet a city")]
+    async fn greet(args: GreetArgs) -> Result<String> {
+        Ok(format!("Hello, {}!", args.city))
+    }
+
+    fn fixture_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "aiform-fixture-{}-{}.json",
+            std::process::id(),
+            name
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let path = fixture_path("round-trip");
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("greet", json!({ "city": "Lisbon" }))
+                .respond_with_text("Done."),
+        );
+        let recording = Agent::builder()
+            .model("mock-model")
+            .tools(tools![GreetTool])
+            .backend(backend)
+            .record_to(&path)
+            .build()
+            .unwrap();
+        assert_eq!(recording.run("Greet Lisbon").await.unwrap(), "Done.");
+
+        let fixture = Fixture::load(&path).unwrap();
+        assert_eq!(fixture.version, FIXTURE_VERSION);
+        assert_eq!(fixture.exchanges.len(), 2);
+        let tool = &fixture.exchanges[0].tools[0];
+        assert_eq!(tool.name, "greet");
+        assert_eq!(tool.arguments, r#"{"city":"Lisbon"}"#);
+        assert_eq!(tool.output, "Hello, Lisbon!");
+        assert!(fixture.exchanges[1].tools.is_empty());
+
+        let replaying = Agent::builder()
+            .model("another-model")
+            .tools(tools![GreetTool])
+            .replay_from(&path)
+            .build()
+            .unwrap();
+        assert_eq!(replaying.run("Greet Lisbon").await.unwrap(), "Done.");
+
+        let diverging = Agent::builder()
+            .model("mock-model")
+            .replay_from(&path)
+            .build()
+            .unwrap();
+        let error = diverging.run("Greet Porto").await.unwrap_err();
+        assert!(error.to_string().contains("Replay diverged at request 1"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_rejects_other_versions() {
+        let path = fixture_path("version");
+        std::fs::write(&path, r#"{ "version": 0, "exchanges": [] }"#).unwrap();
+        let error = Fixture::load(&path).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("format version 0 is not supported"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_match_rules() {
+        let recorded = json!({
+            "model": "gpt-4o",
+            "messages": [
+                { "role": "system", "content": "Today is 2024-01-01" },
+                { "role": "user", "content": "Hi" },
+            ],
+        });
+        let mut request = recorded.clone();
+        request["messages"][0]["content"] = "Today is 2025-06-30".into();
+        request["model"] = "gpt-4o-mini".into();
+
+        assert_eq!(MatchRules::new().mismatch(&recorded, &request), None);
+        assert!(MatchRules::new()
+            .model(true)
+            .mismatch(&recorded, &request)
+            .is_some());
+        assert!(MatchRules::new()
+            .full_body(true)
+            .mismatch(&recorded, &request)
+            .is_some());
+
+        request["messages"][1]["content"] = "Hello".into();
+        assert_eq!(
+            MatchRules::new().mismatch(&recorded, &request),
+            Some(r#"the last user message is Some("Hello"), Some("Hi") was recorded"#.into())
+        );
+        assert_eq!(
+            MatchRules::new()
+                .last_user_message(false)
+                .mismatch(&recorded, &request),
+            None
+        );
+    }
+}
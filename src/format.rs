@@ -0,0 +1,278 @@
+//! Rendering structured output as JSON, YAML or XML.
+//!
+//! [`Agent::run_structured_as`](crate::Agent::run_structured_as) asks the
+//! model for JSON, checks it against the output type, and then renders the
+//! typed value in the [`Format`] a downstream system expects:
+//!
+//! ```no_run
+//! use aiform::format::{Format, XmlOptions};
+//! use aiform::prelude::*;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(StructuredOutput, ToolArg, Serialize, Deserialize)]
+//! struct Invoice {
+//!     number: String,
+//!     total: f64,
+//! }
+//!
+//! # async fn example(agent: Agent) -> Result<()> {
+//! let options = XmlOptions::new().root("invoice");
+//! let rendered = agent
+//!     .run_structured_as::<Invoice>("Extract the invoice: ...", Format::Xml(options))
+//!     .await?;
+//! println!("{} totals {}", rendered.value.number, rendered.value.total);
+//! println!("{}", rendered.text);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Fields are written in declaration order, so output is stable across
+//! runs as long as map fields use an ordered map such as `BTreeMap`.
+
+use crate::error::{Error, Result};
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+
+/// A text format for structured output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// Pretty-printed JSON.
+    Json,
+    /// YAML. Strings with line breaks are written as literal blocks.
+    Yaml,
+    /// XML with one element per field and an `<item>` element per list
+    /// entry.
+    Xml(XmlOptions),
+}
+
+/// How structured output is written as XML.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XmlOptions {
+    root: Option<String>,
+    rename: BTreeMap<String, String>,
+}
+
+impl XmlOptions {
+    /// Creates options that name the root element after the output type.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the name of the root element.
+    pub fn root(mut self, name: impl Into<String>) -> Self {
+        self.root = Some(name.into());
+        self
+    }
+
+    /// Writes the field `field` as an element named `element`.
+    ///
+    /// Fields without a mapping whose names are not valid XML names have
+    /// invalid characters replaced by `_`, and are prefixed with `_` if they
+    /// start with a digit or with `xml`.
+    pub fn rename(mut self, field: impl Into<String>, element: impl Into<String>) -> Self {
+        self.rename.insert(field.into(), element.into());
+        self
+    }
+}
+
+/// A typed value together with its rendering, as returned by
+/// [`Agent::run_structured_as`](crate::Agent::run_structured_as).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendered<T> {
+    /// The typed value.
+    pub value: T,
+    /// The value rendered in the requested format.
+    pub text: String,
+}
+
+/// Renders a value in the given format.
+///
+/// # Errors
+///
+/// Returns an error if the value cannot be represented in the format, e.g.
+/// a map with non-string keys as XML.
+pub fn render<T: Serialize>(value: &T, format: &Format) -> Result<String> {
+    let failed = |format: &str, e: &dyn std::fmt::Display| {
+        Error::Other(format!("Failed to render as {}: {}", format, e).into())
+    };
+    match format {
+        Format::Json => Ok(serde_json::to_string_pretty(value)?),
+        Format::Yaml => serde_yaml::to_string(value).map_err(|e| failed("YAML", &e)),
+        Format::Xml(options) => {
+            let tree = serde_yaml::to_value(value).map_err(|e| failed("XML", &e))?;
+            let tree = rename_keys(tree, &options.rename);
+            let root = match options.root {
+                Some(ref root) => xml_name(root),
+                None => xml_name(&type_name::<T>()),
+            };
+            let mut text = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+            let mut serializer = quick_xml::se::Serializer::with_root(&mut text, Some(&root))
+                .map_err(|e| failed("XML", &e))?;
+            serializer.indent(' ', 2);
+            tree.serialize(serializer).map_err(|e| failed("XML", &e))?;
+            text.push('\n');
+            Ok(text)
+        }
+    }
+}
+
+/// Returns the unqualified name of a type, without generic parameters.
+pub(crate) fn type_name<T: ?Sized>() -> String {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name).to_string()
+}
+
+/// Renames mapping keys to valid XML element names, and wraps list entries
+/// in `<item>` elements.
+fn rename_keys(value: Value, rename: &BTreeMap<String, String>) -> Value {
+    match value {
+        Value::Mapping(mapping) => Value::Mapping(
+            mapping
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        Value::String(key) => key,
+                        other => serde_yaml::to_string(&other)
+                            .unwrap_or_default()
+                            .trim_end()
+                            .to_string(),
+                    };
+                    let name = match rename.get(&key) {
+                        Some(name) => name.clone(),
+                        None => xml_name(&key),
+                    };
+                    (Value::String(name), rename_keys(value, rename))
+                })
+                .collect(),
+        ),
+        Value::Sequence(items) => {
+            let mut wrapper = Mapping::new();
+            let items = items
+                .into_iter()
+                .map(|item| rename_keys(item, rename))
+                .collect();
+            wrapper.insert("item".into(), Value::Sequence(items));
+            Value::Mapping(wrapper)
+        }
+        Value::Tagged(tagged) => {
+            let mut wrapper = Mapping::new();
+            wrapper.insert(
+                Value::String(xml_name(&tagged.tag.to_string())),
+                rename_keys(tagged.value, rename),
+            );
+            Value::Mapping(wrapper)
+        }
+        other => other,
+    }
+}
+
+/// Turns text into a valid XML element name.
+fn xml_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() || matches!(c, '_' | '-' | '.') => c,
+            _ => '_',
+        })
+        .collect();
+    let starts_badly = out
+        .chars()
+        .next()
+        .is_none_or(|c| !(c.is_alphabetic() || c == '_'));
+    if starts_badly || out.to_lowercase().starts_with("xml") {
+        out.insert(0, '_');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        id: u32,
+        customer: String,
+        #[serde(rename = "2fa")]
+        two_factor: bool,
+        #[serde(rename = "shipping address")]
+        shipping_address: Option<String>,
+        notes: String,
+        items: Vec<Item>,
+        tags: BTreeMap<String, String>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        sku: String,
+        quantity: u32,
+        price: f64,
+    }
+
+    fn order() -> Order {
+        Order {
+            id: 1042,
+            customer: "Ana & Rui <Lda>".into(),
+            two_factor: true,
+            shipping_address: None,
+            notes: "Leave at the door.\nRing twice.".into(),
+            items: vec![
+                Item {
+                    sku: "A-1".into(),
+                    quantity: 2,
+                    price: 9.5,
+                },
+                Item {
+                    sku: "B-7".into(),
+                    quantity: 1,
+                    price: 120.0,
+                },
+            ],
+            tags: BTreeMap::from([("priority".into(), "high".into())]),
+        }
+    }
+
+    #[test]
+    fn test_yaml_matches_golden_file_and_round_trips() {
+        let yaml = render(&order(), &Format::Yaml).unwrap();
+        assert_eq!(yaml, include_str!("../tests/golden/order.yaml"));
+        assert_eq!(serde_yaml::from_str::<Order>(&yaml).unwrap(), order());
+    }
+
+    #[test]
+    fn test_xml_matches_golden_file() {
+        let options = XmlOptions::new().rename("2fa", "two_factor");
+        let xml = render(&order(), &Format::Xml(options)).unwrap();
+        assert_eq!(xml, include_str!("../tests/golden/order.xml"));
+
+        #[derive(Deserialize)]
+        struct XmlOrder {
+            customer: String,
+            two_factor: bool,
+            notes: String,
+            items: XmlItems,
+        }
+        #[derive(Deserialize)]
+        struct XmlItems {
+            item: Vec<Item>,
+        }
+        let parsed: XmlOrder = quick_xml::de::from_str(&xml).unwrap();
+        assert_eq!(parsed.customer, order().customer);
+        assert!(parsed.two_factor);
+        assert_eq!(parsed.notes, order().notes);
+        assert_eq!(parsed.items.item, order().items);
+    }
+
+    #[test]
+    fn test_xml_names() {
+        assert_eq!(xml_name("shipping address"), "shipping_address");
+        assert_eq!(xml_name("2fa"), "_2fa");
+        assert_eq!(xml_name("xmlns"), "_xmlns");
+        assert_eq!(xml_name(""), "_");
+        assert_eq!(type_name::<Vec<Order>>(), "Vec");
+        assert_eq!(type_name::<Order>(), "Order");
+    }
+}
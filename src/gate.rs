@@ -0,0 +1,254 @@
+//! A gate that asks for clarification before acting on vague requests.
+//!
+//! Prompting an agent to "ask when unsure" is not enough for workflows that
+//! delete, send or pay. An [`AmbiguityGate`] enforces it: before an
+//! [outcome run](crate::Agent::run_outcome) starts, a cheap classification
+//! request decides whether the user's request is specific enough to execute.
+//! If it is not, the run ends with
+//! [`RunOutcome::NeedsUserInput`](crate::RunOutcome::NeedsUserInput) and a
+//! clarifying question, and no tool is called.
+//!
+//! ```no_run
+//! use aiform::gate::AmbiguityGate;
+//! use aiform::prelude::*;
+//! use aiform::RunOutcome;
+//!
+//! # async fn example() -> Result<()> {
+//! let agent = Agent::builder()
+//!     .model("gpt-4o")
+//!     .ask_user(true)
+//!     .ambiguity_gate(
+//!         AmbiguityGate::new()
+//!             .model("gpt-4o-mini")
+//!             .threshold(0.7)
+//!             .always_proceed("list *"),
+//!     )
+//!     .build()?;
+//!
+//! match agent.run_outcome("Delete the old ones").await? {
+//!     RunOutcome::NeedsUserInput { question } => println!("{}", question),
+//!     other => println!("{:?}", other),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The gate fails open: if the classification request fails or its answer
+//! cannot be parsed, the run proceeds and a
+//! [`Warning::AmbiguityGateFailed`](crate::Warning::AmbiguityGateFailed) is
+//! emitted.
+
+use crate::conversation::Conversation;
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+
+/// Instructions for the classification request.
+pub(crate) const GATE_PROMPT: &str = "You decide whether an assistant can act on the user's \
+latest request without guessing. Consider the whole conversation. Answer with a JSON object: \
+{\"specific_enough\": boolean, \"confidence\": number between 0 and 1, \
+\"missing_information\": [strings], \"question\": string}. \"question\" is a single clarifying \
+question for the user, or an empty string when the request is specific enough.";
+
+/// Configuration of the clarification gate; see the [module docs](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmbiguityGate {
+    pub(crate) model: Option<String>,
+    threshold: f32,
+    always_proceed: Vec<String>,
+}
+
+impl Default for AmbiguityGate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AmbiguityGate {
+    /// Creates a gate that classifies with the agent's own model and asks
+    /// whenever the classifier is at least 50% sure the request is too vague.
+    pub fn new() -> Self {
+        Self {
+            model: None,
+            threshold: 0.5,
+            always_proceed: Vec::new(),
+        }
+    }
+
+    /// Sets the model used for classification, usually a cheaper one than
+    /// the agent's.
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    /// Sets how confident, between 0.0 and 1.0, the classifier must be that
+    /// a request is too vague before the user is asked.
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Lets requests matching `pattern` proceed without classification.
+    ///
+    /// Patterns match the whole request, ignoring case and surrounding
+    /// whitespace; `*` matches any text, e.g. `"show * orders"`.
+    pub fn always_proceed(mut self, pattern: impl Into<String>) -> Self {
+        self.always_proceed.push(pattern.into());
+        self
+    }
+
+    /// Returns whether `request` matches an
+    /// [`always_proceed`](Self::always_proceed) pattern.
+    pub fn is_allowed(&self, request: &str) -> bool {
+        let request = request.trim().to_lowercase();
+        self.always_proceed
+            .iter()
+            .any(|pattern| matches_pattern(&pattern.trim().to_lowercase(), &request))
+    }
+
+    /// Decides from a classification whether the run should proceed.
+    pub(crate) fn verdict(&self, classification: &GateClassification) -> GateVerdict {
+        if !classification.specific_enough && classification.confidence >= self.threshold {
+            GateVerdict::Clarify
+        } else {
+            GateVerdict::Proceed
+        }
+    }
+}
+
+/// The classifier's assessment of a request.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GateClassification {
+    /// Whether the request can be executed without guessing.
+    pub specific_enough: bool,
+    /// How sure the classifier is, between 0.0 and 1.0.
+    #[serde(default = "full_confidence")]
+    pub confidence: f32,
+    /// What the request leaves open.
+    #[serde(default)]
+    pub missing_information: Vec<String>,
+    /// The question to ask the user, empty if none.
+    #[serde(default)]
+    pub question: String,
+}
+
+fn full_confidence() -> f32 {
+    1.0
+}
+
+/// What the gate decided.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GateVerdict {
+    /// The request matched an always-proceed pattern and was not classified.
+    Allowed,
+    /// The request was specific enough, or the classifier was not sure
+    /// enough that it was not.
+    Proceed,
+    /// The user was asked a clarifying question.
+    Clarify,
+    /// Classification failed and the run proceeded anyway.
+    Failed(String),
+}
+
+/// The gate's decision for one run, reported in
+/// [`OutcomeResponse::gate`](crate::agent::OutcomeResponse::gate).
+#[derive(Debug, Clone, PartialEq)]
+pub struct GateReport {
+    /// What the gate decided.
+    pub verdict: GateVerdict,
+    /// The classification, unless the request was allowed without one or
+    /// classification failed.
+    pub classification: Option<GateClassification>,
+    /// Time spent classifying.
+    pub latency: Duration,
+}
+
+/// Renders the user and assistant text of a conversation for the
+/// classifier, along with the latest request. Returns `None` unless the
+/// conversation ends with a user message.
+pub(crate) fn transcript(conversation: &Conversation) -> Option<(String, String)> {
+    let messages = serde_json::to_value(conversation.messages()).ok()?;
+    let mut transcript = String::new();
+    let mut latest = None;
+    for message in messages.as_array()? {
+        let role = message["role"].as_str().unwrap_or_default();
+        let content = message["content"].as_str();
+        latest = match (role, content) {
+            ("user", Some(content)) => Some(content.to_string()),
+            ("system", _) => latest,
+            _ => None,
+        };
+        let speaker = match role {
+            "user" => "User",
+            "assistant" => "Assistant",
+            _ => continue,
+        };
+        if let Some(content) = content {
+            transcript.push_str(&format!("{}: {}\n", speaker, content));
+        }
+    }
+    latest.map(|latest| (transcript, latest))
+}
+
+/// Parses the classifier's answer.
+pub(crate) fn parse_classification(content: &str) -> Result<GateClassification, String> {
+    let value: Value = serde_json::from_str(content.trim())
+        .map_err(|e| format!("classification is not JSON: {}", e))?;
+    serde_json::from_value(value).map_err(|e| format!("unexpected classification: {}", e))
+}
+
+/// Matches `text` against a pattern where `*` matches any text.
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_always_proceed_patterns() {
+        let gate = AmbiguityGate::new()
+            .always_proceed("list *")
+            .always_proceed("show * orders")
+            .always_proceed("help");
+        assert!(gate.is_allowed("List my invoices"));
+        assert!(gate.is_allowed("  show all open ORDERS "));
+        assert!(gate.is_allowed("help"));
+        assert!(!gate.is_allowed("help me delete everything"));
+        assert!(!gate.is_allowed("show orders"));
+        assert!(!gate.is_allowed("delete the list"));
+    }
+
+    #[test]
+    fn test_verdict_respects_threshold() {
+        let gate = AmbiguityGate::new().threshold(0.8);
+        let classification = parse_classification(
+            r#"{"specific_enough": false, "confidence": 0.6, "question": "Which ones?"}"#,
+        )
+        .unwrap();
+        assert_eq!(gate.verdict(&classification), GateVerdict::Proceed);
+        let sure = GateClassification {
+            confidence: 0.9,
+            ..classification
+        };
+        assert_eq!(gate.verdict(&sure), GateVerdict::Clarify);
+        assert!(parse_classification("yes").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Hooks that observe an agent's run.
+//!
+//! An [`AgentHook`] is told about every completion request, every response,
+//! and every tool call the agent loop makes, for logging or auditing without
+//! wrapping the loop. Register hooks with
+//! [`AgentBuilder::hook`](crate::AgentBuilder::hook); several hooks are
+//! called in registration order.
+//!
+//! ```no_run
+//! use aiform::hook::{AgentHook, HookAction};
+//! use aiform::tool_error::ToolError;
+//! use futures::future::BoxFuture;
+//! use std::time::Duration;
+//!
+//! struct AuditLog;
+//!
+//! impl AgentHook for AuditLog {
+//!     fn on_tool_end<'a>(
+//!         &'a self,
+//!         name: &'a str,
+//!         result: &'a Result<String, ToolError>,
+//!         duration: Duration,
+//!     ) -> BoxFuture<'a, HookAction> {
+//!         Box::pin(async move {
+//!             let size = result.as_ref().map_or(0, String::len);
+//!             println!("{} took {:?} and returned {} bytes", name, duration, size);
+//!             HookAction::Continue
+//!         })
+//!     }
+//! }
+//! ```
+//!
+//! Hooks cannot break a run by accident: a hook that panics is reported as a
+//! [`Warning::HookPanicked`](crate::Warning::HookPanicked) and the run goes
+//! on. A run only stops when a hook returns [`HookAction::Abort`].
+
+use crate::agent::Usage;
+use crate::conversation::Conversation;
+use crate::tool_error::ToolError;
+use async_openai::types::ChatCompletionResponseMessage;
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::time::Duration;
+
+/// What the agent does after a hook ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HookAction {
+    /// Carry on with the run.
+    Continue,
+    /// Stop the run with [`Error::HookAborted`](crate::Error::HookAborted).
+    Abort {
+        /// Why the hook stopped the run.
+        reason: String,
+    },
+}
+
+/// Observes an agent's run; see the [module docs](self).
+///
+/// Every method defaults to doing nothing.
+pub trait AgentHook: Send + Sync {
+    /// Called once when a run starts, with the name of the agent running it,
+    /// so a hook shared by several agents can tell their runs apart.
+    fn on_run_start<'a>(&'a self, agent: Option<&'a str>) -> BoxFuture<'a, HookAction> {
+        let _ = agent;
+        Box::pin(async { HookAction::Continue })
+    }
+
+    /// Called at the start of every loop iteration, counting from 1 across
+    /// the whole run.
+    fn on_iteration(&self, iteration: usize) -> BoxFuture<'_, HookAction> {
+        let _ = iteration;
+        Box::pin(async { HookAction::Continue })
+    }
+
+    /// Called before each completion request with the conversation it
+    /// sends.
+    fn on_request<'a>(&'a self, conversation: &'a Conversation) -> BoxFuture<'a, HookAction> {
+        let _ = conversation;
+        Box::pin(async { HookAction::Continue })
+    }
+
+    /// Called with the message of each completion response and the tokens
+    /// the request used.
+    fn on_response<'a>(
+        &'a self,
+        message: &'a ChatCompletionResponseMessage,
+        usage: &'a Usage,
+    ) -> BoxFuture<'a, HookAction> {
+        let _ = (message, usage);
+        Box::pin(async { HookAction::Continue })
+    }
+
+    /// Called before a tool is called, with its parsed arguments.
+    fn on_tool_start<'a>(&'a self, name: &'a str, args: &'a Value) -> BoxFuture<'a, HookAction> {
+        let _ = (name, args);
+        Box::pin(async { HookAction::Continue })
+    }
+
+    /// Called after a tool call finished, with its output or error and how
+    /// long it took, retries included.
+    fn on_tool_end<'a>(
+        &'a self,
+        name: &'a str,
+        result: &'a Result<String, ToolError>,
+        duration: Duration,
+    ) -> BoxFuture<'a, HookAction> {
+        let _ = (name, result, duration);
+        Box::pin(async { HookAction::Continue })
+    }
+}
@@ -62,9 +62,8 @@
 //! conversation.add_user_message("Hello!");
 //!
 //! let response = agent.run_conversation(&mut conversation).await?;
-//! conversation.add_assistant_message(&response);
 //!
-//! // Continue the conversation
+//! // The answer is already in the conversation; continue it
 //! conversation.add_user_message("Tell me more");
 //! let response = agent.run_conversation(&mut conversation).await?;
 //! # Ok(())
@@ -74,113 +73,609 @@
 pub use aiform_macros::*;
 pub use async_openai as openai;
 
+// Lets the derives' `::aiform` paths resolve inside this crate too.
+extern crate self as aiform;
+
+/// Dependencies referenced by macro expansions. Not public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::schema::{define, hoist_defs, nullable, strict, with_defs};
+    pub use serde_json;
+    pub use tokio::task::spawn_blocking;
+
+    /// Builds the tool set of a `tools!` invocation, panicking on duplicate
+    /// names.
+    #[track_caller]
+    pub fn unique_tools(builder: crate::ToolSetBuilder) -> crate::ToolSet {
+        match builder.build() {
+            Ok(tools) => tools,
+            Err(error) => panic!("tools!: {}", error),
+        }
+    }
+
+    /// Parses a tool's arguments, reading `{}` and `null` as "no arguments"
+    /// for types such as unit structs that deserialize from neither.
+    pub fn parse_args<T: serde::de::DeserializeOwned>(
+        args: serde_json::Value,
+    ) -> Result<T, serde_json::Error> {
+        let empty = args.is_null() || args.as_object().is_some_and(|args| args.is_empty());
+        match serde_json::from_value(args) {
+            Err(e) if empty => serde_json::from_value(serde_json::Value::Null)
+                .or_else(|_| serde_json::from_value(serde_json::json!({})))
+                .map_err(|_| e),
+            result => result,
+        }
+    }
+
+    /// Wraps a tool's return value to pick how it becomes the tool message:
+    /// `(&&OutputKind(&value)).output_kind()` resolves to [`IntoStringOutput`]
+    /// for types convertible into `String`, and otherwise to
+    /// [`SerializeOutput`].
+    pub struct OutputKind<'a, T>(pub &'a T);
+
+    /// Converts a return value with `Into<String>`.
+    pub struct IntoStringTag;
+
+    impl IntoStringTag {
+        pub fn convert<T: Into<String>>(self, value: T) -> Result<String, serde_json::Error> {
+            Ok(value.into())
+        }
+    }
+
+    /// Converts a return value by serializing it as JSON.
+    pub struct SerializeTag;
+
+    impl SerializeTag {
+        pub fn convert<T: serde::Serialize>(self, value: T) -> Result<String, serde_json::Error> {
+            serde_json::to_string(&value)
+        }
+    }
+
+    pub trait IntoStringOutput {
+        fn output_kind(&self) -> IntoStringTag {
+            IntoStringTag
+        }
+    }
+
+    impl<T: Into<String>> IntoStringOutput for &OutputKind<'_, T> {}
+
+    pub trait SerializeOutput {
+        fn output_kind(&self) -> SerializeTag {
+            SerializeTag
+        }
+    }
+
+    impl<T: serde::Serialize> SerializeOutput for OutputKind<'_, T> {}
+
+    /// Names a nested type in a `StructuredOutput` schema:
+    /// `(&&NestedSchema::<T>::new()).nested_schema()` resolves to
+    /// [`StructuredOutputSchema`] for types deriving `StructuredOutput`, and
+    /// otherwise to [`ToolArgSchema`].
+    pub struct NestedSchema<T: ?Sized>(std::marker::PhantomData<T>);
+
+    impl<T: ?Sized> NestedSchema<T> {
+        #[allow(clippy::new_without_default)]
+        pub fn new() -> Self {
+            NestedSchema(std::marker::PhantomData)
+        }
+    }
+
+    type Defs = serde_json::Map<String, serde_json::Value>;
+
+    pub trait StructuredOutputSchema {
+        fn nested_schema(&self, defs: &mut Defs) -> serde_json::Value;
+    }
+
+    impl<T: crate::StructuredOutput + ?Sized> StructuredOutputSchema for &NestedSchema<T> {
+        fn nested_schema(&self, defs: &mut Defs) -> serde_json::Value {
+            hoist_defs(T::schema(), defs)
+        }
+    }
+
+    pub trait ToolArgSchema {
+        fn nested_schema(&self, defs: &mut Defs) -> serde_json::Value;
+    }
+
+    impl<T: crate::ToolArg + ?Sized> ToolArgSchema for NestedSchema<T> {
+        fn nested_schema(&self, defs: &mut Defs) -> serde_json::Value {
+            T::schema_with_defs(defs)
+        }
+    }
+}
+
 pub mod agent;
 pub mod agent_tool;
+pub mod approval;
+pub mod attachment;
+pub mod backend;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cache;
+pub mod cancel;
+pub mod client;
+pub mod context;
 pub mod conversation;
+pub mod doctor;
+pub mod duration;
 pub mod error;
+pub mod ext;
+pub mod finetune;
+pub mod fixture;
+pub mod format;
+pub mod gate;
+pub mod hook;
+pub mod limiter;
+pub mod metrics;
+pub mod middleware;
+pub mod openrouter;
+pub mod plain_text;
+pub mod profile;
+pub mod provider;
+pub mod registry;
+pub mod render;
+pub mod retry;
+mod schema;
+pub mod store;
+pub mod stream;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+pub mod tokens;
+pub mod tool_error;
+mod trace;
+pub mod warning;
 
-pub use agent::{Agent, AgentBuilder};
+pub use agent::{
+    Agent, AgentBuilder, AgentResponse, AgentRun, AgentStep, ArgumentContinuation, HandoffResponse,
+    OutcomeResponse, PendingToolCall, PostProcessor, RunOptions, RunOutcome, RunReport, RunTimings,
+    ToolChoice, Usage,
+};
 pub use agent_tool::AgentTool;
+pub use backend::ChatBackend;
+pub use cancel::CancelHandle;
+pub use client::{default_client, set_default_client};
 pub use conversation::Conversation;
+pub use doctor::doctor;
 pub use error::{Error, Result};
+pub use registry::AgentRegistry;
+pub use render::ToolOutput;
+pub use stream::AgentEvent;
+pub use tool_error::{ToolError, ToolErrorPolicy};
+pub use warning::Warning;
 
 /// Convenience re-exports for common imports.
 pub mod prelude {
     pub use crate::agent::{Agent, AgentBuilder};
     pub use crate::conversation::Conversation;
     pub use crate::error::{Error, Result};
-    pub use crate::{msg, tool, tools, StructuredOutput, Tool, ToolArg, ToolSet};
+    pub use crate::{msg, tool, tools, StructuredOutput, Tool, ToolArg, ToolEffects, ToolSet};
 }
 
+/// The boxed future returned by a [`ToolSet`] dispatcher.
+pub type ToolFuture = std::pin::Pin<
+    Box<
+        dyn std::future::Future<
+                Output = std::result::Result<String, Box<dyn std::error::Error + Send + Sync>>,
+            > + Send,
+    >,
+>;
+
+/// Routes tool calls by name to their implementations.
+pub(crate) type Dispatcher =
+    std::sync::Arc<dyn Fn(String, serde_json::Value) -> ToolFuture + Send + Sync>;
+
 /// Combines tool definitions with their dispatch logic.
 ///
-/// Created using the `tools!` macro, this bundles OpenAI tool definitions
-/// with a dispatcher that routes tool calls to their implementations.
+/// Created using the `tools!` macro or [`ToolSet::builder`], this bundles
+/// OpenAI tool definitions with a dispatcher that routes tool calls to their
+/// implementations.
+///
+/// Cloning is cheap: clones share the dispatcher.
+#[derive(Clone)]
 pub struct ToolSet {
     /// The OpenAI tool definitions for API requests.
     pub tools: Vec<async_openai::types::ChatCompletionTool>,
     /// Dispatcher function that routes tool calls by name.
-    pub dispatcher: Box<
-        dyn Fn(
-                String,
-                serde_json::Value,
-            ) -> std::pin::Pin<
-                Box<
-                    dyn std::future::Future<
-                            Output = std::result::Result<
-                                String,
-                                Box<dyn std::error::Error + Send + Sync>,
-                            >,
-                        > + Send,
-                >,
-            > + Send
-            + Sync,
-    >,
+    pub dispatcher: std::sync::Arc<dyn Fn(String, serde_json::Value) -> ToolFuture + Send + Sync>,
+    /// Side-effect classification by tool name. Tools missing from the map
+    /// are treated as [`ToolEffects::Mutating`].
+    pub effects: std::collections::HashMap<String, ToolEffects>,
+    /// Middleware wrapping every call, innermost first; see
+    /// [`with_middleware`](Self::with_middleware).
+    pub middleware: Vec<std::sync::Arc<dyn middleware::ToolMiddleware>>,
+    /// Results remembered between calls; see
+    /// [`with_cache`](Self::with_cache).
+    pub cache: Option<cache::ToolCache>,
+    /// The agent each handoff tool transfers to, by tool name; see
+    /// [`Agent::handoff_tools`].
+    pub handoffs: std::collections::HashMap<String, String>,
 }
 
 impl ToolSet {
+    /// Creates a builder for a tool set assembled at runtime.
+    ///
+    /// ```ignore
+    /// let mut builder = ToolSet::builder().register(Box::new(GetWeatherTool));
+    /// for plugin in plugins {
+    ///     builder = builder.register(plugin);
+    /// }
+    /// let tools = builder.build()?;
+    /// ```
+    pub fn builder() -> ToolSetBuilder {
+        ToolSetBuilder::default()
+    }
+
     /// Returns the tool definitions for use in API requests.
     pub fn tools(&self) -> &[async_openai::types::ChatCompletionTool] {
         &self.tools
     }
 
+    /// Returns the names of the tools in the set, in the order they are
+    /// offered to the model.
+    pub fn tool_names(&self) -> Vec<&str> {
+        self.tools
+            .iter()
+            .map(|tool| tool.function.name.as_str())
+            .collect()
+    }
+
+    /// Returns the side-effect classification of a tool.
+    pub fn effects(&self, name: &str) -> ToolEffects {
+        self.effects.get(name).copied().unwrap_or_default()
+    }
+
+    /// Returns the names of all tools classified as mutating.
+    pub fn mutating_tools(&self) -> Vec<&str> {
+        self.tools
+            .iter()
+            .map(|tool| tool.function.name.as_str())
+            .filter(|name| self.effects(name) == ToolEffects::Mutating)
+            .collect()
+    }
+
+    /// Exports the tool definitions together with their metadata.
+    ///
+    /// Each entry holds the tool's `name`, `description`, `parameters` and
+    /// `effects`, for auditing what an agent is able to do.
+    pub fn manifest(&self) -> serde_json::Value {
+        self.tools
+            .iter()
+            .map(|tool| {
+                serde_json::json!({
+                    "name": tool.function.name,
+                    "description": tool.function.description,
+                    "parameters": tool.function.parameters,
+                    "effects": self.effects(&tool.function.name),
+                })
+            })
+            .collect()
+    }
+
     /// Dispatches a tool call by name with the provided arguments.
+    ///
+    /// Calls to a tool not in the set fail with an [`Error::ToolNotFound`],
+    /// which can be recovered with `error.downcast::<Error>()`.
     pub async fn dispatch(
         &self,
         name: String,
         args: serde_json::Value,
     ) -> std::result::Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        (self.dispatcher)(name, args).await
+        let dispatcher = match self.cache {
+            Some(ref cache) => cache.wrap(self.dispatcher.clone()),
+            None => self.dispatcher.clone(),
+        };
+        if self.middleware.is_empty() {
+            return dispatcher(name, args).await;
+        }
+        let parameters = self
+            .tools
+            .iter()
+            .find(|tool| tool.function.name == name)
+            .and_then(|tool| tool.function.parameters.clone());
+        let call = middleware::ToolCall {
+            name,
+            arguments: args,
+            parameters,
+        };
+        middleware::Next::new(dispatcher, self.middleware.clone())
+            .run(call)
+            .await
+    }
+
+    /// Wraps every call of the set, including calls to tools merged in
+    /// later, in `middleware`; see the [`middleware`] module.
+    ///
+    /// Middleware added later runs first.
+    pub fn with_middleware(
+        mut self,
+        middleware: impl middleware::ToolMiddleware + 'static,
+    ) -> Self {
+        self.middleware.push(std::sync::Arc::new(middleware));
+        self
+    }
+
+    /// Remembers the results of the tools named in `policy`, answering
+    /// later calls with equal arguments without running the tool; see the
+    /// [`cache`] module.
+    ///
+    /// The cache sits inside the set's middleware, so middleware sees every
+    /// call. It covers tools merged in later too, and replaces an earlier
+    /// cache.
+    pub fn with_cache(mut self, policy: cache::CachePolicy) -> Self {
+        self.cache = Some(cache::ToolCache::new(policy));
+        self
+    }
+
+    /// Returns how often the set's cache answered calls, if it has one.
+    pub fn cache_stats(&self) -> Option<cache::CacheStats> {
+        self.cache.as_ref().map(cache::ToolCache::stats)
+    }
+
+    /// A dispatcher that runs the set's middleware and cache before its
+    /// tools.
+    fn routed(&self) -> Dispatcher {
+        if self.middleware.is_empty() && self.cache.is_none() {
+            return self.dispatcher.clone();
+        }
+        let set = std::sync::Arc::new(self.clone());
+        std::sync::Arc::new(move |name: String, args: serde_json::Value| {
+            let set = set.clone();
+            Box::pin(async move { set.dispatch(name, args).await }) as ToolFuture
+        })
+    }
+
+    /// Combines two tool sets into one.
+    ///
+    /// ```ignore
+    /// let tools = tools![ReadFileTool, WriteFileTool].merge(tools![FetchUrlTool])?;
+    /// ```
+    ///
+    /// See [`extend`](Self::extend) for how calls are routed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfiguration`] naming the tools both sets
+    /// define.
+    pub fn merge(mut self, other: ToolSet) -> Result<ToolSet> {
+        self.extend(other);
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Adds the tools of `other` to this set.
+    ///
+    /// Calls go to this set's dispatcher for tools it defines and to
+    /// `other`'s otherwise. This set's middleware wraps calls to `other`'s
+    /// tools too, around `other`'s own. Duplicate names are kept; check for
+    /// them with [`validate`](Self::validate).
+    pub fn extend(&mut self, other: ToolSet) {
+        let names: std::collections::HashSet<String> = self
+            .tools
+            .iter()
+            .map(|tool| tool.function.name.clone())
+            .collect();
+        let first = self.dispatcher.clone();
+        let second = other.routed();
+        self.dispatcher = std::sync::Arc::new(move |name: String, args: serde_json::Value| {
+            if names.contains(&name) {
+                first(name, args)
+            } else {
+                second(name, args)
+            }
+        });
+        self.tools.extend(other.tools);
+        for (name, effects) in other.effects {
+            self.effects.entry(name).or_insert(effects);
+        }
+        for (name, target) in other.handoffs {
+            self.handoffs.entry(name).or_insert(target);
+        }
+    }
+
+    /// Checks that no two tools share a name.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfiguration`] naming the duplicated tools.
+    pub fn validate(&self) -> Result<()> {
+        check_unique_names(self.tool_names())
     }
 }
 
-impl Clone for ToolSet {
-    fn clone(&self) -> Self {
-        panic!("ToolSet cannot be cloned due to containing a closure. Use a reference instead.");
+/// Fails with [`Error::InvalidConfiguration`] naming each name that occurs
+/// more than once.
+fn check_unique_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<()> {
+    let mut seen = std::collections::HashSet::new();
+    let mut duplicates: Vec<&str> = Vec::new();
+    for name in names {
+        if !seen.insert(name) && !duplicates.contains(&name) {
+            duplicates.push(name);
+        }
+    }
+    if !duplicates.is_empty() {
+        return Err(Error::InvalidConfiguration(format!(
+            "Duplicate tool names: {}",
+            duplicates.join(", ")
+        )));
     }
+    Ok(())
 }
 
-/// Creates a `ToolSet` from tool structs.
-///
-/// # Example
-///
-/// ```ignore
-/// let tools = tools![GetWeatherTool, CalculateTool];
-/// ```
-#[macro_export]
-macro_rules! tools {
-    ($($tool:ident),* $(,)?) => {{
-        let tools_vec = vec![
-            $(
-                async_openai::types::ChatCompletionTool {
-                    r#type: async_openai::types::ChatCompletionToolType::Function,
-                    function: async_openai::types::FunctionObject {
-                        name: $tool::NAME.to_string(),
-                        description: Some($tool::DESCRIPTION.to_string()),
-                        parameters: Some($tool::parameters()),
-                    },
+impl ToolSet {
+    /// Combines a shared tool set with additional tools.
+    ///
+    /// Calls to a tool defined in `extra` go to `extra`; everything else goes
+    /// to `base`. `extra` definitions replace same-named ones in `base`.
+    pub(crate) fn layered(base: std::sync::Arc<ToolSet>, extra: ToolSet) -> ToolSet {
+        let base_dispatcher = base.routed();
+        let extra_dispatcher = extra.routed();
+        let extra_names: std::collections::HashSet<String> = extra
+            .tools
+            .iter()
+            .map(|tool| tool.function.name.clone())
+            .collect();
+
+        let mut tools: Vec<_> = base
+            .tools
+            .iter()
+            .filter(|tool| !extra_names.contains(&tool.function.name))
+            .cloned()
+            .collect();
+        tools.extend(extra.tools);
+
+        let mut effects = base.effects.clone();
+        effects.extend(extra.effects);
+        let mut handoffs = base.handoffs.clone();
+        handoffs.extend(extra.handoffs);
+
+        let dispatcher = std::sync::Arc::new(move |name: String, args: serde_json::Value| {
+            if extra_names.contains(&name) {
+                extra_dispatcher(name, args)
+            } else {
+                base_dispatcher(name, args)
+            }
+        });
+
+        ToolSet {
+            tools,
+            dispatcher,
+            effects,
+            middleware: Vec::new(),
+            cache: None,
+            handoffs,
+        }
+    }
+}
+
+/// Builds a [`ToolSet`] from [`ErasedTool`]s; see [`ToolSet::builder`].
+#[derive(Default)]
+pub struct ToolSetBuilder {
+    tools: Vec<Box<dyn ErasedTool>>,
+}
+
+impl ToolSetBuilder {
+    /// Adds a tool. Its name must differ from those of the tools registered
+    /// before it; see [`build`](Self::build).
+    pub fn register(mut self, tool: Box<dyn ErasedTool>) -> Self {
+        self.tools.push(tool);
+        self
+    }
+
+    /// Adds a tool created from a shared context, such as application state
+    /// holding a database pool; see [`FromContext`].
+    ///
+    /// ```ignore
+    /// let tools = ToolSet::builder()
+    ///     .register_from_context::<QueryTool, _>(&state)
+    ///     .build()?;
+    /// ```
+    pub fn register_from_context<T, C>(self, context: &std::sync::Arc<C>) -> Self
+    where
+        T: FromContext<C> + ErasedTool + 'static,
+        C: ?Sized,
+    {
+        self.register(Box::new(T::from_context(context)))
+    }
+
+    /// Builds the tool set, offering tools in registration order.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfiguration`] naming the tools registered
+    /// more than once under the same name.
+    pub fn build(self) -> Result<ToolSet> {
+        check_unique_names(self.tools.iter().map(|tool| tool.name()))?;
+        let tools = self
+            .tools
+            .iter()
+            .map(|tool| async_openai::types::ChatCompletionTool {
+                r#type: async_openai::types::ChatCompletionToolType::Function,
+                function: async_openai::types::FunctionObject {
+                    name: tool.name().to_string(),
+                    description: Some(tool.description().to_string()),
+                    parameters: Some(tool.parameters()),
+                    strict: None,
                 },
-            )*
-        ];
+            })
+            .collect();
+        let effects = self
+            .tools
+            .iter()
+            .map(|tool| (tool.name().to_string(), tool.effects()))
+            .collect();
 
-        let dispatcher = Box::new(|name: String, args: serde_json::Value| {
+        let by_name: std::collections::HashMap<String, Box<dyn ErasedTool>> = self
+            .tools
+            .into_iter()
+            .map(|tool| (tool.name().to_string(), tool))
+            .collect();
+        let by_name = std::sync::Arc::new(by_name);
+        let dispatcher = std::sync::Arc::new(move |name: String, args: serde_json::Value| {
+            let by_name = by_name.clone();
             Box::pin(async move {
-                match name.as_str() {
-                    $(
-                        $tool::NAME => {
-                            $tool.call(args).await
-                        }
-                    )*
-                    _ => Err("Unknown tool".into()),
+                match by_name.get(&name) {
+                    Some(tool) => tool.call(args).await,
+                    None => {
+                        trace::unknown_tool(&name);
+                        Err(Error::ToolNotFound(name).into())
+                    }
                 }
-            }) as std::pin::Pin<Box<dyn std::future::Future<Output = std::result::Result<String, Box<dyn std::error::Error + Send + Sync>>> + Send>>
+            }) as ToolFuture
         });
 
-        ToolSet {
-            tools: tools_vec,
+        Ok(ToolSet {
+            tools,
             dispatcher,
-        }
-    }};
+            effects,
+            middleware: Vec::new(),
+            cache: None,
+            handoffs: std::collections::HashMap::new(),
+        })
+    }
+}
+
+/// Creates a `ToolSet` from tools.
+///
+/// Takes unit tool structs as generated by `#[tool]`, or any expression
+/// evaluating to a [`Tool`], so tools can carry state such as a database
+/// pool. Tools from other modules or crates can be named by their path.
+/// Instances must be `Send + Sync + 'static`; they are moved into the tool
+/// set and reused for every call, also by its clones. `tools![]` is an
+/// empty tool set.
+///
+/// Tools generated from functions with a context parameter are created from
+/// a shared context with `with context: Tool, ...`, where `context` is an
+/// `Arc` of the context type. Tools without one ignore it.
+///
+/// # Example
+///
+/// ```ignore
+/// let tools = tools![GetWeatherTool, CalculateTool, QueryTool::new(pool)];
+/// let tools = tools![weather::GetWeatherTool, math::CalculateTool];
+///
+/// let state = Arc::new(AppState::connect().await?);
+/// let tools = tools![with state: QueryTool, GetWeatherTool];
+/// ```
+///
+/// # Panics
+///
+/// Panics if two of the tools have the same name, which would leave the
+/// model unable to call one of them.
+#[macro_export]
+macro_rules! tools {
+    (with $context:ident : $($tool:ty),* $(,)?) => {
+        $crate::__private::unique_tools(
+            $crate::ToolSet::builder()
+                $(.register(::std::boxed::Box::new(
+                    <$tool as $crate::FromContext<_>>::from_context(&$context),
+                )))*
+        )
+    };
+    ($($tool:expr),* $(,)?) => {
+        $crate::__private::unique_tools(
+            $crate::ToolSet::builder()
+                $(.register(::std::boxed::Box::new($tool)))*
+        )
+    };
 }
 
 /// Creates chat messages for OpenAI API requests.
@@ -189,47 +684,74 @@ macro_rules! tools {
 ///
 /// ```ignore
 /// msg!(user "What's the weather?")
+/// msg!(user "What's in this picture?", images: [ImageInput::url(url)])
 /// msg!(assistant "It's sunny")
 /// msg!(assistant content, tool_calls)
 /// msg!(tool tool_call_id, "result")
 /// ```
 #[macro_export]
 macro_rules! msg {
+    (user $content:expr, images: [$($image:expr),* $(,)?]) => {
+        $crate::openai::types::ChatCompletionRequestMessage::User(
+            $crate::openai::types::ChatCompletionRequestUserMessage {
+                content: $crate::openai::types::ChatCompletionRequestUserMessageContent::Array(
+                    ::std::vec![
+                        $crate::openai::types::ChatCompletionRequestUserMessageContentPart::Text(
+                            $crate::openai::types::ChatCompletionRequestMessageContentPartText {
+                                text: ::std::string::ToString::to_string(&$content),
+                            },
+                        ),
+                        $(::std::convert::Into::<
+                            $crate::openai::types::ChatCompletionRequestUserMessageContentPart,
+                        >::into($image),)*
+                    ],
+                ),
+                name: ::std::option::Option::None,
+            },
+        )
+    };
     (user $content:expr) => {
-        async_openai::types::ChatCompletionRequestMessage::User(
-            async_openai::types::ChatCompletionRequestUserMessage {
-                content: async_openai::types::ChatCompletionRequestUserMessageContent::Text(
-                    $content.to_string(),
+        $crate::openai::types::ChatCompletionRequestMessage::User(
+            $crate::openai::types::ChatCompletionRequestUserMessage {
+                content: $crate::openai::types::ChatCompletionRequestUserMessageContent::Text(
+                    ::std::string::ToString::to_string(&$content),
                 ),
-                role: async_openai::types::Role::User,
-                name: None,
+                name: ::std::option::Option::None,
             },
         )
     };
     (assistant $content:expr) => {
-        async_openai::types::ChatCompletionRequestMessage::Assistant(
-            async_openai::types::ChatCompletionRequestAssistantMessage {
-                content: Some($content.to_string()),
-                tool_calls: None,
-                ..Default::default()
+        $crate::openai::types::ChatCompletionRequestMessage::Assistant(
+            $crate::openai::types::ChatCompletionRequestAssistantMessage {
+                content: ::std::option::Option::Some(
+                    $crate::openai::types::ChatCompletionRequestAssistantMessageContent::Text(
+                        ::std::string::ToString::to_string(&$content),
+                    ),
+                ),
+                tool_calls: ::std::option::Option::None,
+                ..::std::default::Default::default()
             },
         )
     };
     (assistant $content:expr, $tool_calls:expr) => {
-        async_openai::types::ChatCompletionRequestMessage::Assistant(
-            async_openai::types::ChatCompletionRequestAssistantMessage {
-                content: $content,
+        $crate::openai::types::ChatCompletionRequestMessage::Assistant(
+            $crate::openai::types::ChatCompletionRequestAssistantMessage {
+                content: ::std::option::Option::map(
+                    $content,
+                    $crate::openai::types::ChatCompletionRequestAssistantMessageContent::Text,
+                ),
                 tool_calls: $tool_calls,
-                ..Default::default()
+                ..::std::default::Default::default()
             },
         )
     };
     (tool $tool_call_id:expr, $content:expr) => {
-        async_openai::types::ChatCompletionRequestMessage::Tool(
-            async_openai::types::ChatCompletionRequestToolMessage {
-                role: async_openai::types::Role::Tool,
-                tool_call_id: $tool_call_id.to_string(),
-                content: $content.to_string(),
+        $crate::openai::types::ChatCompletionRequestMessage::Tool(
+            $crate::openai::types::ChatCompletionRequestToolMessage {
+                tool_call_id: ::std::string::ToString::to_string(&$tool_call_id),
+                content: $crate::openai::types::ChatCompletionRequestToolMessageContent::Text(
+                    ::std::string::ToString::to_string(&$content),
+                ),
             },
         )
     };
@@ -252,12 +774,180 @@ pub async fn dispatch_tool_calls(
     Ok(results)
 }
 
+/// The result of one tool call, as returned by [`dispatch_tool_calls_all`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolCallOutcome {
+    /// The id of the tool call, to answer it with a tool message.
+    pub tool_call_id: String,
+    /// The tool that was called.
+    pub tool_name: String,
+    /// The tool's output, or why the call failed.
+    pub result: std::result::Result<String, String>,
+}
+
+impl ToolCallOutcome {
+    /// Returns the text to send back to the model: the output, or the
+    /// failure prefixed with `Error: `.
+    pub fn content(&self) -> String {
+        match self.result {
+            Ok(ref output) => output.clone(),
+            Err(ref error) => format!("Error: {}", error),
+        }
+    }
+}
+
+/// Dispatches every tool call, also after one fails, and returns one
+/// outcome per call in call order.
+///
+/// The API expects a tool message for every tool call in an assistant
+/// message, so unlike [`dispatch_tool_calls`] this never stops early:
+///
+/// ```ignore
+/// for outcome in dispatch_tool_calls_all(tool_calls, &tools).await {
+///     messages.push(msg!(tool outcome.tool_call_id, outcome.content()));
+/// }
+/// ```
+pub async fn dispatch_tool_calls_all(
+    tool_calls: &[async_openai::types::ChatCompletionMessageToolCall],
+    toolset: &ToolSet,
+) -> Vec<ToolCallOutcome> {
+    let mut outcomes = Vec::with_capacity(tool_calls.len());
+    for tool_call in tool_calls {
+        let tool_name = tool_call.function.name.clone();
+        let result = match serde_json::from_str(&tool_call.function.arguments) {
+            Ok(args) => toolset
+                .dispatch(tool_name.clone(), args)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(format!("invalid arguments: {}", e)),
+        };
+        outcomes.push(ToolCallOutcome {
+            tool_call_id: tool_call.id.clone(),
+            tool_name,
+            result,
+        });
+    }
+    outcomes
+}
+
 /// Generates JSON schema for tool arguments.
 ///
 /// Derive this on structs to use them as tool parameters.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be used as a tool argument",
+    label = "no schema for `{Self}`",
+    note = "derive `ToolArg` for it, or use `String`, a number, `bool`, `Vec<T>` or `Option<T>`"
+)]
 pub trait ToolArg {
     /// Returns the JSON schema for this type.
     fn schema() -> serde_json::Value;
+
+    /// Returns the JSON schema for this type as part of a larger schema,
+    /// adding the definitions its `$ref`s point to to `defs` instead of its
+    /// own `$defs`.
+    ///
+    /// Recursive types refer to themselves by `$ref`, so the schemas of types
+    /// containing them collect every definition at the top level. The default
+    /// moves the `$defs` of [`ToolArg::schema`] into `defs`.
+    fn schema_with_defs(
+        defs: &mut serde_json::Map<String, serde_json::Value>,
+    ) -> serde_json::Value {
+        schema::hoist_defs(Self::schema(), defs)
+    }
+}
+
+/// Implements `ToolArg` for types with a fixed schema, matching the schemas
+/// the derive generates for fields of these types, so they can fill the type
+/// parameters of generic `ToolArg` types.
+macro_rules! fixed_schema {
+    ($($ty:ty => $schema:tt),* $(,)?) => {
+        $(
+            impl ToolArg for $ty {
+                fn schema() -> serde_json::Value {
+                    serde_json::json!($schema)
+                }
+            }
+        )*
+    };
+}
+
+fixed_schema! {
+    String => { "type": "string" },
+    bool => { "type": "boolean" },
+    i8 => { "type": "integer" },
+    i16 => { "type": "integer" },
+    i32 => { "type": "integer" },
+    i64 => { "type": "integer" },
+    isize => { "type": "integer" },
+    u8 => { "type": "integer" },
+    u16 => { "type": "integer" },
+    u32 => { "type": "integer" },
+    u64 => { "type": "integer" },
+    usize => { "type": "integer" },
+    f32 => { "type": "number" },
+    f64 => { "type": "number" },
+}
+
+impl<T: ToolArg> ToolArg for Vec<T> {
+    fn schema() -> serde_json::Value {
+        schema::with_defs(Self::schema_with_defs)
+    }
+
+    fn schema_with_defs(
+        defs: &mut serde_json::Map<String, serde_json::Value>,
+    ) -> serde_json::Value {
+        serde_json::json!({ "type": "array", "items": T::schema_with_defs(defs) })
+    }
+}
+
+impl<T: ToolArg> ToolArg for Option<T> {
+    fn schema() -> serde_json::Value {
+        schema::with_defs(Self::schema_with_defs)
+    }
+
+    fn schema_with_defs(
+        defs: &mut serde_json::Map<String, serde_json::Value>,
+    ) -> serde_json::Value {
+        schema::nullable(T::schema_with_defs(defs))
+    }
+}
+
+/// Implements `ToolArg` for pointers, which serde reads as what they point to.
+macro_rules! transparent_schema {
+    ($($($pointer:ident)::+),*) => {
+        $(
+            impl<T: ToolArg> ToolArg for $($pointer)::+<T> {
+                fn schema() -> serde_json::Value {
+                    T::schema()
+                }
+
+                fn schema_with_defs(
+                    defs: &mut serde_json::Map<String, serde_json::Value>,
+                ) -> serde_json::Value {
+                    T::schema_with_defs(defs)
+                }
+            }
+        )*
+    };
+}
+
+transparent_schema!(Box, std::rc::Rc, std::sync::Arc);
+
+/// Whether a tool can change state outside the agent.
+///
+/// Declared with `#[tool("...", effects = "read_only")]`. Tools are
+/// [`Mutating`](ToolEffects::Mutating) unless declared otherwise, so an
+/// unclassified tool is never mistaken for a safe one.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolEffects {
+    /// The tool only reads data.
+    ReadOnly,
+    /// The tool may create, modify or delete data.
+    #[default]
+    Mutating,
 }
 
 /// Trait implemented by the `#[tool]` macro.
@@ -268,6 +958,8 @@ pub trait Tool {
     const NAME: &'static str;
     /// The tool's description.
     const DESCRIPTION: &'static str;
+    /// The tool's side-effect classification.
+    const EFFECTS: ToolEffects = ToolEffects::Mutating;
     /// Returns the JSON schema for the tool's parameters.
     fn parameters() -> serde_json::Value;
     /// Returns the tool's name.
@@ -279,65 +971,101 @@ pub trait Tool {
         Self::DESCRIPTION
     }
     /// Executes the tool with the provided arguments.
-    #[allow(async_fn_in_trait)]
-    async fn call(
+    fn call(
+        &self,
+        args: serde_json::Value,
+    ) -> impl std::future::Future<
+        Output = std::result::Result<String, Box<dyn std::error::Error + Send + Sync>>,
+    > + Send;
+}
+
+/// Creates a tool from a context shared by the tools of a tool set.
+///
+/// `#[tool]` implements it for every tool it generates: tools whose function
+/// takes a context parameter hold the context, and others ignore it. Used by
+/// `tools![with context: ...]` and [`ToolSetBuilder::register_from_context`].
+pub trait FromContext<C: ?Sized>: Sized {
+    /// Creates the tool.
+    fn from_context(context: &std::sync::Arc<C>) -> Self;
+}
+
+/// A dyn-compatible view of a tool, for tool sets assembled at runtime.
+///
+/// Implemented for every [`Tool`]; implement it directly for tools whose
+/// name or schema is only known at runtime, e.g. loaded from configuration.
+/// Register tools with [`ToolSet::builder`].
+pub trait ErasedTool: Send + Sync {
+    /// The tool's name.
+    fn name(&self) -> &str;
+    /// The tool's description.
+    fn description(&self) -> &str;
+    /// Returns the JSON schema for the tool's parameters.
+    fn parameters(&self) -> serde_json::Value;
+    /// The tool's side-effect classification.
+    fn effects(&self) -> ToolEffects {
+        ToolEffects::Mutating
+    }
+    /// Executes the tool with the provided arguments.
+    fn call(
+        &self,
+        args: serde_json::Value,
+    ) -> futures::future::BoxFuture<
+        '_,
+        std::result::Result<String, Box<dyn std::error::Error + Send + Sync>>,
+    >;
+}
+
+impl<T: Tool + Send + Sync> ErasedTool for T {
+    fn name(&self) -> &str {
+        T::name()
+    }
+
+    fn description(&self) -> &str {
+        T::description()
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        T::parameters()
+    }
+
+    fn effects(&self) -> ToolEffects {
+        T::EFFECTS
+    }
+
+    fn call(
         &self,
         args: serde_json::Value,
-    ) -> std::result::Result<String, Box<dyn std::error::Error + Send + Sync>>;
+    ) -> futures::future::BoxFuture<
+        '_,
+        std::result::Result<String, Box<dyn std::error::Error + Send + Sync>>,
+    > {
+        Box::pin(Tool::call(self, args))
+    }
 }
 
 /// Generates JSON schema for structured output.
 ///
 /// Derive this on structs to use them with OpenAI's structured output feature.
+/// The derived schema follows strict mode: every property is required, and
+/// `Option` fields accept `null` instead of being left out.
 pub trait StructuredOutput {
     /// Returns the JSON schema for this type.
     fn schema() -> serde_json::Value;
-}
-
-/// Extension traits for the OpenAI client.
-pub mod ext {
-    use super::*;
-    use async_openai::types::ChatCompletionRequestMessage;
-
-    /// Extension methods for OpenAI client (not yet implemented).
-    #[allow(async_fn_in_trait)]
-    pub trait OpenAIClientExt {
-        /// Makes a chat completion request with tools.
-        async fn call_with_tools<T: Tool>(
-            &self,
-            messages: Vec<ChatCompletionRequestMessage>,
-            tools: Vec<T>,
-        ) -> std::result::Result<String, async_openai::error::OpenAIError>;
 
-        /// Makes a chat completion request with structured output.
-        async fn structured_output<S: StructuredOutput>(
-            &self,
-            messages: Vec<ChatCompletionRequestMessage>,
-        ) -> std::result::Result<S, async_openai::error::OpenAIError>;
+    /// Returns the name the schema is sent under: the type's name, without
+    /// its module path or generic parameters.
+    fn schema_name() -> String {
+        format::type_name::<Self>()
     }
 
-    impl<C: async_openai::config::Config> OpenAIClientExt for async_openai::Client<C> {
-        async fn call_with_tools<T: Tool>(
-            &self,
-            _messages: Vec<ChatCompletionRequestMessage>,
-            _tools: Vec<T>,
-        ) -> std::result::Result<String, async_openai::error::OpenAIError> {
-            // Implementation would create the request with tools
-            // For now, placeholder
-            Err(async_openai::error::OpenAIError::InvalidArgument(
-                "Not implemented".to_string(),
-            ))
-        }
-
-        async fn structured_output<S: StructuredOutput>(
-            &self,
-            _messages: Vec<ChatCompletionRequestMessage>,
-        ) -> std::result::Result<S, async_openai::error::OpenAIError> {
-            // Implementation would use structured output
-            // For now, placeholder
-            Err(async_openai::error::OpenAIError::InvalidArgument(
-                "Not implemented".to_string(),
-            ))
+    /// Returns the strict `json_schema` response format for this type, ready
+    /// to set on a chat completion request.
+    fn response_format() -> openai::types::ResponseFormat
+    where
+        Self: Sized,
+    {
+        openai::types::ResponseFormat::JsonSchema {
+            json_schema: agent::output_schema::<Self>(),
         }
     }
 }
@@ -371,27 +1099,380 @@ mod tests {
             .contains(&json!("count")));
     }
 
+    #[derive(ToolArg, serde::Deserialize)]
+    struct PollArgs {
+        #[serde(with = "crate::duration")]
+        interval: std::time::Duration,
+        #[serde(default, with = "crate::duration::option")]
+        timeout: Option<std::time::Duration>,
+        #[desc("How long the job may run")]
+        #[serde(with = "crate::duration")]
+        budget: core::time::Duration,
+        elapsed: clock::Duration,
+    }
+
+    mod clock {
+        /// A duration in ticks, not a `std::time::Duration`.
+        #[derive(crate::ToolArg, serde::Deserialize)]
+        pub struct Duration {
+            pub ticks: u64,
+        }
+    }
+
+    #[test]
+    fn test_duration_schema() {
+        let schema = PollArgs::schema();
+        assert_eq!(schema["properties"]["interval"]["type"], "string");
+        assert_eq!(
+            schema["properties"]["interval"]["description"],
+            crate::duration::SCHEMA_HINT
+        );
+        assert_eq!(
+            schema["properties"]["budget"]["description"],
+            format!(
+                "How long the job may run ({})",
+                crate::duration::SCHEMA_HINT
+            )
+        );
+        assert_eq!(schema["properties"]["elapsed"]["type"], "object");
+        assert_eq!(
+            schema["properties"]["timeout"]["type"],
+            json!(["string", "null"])
+        );
+        assert_eq!(schema["required"], json!(["interval", "budget", "elapsed"]));
+
+        let args: PollArgs = serde_json::from_value(json!({
+            "interval": "1h30m",
+            "timeout": 45,
+            "budget": "2h",
+            "elapsed": { "ticks": 3 }
+        }))
+        .unwrap();
+        assert_eq!(args.interval, std::time::Duration::from_secs(5400));
+        assert_eq!(args.timeout, Some(std::time::Duration::from_secs(45)));
+    }
+
     #[tool("A test tool")]
     async fn test_tool(args: TestArgs) -> Result<String> {
         // Dummy implementation
         Ok(format!("Called with {} items", args.count))
     }
 
+    #[tokio::test]
+    async fn test_dispatch_tool_calls_all_runs_every_call() {
+        let call = |id: &str, name: &str, arguments: &str| {
+            serde_json::from_value::<async_openai::types::ChatCompletionMessageToolCall>(json!({
+                "id": id,
+                "type": "function",
+                "function": { "name": name, "arguments": arguments },
+            }))
+            .unwrap()
+        };
+        let calls = [
+            call("call_1", "test_tool", r#"{"name": "a", "count": 1}"#),
+            call("call_2", "missing_tool", "{}"),
+            call("call_3", "test_tool", "{oops"),
+            call("call_4", "test_tool", r#"{"name": "b", "count": 2}"#),
+        ];
+        let outcomes = dispatch_tool_calls_all(&calls, &tools![TestToolTool]).await;
+
+        let ids: Vec<_> = outcomes.iter().map(|o| o.tool_call_id.as_str()).collect();
+        assert_eq!(ids, ["call_1", "call_2", "call_3", "call_4"]);
+        assert_eq!(outcomes[0].result, Ok("Called with 1 items".into()));
+        assert_eq!(outcomes[1].tool_name, "missing_tool");
+        assert_eq!(outcomes[1].content(), "Error: Tool not found: missing_tool");
+        assert!(outcomes[2]
+            .result
+            .as_ref()
+            .unwrap_err()
+            .starts_with("invalid arguments"));
+        assert_eq!(outcomes[3].result, Ok("Called with 2 items".into()));
+    }
+
     #[test]
     fn test_tool_impl() {
-        assert_eq!(TestToolTool::name(), "test_tool");
-        assert_eq!(TestToolTool::description(), "A test tool");
-        let params = TestToolTool::parameters();
+        assert_eq!(<TestToolTool as Tool>::name(), "test_tool");
+        assert_eq!(<TestToolTool as Tool>::description(), "A test tool");
+        let params = <TestToolTool as Tool>::parameters();
         assert_eq!(params["type"], "object");
     }
 
-    #[derive(StructuredOutput, ToolArg)]
-    struct TestOutput {
-        result: String,
+    #[tool("Look up a record", effects = "read_only")]
+    async fn lookup_record(args: TestArgs) -> Result<String> {
+        Ok(args.name)
+    }
+
+    #[tool("Delete a record", effects = "mutating")]
+    async fn delete_record(args: TestArgs) -> Result<String> {
+        Ok(args.name)
     }
 
     #[test]
-    fn test_structured_output_schema() {
+    fn test_tool_effects() {
+        assert_eq!(TestToolTool::EFFECTS, ToolEffects::Mutating);
+        assert_eq!(LookupRecordTool::EFFECTS, ToolEffects::ReadOnly);
+        assert_eq!(DeleteRecordTool::EFFECTS, ToolEffects::Mutating);
+
+        let toolset = tools![TestToolTool, LookupRecordTool, DeleteRecordTool];
+        assert_eq!(toolset.effects("lookup_record"), ToolEffects::ReadOnly);
+        assert_eq!(toolset.effects("unknown"), ToolEffects::Mutating);
+        assert_eq!(toolset.mutating_tools(), vec!["test_tool", "delete_record"]);
+    }
+
+    #[tool(
+        name = "fs-read",
+        description = "Read a record",
+        struct_name = "RecordReader"
+    )]
+    async fn read_record(args: TestArgs) -> Result<String> {
+        Ok(args.name)
+    }
+
+    #[tokio::test]
+    async fn test_tool_naming_options() {
+        assert_eq!(RecordReader::NAME, "fs-read");
+        assert_eq!(RecordReader::DESCRIPTION, "Read a record");
+        let toolset = tools![RecordReader];
+        toolset.validate().unwrap();
+        let args = json!({ "name": "ada", "count": 1 });
+        assert_eq!(
+            toolset.dispatch("fs-read".into(), args).await.unwrap(),
+            "ada"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cloned_tool_sets_share_the_dispatcher() {
+        let toolset = tools![LookupRecordTool];
+        let copy = toolset.clone();
+
+        let args = serde_json::json!({ "name": "Ana", "count": 1 });
+        assert_eq!(
+            toolset
+                .dispatch("lookup_record".into(), args.clone())
+                .await
+                .unwrap(),
+            "Ana"
+        );
+        assert_eq!(
+            copy.dispatch("lookup_record".into(), args).await.unwrap(),
+            "Ana"
+        );
+        assert_eq!(copy.manifest(), toolset.manifest());
+    }
+
+    #[tokio::test]
+    async fn test_merged_tool_sets_route_by_name() {
+        let merged = tools![TestToolTool, LookupRecordTool]
+            .merge(tools![DeleteRecordTool])
+            .unwrap();
+        merged.validate().unwrap();
+        assert_eq!(
+            merged.tool_names(),
+            ["test_tool", "lookup_record", "delete_record"]
+        );
+        assert_eq!(merged.effects("delete_record"), ToolEffects::Mutating);
+
+        let args = serde_json::json!({ "name": "Ana", "count": 1 });
+        for name in ["lookup_record", "delete_record"] {
+            assert_eq!(
+                merged.dispatch(name.into(), args.clone()).await.unwrap(),
+                "Ana"
+            );
+        }
+        let error = merged.dispatch("unknown".into(), args).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::ToolNotFound(name)) if name == "unknown"
+        ));
+
+        let mut duplicated = tools![LookupRecordTool];
+        duplicated.extend(tools![LookupRecordTool, DeleteRecordTool]);
+        let error = duplicated.validate().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid configuration: Duplicate tool names: lookup_record"
+        );
+
+        let error = tools![TestToolTool, LookupRecordTool]
+            .merge(tools![LookupRecordTool, TestToolTool])
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Invalid configuration: Duplicate tool names: lookup_record, test_tool"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "tools!: Invalid configuration: Duplicate tool names: lookup_record")]
+    fn test_tools_macro_rejects_duplicate_names() {
+        tools![LookupRecordTool, TestToolTool, LookupRecordTool];
+    }
+
+    mod travel {
+        pub mod flights {
+            use crate::prelude::*;
+
+            #[derive(ToolArg, serde::Deserialize)]
+            pub struct FlightArgs {
+                to: String,
+            }
+
+            #[tool("Find flights")]
+            pub async fn find_flights(args: FlightArgs) -> Result<String> {
+                Ok(format!("2 flights to {}", args.to))
+            }
+        }
+
+        use crate::prelude::*;
+
+        pub struct Fares {
+            pub currency: &'static str,
+        }
+
+        #[tool("Quote a fare")]
+        pub async fn quote_fare(#[context] fares: &Fares) -> Result<String> {
+            Ok(format!("129 {}", fares.currency))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tools_macro_accepts_paths() {
+        let toolset = tools![
+            travel::flights::FindFlightsTool,
+            self::travel::QuoteFareTool::new(travel::Fares { currency: "EUR" }),
+        ];
+        let flights = toolset
+            .dispatch("find_flights".into(), json!({ "to": "Oslo" }))
+            .await
+            .unwrap();
+        assert_eq!(flights, "2 flights to Oslo");
+
+        let fares = std::sync::Arc::new(travel::Fares { currency: "NOK" });
+        let toolset = tools![with fares: travel::QuoteFareTool, travel::flights::FindFlightsTool];
+        let quote = toolset
+            .dispatch("quote_fare".into(), json!({}))
+            .await
+            .unwrap();
+        assert_eq!(quote, "129 NOK");
+        assert_eq!(toolset.tools().len(), 2);
+
+        let empty: ToolSet = tools![];
+        assert!(empty.tools().is_empty());
+    }
+
+    struct Counter {
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    impl Tool for Counter {
+        const NAME: &'static str = "count";
+        const DESCRIPTION: &'static str = "Counts its calls";
+
+        fn parameters() -> serde_json::Value {
+            serde_json::json!({ "type": "object" })
+        }
+
+        async fn call(
+            &self,
+            _args: serde_json::Value,
+        ) -> std::result::Result<String, Box<dyn std::error::Error + Send + Sync>> {
+            let calls = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok((calls + 1).to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tools_keep_instance_state() {
+        let counter = Counter {
+            calls: Default::default(),
+        };
+        let toolset = tools![LookupRecordTool, counter];
+        let copy = toolset.clone();
+        for (toolset, expected) in [(&toolset, "1"), (&copy, "2"), (&toolset, "3")] {
+            let result = toolset.dispatch("count".into(), json!({})).await.unwrap();
+            assert_eq!(result, expected);
+        }
+    }
+
+    struct Echo {
+        name: String,
+    }
+
+    impl ErasedTool for Echo {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn description(&self) -> &str {
+            "Echoes its arguments"
+        }
+
+        fn parameters(&self) -> serde_json::Value {
+            serde_json::json!({ "type": "object" })
+        }
+
+        fn effects(&self) -> ToolEffects {
+            ToolEffects::ReadOnly
+        }
+
+        fn call(
+            &self,
+            args: serde_json::Value,
+        ) -> futures::future::BoxFuture<
+            '_,
+            std::result::Result<String, Box<dyn std::error::Error + Send + Sync>>,
+        > {
+            Box::pin(async move { Ok(format!("{}: {}", self.name, args)) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_set_builder_registers_runtime_tools() {
+        let mut builder = ToolSet::builder().register(Box::new(LookupRecordTool));
+        for name in ["echo", "shout"] {
+            builder = builder.register(Box::new(Echo { name: name.into() }));
+        }
+        let toolset = builder.build().unwrap();
+        toolset.validate().unwrap();
+        assert_eq!(toolset.tool_names(), ["lookup_record", "echo", "shout"]);
+        assert_eq!(toolset.effects("echo"), ToolEffects::ReadOnly);
+        assert_eq!(toolset.effects("lookup_record"), ToolEffects::ReadOnly);
+
+        let args = serde_json::json!({ "name": "Ana", "count": 1 });
+        assert_eq!(
+            toolset.dispatch("shout".into(), json!(1)).await.unwrap(),
+            "shout: 1"
+        );
+        assert_eq!(
+            toolset
+                .dispatch("lookup_record".into(), args)
+                .await
+                .unwrap(),
+            "Ana"
+        );
+        assert!(toolset.dispatch("whisper".into(), json!(1)).await.is_err());
+    }
+
+    #[test]
+    fn test_manifest_exports_effects() {
+        let manifest = tools![TestToolTool, LookupRecordTool].manifest();
+        assert_eq!(manifest[0]["name"], "test_tool");
+        assert_eq!(manifest[0]["effects"], "mutating");
+        assert_eq!(manifest[1]["name"], "lookup_record");
+        assert_eq!(manifest[1]["description"], "Look up a record");
+        assert_eq!(manifest[1]["parameters"]["type"], "object");
+        assert_eq!(manifest[1]["effects"], "read_only");
+    }
+
+    #[derive(StructuredOutput, ToolArg)]
+    struct TestOutput {
+        result: String,
+    }
+
+    #[test]
+    fn test_structured_output_schema() {
         let schema = <TestOutput as ToolArg>::schema();
         assert_eq!(schema["type"], "object");
         assert_eq!(schema["properties"]["result"]["type"], "string");
@@ -415,13 +1496,13 @@ mod tests {
         C,
     }
 
-    #[derive(ToolArg)]
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
     enum WrappedEnum {
         Text(String),
         Number(i32),
     }
 
-    #[derive(ToolArg)]
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
     enum ComplexEnum {
         Unit,
         Single(String),
@@ -448,59 +1529,127 @@ mod tests {
 
     #[test]
     fn test_enum_schema() {
-        let schema = MyEnum::schema();
-        let one_of = schema["oneOf"].as_array().unwrap();
-        assert_eq!(one_of.len(), 3);
-        for item in one_of {
-            assert_eq!(item["type"], "object");
-            assert!(item["required"]
-                .as_array()
-                .unwrap()
-                .contains(&json!("type")));
-            let props = &item["properties"];
-            assert!(props["type"]["const"].is_string());
+        assert_eq!(
+            MyEnum::schema(),
+            json!({ "type": "string", "enum": ["A", "B", "C"] })
+        );
+    }
+
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "kebab-case")]
+    #[desc("How to sort results")]
+    enum SortOrder {
+        #[desc("Newest first")]
+        MostRecent,
+        Oldest,
+        #[serde(skip)]
+        Unsorted,
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct SortArgs {
+        order: SortOrder,
+    }
+
+    #[test]
+    fn test_unit_enum_deserializes_from_its_schema() {
+        let schema = SortOrder::schema();
+        assert_eq!(
+            schema,
+            json!({
+                "type": "string",
+                "enum": ["most-recent", "oldest"],
+                "description": "How to sort results\n\n- most-recent: Newest first"
+            })
+        );
+        for value in schema["enum"].as_array().unwrap() {
+            let args: SortArgs = serde_json::from_value(json!({ "order": value })).unwrap();
+            assert_ne!(args.order, SortOrder::Unsorted);
         }
-        // Check specific variants
-        let types: std::collections::HashSet<_> = one_of
-            .iter()
-            .map(|item| item["properties"]["type"]["const"].as_str().unwrap())
-            .collect();
-        assert!(types.contains("A"));
-        assert!(types.contains("B"));
-        assert!(types.contains("C"));
+        assert_eq!(SortArgs::schema()["properties"]["order"], schema);
+    }
+
+    /// Builds one value conforming to `schema` per alternative of each
+    /// `oneOf` or `anyOf` in it.
+    fn examples(schema: &serde_json::Value) -> Vec<serde_json::Value> {
+        if let Some(value) = schema.get("const") {
+            return vec![value.clone()];
+        }
+        if let Some(values) = schema["enum"].as_array() {
+            return values.clone();
+        }
+        if let Some(variants) = schema["oneOf"].as_array().or(schema["anyOf"].as_array()) {
+            return variants.iter().flat_map(examples).collect();
+        }
+        match schema["type"].as_str().unwrap_or("object") {
+            "null" => vec![serde_json::Value::Null],
+            "string" => vec![json!("text")],
+            "integer" => vec![json!(7)],
+            "number" => vec![json!(1.5)],
+            "boolean" => vec![json!(true)],
+            "array" => match schema.get("prefixItems").unwrap_or(&schema["items"]) {
+                serde_json::Value::Array(items) => {
+                    vec![items.iter().map(|item| examples(item)[0].clone()).collect()]
+                }
+                item => {
+                    let len = schema["minItems"].as_u64().unwrap_or(1).max(1) as usize;
+                    vec![json!(vec![examples(item)[0].clone(); len])]
+                }
+            },
+            _ => {
+                let mut values = vec![serde_json::Map::new()];
+                for (name, property) in schema["properties"].as_object().into_iter().flatten() {
+                    let options = examples(property);
+                    values = values
+                        .into_iter()
+                        .flat_map(|value| {
+                            options.iter().map(move |option| {
+                                let mut value = value.clone();
+                                value.insert(name.clone(), option.clone());
+                                value
+                            })
+                        })
+                        .collect();
+                }
+                values.into_iter().map(serde_json::Value::Object).collect()
+            }
+        }
+    }
+
+    /// Asserts that every example of `T`'s schema deserializes, and returns
+    /// the deserialized values.
+    fn round_trip<T: ToolArg + serde::de::DeserializeOwned>() -> Vec<T> {
+        examples(&T::schema())
+            .into_iter()
+            .map(|value| {
+                serde_json::from_value(value.clone())
+                    .unwrap_or_else(|e| panic!("{} does not deserialize: {}", value, e))
+            })
+            .collect()
     }
 
     #[test]
     fn test_wrapped_enum_schema() {
         let schema = WrappedEnum::schema();
-        let one_of = schema["oneOf"].as_array().unwrap();
-        assert_eq!(one_of.len(), 2);
-        // Check Text variant
-        let text_item = &one_of[0];
-        assert_eq!(text_item["type"], "object");
-        assert_eq!(text_item["properties"]["type"]["const"], "Text");
-        assert_eq!(text_item["properties"]["value"]["type"], "string");
-        assert!(text_item["required"]
-            .as_array()
-            .unwrap()
-            .contains(&json!("type")));
-        assert!(text_item["required"]
-            .as_array()
-            .unwrap()
-            .contains(&json!("value")));
-        // Check Number variant
-        let number_item = &one_of[1];
-        assert_eq!(number_item["type"], "object");
-        assert_eq!(number_item["properties"]["type"]["const"], "Number");
-        assert_eq!(number_item["properties"]["value"]["type"], "integer");
-        assert!(number_item["required"]
-            .as_array()
-            .unwrap()
-            .contains(&json!("type")));
-        assert!(number_item["required"]
-            .as_array()
-            .unwrap()
-            .contains(&json!("value")));
+        assert_eq!(
+            schema["oneOf"],
+            json!([
+                {
+                    "type": "object",
+                    "properties": { "Text": { "type": "string" } },
+                    "required": ["Text"]
+                },
+                {
+                    "type": "object",
+                    "properties": { "Number": { "type": "integer" } },
+                    "required": ["Number"]
+                }
+            ])
+        );
+        assert_eq!(
+            round_trip::<WrappedEnum>(),
+            [WrappedEnum::Text("text".into()), WrappedEnum::Number(7)]
+        );
     }
 
     #[test]
@@ -508,44 +1657,1200 @@ mod tests {
         let schema = ComplexEnum::schema();
         let one_of = schema["oneOf"].as_array().unwrap();
         assert_eq!(one_of.len(), 4);
+        assert_eq!(one_of[0], json!({ "type": "string", "const": "Unit" }));
+        assert_eq!(one_of[1]["properties"]["Single"]["type"], "string");
+        let multiple = &one_of[2]["properties"]["Multiple"];
+        assert_eq!(multiple["type"], "array");
+        assert_eq!(multiple["items"].as_array().unwrap().len(), 2);
+        let named = &one_of[3]["properties"]["Named"];
+        assert_eq!(named["properties"]["text"]["type"], "string");
+        assert_eq!(named["required"], json!(["text", "num"]));
+
+        assert_eq!(
+            round_trip::<ComplexEnum>(),
+            [
+                ComplexEnum::Unit,
+                ComplexEnum::Single("text".into()),
+                ComplexEnum::Multiple("text".into(), 7),
+                ComplexEnum::Named {
+                    text: "text".into(),
+                    num: 7
+                },
+            ]
+        );
+    }
+
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
+    struct Coordinates {
+        lat: f64,
+        lon: f64,
+    }
+
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
+    #[serde(tag = "kind")]
+    enum InternalPlace {
+        Unknown,
+        City { name: String },
+        Point(Coordinates),
+    }
+
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
+    #[serde(tag = "kind", content = "data")]
+    enum AdjacentPlace {
+        Unknown,
+        City(String),
+        Point { lat: f64, lon: f64 },
+        Pair(String, String),
+    }
+
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
+    #[serde(untagged)]
+    enum UntaggedPlace {
+        Point(Coordinates),
+        City { name: String },
+        Id(u32),
+        Unknown,
+    }
+
+    #[test]
+    fn test_internally_tagged_enum_schema() {
+        let schema = InternalPlace::schema();
+        assert_eq!(
+            schema["oneOf"][1],
+            json!({
+                "type": "object",
+                "properties": { "kind": { "const": "City" }, "name": { "type": "string" } },
+                "required": ["kind", "name"]
+            })
+        );
+        assert_eq!(schema["oneOf"][2]["properties"]["kind"]["const"], "Point");
+        assert_eq!(
+            schema["oneOf"][2]["required"],
+            json!(["kind", "lat", "lon"])
+        );
+        assert_eq!(
+            round_trip::<InternalPlace>(),
+            [
+                InternalPlace::Unknown,
+                InternalPlace::City {
+                    name: "text".into()
+                },
+                InternalPlace::Point(Coordinates { lat: 1.5, lon: 1.5 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_adjacently_tagged_enum_schema() {
+        let schema = AdjacentPlace::schema();
+        assert_eq!(
+            schema["oneOf"][0],
+            json!({
+                "type": "object",
+                "properties": { "kind": { "const": "Unknown" } },
+                "required": ["kind"]
+            })
+        );
+        assert_eq!(
+            schema["oneOf"][1]["properties"]["data"],
+            json!({ "type": "string" })
+        );
+        assert_eq!(schema["oneOf"][1]["required"], json!(["kind", "data"]));
+        assert_eq!(
+            round_trip::<AdjacentPlace>(),
+            [
+                AdjacentPlace::Unknown,
+                AdjacentPlace::City("text".into()),
+                AdjacentPlace::Point { lat: 1.5, lon: 1.5 },
+                AdjacentPlace::Pair("text".into(), "text".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_untagged_enum_schema() {
+        let schema = UntaggedPlace::schema();
+        assert_eq!(schema["oneOf"][0], Coordinates::schema());
+        assert_eq!(
+            schema["oneOf"][2],
+            json!({ "type": "integer", "minimum": 0 })
+        );
+        assert_eq!(schema["oneOf"][3], json!({ "type": "null" }));
+        assert_eq!(
+            round_trip::<UntaggedPlace>(),
+            [
+                UntaggedPlace::Point(Coordinates { lat: 1.5, lon: 1.5 }),
+                UntaggedPlace::City {
+                    name: "text".into()
+                },
+                UntaggedPlace::Id(7),
+                UntaggedPlace::Unknown,
+            ]
+        );
+    }
 
-        // Unit variant
-        let unit = &one_of[0];
-        assert_eq!(unit["properties"]["type"]["const"], "Unit");
-        assert_eq!(unit["required"].as_array().unwrap().len(), 1);
+    /// The id of a user.
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
+    struct UserId(String);
 
-        // Single variant
-        let single = &one_of[1];
-        assert_eq!(single["properties"]["type"]["const"], "Single");
-        assert_eq!(single["properties"]["value"]["type"], "string");
-        assert!(single["required"]
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
+    struct Venue(Coordinates);
+
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
+    struct SeatRange(#[desc("The first seat")] u32, #[schema(maximum = 400)] u32);
+
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
+    struct Booking {
+        user: UserId,
+        venue: Venue,
+        seats: SeatRange,
+    }
+
+    #[test]
+    fn test_tuple_struct_schemas() {
+        assert_eq!(
+            UserId::schema(),
+            json!({ "type": "string", "description": "The id of a user." })
+        );
+        assert_eq!(Venue::schema(), Coordinates::schema());
+        assert_eq!(
+            SeatRange::schema(),
+            json!({
+                "type": "array",
+                "prefixItems": [
+                    { "type": "integer", "minimum": 0, "description": "The first seat" },
+                    { "type": "integer", "minimum": 0, "maximum": 400 }
+                ],
+                "minItems": 2,
+                "maxItems": 2
+            })
+        );
+
+        let schema = Booking::schema();
+        assert_eq!(schema["properties"]["user"], UserId::schema());
+        assert_eq!(schema["properties"]["venue"], Coordinates::schema());
+        assert_eq!(schema["properties"]["seats"], SeatRange::schema());
+        assert_eq!(
+            round_trip::<Booking>(),
+            [Booking {
+                user: UserId("text".into()),
+                venue: Venue(Coordinates { lat: 1.5, lon: 1.5 }),
+                seats: SeatRange(7, 7),
+            }]
+        );
+    }
+
+    macro_rules! casing_args {
+        ($name:ident, $case:literal) => {
+            #[derive(ToolArg, serde::Deserialize)]
+            #[serde(rename_all = $case)]
+            #[allow(dead_code)]
+            struct $name {
+                first_name: String,
+                r#type: String,
+            }
+        };
+    }
+
+    casing_args!(LowerArgs, "lowercase");
+    casing_args!(UpperArgs, "UPPERCASE");
+    casing_args!(PascalArgs, "PascalCase");
+    casing_args!(CamelArgs, "camelCase");
+    casing_args!(SnakeArgs, "snake_case");
+    casing_args!(ScreamingSnakeArgs, "SCREAMING_SNAKE_CASE");
+    casing_args!(KebabArgs, "kebab-case");
+    casing_args!(ScreamingKebabArgs, "SCREAMING-KEBAB-CASE");
+
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum CasedEnum {
+        #[serde(rename_all = "camelCase")]
+        SendEmail {
+            to_address: String,
+        },
+        ReadInbox,
+        #[serde(rename = "purge")]
+        DeleteAll,
+        #[serde(skip)]
+        #[allow(dead_code)]
+        Internal,
+    }
+
+    #[test]
+    fn test_rename_all_casings() {
+        fn properties<T: ToolArg>() -> Vec<String> {
+            let schema = T::schema();
+            let mut names: Vec<_> = schema["properties"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect();
+            names.sort();
+            assert_eq!(schema["required"].as_array().unwrap().len(), 2);
+            names
+        }
+        assert_eq!(properties::<LowerArgs>(), ["first_name", "type"]);
+        assert_eq!(properties::<UpperArgs>(), ["FIRST_NAME", "TYPE"]);
+        assert_eq!(properties::<PascalArgs>(), ["FirstName", "Type"]);
+        assert_eq!(properties::<CamelArgs>(), ["firstName", "type"]);
+        assert_eq!(properties::<SnakeArgs>(), ["first_name", "type"]);
+        assert_eq!(properties::<ScreamingSnakeArgs>(), ["FIRST_NAME", "TYPE"]);
+        assert_eq!(properties::<KebabArgs>(), ["first-name", "type"]);
+        assert_eq!(properties::<ScreamingKebabArgs>(), ["FIRST-NAME", "TYPE"]);
+
+        let args: CamelArgs =
+            serde_json::from_value(json!({ "firstName": "Ada", "type": "admin" })).unwrap();
+        assert_eq!(args.first_name, "Ada");
+    }
+
+    #[test]
+    fn test_rename_all_variant_casings() {
+        let schema = CasedEnum::schema();
+        let one_of = schema["oneOf"].as_array().unwrap();
+        let names: Vec<_> = one_of
+            .iter()
+            .map(|variant| variant["properties"]["type"]["const"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["send_email", "read_inbox", "purge"]);
+        assert_eq!(one_of[0]["properties"]["toAddress"]["type"], "string");
+        assert!(one_of[0]["required"]
             .as_array()
             .unwrap()
-            .contains(&json!("value")));
+            .contains(&json!("toAddress")));
 
-        // Multiple variant
-        let multiple = &one_of[2];
-        assert_eq!(multiple["properties"]["type"]["const"], "Multiple");
-        assert_eq!(multiple["properties"]["value"]["type"], "array");
+        let parsed: CasedEnum =
+            serde_json::from_value(json!({ "type": "send_email", "toAddress": "ada@example.com" }))
+                .unwrap();
         assert_eq!(
-            multiple["properties"]["value"]["items"]
-                .as_array()
-                .unwrap()
-                .len(),
-            2
+            parsed,
+            CasedEnum::SendEmail {
+                to_address: "ada@example.com".into()
+            }
+        );
+        let parsed: CasedEnum = serde_json::from_value(json!({ "type": "purge" })).unwrap();
+        assert_eq!(parsed, CasedEnum::DeleteAll);
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    #[allow(dead_code)]
+    struct SerdeFieldArgs {
+        #[serde(rename = "q", alias = "query")]
+        search: String,
+        #[serde(rename(serialize = "max", deserialize = "limit"))]
+        max_results: u32,
+        #[serde(default)]
+        page: u32,
+        #[serde(skip)]
+        cache: Vec<String>,
+        #[serde(skip_deserializing)]
+        session: String,
+    }
+
+    #[derive(ToolArg, serde::Deserialize, Default)]
+    #[serde(default)]
+    #[allow(dead_code)]
+    struct DefaultedArgs {
+        verbose: bool,
+    }
+
+    #[test]
+    fn test_serde_field_attributes() {
+        let schema = SerdeFieldArgs::schema();
+        let properties = schema["properties"].as_object().unwrap();
+        let mut names: Vec<_> = properties.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["limit", "page", "q"]);
+        assert_eq!(schema["required"], json!(["q", "limit"]));
+
+        let args: SerdeFieldArgs =
+            serde_json::from_value(json!({ "q": "rust", "limit": 5 })).unwrap();
+        assert_eq!(args.search, "rust");
+        assert_eq!(args.max_results, 5);
+
+        assert_eq!(DefaultedArgs::schema()["required"], json!([]));
+        serde_json::from_value::<DefaultedArgs>(json!({})).unwrap();
+    }
+
+    #[derive(ToolArg, StructuredOutput, serde::Deserialize, Debug, PartialEq)]
+    struct Address {
+        city: String,
+    }
+
+    #[derive(ToolArg, StructuredOutput, serde::Deserialize, Debug, PartialEq)]
+    struct Contact {
+        name: String,
+        #[desc("Preferred name")]
+        nickname: Option<String>,
+        tags: Option<Vec<String>>,
+        address: Option<Address>,
+        unit: Option<Unit>,
+    }
+
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
+    enum Unit {
+        Metric,
+        Imperial,
+    }
+
+    #[test]
+    fn test_option_fields_are_nullable() {
+        let schema = <Contact as ToolArg>::schema();
+        assert_eq!(schema["required"], json!(["name"]));
+        let properties = &schema["properties"];
+        assert_eq!(
+            properties["nickname"],
+            json!({ "type": ["string", "null"], "description": "Preferred name" })
+        );
+        assert_eq!(properties["tags"]["type"], json!(["array", "null"]));
+        assert_eq!(properties["tags"]["items"], json!({ "type": "string" }));
+        assert_eq!(properties["address"]["type"], json!(["object", "null"]));
+        assert_eq!(
+            properties["address"]["properties"]["city"],
+            json!({ "type": "string" })
+        );
+        assert_eq!(
+            properties["unit"],
+            json!({ "type": ["string", "null"], "enum": ["Metric", "Imperial", null] })
+        );
+
+        let contact: Contact = serde_json::from_value(json!({
+            "name": "Ada",
+            "nickname": null,
+            "tags": null,
+            "address": null,
+            "unit": null,
+        }))
+        .unwrap();
+        assert_eq!(contact.address, None);
+    }
+
+    #[test]
+    fn test_structured_output_schema_is_strict() {
+        let schema = <Contact as StructuredOutput>::schema();
+        assert_eq!(
+            schema["required"],
+            json!(["address", "name", "nickname", "tags", "unit"])
+        );
+        assert_eq!(schema["additionalProperties"], false);
+        let properties = &schema["properties"];
+        assert_eq!(properties["name"], json!({ "type": "string" }));
+        assert_eq!(properties["nickname"]["type"], json!(["string", "null"]));
+        assert_eq!(properties["tags"]["type"], json!(["array", "null"]));
+        assert_eq!(properties["address"]["type"], json!(["object", "null"]));
+        assert_eq!(properties["address"]["required"], json!(["city"]));
+        assert_eq!(properties["address"]["additionalProperties"], false);
+        assert_eq!(properties["unit"]["enum"].as_array().unwrap().len(), 3);
+    }
+
+    #[derive(ToolArg, StructuredOutput, serde::Deserialize, Debug, PartialEq)]
+    struct Tally {
+        name: String,
+        #[serde(default)]
+        count: u32,
+        #[serde(default)]
+        note: Option<String>,
+    }
+
+    #[test]
+    fn test_structured_output_keeps_defaulted_fields_non_nullable() {
+        let schema = <Tally as StructuredOutput>::schema();
+        assert_eq!(schema["required"], json!(["count", "name", "note"]));
+        assert_eq!(schema["properties"]["count"]["type"], "integer");
+        assert_eq!(
+            schema["properties"]["note"]["type"],
+            json!(["string", "null"])
+        );
+        // Tool arguments may still leave the defaulted field out.
+        assert_eq!(<Tally as ToolArg>::schema()["required"], json!(["name"]));
+
+        // Every answer the strict schema accepts deserializes.
+        let answer = json!({ "name": "visits", "count": 3, "note": null });
+        assert!(jsonschema::is_valid(&schema, &answer));
+        let tally: Tally = serde_json::from_value(answer).unwrap();
+        assert_eq!(tally.count, 3);
+        assert_eq!(tally.note, None);
+        let null_count = json!({ "name": "visits", "count": null, "note": null });
+        assert!(!jsonschema::is_valid(&schema, &null_count));
+    }
+
+    /// A verdict, with no `ToolArg` impl of its own.
+    #[derive(StructuredOutput, serde::Deserialize)]
+    struct Verdict {
+        #[desc("Whether the claim holds")]
+        holds: bool,
+        sources: Vec<Source>,
+        unit: Option<Unit>,
+    }
+
+    #[derive(StructuredOutput, serde::Deserialize)]
+    struct Source {
+        url: String,
+        #[serde(default)]
+        quote: Option<String>,
+    }
+
+    #[test]
+    fn test_structured_output_without_tool_arg() {
+        let schema = Verdict::schema();
+        assert_eq!(
+            schema["description"],
+            "A verdict, with no `ToolArg` impl of its own."
+        );
+        assert_eq!(schema["required"], json!(["holds", "sources", "unit"]));
+        assert_eq!(schema["additionalProperties"], false);
+        assert_eq!(
+            schema["properties"]["holds"],
+            json!({ "type": "boolean", "description": "Whether the claim holds" })
+        );
+        let source = &schema["properties"]["sources"]["items"];
+        assert_eq!(source["required"], json!(["quote", "url"]));
+        assert_eq!(source["additionalProperties"], false);
+        assert_eq!(
+            source["properties"]["quote"]["type"],
+            json!(["string", "null"])
+        );
+        // `Unit` only derives `ToolArg`.
+        assert_eq!(
+            schema["properties"]["unit"]["type"],
+            json!(["string", "null"])
+        );
+    }
+
+    #[test]
+    fn test_structured_output_response_format() {
+        assert_eq!(Verdict::schema_name(), "Verdict");
+        assert_eq!(TestOutput::schema_name(), "TestOutput");
+        let openai::types::ResponseFormat::JsonSchema { json_schema } = Verdict::response_format()
+        else {
+            panic!("expected a json_schema response format");
+        };
+        assert_eq!(json_schema.name, "Verdict");
+        assert_eq!(
+            json_schema.description.as_deref(),
+            Some("A verdict, with no `ToolArg` impl of its own.")
+        );
+        assert_eq!(json_schema.schema, Some(Verdict::schema()));
+        assert_eq!(json_schema.strict, Some(true));
+    }
+
+    /// A folder and everything in it.
+    #[derive(ToolArg, StructuredOutput, serde::Deserialize)]
+    struct Folder {
+        name: String,
+        children: Vec<Folder>,
+        parent: Option<Box<Self>>,
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct SyncArgs {
+        root: Folder,
+        mirror: std::sync::Arc<Folder>,
+    }
+
+    #[test]
+    fn test_recursive_schema() {
+        let schema = <Folder as ToolArg>::schema();
+        assert_eq!(schema["type"], "object");
+        assert_eq!(
+            schema["properties"]["children"]["items"],
+            json!({ "$ref": "#/$defs/Folder" })
+        );
+        assert_eq!(
+            schema["properties"]["parent"],
+            json!({ "anyOf": [{ "$ref": "#/$defs/Folder" }, { "type": "null" }] })
+        );
+        assert_eq!(
+            schema["$defs"]["Folder"]["required"],
+            json!(["name", "children"])
+        );
+
+        let document = json!({
+            "name": "src",
+            "children": [{ "name": "bin", "children": [], "parent": null }],
+        });
+        assert!(jsonschema::is_valid(&schema, &document));
+        assert!(!jsonschema::is_valid(
+            &schema,
+            &json!({ "name": "src", "children": [{ "name": 1, "children": [] }] })
+        ));
+        serde_json::from_value::<Folder>(document).unwrap();
+
+        // Definitions of nested types are collected at the top level.
+        let schema = SyncArgs::schema();
+        assert_eq!(
+            schema["properties"]["root"],
+            json!({ "$ref": "#/$defs/Folder" })
+        );
+        assert_eq!(
+            schema["properties"]["mirror"],
+            json!({ "$ref": "#/$defs/Folder" })
+        );
+        assert_eq!(schema["$defs"].as_object().unwrap().len(), 1);
+        let document = json!({
+            "root": { "name": "a", "children": [] },
+            "mirror": { "name": "b", "children": [{ "name": "c", "children": [] }] },
+        });
+        assert!(jsonschema::is_valid(&schema, &document));
+
+        let schema = <Folder as StructuredOutput>::schema();
+        assert_eq!(schema["$defs"]["Folder"]["additionalProperties"], false);
+        assert_eq!(
+            schema["$defs"]["Folder"]["required"],
+            json!(["children", "name", "parent"])
+        );
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    #[allow(dead_code)]
+    struct PrimitiveArgs {
+        a_i8: i8,
+        a_i16: i16,
+        a_i32: i32,
+        a_i64: i64,
+        a_i128: i128,
+        a_isize: isize,
+        a_u8: u8,
+        a_u16: u16,
+        a_u32: u32,
+        a_u64: u64,
+        a_u128: u128,
+        a_usize: usize,
+        a_f32: f32,
+        a_f64: f64,
+        a_bool: bool,
+        a_char: char,
+        a_string: String,
+        #[desc("A percentage")]
+        #[schema(maximum = 100)]
+        a_percent: u8,
+    }
+
+    #[test]
+    fn test_primitive_schemas() {
+        let properties = &PrimitiveArgs::schema()["properties"];
+        let integer = json!({ "type": "integer" });
+        let unsigned = json!({ "type": "integer", "minimum": 0 });
+        let expected = [
+            (
+                "a_i8",
+                json!({ "type": "integer", "minimum": -128, "maximum": 127 }),
+            ),
+            (
+                "a_i16",
+                json!({ "type": "integer", "minimum": -32768, "maximum": 32767 }),
+            ),
+            ("a_i32", integer.clone()),
+            ("a_i64", integer.clone()),
+            ("a_i128", integer.clone()),
+            ("a_isize", integer),
+            (
+                "a_u8",
+                json!({ "type": "integer", "minimum": 0, "maximum": 255 }),
+            ),
+            (
+                "a_u16",
+                json!({ "type": "integer", "minimum": 0, "maximum": 65535 }),
+            ),
+            ("a_u32", unsigned.clone()),
+            ("a_u64", unsigned.clone()),
+            ("a_u128", unsigned.clone()),
+            ("a_usize", unsigned),
+            ("a_f32", json!({ "type": "number" })),
+            ("a_f64", json!({ "type": "number" })),
+            ("a_bool", json!({ "type": "boolean" })),
+            (
+                "a_char",
+                json!({ "type": "string", "minLength": 1, "maxLength": 1 }),
+            ),
+            ("a_string", json!({ "type": "string" })),
+            (
+                "a_percent",
+                json!({
+                    "type": "integer",
+                    "minimum": 0,
+                    "maximum": 100,
+                    "description": "A percentage"
+                }),
+            ),
+        ];
+        for (name, schema) in expected {
+            assert_eq!(properties[name], schema, "{}", name);
+        }
+        assert_eq!(properties.as_object().unwrap().len(), 18);
+
+        // The bounds are what the types accept.
+        let schema = PrimitiveArgs::schema();
+        let valid = |a_u8: i64, a_char: &str| {
+            let mut args = examples(&schema).remove(0);
+            args["a_u8"] = json!(a_u8);
+            args["a_char"] = json!(a_char);
+            let fits = serde_json::from_value::<PrimitiveArgs>(args.clone()).is_ok();
+            assert_eq!(jsonschema::is_valid(&schema, &args), fits);
+            fits
+        };
+        assert!(valid(255, "x"));
+        assert!(!valid(256, "x"));
+        assert!(!valid(-1, "x"));
+        assert!(!valid(7, "xy"));
+    }
+
+    #[derive(ToolArg, serde::Deserialize, Debug, PartialEq)]
+    struct RouteArgs {
+        #[desc("Origin, stopover and destination")]
+        stops: [String; 3],
+        start: (f64, f64),
+        segments: Vec<[i32; 2]>,
+    }
+
+    #[test]
+    fn test_array_and_tuple_schemas() {
+        let properties = &RouteArgs::schema()["properties"];
+        assert_eq!(
+            properties["stops"],
+            json!({
+                "type": "array",
+                "items": { "type": "string" },
+                "minItems": 3,
+                "maxItems": 3,
+                "description": "Origin, stopover and destination"
+            })
+        );
+        assert_eq!(
+            properties["start"],
+            json!({
+                "type": "array",
+                "prefixItems": [{ "type": "number" }, { "type": "number" }],
+                "minItems": 2,
+                "maxItems": 2
+            })
+        );
+        assert_eq!(
+            properties["segments"],
+            json!({
+                "type": "array",
+                "items": {
+                    "type": "array",
+                    "items": { "type": "integer" },
+                    "minItems": 2,
+                    "maxItems": 2
+                }
+            })
         );
-        assert!(multiple["required"]
+        assert_eq!(
+            round_trip::<RouteArgs>(),
+            [RouteArgs {
+                stops: ["text".into(), "text".into(), "text".into()],
+                start: (1.5, 1.5),
+                segments: vec![[7, 7]],
+            }]
+        );
+        let schema = RouteArgs::schema();
+        let too_short = json!({ "stops": ["a", "b"], "start": [1.0, 2.0], "segments": [] });
+        assert!(!jsonschema::is_valid(&schema, &too_short));
+    }
+
+    // Not `Deserialize`, as serde only reads `Rc` and `Arc` with its `rc`
+    // feature.
+    #[derive(ToolArg)]
+    #[allow(dead_code)]
+    struct WrappedArgs {
+        #[desc("The greeting")]
+        greeting: std::borrow::Cow<'static, str>,
+        ids: std::borrow::Cow<'static, [u32]>,
+        nickname: Option<Box<str>>,
+        inner: Box<Inner>,
+        shared: std::sync::Arc<Inner>,
+        local: std::rc::Rc<Vec<String>>,
+    }
+
+    #[test]
+    fn test_owning_wrapper_schemas() {
+        let properties = &WrappedArgs::schema()["properties"];
+        assert_eq!(
+            properties["greeting"],
+            json!({ "type": "string", "description": "The greeting" })
+        );
+        assert_eq!(
+            properties["ids"],
+            json!({ "type": "array", "items": { "type": "integer", "minimum": 0 } })
+        );
+        assert_eq!(
+            properties["nickname"],
+            json!({ "type": ["string", "null"] })
+        );
+        assert_eq!(properties["inner"], Inner::schema());
+        assert_eq!(properties["shared"], Inner::schema());
+        assert_eq!(
+            properties["local"],
+            json!({ "type": "array", "items": { "type": "string" } })
+        );
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct Labelled<T> {
+        label: String,
+        value: T,
+    }
+
+    #[test]
+    fn test_schemas_are_cached() {
+        let fresh = __private::with_defs(SyncArgs::schema_with_defs);
+        assert_eq!(SyncArgs::schema(), fresh);
+        assert_eq!(SyncArgs::schema(), fresh);
+        assert_eq!(
+            <Folder as StructuredOutput>::schema(),
+            <Folder as StructuredOutput>::schema()
+        );
+
+        // Each instantiation of a generic type has its own schema.
+        assert_eq!(
+            Labelled::<u8>::schema()["properties"]["value"],
+            json!({ "type": "integer" })
+        );
+        assert_eq!(
+            Labelled::<String>::schema()["properties"]["value"],
+            json!({ "type": "string" })
+        );
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct ConnectArgs {
+        #[desc("Where the socket lives")]
+        socket: std::path::PathBuf,
+        host: std::net::IpAddr,
+        gateway: Option<std::net::Ipv4Addr>,
+    }
+
+    #[test]
+    fn test_std_string_types() {
+        let properties = &ConnectArgs::schema()["properties"];
+        assert_eq!(
+            properties["socket"],
+            json!({ "type": "string", "description": "Where the socket lives (file path)" })
+        );
+        assert_eq!(
+            properties["host"],
+            json!({ "type": "string", "description": "IPv4 or IPv6 address" })
+        );
+        assert_eq!(
+            properties["gateway"],
+            json!({ "type": ["string", "null"], "format": "ipv4" })
+        );
+        let args: ConnectArgs = serde_json::from_value(json!({
+            "socket": "/tmp/app.sock",
+            "host": "::1",
+            "gateway": "10.0.0.1",
+        }))
+        .unwrap();
+        assert!(args.host.is_loopback());
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_chrono_types() {
+        #[derive(ToolArg, serde::Deserialize)]
+        struct ScheduleArgs {
+            at: chrono::DateTime<chrono::Utc>,
+            until: Option<chrono::NaiveDate>,
+        }
+
+        let properties = &ScheduleArgs::schema()["properties"];
+        assert_eq!(
+            properties["at"],
+            json!({ "type": "string", "format": "date-time" })
+        );
+        assert_eq!(
+            properties["until"],
+            json!({ "type": ["string", "null"], "format": "date" })
+        );
+        serde_json::from_value::<ScheduleArgs>(json!({
+            "at": "2024-05-01T09:30:00Z",
+            "until": "2024-06-01",
+        }))
+        .unwrap();
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn test_uuid_type() {
+        #[derive(ToolArg, serde::Deserialize)]
+        struct OrderArgs {
+            id: uuid::Uuid,
+        }
+
+        assert_eq!(
+            OrderArgs::schema()["properties"]["id"],
+            json!({ "type": "string", "format": "uuid" })
+        );
+        serde_json::from_value::<OrderArgs>(
+            json!({ "id": "67e55044-10b1-426f-9247-bb680e5fe0c8" }),
+        )
+        .unwrap();
+    }
+
+    #[cfg(feature = "url")]
+    #[test]
+    fn test_url_type() {
+        #[derive(ToolArg, serde::Deserialize)]
+        struct FetchArgs {
+            #[desc("Page to fetch")]
+            url: url::Url,
+        }
+
+        assert_eq!(
+            FetchArgs::schema()["properties"]["url"],
+            json!({ "type": "string", "format": "uri", "description": "Page to fetch" })
+        );
+        serde_json::from_value::<FetchArgs>(json!({ "url": "https://example.com/" })).unwrap();
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct PostArgs {
+        #[schema(min_length = 1, max_length = 280, pattern = "^[a-z-]+$")]
+        slug: String,
+        #[schema(minimum = 1, maximum = 100)]
+        priority: u32,
+        #[schema(minimum = -1.5)]
+        score: Option<f64>,
+        #[schema(min_items = 1, max_items = 10)]
+        tags: Vec<String>,
+        #[desc("Where to send replies")]
+        #[schema(format = "email")]
+        reply_to: Option<String>,
+    }
+
+    #[test]
+    fn test_schema_constraints() {
+        let schema = PostArgs::schema();
+        let properties = &schema["properties"];
+        assert_eq!(
+            properties["slug"],
+            json!({ "type": "string", "minLength": 1, "maxLength": 280, "pattern": "^[a-z-]+$" })
+        );
+        assert_eq!(
+            properties["priority"],
+            json!({ "type": "integer", "minimum": 1, "maximum": 100 })
+        );
+        assert_eq!(
+            properties["score"],
+            json!({ "type": ["number", "null"], "minimum": -1.5 })
+        );
+        assert_eq!(properties["tags"]["minItems"], 1);
+        assert_eq!(properties["tags"]["maxItems"], 10);
+        assert_eq!(
+            properties["reply_to"],
+            json!({
+                "type": ["string", "null"],
+                "description": "Where to send replies",
+                "format": "email"
+            })
+        );
+        assert_eq!(schema["required"], json!(["slug", "priority", "tags"]));
+    }
+
+    fn default_page_size() -> u32 {
+        20
+    }
+
+    #[derive(ToolArg, serde::Deserialize, Default, Debug, PartialEq)]
+    struct SearchFilters {
+        language: String,
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct SearchArgs {
+        query: String,
+        #[serde(default = "default_page_size")]
+        #[schema(default = 20)]
+        page_size: u32,
+        #[serde(default)]
+        #[schema(minimum = -10, default = -1)]
+        offset: i64,
+        #[serde(default)]
+        #[schema(default = "relevance")]
+        sort: String,
+        #[serde(default)]
+        #[schema(default = false)]
+        exact: bool,
+        #[serde(default)]
+        filters: SearchFilters,
+    }
+
+    #[test]
+    fn test_defaulted_fields_are_optional() {
+        let schema = SearchArgs::schema();
+        assert_eq!(schema["required"], json!(["query"]));
+        let properties = &schema["properties"];
+        assert_eq!(properties["page_size"]["default"], 20);
+        assert_eq!(
+            properties["offset"],
+            json!({ "type": "integer", "minimum": -10, "default": -1 })
+        );
+        assert_eq!(properties["sort"]["default"], "relevance");
+        assert_eq!(properties["exact"]["default"], false);
+        assert!(properties["filters"].get("default").is_none());
+        assert_eq!(properties["filters"]["required"], json!(["language"]));
+
+        let args: SearchArgs = serde_json::from_value(json!({ "query": "rust" })).unwrap();
+        assert_eq!(args.page_size, 20);
+        assert_eq!(args.filters, SearchFilters::default());
+    }
+
+    #[tool("Get the current time")]
+    async fn now() -> Result<String> {
+        Ok("12:00".into())
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct NoArgs;
+
+    #[tool("List the available models")]
+    async fn list_models(_args: NoArgs) -> Result<String> {
+        Ok("gpt-4o".into())
+    }
+
+    #[tokio::test]
+    async fn test_tools_without_arguments() {
+        assert_eq!(
+            <NowTool as Tool>::parameters(),
+            json!({ "type": "object", "properties": {} })
+        );
+        assert_eq!(
+            <ListModelsTool as Tool>::parameters(),
+            json!({ "type": "object", "properties": {}, "required": [] })
+        );
+
+        let toolset = tools![NowTool, ListModelsTool];
+        for args in [json!({}), serde_json::Value::Null] {
+            let time = toolset.dispatch("now".into(), args.clone()).await.unwrap();
+            assert_eq!(time, "12:00");
+            let models = toolset.dispatch("list_models".into(), args).await.unwrap();
+            assert_eq!(models, "gpt-4o");
+        }
+        assert!(toolset
+            .dispatch("list_models".into(), json!([1]))
+            .await
+            .is_err());
+
+        let backend = std::sync::Arc::new(
+            crate::testing::MockChatBackend::new()
+                .respond_with_raw_tool_call("now", "", "tool_calls")
+                .respond_with_text("It is noon."),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(toolset)
+            .build()
+            .unwrap();
+        assert_eq!(agent.run("What time is it?").await.unwrap(), "It is noon.");
+        let messages = serde_json::to_value(&backend.requests()[1].messages).unwrap();
+        assert_eq!(messages[2]["content"], "12:00");
+    }
+
+    #[tool("Add numbers", args(a = "first operand", b = "second operand"))]
+    async fn add(a: f64, b: f64, round: Option<bool>) -> Result<String> {
+        let sum = a + b;
+        Ok(if round == Some(true) {
+            sum.round().to_string()
+        } else {
+            sum.to_string()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_tool_with_several_parameters() {
+        assert_eq!(
+            <AddTool as Tool>::parameters(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "a": { "type": "number", "description": "first operand" },
+                    "b": { "type": "number", "description": "second operand" },
+                    "round": { "type": ["boolean", "null"] },
+                },
+                "required": ["a", "b"]
+            })
+        );
+
+        let toolset = tools![AddTool];
+        let sum = toolset
+            .dispatch("add".into(), json!({ "a": 1.5, "b": 2 }))
+            .await
+            .unwrap();
+        assert_eq!(sum, "3.5");
+        let sum = toolset
+            .dispatch("add".into(), json!({ "a": 1.5, "b": 2, "round": true }))
+            .await
+            .unwrap();
+        assert_eq!(sum, "4");
+        let error = toolset
+            .dispatch("add".into(), json!({ "a": 1.5 }))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "missing field `b`");
+        assert!(toolset.dispatch("add".into(), json!([1, 2])).await.is_err());
+    }
+
+    struct AppState {
+        greeting: String,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    #[tool("Greet someone")]
+    async fn greet(state: &AppState, args: TestArgs) -> Result<String> {
+        state
+            .calls
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Ok(format!("{}, {}", state.greeting, args.name))
+    }
+
+    #[tool("Count greetings")]
+    async fn count_greetings(#[context] state: &AppState) -> Result<String> {
+        Ok(state
+            .calls
+            .load(std::sync::atomic::Ordering::SeqCst)
+            .to_string())
+    }
+
+    #[tokio::test]
+    async fn test_tools_with_context() {
+        assert_eq!(<GreetTool as Tool>::parameters(), TestArgs::schema());
+
+        let state = std::sync::Arc::new(AppState {
+            greeting: "Hello".into(),
+            calls: Default::default(),
+        });
+        let toolset = tools![with state: GreetTool, CountGreetingsTool, NowTool];
+        let args = json!({ "name": "Ada", "count": 1 });
+        let greeting = toolset.dispatch("greet".into(), args).await.unwrap();
+        assert_eq!(greeting, "Hello, Ada");
+        let count = toolset
+            .dispatch("count_greetings".into(), json!({}))
+            .await
+            .unwrap();
+        assert_eq!(count, "1");
+        assert_eq!(
+            toolset.dispatch("now".into(), json!({})).await.unwrap(),
+            "12:00"
+        );
+
+        let toolset = ToolSet::builder()
+            .register_from_context::<CountGreetingsTool, _>(&state)
+            .register(Box::new(GreetTool::new(state.clone())))
+            .build()
+            .unwrap();
+        let args = json!({ "name": "Grace", "count": 1 });
+        toolset.dispatch("greet".into(), args).await.unwrap();
+        let count = toolset
+            .dispatch("count_greetings".into(), json!({}))
+            .await
+            .unwrap();
+        assert_eq!(count, "2");
+    }
+
+    #[derive(serde::Serialize)]
+    struct Forecast {
+        city: String,
+        high: i32,
+    }
+
+    #[tool("Get the forecast")]
+    async fn forecast(args: TestArgs) -> Result<Forecast> {
+        Ok(Forecast {
+            city: args.name,
+            high: 21,
+        })
+    }
+
+    #[tool("List cities")]
+    async fn list_cities() -> Result<Vec<&'static str>> {
+        Ok(vec!["Lisbon", "Porto"])
+    }
+
+    #[tool("Describe a city")]
+    async fn describe_city() -> Result<serde_json::Value> {
+        Ok(json!({ "name": "Lisbon", "hills": 7 }))
+    }
+
+    #[tokio::test]
+    async fn test_tools_return_serializable_values() {
+        let backend = std::sync::Arc::new(
+            crate::testing::MockChatBackend::new()
+                .respond_with_tool_call("forecast", json!({ "name": "Lisbon", "count": 1 }))
+                .respond_with_tool_call("list_cities", json!({}))
+                .respond_with_tool_call("describe_city", json!({}))
+                .respond_with_tool_call("test_tool", json!({ "name": "Lisbon", "count": 2 }))
+                .respond_with_text("Done."),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .backend(backend.clone())
+            .tools(tools![
+                ForecastTool,
+                ListCitiesTool,
+                DescribeCityTool,
+                TestToolTool
+            ])
+            .build()
+            .unwrap();
+        agent.run("Tell me about Lisbon").await.unwrap();
+
+        let requests = backend.requests();
+        let messages = serde_json::to_value(&requests.last().unwrap().messages).unwrap();
+        let results: Vec<_> = messages
             .as_array()
             .unwrap()
-            .contains(&json!("value")));
+            .iter()
+            .filter(|message| message["role"] == "tool")
+            .map(|message| message["content"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            results,
+            [
+                r#"{"city":"Lisbon","high":21}"#,
+                r#"["Lisbon","Porto"]"#,
+                r#"{"hills":7,"name":"Lisbon"}"#,
+                "Called with 2 items",
+            ]
+        );
+    }
 
-        // Named variant
-        let named = &one_of[3];
-        assert_eq!(named["properties"]["type"]["const"], "Named");
-        assert_eq!(named["properties"]["text"]["type"], "string");
-        assert_eq!(named["properties"]["num"]["type"], "integer");
-        let required = named["required"].as_array().unwrap();
-        assert!(required.contains(&json!("text")));
-        assert!(required.contains(&json!("num")));
+    #[tool("Convert Celsius to Fahrenheit")]
+    fn to_fahrenheit(celsius: f64, offset: Option<f64>) -> Result<String> {
+        Ok((celsius * 9.0 / 5.0 + 32.0 + offset.unwrap_or(0.0)).to_string())
+    }
+
+    static COUNTING_THREAD: std::sync::Mutex<Option<std::thread::ThreadId>> =
+        std::sync::Mutex::new(None);
+
+    #[tool("Count the words of a text", blocking)]
+    fn count_words(state: &AppState, args: TestArgs) -> Result<usize> {
+        state
+            .calls
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        *COUNTING_THREAD.lock().unwrap() = Some(std::thread::current().id());
+        Ok(args.name.split_whitespace().count())
+    }
+
+    #[tokio::test]
+    async fn test_synchronous_tools() {
+        let state = std::sync::Arc::new(AppState {
+            greeting: "Hello".into(),
+            calls: Default::default(),
+        });
+        let toolset = tools![with state: ToFahrenheitTool, CountWordsTool];
+        let fahrenheit = toolset
+            .dispatch("to_fahrenheit".into(), json!({ "celsius": 100 }))
+            .await
+            .unwrap();
+        assert_eq!(fahrenheit, "212");
+        let args = json!({ "name": "one two three", "count": 1 });
+        let words = toolset.dispatch("count_words".into(), args).await.unwrap();
+        assert_eq!(words, "3");
+        assert_eq!(state.calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        let counting_thread = COUNTING_THREAD.lock().unwrap().unwrap();
+        assert_ne!(counting_thread, std::thread::current().id());
     }
 }
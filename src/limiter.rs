@@ -0,0 +1,379 @@
+//! Shared request permits with priority scheduling.
+//!
+//! A [`Limiter`] caps how many chat completion requests are in flight across
+//! every agent it is attached to. When permits run out, waiting requests are
+//! granted in priority order as permits are released: [`Priority::High`]
+//! before [`Priority::Normal`] before [`Priority::Low`], first come first
+//! served within a priority. Requests are never preempted; priorities only
+//! decide who goes next.
+//!
+//! To keep low-priority work from starving under constant interactive load,
+//! waiting requests age: every [`aging`](Limiter::aging) interval spent in
+//! the queue raises a request's effective priority by one level.
+//!
+//! ```no_run
+//! use aiform::agent::RunOptions;
+//! use aiform::limiter::{Limiter, Priority};
+//! use aiform::prelude::*;
+//!
+//! # async fn example() -> Result<()> {
+//! let limiter = Limiter::new(4);
+//! let agent = Agent::builder()
+//!     .model("gpt-4o")
+//!     .limiter(limiter.clone())
+//!     .build()?;
+//!
+//! let report = agent
+//!     .run_with_options(
+//!         "Summarize yesterday's tickets",
+//!         RunOptions::new().priority(Priority::Low),
+//!     )
+//!     .await?;
+//! println!("waited {:?} for permits", report.timings.scheduling_wait);
+//! println!("{} low-priority requests queued", limiter.stats(Priority::Low).queued);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+/// How urgently a run needs permits from a [`Limiter`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Priority {
+    /// Background work that should yield to everything else.
+    Low,
+    /// The default priority.
+    #[default]
+    Normal,
+    /// Interactive traffic.
+    High,
+}
+
+impl Priority {
+    fn rank(self) -> usize {
+        self as usize
+    }
+}
+
+/// Queue and wait statistics for one priority.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PriorityStats {
+    /// Requests currently waiting for a permit.
+    pub queued: usize,
+    /// Permits granted so far.
+    pub granted: u64,
+    /// Total time granted requests spent waiting.
+    pub total_wait: Duration,
+    /// Longest time a granted request spent waiting.
+    pub max_wait: Duration,
+}
+
+/// A permit-based request limiter shared between agents.
+///
+/// Cheap to clone; clones share the same permits.
+#[derive(Clone)]
+pub struct Limiter {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    aging: Duration,
+    available: usize,
+    next_seq: u64,
+    waiters: Vec<Waiter>,
+    stats: [PriorityStats; 3],
+}
+
+struct Waiter {
+    seq: u64,
+    priority: Priority,
+    enqueued: Instant,
+    grant: oneshot::Sender<()>,
+}
+
+impl Limiter {
+    /// Creates a limiter allowing `capacity` concurrent requests, with an
+    /// aging interval of ten seconds.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                aging: Duration::from_secs(10),
+                available: capacity,
+                next_seq: 0,
+                waiters: Vec::new(),
+                stats: Default::default(),
+            })),
+        }
+    }
+
+    /// Sets how long a request waits before its priority is raised by one
+    /// level. A zero interval disables aging.
+    pub fn aging(self, interval: Duration) -> Self {
+        self.state.lock().unwrap().aging = interval;
+        self
+    }
+
+    /// Waits for a permit, returning it once granted.
+    ///
+    /// The permit is released when dropped. Cancelling the returned future
+    /// gives up the place in the queue.
+    pub async fn acquire(&self, priority: Priority) -> LimiterPermit {
+        let enqueued = Instant::now();
+        let (seq, receiver) = {
+            let mut state = self.state.lock().unwrap();
+            if state.available > 0 && state.waiters.is_empty() {
+                state.available -= 1;
+                state.record_grant(priority, Duration::ZERO);
+                return LimiterPermit {
+                    limiter: self.clone(),
+                    waited: Duration::ZERO,
+                };
+            }
+            let (grant, receiver) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.stats[priority.rank()].queued += 1;
+            state.waiters.push(Waiter {
+                seq,
+                priority,
+                enqueued,
+                grant,
+            });
+            (seq, receiver)
+        };
+
+        let mut queued = QueuedGuard {
+            limiter: self,
+            seq,
+            receiver,
+            granted: false,
+        };
+        // The sender is only dropped after a successful send.
+        let _ = (&mut queued.receiver).await;
+        queued.granted = true;
+
+        LimiterPermit {
+            limiter: self.clone(),
+            waited: enqueued.elapsed(),
+        }
+    }
+
+    /// Returns queue and wait statistics for a priority.
+    pub fn stats(&self, priority: Priority) -> PriorityStats {
+        self.state.lock().unwrap().stats[priority.rank()]
+    }
+
+    /// Returns the number of permits not currently held.
+    pub fn available(&self) -> usize {
+        self.state.lock().unwrap().available
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.available += 1;
+        state.grant_waiters();
+    }
+}
+
+impl State {
+    fn record_grant(&mut self, priority: Priority, waited: Duration) {
+        let stats = &mut self.stats[priority.rank()];
+        stats.granted += 1;
+        stats.total_wait += waited;
+        stats.max_wait = stats.max_wait.max(waited);
+    }
+
+    /// Hands available permits to the waiters with the highest effective
+    /// priority, oldest first.
+    fn grant_waiters(&mut self) {
+        let aging = self.aging;
+        let now = Instant::now();
+        while self.available > 0 && !self.waiters.is_empty() {
+            let effective = |waiter: &Waiter| {
+                let boost = if aging.is_zero() {
+                    0
+                } else {
+                    (now.duration_since(waiter.enqueued).as_nanos() / aging.as_nanos()) as usize
+                };
+                (waiter.priority.rank() + boost).min(Priority::High.rank())
+            };
+            let next = self
+                .waiters
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| effective(a).cmp(&effective(b)).then(b.seq.cmp(&a.seq)))
+                .map(|(index, _)| index)
+                .expect("waiters is not empty");
+            let waiter = self.waiters.swap_remove(next);
+            self.stats[waiter.priority.rank()].queued -= 1;
+            if waiter.grant.send(()).is_ok() {
+                self.available -= 1;
+                self.record_grant(waiter.priority, now.duration_since(waiter.enqueued));
+            }
+        }
+    }
+}
+
+/// Removes a waiter whose `acquire` future was dropped before completing.
+struct QueuedGuard<'a> {
+    limiter: &'a Limiter,
+    seq: u64,
+    receiver: oneshot::Receiver<()>,
+    granted: bool,
+}
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        if self.granted {
+            return;
+        }
+        if self.receiver.try_recv().is_ok() {
+            // Granted after the future stopped being polled.
+            self.limiter.release();
+            return;
+        }
+        let mut state = self.limiter.state.lock().unwrap();
+        if let Some(index) = state.waiters.iter().position(|w| w.seq == self.seq) {
+            let waiter = state.waiters.swap_remove(index);
+            state.stats[waiter.priority.rank()].queued -= 1;
+        }
+    }
+}
+
+/// A permit from a [`Limiter`], released on drop.
+pub struct LimiterPermit {
+    limiter: Limiter,
+    waited: Duration,
+}
+
+impl LimiterPermit {
+    /// Returns how long the request waited for this permit.
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+}
+
+impl Drop for LimiterPermit {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
+
+impl std::fmt::Debug for Limiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("Limiter")
+            .field("available", &state.available)
+            .field("queued", &state.waiters.len())
+            .field("aging", &state.aging)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Queues an acquire and records the order permits are granted in.
+    fn spawn_waiter(
+        limiter: &Limiter,
+        priority: Priority,
+        label: &'static str,
+        order: &Arc<Mutex<Vec<&'static str>>>,
+    ) -> tokio::task::JoinHandle<Duration> {
+        let limiter = limiter.clone();
+        let order = order.clone();
+        tokio::spawn(async move {
+            let permit = limiter.acquire(priority).await;
+            order.lock().unwrap().push(label);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            permit.waited()
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_higher_priorities_are_granted_first() {
+        let limiter = Limiter::new(1).aging(Duration::ZERO);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let held = limiter.acquire(Priority::Normal).await;
+
+        let low = spawn_waiter(&limiter, Priority::Low, "low", &order);
+        tokio::task::yield_now().await;
+        let normal = spawn_waiter(&limiter, Priority::Normal, "normal", &order);
+        tokio::task::yield_now().await;
+        let high = spawn_waiter(&limiter, Priority::High, "high", &order);
+        tokio::task::yield_now().await;
+        assert_eq!(limiter.stats(Priority::Low).queued, 1);
+        assert_eq!(limiter.stats(Priority::High).queued, 1);
+
+        drop(held);
+        let (low, normal, high) = (
+            low.await.unwrap(),
+            normal.await.unwrap(),
+            high.await.unwrap(),
+        );
+        assert_eq!(*order.lock().unwrap(), vec!["high", "normal", "low"]);
+
+        // Each waiter held the permit for one second before the next ran.
+        assert_eq!(high, Duration::ZERO);
+        assert_eq!(normal, Duration::from_secs(1));
+        assert_eq!(low, Duration::from_secs(2));
+
+        let stats = limiter.stats(Priority::Low);
+        assert_eq!(stats.queued, 0);
+        assert_eq!(stats.granted, 1);
+        assert_eq!(stats.max_wait, Duration::from_secs(2));
+        assert_eq!(limiter.stats(Priority::Normal).granted, 2);
+        assert_eq!(limiter.available(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_aging_prevents_starvation() {
+        let limiter = Limiter::new(1).aging(Duration::from_secs(5));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let held = limiter.acquire(Priority::High).await;
+
+        let low = spawn_waiter(&limiter, Priority::Low, "low", &order);
+        tokio::task::yield_now().await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+
+        // Low has aged to High and was queued first, so it beats a fresh
+        // High request.
+        let high = spawn_waiter(&limiter, Priority::High, "high", &order);
+        tokio::task::yield_now().await;
+        drop(held);
+        low.await.unwrap();
+        high.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), vec!["low", "high"]);
+
+        // Without enough waiting, the fresh High request goes first.
+        let held = limiter.acquire(Priority::High).await;
+        let low = spawn_waiter(&limiter, Priority::Low, "low", &order);
+        tokio::task::yield_now().await;
+        tokio::time::sleep(Duration::from_secs(4)).await;
+        let high = spawn_waiter(&limiter, Priority::High, "high", &order);
+        tokio::task::yield_now().await;
+        drop(held);
+        low.await.unwrap();
+        high.await.unwrap();
+        assert_eq!(order.lock().unwrap()[2..], ["high", "low"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancelled_waiters_leave_the_queue() {
+        let limiter = Limiter::new(1);
+        let held = limiter.acquire(Priority::Normal).await;
+
+        let waiting = tokio::time::timeout(Duration::from_secs(1), limiter.acquire(Priority::Low));
+        assert!(waiting.await.is_err());
+        assert_eq!(limiter.stats(Priority::Low).queued, 0);
+
+        drop(held);
+        assert_eq!(limiter.available(), 1);
+        let _permit = limiter.acquire(Priority::Low).await;
+        assert_eq!(limiter.available(), 0);
+    }
+}
@@ -0,0 +1,274 @@
+//! Counters accumulated over all the runs of an agent.
+//!
+//! [`Agent::metrics`](crate::Agent::metrics) returns a [`MetricsSnapshot`]
+//! of every completion request and tool call the agent made since it was
+//! built or last [reset](crate::Agent::reset_metrics). Snapshots serialize
+//! with serde, for handing to a monitoring system:
+//!
+//! ```no_run
+//! use aiform::prelude::*;
+//!
+//! # async fn example(agent: Agent) -> Result<()> {
+//! agent.run("Find flights to Lisbon").await?;
+//! let metrics = agent.metrics();
+//! println!("{}", serde_json::to_string(&metrics)?);
+//! println!("{} tokens in {} requests", metrics.total_tokens(), metrics.requests);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Runs update the counters without taking locks, except for calls to tools
+//! the agent does not have, which are rare.
+
+use crate::error::Error;
+use async_openai::types::CompletionUsage;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The categories errors are counted under, as [`error_category`] names
+/// them.
+const ERROR_CATEGORIES: [&str; 18] = [
+    "api",
+    "json",
+    "tool_not_found",
+    "agent_not_found",
+    "max_iterations",
+    "tool_execution",
+    "malformed_tool_arguments",
+    "invalid_configuration",
+    "render",
+    "tool",
+    "timeout",
+    "cancelled",
+    "structured_output_parse",
+    "hook_aborted",
+    "split_tool_round",
+    "other",
+    "invalid_tool_arguments",
+    "max_handoffs",
+];
+
+/// The index of the `tool` category.
+const TOOL_ERRORS: usize = 9;
+
+/// The index in [`ERROR_CATEGORIES`] of the category of `error`.
+fn error_category(error: &Error) -> usize {
+    match error {
+        Error::OpenAI(_) => 0,
+        Error::Json(_) => 1,
+        Error::ToolNotFound(_) => 2,
+        Error::AgentNotFound(_) => 3,
+        Error::MaxIterationsExceeded { .. } => 4,
+        Error::ToolExecution { .. } => 5,
+        Error::MalformedToolArguments { .. } => 6,
+        Error::InvalidConfiguration(_) => 7,
+        Error::Render(_) => 8,
+        Error::Tool(_) => TOOL_ERRORS,
+        Error::Timeout { .. } => 10,
+        Error::Cancelled => 11,
+        Error::StructuredOutputParse { .. } => 12,
+        Error::HookAborted { .. } => 13,
+        Error::SplitToolRound { .. } => 14,
+        Error::Other(_) => 15,
+        Error::InvalidToolArguments { .. } => 16,
+        Error::MaxHandoffsExceeded { .. } => 17,
+    }
+}
+
+/// Minimum, mean and maximum of a set of durations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    /// How many durations were measured.
+    pub count: u64,
+    /// The shortest duration, zero if none was measured.
+    pub min: Duration,
+    /// The mean duration, zero if none was measured.
+    pub mean: Duration,
+    /// The longest duration, zero if none was measured.
+    pub max: Duration,
+}
+
+/// The counters of an agent at one point in time, as returned by
+/// [`Agent::metrics`](crate::Agent::metrics).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    /// Completion requests sent, including failed and retried ones.
+    pub requests: u64,
+    /// Tokens sent in prompts, as reported by the API.
+    pub prompt_tokens: u64,
+    /// Tokens generated in completions, as reported by the API.
+    pub completion_tokens: u64,
+    /// Tool calls by tool name.
+    pub tool_calls: BTreeMap<String, u64>,
+    /// Errors by category: failed runs by the kind of [`Error`] that ended
+    /// them, and tool calls that returned a tool error under `tool`.
+    pub errors: BTreeMap<String, u64>,
+    /// How long completion requests took.
+    pub request_latency: LatencyStats,
+    /// How long tool calls took.
+    pub tool_latency: LatencyStats,
+}
+
+impl MetricsSnapshot {
+    /// Prompt and completion tokens together.
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// Durations in microseconds, summarized as they are recorded.
+#[derive(Debug)]
+struct Latency {
+    count: AtomicU64,
+    total: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Latency {
+    fn record(&self, duration: Duration) {
+        let micros = duration.as_micros().min(u64::MAX as u128) as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(micros, Ordering::Relaxed);
+        self.min.fetch_min(micros, Ordering::Relaxed);
+        self.max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> LatencyStats {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0 {
+            return LatencyStats::default();
+        }
+        LatencyStats {
+            count,
+            min: Duration::from_micros(self.min.load(Ordering::Relaxed)),
+            mean: Duration::from_micros(self.total.load(Ordering::Relaxed) / count),
+            max: Duration::from_micros(self.max.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn reset(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        self.total.store(0, Ordering::Relaxed);
+        self.min.store(u64::MAX, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+}
+
+/// The live counters of an agent.
+#[derive(Debug, Default)]
+pub(crate) struct AgentMetrics {
+    requests: AtomicU64,
+    prompt_tokens: AtomicU64,
+    completion_tokens: AtomicU64,
+    /// Calls of the agent's own tools, whose names are known when it is
+    /// built.
+    tool_calls: HashMap<String, AtomicU64>,
+    /// Calls of tools the agent does not have.
+    unknown_tool_calls: Mutex<HashMap<String, u64>>,
+    errors: [AtomicU64; ERROR_CATEGORIES.len()],
+    request_latency: Latency,
+    tool_latency: Latency,
+}
+
+impl AgentMetrics {
+    /// Counters for an agent with the given tools.
+    pub(crate) fn new(tools: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            tool_calls: tools
+                .into_iter()
+                .map(|name| (name, AtomicU64::new(0)))
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Counts a completion request and the tokens of its response.
+    pub(crate) fn record_request(&self, latency: Duration, usage: Option<&CompletionUsage>) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.request_latency.record(latency);
+        if let Some(usage) = usage {
+            self.prompt_tokens
+                .fetch_add(usage.prompt_tokens.into(), Ordering::Relaxed);
+            self.completion_tokens
+                .fetch_add(usage.completion_tokens.into(), Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a tool call, and its tool error if it returned one.
+    pub(crate) fn record_tool_call(&self, name: &str, latency: Duration, failed: bool) {
+        match self.tool_calls.get(name) {
+            Some(calls) => {
+                calls.fetch_add(1, Ordering::Relaxed);
+            }
+            None => {
+                let mut unknown = self.unknown_tool_calls.lock().unwrap();
+                *unknown.entry(name.to_string()).or_default() += 1;
+            }
+        }
+        self.tool_latency.record(latency);
+        if failed {
+            self.errors[TOOL_ERRORS].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts the error that ended a run.
+    pub(crate) fn record_error(&self, error: &Error) {
+        self.errors[error_category(error)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        let mut tool_calls: BTreeMap<String, u64> = self
+            .tool_calls
+            .iter()
+            .map(|(name, calls)| (name.clone(), calls.load(Ordering::Relaxed)))
+            .filter(|(_, calls)| *calls > 0)
+            .collect();
+        for (name, calls) in self.unknown_tool_calls.lock().unwrap().iter() {
+            tool_calls.insert(name.clone(), *calls);
+        }
+        MetricsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            prompt_tokens: self.prompt_tokens.load(Ordering::Relaxed),
+            completion_tokens: self.completion_tokens.load(Ordering::Relaxed),
+            tool_calls,
+            errors: ERROR_CATEGORIES
+                .iter()
+                .zip(&self.errors)
+                .map(|(category, count)| (category.to_string(), count.load(Ordering::Relaxed)))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            request_latency: self.request_latency.snapshot(),
+            tool_latency: self.tool_latency.snapshot(),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.requests.store(0, Ordering::Relaxed);
+        self.prompt_tokens.store(0, Ordering::Relaxed);
+        self.completion_tokens.store(0, Ordering::Relaxed);
+        for calls in self.tool_calls.values() {
+            calls.store(0, Ordering::Relaxed);
+        }
+        self.unknown_tool_calls.lock().unwrap().clear();
+        for count in &self.errors {
+            count.store(0, Ordering::Relaxed);
+        }
+        self.request_latency.reset();
+        self.tool_latency.reset();
+    }
+}
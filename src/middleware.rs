@@ -0,0 +1,473 @@
+//! Behavior wrapped around every call of a tool set.
+//!
+//! A [`ToolMiddleware`] sees each call before the tool does and its result
+//! after, so argument checks, authorization, caching or redaction can be
+//! written once instead of in every tool. Middleware can change the
+//! arguments, change the result, or answer without calling the tool at all:
+//!
+//! ```
+//! use aiform::middleware::{Logging, Next, ToolCall};
+//! use aiform::prelude::*;
+//! use aiform::tool_error::ToolError;
+//! use aiform::ToolFuture;
+//!
+//! # fn example(tools: ToolSet) -> ToolSet {
+//! tools
+//!     .with_middleware(|call: ToolCall, next: Next| -> ToolFuture {
+//!         if call.name == "delete_file" {
+//!             return Box::pin(async {
+//!                 Err(ToolError::permission_denied("files are read-only").into())
+//!             });
+//!         }
+//!         next.run(call)
+//!     })
+//!     .with_middleware(Logging::new())
+//! # }
+//! ```
+//!
+//! Middleware composes like layers: the one added last runs first and
+//! passes the call inwards with [`Next::run`]. It wraps the tools of sets
+//! [merged](crate::ToolSet::merge) in later too.
+
+use crate::ToolFuture;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// A tool call on its way to the tool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    /// The name of the called tool.
+    pub name: String,
+    /// The arguments of the call.
+    pub arguments: Value,
+    /// The JSON schema the tool advertises for its arguments, if the set
+    /// has a tool of this name.
+    pub parameters: Option<Value>,
+}
+
+/// Wraps the calls of a tool set; see the [module docs](self).
+pub trait ToolMiddleware: Send + Sync {
+    /// Handles a call, passing it on with `next` to reach the tool.
+    fn call(&self, call: ToolCall, next: Next) -> ToolFuture;
+}
+
+impl<F> ToolMiddleware for F
+where
+    F: Fn(ToolCall, Next) -> ToolFuture + Send + Sync,
+{
+    fn call(&self, call: ToolCall, next: Next) -> ToolFuture {
+        self(call, next)
+    }
+}
+
+/// The rest of the middleware chain, ending at the tool.
+#[derive(Clone)]
+pub struct Next {
+    dispatcher: crate::Dispatcher,
+    middleware: Vec<Arc<dyn ToolMiddleware>>,
+}
+
+impl Next {
+    pub(crate) fn new(
+        dispatcher: crate::Dispatcher,
+        middleware: Vec<Arc<dyn ToolMiddleware>>,
+    ) -> Self {
+        Self {
+            dispatcher,
+            middleware,
+        }
+    }
+
+    /// Passes the call to the next middleware, or to the tool.
+    pub fn run(mut self, call: ToolCall) -> ToolFuture {
+        match self.middleware.pop() {
+            Some(middleware) => middleware.call(call, self),
+            None => (self.dispatcher)(call.name, call.arguments),
+        }
+    }
+}
+
+/// Middleware writing a line for every call and its outcome.
+///
+/// Lines go to standard error, or to the sink given to
+/// [`with_sink`](Self::with_sink).
+#[derive(Clone)]
+pub struct Logging {
+    sink: Arc<dyn Fn(&str) + Send + Sync>,
+}
+
+impl Logging {
+    /// Logs to standard error.
+    pub fn new() -> Self {
+        Self::with_sink(|line| eprintln!("{}", line))
+    }
+
+    /// Logs to `sink`, one line per call.
+    pub fn with_sink(sink: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self {
+            sink: Arc::new(sink),
+        }
+    }
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ToolMiddleware for Logging {
+    fn call(&self, call: ToolCall, next: Next) -> ToolFuture {
+        let sink = self.sink.clone();
+        Box::pin(async move {
+            let name = call.name.clone();
+            let arguments = call.arguments.to_string();
+            let started = Instant::now();
+            let result = next.run(call).await;
+            let elapsed = started.elapsed();
+            match result {
+                Ok(ref output) => sink(&format!(
+                    "tool {}({}) returned {} bytes in {:?}",
+                    name,
+                    arguments,
+                    output.len(),
+                    elapsed
+                )),
+                Err(ref error) => sink(&format!(
+                    "tool {}({}) failed in {:?}: {}",
+                    name, arguments, elapsed, error
+                )),
+            }
+            result
+        })
+    }
+}
+
+/// Middleware checking arguments against the schema their tool advertises.
+///
+/// Calls with arguments that do not match fail with
+/// [`Error::InvalidToolArguments`](crate::Error::InvalidToolArguments)
+/// without reaching the tool. Each violated constraint is described in
+/// words the model can act on, naming the field and its description:
+///
+/// ```text
+/// missing required field `location` (The city to look up)
+/// `unit` must be one of "celsius", "fahrenheit", got "kelvin"
+/// ```
+///
+/// Agents hand the failure to their
+/// [`tool_error_policy`](crate::AgentBuilder::tool_error_policy) as an
+/// [`INVALID_INPUT`](crate::tool_error::ToolError::INVALID_INPUT) tool
+/// error, so a model told about it can correct the call. Requires the
+/// `jsonschema` feature.
+#[cfg(any(test, feature = "jsonschema"))]
+#[derive(Default)]
+pub struct ValidateArguments {
+    /// Compiled schemas by tool name.
+    validators: std::sync::Mutex<std::collections::HashMap<String, Arc<jsonschema::Validator>>>,
+}
+
+#[cfg(any(test, feature = "jsonschema"))]
+impl ValidateArguments {
+    /// Validates the arguments of every tool with a schema.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The compiled schema of a tool, compiling it on first use.
+    fn validator(&self, name: &str, schema: &Value) -> Result<Arc<jsonschema::Validator>, String> {
+        let mut validators = self.validators.lock().unwrap();
+        if let Some(validator) = validators.get(name) {
+            return Ok(validator.clone());
+        }
+        let validator =
+            Arc::new(jsonschema::validator_for(schema).map_err(|error| error.to_string())?);
+        validators.insert(name.to_string(), validator.clone());
+        Ok(validator)
+    }
+}
+
+#[cfg(any(test, feature = "jsonschema"))]
+impl ToolMiddleware for ValidateArguments {
+    fn call(&self, call: ToolCall, next: Next) -> ToolFuture {
+        let Some(ref schema) = call.parameters else {
+            return next.run(call);
+        };
+        let validator = match self.validator(&call.name, schema) {
+            Ok(validator) => validator,
+            Err(error) => {
+                let error = crate::tool_error::ToolError::fatal(format!(
+                    "the schema of tool '{}' is invalid: {}",
+                    call.name, error
+                ));
+                return Box::pin(async move { Err(error.into()) });
+            }
+        };
+        let errors: Vec<String> = validator
+            .iter_errors(&call.arguments)
+            .map(|error| describe_violation(&error, schema))
+            .collect();
+        if errors.is_empty() {
+            return next.run(call);
+        }
+        let error = crate::Error::InvalidToolArguments {
+            tool_name: call.name,
+            errors,
+            raw: call.arguments,
+        };
+        Box::pin(async move { Err(error.into()) })
+    }
+}
+
+/// Phrases a schema violation for the model.
+#[cfg(any(test, feature = "jsonschema"))]
+fn describe_violation(error: &jsonschema::ValidationError, schema: &Value) -> String {
+    use jsonschema::error::{TypeKind, ValidationErrorKind};
+
+    let mut path: Vec<String> = error
+        .instance_path
+        .as_str()
+        .split('/')
+        .skip(1)
+        .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+        .collect();
+    let field = |path: &[String]| {
+        let description = field_schema(schema, path)
+            .and_then(|field| field.get("description"))
+            .and_then(Value::as_str)
+            .map(|description| format!(" ({})", description))
+            .unwrap_or_default();
+        match path {
+            [] => format!("the arguments{}", description),
+            path => format!("`{}`{}", path.join("."), description),
+        }
+    };
+    match error.kind {
+        ValidationErrorKind::Required { ref property } => {
+            path.push(property.as_str().unwrap_or_default().to_string());
+            format!("missing required field {}", field(&path))
+        }
+        ValidationErrorKind::Type {
+            kind: TypeKind::Single(ref expected),
+        } => format!(
+            "{} must be of type {}, got {}",
+            field(&path),
+            expected,
+            error.instance
+        ),
+        ValidationErrorKind::Enum { ref options } => {
+            let options: Vec<String> = options
+                .as_array()
+                .map(|options| options.iter().map(Value::to_string).collect())
+                .unwrap_or_default();
+            format!(
+                "{} must be one of {}, got {}",
+                field(&path),
+                options.join(", "),
+                error.instance
+            )
+        }
+        ValidationErrorKind::AdditionalProperties { ref unexpected } => format!(
+            "{} has unknown fields: {}",
+            field(&path),
+            unexpected.join(", ")
+        ),
+        _ => format!("{}: {}", field(&path), error),
+    }
+}
+
+/// The schema of the field at `path`, following `$ref`s into `$defs`.
+#[cfg(any(test, feature = "jsonschema"))]
+fn field_schema<'a>(root: &'a Value, path: &[String]) -> Option<&'a Value> {
+    let resolve = |mut schema: &'a Value| {
+        while let Some(pointer) = schema.get("$ref").and_then(Value::as_str) {
+            schema = root.pointer(pointer.strip_prefix('#')?)?;
+        }
+        Some(schema)
+    };
+    let mut schema = resolve(root)?;
+    for segment in path {
+        let child = match segment.parse::<usize>() {
+            Ok(_) if schema.get("items").is_some() => schema.get("items"),
+            _ => schema
+                .get("properties")
+                .and_then(|properties| properties.get(segment)),
+        };
+        schema = resolve(child?)?;
+    }
+    Some(schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::tool_error::ToolError;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    #[derive(ToolArg, serde::Deserialize)]
+    struct EchoArgs {
+        text: String,
+    }
+
+    #[tool("Echo the text")]
+    async fn echo(args: EchoArgs) -> Result<String> {
+        Ok(args.text)
+    }
+
+    #[tool("Shout the text")]
+    async fn shout(args: EchoArgs) -> Result<String> {
+        Ok(args.text.to_uppercase())
+    }
+
+    /// Middleware appending `tag` to the `text` argument and the result.
+    fn tagging(tag: &'static str) -> impl ToolMiddleware {
+        move |mut call: ToolCall, next: Next| -> ToolFuture {
+            let text = format!("{}{}", call.arguments["text"].as_str().unwrap(), tag);
+            call.arguments["text"] = json!(text);
+            Box::pin(async move { Ok(format!("{}{}", next.run(call).await?, tag)) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware_composes_and_covers_merged_tools() {
+        let tools = tools![EchoTool]
+            .with_middleware(tagging("1"))
+            .with_middleware(tagging("2"))
+            .merge(tools![ShoutTool].with_middleware(tagging("3")))
+            .unwrap();
+
+        // The middleware added last sees the call first and the result last.
+        let echoed = tools
+            .dispatch("echo".into(), json!({ "text": "a" }))
+            .await
+            .unwrap();
+        assert_eq!(echoed, "a2112");
+        let shouted = tools
+            .dispatch("shout".into(), json!({ "text": "a" }))
+            .await
+            .unwrap();
+        assert_eq!(shouted, "A213312");
+
+        let blocked = tools![EchoTool].with_middleware(|call: ToolCall, _: Next| -> ToolFuture {
+            Box::pin(async move { Err(ToolError::permission_denied(call.name).into()) })
+        });
+        let error = blocked
+            .dispatch("echo".into(), json!({ "text": "a" }))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            ToolError::from_boxed(error).code,
+            ToolError::PERMISSION_DENIED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_logging() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        let tools = tools![EchoTool].with_middleware(Logging::with_sink(move |line| {
+            sink.lock().unwrap().push(line.to_string())
+        }));
+        tools
+            .dispatch("echo".into(), json!({ "text": "hello" }))
+            .await
+            .unwrap();
+        assert!(tools.dispatch("missing".into(), json!({})).await.is_err());
+
+        let lines = lines.lock().unwrap();
+        assert!(lines[0].starts_with(r#"tool echo({"text":"hello"}) returned 5 bytes in "#));
+        assert!(lines[1].starts_with("tool missing({}) failed in "));
+        assert!(lines[1].ends_with("Tool not found: missing"));
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    enum Unit {
+        Celsius,
+        Fahrenheit,
+    }
+
+    #[derive(ToolArg, serde::Deserialize)]
+    #[allow(dead_code)]
+    struct WeatherArgs {
+        /// The city to look up
+        location: String,
+        unit: Unit,
+        days: Option<u32>,
+    }
+
+    #[tool("Get the weather forecast")]
+    async fn forecast(args: WeatherArgs) -> Result<String> {
+        Ok(format!("sunny in {}", args.location))
+    }
+
+    async fn invalid_arguments(tools: &ToolSet, arguments: serde_json::Value) -> Vec<String> {
+        let error = tools
+            .dispatch("forecast".into(), arguments.clone())
+            .await
+            .unwrap_err();
+        match *error.downcast::<Error>().unwrap() {
+            Error::InvalidToolArguments {
+                tool_name,
+                errors,
+                raw,
+            } => {
+                assert_eq!(tool_name, "forecast");
+                assert_eq!(raw, arguments);
+                errors
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_arguments() {
+        let tools = tools![ForecastTool].with_middleware(ValidateArguments::new());
+        let answer = tools
+            .dispatch(
+                "forecast".into(),
+                json!({ "location": "Paris", "unit": "Celsius", "days": null }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(answer, "sunny in Paris");
+
+        // Missing fields.
+        assert_eq!(
+            invalid_arguments(&tools, json!({ "unit": "Celsius", "days": 3 })).await,
+            ["missing required field `location` (The city to look up)"]
+        );
+        // Wrong types.
+        assert_eq!(
+            invalid_arguments(
+                &tools,
+                json!({ "location": 75001, "unit": "Celsius", "days": 3 })
+            )
+            .await,
+            ["`location` (The city to look up) must be of type string, got 75001"]
+        );
+        // Enum mismatches.
+        assert_eq!(
+            invalid_arguments(
+                &tools,
+                json!({ "location": "Paris", "unit": "Kelvin", "days": 3 })
+            )
+            .await,
+            [r#"`unit` must be one of "Celsius", "Fahrenheit", got "Kelvin""#]
+        );
+
+        let error = tools
+            .dispatch("forecast".into(), json!({ "unit": "Celsius", "days": 3 }))
+            .await
+            .unwrap_err();
+        let error = ToolError::from_boxed(error);
+        assert_eq!(error.code, ToolError::INVALID_INPUT);
+        assert_eq!(
+            error.message,
+            "the arguments do not match the tool's schema: missing required field `location` \
+             (The city to look up)"
+        );
+    }
+}
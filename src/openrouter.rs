@@ -0,0 +1,260 @@
+//! OpenRouter attribution headers and provider routing.
+//!
+//! [OpenRouter](https://openrouter.ai) attributes requests to an app through
+//! the `HTTP-Referer` and `X-Title` headers, and routes each request to one
+//! of the providers hosting the model, following the routing preferences in
+//! the request body. [`AgentBuilder::openrouter`](crate::AgentBuilder::openrouter)
+//! configures both:
+//!
+//! ```no_run
+//! use aiform::openrouter::{OpenRouterOptions, ProviderRouting};
+//! use aiform::prelude::*;
+//!
+//! # fn example() -> Result<()> {
+//! let agent = Agent::builder()
+//!     .model("meta-llama/llama-3.1-70b-instruct:nitro")
+//!     .openrouter(
+//!         OpenRouterOptions::new()
+//!             .referer("https://example.com")
+//!             .title("Flight Finder")
+//!             .routing(
+//!                 ProviderRouting::new()
+//!                     .order(["fireworks", "together"])
+//!                     .allow_fallbacks(false),
+//!             ),
+//!     )
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Model variants such as `:nitro`, `:floor` or `:online` are part of the
+//! model name and need no option.
+
+use crate::error::{Error, Result};
+use async_openai::config::{Config, OpenAIConfig};
+use reqwest::header::{HeaderMap, HeaderValue};
+use secrecy::SecretString;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Base URL of the OpenRouter API.
+pub const OPENROUTER_API_BASE: &str = "https://openrouter.ai/api/v1";
+
+/// How OpenRouter picks the provider that serves a request.
+///
+/// Sent as the request's `provider` field.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ProviderRouting {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    order: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allow_fallbacks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    require_parameters: Option<bool>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ignore: Vec<String>,
+}
+
+impl ProviderRouting {
+    /// Routing with OpenRouter's defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tries these providers first, in order.
+    pub fn order<S: Into<String>>(mut self, providers: impl IntoIterator<Item = S>) -> Self {
+        self.order = providers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets whether other providers may serve the request when the preferred
+    /// ones are unavailable.
+    pub fn allow_fallbacks(mut self, allow: bool) -> Self {
+        self.allow_fallbacks = Some(allow);
+        self
+    }
+
+    /// Sets whether only providers supporting every request parameter may
+    /// serve the request.
+    pub fn require_parameters(mut self, require: bool) -> Self {
+        self.require_parameters = Some(require);
+        self
+    }
+
+    /// Never routes to these providers.
+    pub fn ignore<S: Into<String>>(mut self, providers: impl IntoIterator<Item = S>) -> Self {
+        self.ignore = providers.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+/// Settings for talking to OpenRouter; see the [module docs](self).
+#[derive(Debug, Clone, Default)]
+pub struct OpenRouterOptions {
+    api_key: Option<String>,
+    referer: Option<String>,
+    title: Option<String>,
+    routing: Option<ProviderRouting>,
+    fallback_models: Vec<String>,
+}
+
+impl OpenRouterOptions {
+    /// Options reading the API key from `OPENROUTER_API_KEY`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the API key instead of reading `OPENROUTER_API_KEY`.
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Sets the app URL sent as `HTTP-Referer`.
+    pub fn referer(mut self, referer: impl Into<String>) -> Self {
+        self.referer = Some(referer.into());
+        self
+    }
+
+    /// Sets the app name sent as `X-Title`.
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Sets the provider routing preferences.
+    pub fn routing(mut self, routing: ProviderRouting) -> Self {
+        self.routing = Some(routing);
+        self
+    }
+
+    /// Sets models to fall back to, in order, when the agent's model fails.
+    pub fn fallback_models<S: Into<String>>(mut self, models: impl IntoIterator<Item = S>) -> Self {
+        self.fallback_models = models.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Builds the client configuration, with the attribution headers.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfiguration`] if the referer or title
+    /// cannot be sent as a header value.
+    pub fn config(&self) -> Result<OpenRouterConfig> {
+        let api_key = self
+            .api_key
+            .clone()
+            .or_else(|| std::env::var("OPENROUTER_API_KEY").ok())
+            .unwrap_or_default();
+        let mut headers = HeaderMap::new();
+        for (name, value) in [("HTTP-Referer", &self.referer), ("X-Title", &self.title)] {
+            let Some(value) = value else {
+                continue;
+            };
+            let value = HeaderValue::from_str(value).map_err(|_| {
+                Error::InvalidConfiguration(format!(
+                    "OpenRouter {} '{}' is not a valid header value",
+                    name, value
+                ))
+            })?;
+            headers.insert(name, value);
+        }
+        Ok(OpenRouterConfig {
+            inner: OpenAIConfig::new()
+                .with_api_base(OPENROUTER_API_BASE)
+                .with_api_key(api_key),
+            headers,
+        })
+    }
+
+    /// The request body fields for the routing options.
+    pub(crate) fn body_fields(&self) -> Map<String, Value> {
+        let mut fields = Map::new();
+        if let Some(ref routing) = self.routing {
+            fields.insert("provider".into(), serde_json::to_value(routing).unwrap());
+        }
+        if !self.fallback_models.is_empty() {
+            fields.insert("models".into(), self.fallback_models.clone().into());
+        }
+        fields
+    }
+}
+
+/// An `async_openai` client configuration for OpenRouter that sends the
+/// attribution headers; built by [`OpenRouterOptions::config`].
+#[derive(Debug, Clone)]
+pub struct OpenRouterConfig {
+    inner: OpenAIConfig,
+    headers: HeaderMap,
+}
+
+impl Config for OpenRouterConfig {
+    fn headers(&self) -> HeaderMap {
+        let mut headers = self.inner.headers();
+        headers.extend(self.headers.clone());
+        headers
+    }
+
+    fn url(&self, path: &str) -> String {
+        self.inner.url(path)
+    }
+
+    fn query(&self) -> Vec<(&str, &str)> {
+        self.inner.query()
+    }
+
+    fn api_base(&self) -> &str {
+        self.inner.api_base()
+    }
+
+    fn api_key(&self) -> &SecretString {
+        self.inner.api_key()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_config_sends_attribution_headers() {
+        let config = OpenRouterOptions::new()
+            .api_key("sk-or-test")
+            .referer("https://example.com")
+            .title("Flight Finder")
+            .config()
+            .unwrap();
+        let headers = config.headers();
+        assert_eq!(headers["http-referer"], "https://example.com");
+        assert_eq!(headers["x-title"], "Flight Finder");
+        assert_eq!(headers["authorization"], "Bearer sk-or-test");
+        assert_eq!(
+            config.url("/chat/completions"),
+            "https://openrouter.ai/api/v1/chat/completions"
+        );
+
+        let invalid = OpenRouterOptions::new().title("Flight\nFinder").config();
+        assert!(matches!(invalid, Err(Error::InvalidConfiguration(_))));
+    }
+
+    #[test]
+    fn test_body_fields() {
+        assert!(OpenRouterOptions::new().body_fields().is_empty());
+        let options = OpenRouterOptions::new()
+            .routing(
+                ProviderRouting::new()
+                    .order(["anthropic", "openai"])
+                    .allow_fallbacks(false),
+            )
+            .fallback_models(["openai/gpt-4o-mini"]);
+        assert_eq!(
+            Value::Object(options.body_fields()),
+            json!({
+                "provider": { "order": ["anthropic", "openai"], "allow_fallbacks": false },
+                "models": ["openai/gpt-4o-mini"],
+            })
+        );
+    }
+}
@@ -0,0 +1,496 @@
+//! Normalization of model output into plain text.
+//!
+//! Some consumers (text-to-speech engines, legacy terminals) break on emoji,
+//! markdown, or terminal escape codes, and prompting alone does not reliably
+//! keep them out. [`to_plain_text`] removes all three while keeping the
+//! readable text. Agents apply it automatically with
+//! [`AgentBuilder::plain_text`](crate::AgentBuilder::plain_text).
+
+use serde_json::Value;
+
+/// Converts text to plain text.
+///
+/// - ANSI/VT escape sequences and control characters (other than newlines
+///   and tabs) are dropped.
+/// - Emoji are removed together with their modifiers, variation selectors
+///   and zero-width joiners, so multi-codepoint emoji never leave fragments
+///   behind. Other combining sequences are left intact.
+/// - Markdown syntax is removed and its text kept: headings, emphasis,
+///   links and images, blockquotes, bullets, tables and rules. Code fences
+///   are dropped but the code inside them is preserved verbatim.
+///
+/// ```
+/// use aiform::plain_text::to_plain_text;
+///
+/// let text = to_plain_text("## Done 🎉\n\n**All** tests [passed](https://ci)!");
+/// assert_eq!(text, "Done\n\nAll tests passed!");
+/// ```
+pub fn to_plain_text(input: &str) -> String {
+    let cleaned = strip_emoji(&strip_control(input));
+    strip_markdown(&cleaned)
+}
+
+/// Normalizes a tool result for the model.
+///
+/// JSON results keep their structure: only string values are converted, so
+/// keys and numbers survive untouched. Anything else is treated as text.
+pub(crate) fn normalize_tool_result(result: &str) -> String {
+    match serde_json::from_str::<Value>(result) {
+        Ok(mut value @ (Value::Object(_) | Value::Array(_) | Value::String(_))) => {
+            normalize_strings(&mut value);
+            value.to_string()
+        }
+        _ => to_plain_text(result),
+    }
+}
+
+fn normalize_strings(value: &mut Value) {
+    match value {
+        Value::String(s) => *s = to_plain_text(s),
+        Value::Array(items) => items.iter_mut().for_each(normalize_strings),
+        Value::Object(map) => map.values_mut().for_each(normalize_strings),
+        _ => {}
+    }
+}
+
+/// Removes ANSI escape sequences and control characters.
+fn strip_control(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{1b}' => match chars.next() {
+                // CSI: parameters and intermediates, then a final byte.
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\u{40}'..='\u{7e}').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC, DCS, APC, PM, SOS: terminated by BEL or ST (ESC \).
+                Some(']' | 'P' | '_' | '^' | 'X') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\u{7}' {
+                            break;
+                        }
+                        if c == '\u{1b}' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                // Two-character sequences (ESC c, ESC 7, ...).
+                _ => {}
+            },
+            // 8-bit CSI.
+            '\u{9b}' => {
+                for c in chars.by_ref() {
+                    if ('\u{40}'..='\u{7e}').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            '\r' => {
+                if chars.peek() != Some(&'\n') {
+                    out.push('\n');
+                }
+            }
+            '\n' | '\t' => out.push(c),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Removes emoji sequences, tidying the whitespace they leave behind.
+fn strip_emoji(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut in_sequence = false;
+    let mut removed = false;
+
+    for c in input.chars() {
+        // Presentation selectors and keycaps also follow plain characters
+        // (`1️⃣`, `©️`); the base character is kept.
+        if matches!(c, '\u{FE0E}' | '\u{FE0F}' | '\u{20E3}') {
+            continue;
+        }
+        if is_emoji(c) || (in_sequence && is_emoji_component(c)) {
+            in_sequence = true;
+            removed = true;
+            continue;
+        }
+        in_sequence = false;
+
+        if removed {
+            let at_line_start = out.is_empty() || out.ends_with('\n');
+            if c == ' ' && (at_line_start || out.ends_with(' ')) {
+                continue;
+            }
+            if matches!(c, '.' | ',' | '!' | '?' | ';' | ':' | '\n') && out.ends_with(' ') {
+                out.pop();
+            }
+            removed = c == ' ';
+        }
+        out.push(c);
+    }
+
+    if removed {
+        out.truncate(out.trim_end_matches(' ').len());
+    }
+    out
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1F0FF   // mahjong, domino and playing cards
+            | 0x1F170..=0x1F251 // enclosed alphanumerics, including flag letters
+            | 0x1F300..=0x1F6FF // pictographs, emoticons, transport
+            | 0x1F700..=0x1FAFF // alchemical, geometric ext., supplemental symbols
+            | 0x2600..=0x27BF   // misc symbols and dingbats
+            | 0x2B05..=0x2B07
+            | 0x2B1B..=0x2B1C
+            | 0x2B50
+            | 0x2B55
+            | 0x231A..=0x231B
+            | 0x23E9..=0x23F3
+            | 0x23F8..=0x23FA
+            | 0x3030
+            | 0x303D
+            | 0x3297
+            | 0x3299
+    )
+}
+
+/// Codepoints that only have meaning as part of an emoji sequence.
+fn is_emoji_component(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x200D                  // zero-width joiner
+            | 0x1F3FB..=0x1F3FF // skin tone modifiers
+            | 0xE0020..=0xE007F // tag sequences (subdivision flags)
+    )
+}
+
+/// Removes markdown syntax line by line, leaving code blocks untouched.
+fn strip_markdown(input: &str) -> String {
+    let mut lines = Vec::new();
+    let mut fence: Option<&str> = None;
+
+    for line in input.split('\n') {
+        let trimmed = line.trim_start();
+
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) && trimmed.trim_start_matches(marker).trim().is_empty() {
+                fence = None;
+            } else {
+                lines.push(line.to_string());
+            }
+            continue;
+        }
+
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+            fence = Some(marker);
+            continue;
+        }
+
+        if is_rule(trimmed) || is_table_separator(trimmed) {
+            continue;
+        }
+
+        lines.push(strip_block_markers(line));
+    }
+
+    let text = lines
+        .iter()
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n");
+    text.trim_matches('\n').to_string()
+}
+
+fn is_rule(line: &str) -> bool {
+    let compact: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    compact.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|&m| compact.chars().all(|c| c == m))
+}
+
+fn is_table_separator(line: &str) -> bool {
+    line.contains('-')
+        && line.contains('|')
+        && line
+            .chars()
+            .all(|c| matches!(c, '|' | '-' | ':' | ' ' | '\t'))
+}
+
+fn strip_block_markers(line: &str) -> String {
+    let indent_len = line.len() - line.trim_start().len();
+    let (indent, mut rest) = line.split_at(indent_len);
+
+    while let Some(stripped) = rest.strip_prefix('>') {
+        rest = stripped.trim_start();
+    }
+
+    let hashes = rest.chars().take_while(|&c| c == '#').count();
+    if (1..=6).contains(&hashes) && rest[hashes..].starts_with(' ') {
+        rest = rest[hashes..].trim();
+        rest = rest.trim_end_matches('#').trim_end();
+    }
+
+    for bullet in ["- [ ] ", "- [x] ", "- ", "* ", "+ "] {
+        if let Some(stripped) = rest.strip_prefix(bullet) {
+            rest = stripped;
+            break;
+        }
+    }
+
+    let rest = if rest.starts_with('|') && rest.trim_end().ends_with('|') {
+        rest.trim()
+            .trim_matches('|')
+            .split('|')
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join("  ")
+    } else {
+        rest.to_string()
+    };
+
+    format!("{}{}", indent, strip_inline(&rest))
+}
+
+/// Removes inline markdown: code spans, links, images and emphasis.
+fn strip_inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if i + 1 < chars.len() && chars[i + 1].is_ascii_punctuation() => {
+                out.push(chars[i + 1]);
+                i += 2;
+            }
+            '`' => {
+                let ticks = chars[i..].iter().take_while(|&&c| c == '`').count();
+                let body_start = i + ticks;
+                match find_run(&chars, body_start, '`', ticks) {
+                    Some(end) => {
+                        let body: String = chars[body_start..end].iter().collect();
+                        out.push_str(body.trim());
+                        i = end + ticks;
+                    }
+                    None => {
+                        out.extend(&chars[i..body_start]);
+                        i = body_start;
+                    }
+                }
+            }
+            '!' if chars.get(i + 1) == Some(&'[') => match parse_link(&chars, i + 1) {
+                Some((label, end)) => {
+                    out.push_str(&strip_inline(&label));
+                    i = end;
+                }
+                None => {
+                    out.push(c);
+                    i += 1;
+                }
+            },
+            '[' => match parse_link(&chars, i) {
+                Some((label, end)) => {
+                    out.push_str(&strip_inline(&label));
+                    i = end;
+                }
+                None => {
+                    out.push(c);
+                    i += 1;
+                }
+            },
+            '<' => {
+                let close = chars[i..].iter().position(|&c| c == '>').map(|p| i + p);
+                let inner: Option<String> = close.map(|end| chars[i + 1..end].iter().collect());
+                match (close, inner) {
+                    (Some(end), Some(inner)) if inner.contains("://") && !inner.contains(' ') => {
+                        out.push_str(&inner);
+                        i = end + 1;
+                    }
+                    _ => {
+                        out.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            '*' | '_' | '~' => {
+                let run = chars[i..].iter().take_while(|&&x| x == c).count();
+                let prev = i.checked_sub(1).map(|p| chars[p]);
+                let next = chars.get(i + run).copied();
+                let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric());
+                // Markers inside words (snake_case, 2*3) are kept, as is a lone `~`.
+                let intraword = is_word(prev) && is_word(next);
+                let keep =
+                    intraword || (c == '~' && run == 1) || (prev.is_none() && next.is_none());
+                if keep {
+                    out.extend(std::iter::repeat_n(c, run));
+                }
+                i += run;
+            }
+            _ => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    out
+}
+
+/// Finds the next run of exactly `len` `marker` characters at or after `from`.
+fn find_run(chars: &[char], from: usize, marker: char, len: usize) -> Option<usize> {
+    let mut i = from;
+    while i < chars.len() {
+        if chars[i] == marker {
+            let run = chars[i..].iter().take_while(|&&c| c == marker).count();
+            if run == len {
+                return Some(i);
+            }
+            i += run;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Parses `[label](target)` starting at `[`, returning the label and the
+/// index just past the closing parenthesis.
+fn parse_link(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let mut depth = 0;
+    let mut label_end = None;
+    for (offset, &c) in chars[start..].iter().enumerate() {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    label_end = Some(start + offset);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let label_end = label_end?;
+    if chars.get(label_end + 1) != Some(&'(') {
+        return None;
+    }
+    let target_end = chars[label_end + 2..].iter().position(|&c| c == ')')? + label_end + 2;
+    let label = chars[start + 1..label_end].iter().collect();
+    Some((label, target_end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(input: &str, expected: &str) {
+        assert_eq!(to_plain_text(input), expected, "input: {:?}", input);
+    }
+
+    #[test]
+    fn test_ansi_and_control_sequences() {
+        check("\u{1b}[1;31mError\u{1b}[0m: failed", "Error: failed");
+        check(
+            "\u{1b}]8;;https://x.dev\u{7}link\u{1b}]8;;\u{1b}\\ text",
+            "link text",
+        );
+        check("a\u{0}b\u{7}c\u{9b}2Jd", "abcd");
+        check(
+            "line one\r\nline two\rline three",
+            "line one\nline two\nline three",
+        );
+        check("col\tcol", "col\tcol");
+    }
+
+    #[test]
+    fn test_emoji() {
+        check("Great job 🎉!", "Great job!");
+        check("🚀 Launching now", "Launching now");
+        check("Family: 👨‍👩‍👧‍👦 done", "Family: done");
+        check("Thumbs 👍🏽 up", "Thumbs up");
+        check("Flag 🇳🇱 and ❤️ here", "Flag and here");
+        check("Press 1️⃣ to continue", "Press 1 to continue");
+        check("Scotland 🏴󠁧󠁢󠁳󠁣󠁴󠁿 flag", "Scotland flag");
+        check("Done ✅\nNext", "Done\nNext");
+    }
+
+    #[test]
+    fn test_graphemes_are_not_split() {
+        // Combining marks and joiners outside emoji sequences are untouched.
+        check("Cafe\u{301} na\u{308}ive", "Cafe\u{301} na\u{308}ive");
+        check("क्‍ष", "क्‍ष");
+        check("日本語のテキスト", "日本語のテキスト");
+        check("Ünïcödé 🎉 text", "Ünïcödé text");
+    }
+
+    #[test]
+    fn test_markdown() {
+        check("# Title\n## Sub ##", "Title\nSub");
+        check(
+            "**bold**, *italic*, __strong__, ~~gone~~",
+            "bold, italic, strong, gone",
+        );
+        check("keep snake_case and 2*3*4", "keep snake_case and 2*3*4");
+        check(
+            "See [the docs](https://docs.rs) or <https://x.dev>",
+            "See the docs or https://x.dev",
+        );
+        check("![diagram](img.png) shows it", "diagram shows it");
+        check("> quoted\n> > nested", "quoted\nnested");
+        check(
+            "- one\n* two\n+ three\n- [x] done\n1. first",
+            "one\ntwo\nthree\ndone\n1. first",
+        );
+        check("above\n\n---\n\nbelow", "above\n\n\nbelow");
+        check("Use `cargo test` now", "Use cargo test now");
+        check("Escaped \\*stars\\*", "Escaped *stars*");
+        check("| a | b |\n|---|:-:|\n| 1 | 2 |", "a  b\n1  2");
+    }
+
+    #[test]
+    fn test_code_blocks_keep_content() {
+        check(
+            "Run this:\n```rust\nfn main() {\n    println!(\"**hi**\");\n}\n```\nDone.",
+            "Run this:\nfn main() {\n    println!(\"**hi**\");\n}\nDone.",
+        );
+        check("~~~\n# not a heading\n~~~", "# not a heading");
+        check(
+            "```\nunterminated\n- still code",
+            "unterminated\n- still code",
+        );
+    }
+
+    #[test]
+    fn test_plain_text_is_unchanged() {
+        let text = "Nothing to change here. Numbers: 1, 2, 3.\nSecond line.";
+        check(text, text);
+        check(&to_plain_text("**x** 🎉"), "x");
+    }
+
+    #[test]
+    fn test_json_tool_results_keep_structure() {
+        let result = r#"{"_id":"a_1","title":"**New** 🔥","tags":["\u001b[32mok\u001b[0m"],"n":2}"#;
+        let normalized: Value = serde_json::from_str(&normalize_tool_result(result)).unwrap();
+        assert_eq!(
+            normalized,
+            serde_json::json!({ "_id": "a_1", "title": "New", "tags": ["ok"], "n": 2 })
+        );
+        assert_eq!(normalize_tool_result("**done** ✅"), "done");
+        assert_eq!(normalize_tool_result("42"), "42");
+    }
+}
@@ -0,0 +1,192 @@
+//! Named bundles of reliability settings.
+//!
+//! A [`Profile`] sets the agent's iteration budget, request timeout, request
+//! and tool retries, and warning verbosity in one go. Apply one with
+//! [`AgentBuilder::profile`](crate::AgentBuilder::profile); settings made
+//! explicitly on the builder take precedence over the profile, regardless of
+//! the order they are applied in.
+//!
+//! ```no_run
+//! use aiform::prelude::*;
+//! use aiform::profile::Profile;
+//!
+//! # fn example() -> Result<()> {
+//! let agent = Agent::builder()
+//!     .model("gpt-4o")
+//!     .profile(Profile::interactive())
+//!     .max_iterations(8)
+//!     .build()?;
+//! assert_eq!(agent.effective_policy().max_iterations, 8);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Profiles serialize as objects, and deserialize either from an object or
+//! from the name of a built-in profile, e.g. `"batch"`.
+
+use crate::retry::RetryPolicy;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::time::Duration;
+
+/// A named bundle of reliability settings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub struct Profile {
+    /// The profile's name, reported by [`EffectivePolicy::profile`].
+    pub name: String,
+    /// Maximum agent loop iterations per run.
+    pub max_iterations: usize,
+    /// Time limit for each completion request.
+    #[serde(default, with = "crate::duration::option")]
+    pub request_timeout: Option<Duration>,
+    /// Retries for completion requests that fail with a retryable error.
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+    /// Automatic retries for retryable tool errors.
+    #[serde(default)]
+    pub tool_retries: usize,
+    /// Print warnings to stderr unless a warning handler is set.
+    #[serde(default)]
+    pub verbose_warnings: bool,
+}
+
+impl Profile {
+    /// For interactive traffic: tight deadlines, one retry and a small
+    /// iteration budget.
+    pub fn interactive() -> Self {
+        Self {
+            name: "interactive".to_string(),
+            max_iterations: 5,
+            request_timeout: Some(Duration::from_secs(20)),
+            retry: Some(RetryPolicy {
+                max_retries: 1,
+                initial_backoff: Duration::from_millis(250),
+                max_backoff: Duration::from_secs(2),
+                jitter: true,
+            }),
+            tool_retries: 1,
+            verbose_warnings: false,
+        }
+    }
+
+    /// For background jobs: generous timeouts, more retries and a larger
+    /// iteration budget.
+    pub fn batch() -> Self {
+        Self {
+            name: "batch".to_string(),
+            max_iterations: 20,
+            request_timeout: Some(Duration::from_secs(120)),
+            retry: Some(RetryPolicy {
+                max_retries: 5,
+                initial_backoff: Duration::from_secs(1),
+                max_backoff: Duration::from_secs(60),
+                jitter: true,
+            }),
+            tool_retries: 3,
+            verbose_warnings: false,
+        }
+    }
+
+    /// For development: no retries, so failures surface immediately, and
+    /// warnings printed to stderr.
+    pub fn dev() -> Self {
+        Self {
+            name: "dev".to_string(),
+            max_iterations: 10,
+            request_timeout: None,
+            retry: None,
+            tool_retries: 0,
+            verbose_warnings: true,
+        }
+    }
+
+    /// Looks up a built-in profile by name.
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "interactive" => Some(Self::interactive()),
+            "batch" => Some(Self::batch()),
+            "dev" => Some(Self::dev()),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for Profile {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Profile::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Profile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Name(String),
+            #[serde(with = "Profile")]
+            Full(Profile),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Name(name) => Profile::named(&name).ok_or_else(|| {
+                serde::de::Error::custom(format!(
+                    "unknown profile '{}', expected 'interactive', 'batch' or 'dev'",
+                    name
+                ))
+            }),
+            Repr::Full(profile) => Ok(profile),
+        }
+    }
+}
+
+/// The reliability settings an agent ended up with, after applying its
+/// profile and explicit settings. Returned by
+/// [`Agent::effective_policy`](crate::Agent::effective_policy).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectivePolicy {
+    /// The name of the applied profile, if any.
+    pub profile: Option<String>,
+    /// Maximum agent loop iterations per run.
+    pub max_iterations: usize,
+    /// Time limit for each completion request.
+    pub request_timeout: Option<Duration>,
+    /// Time limit for each tool call.
+    pub tool_timeout: Option<Duration>,
+    /// Retries for completion requests that fail with a retryable error.
+    pub retry: Option<RetryPolicy>,
+    /// Automatic retries for retryable tool errors.
+    pub tool_retries: usize,
+    /// Whether warnings are printed to stderr.
+    pub verbose_warnings: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_profiles_round_trip() {
+        for profile in [Profile::interactive(), Profile::batch(), Profile::dev()] {
+            let value = serde_json::to_value(&profile).unwrap();
+            assert_eq!(serde_json::from_value::<Profile>(value).unwrap(), profile);
+        }
+        let value = serde_json::to_value(Profile::batch()).unwrap();
+        assert_eq!(value["request_timeout"], "2m");
+    }
+
+    #[test]
+    fn test_profiles_by_name() {
+        let profile: Profile = serde_json::from_value(json!("interactive")).unwrap();
+        assert_eq!(profile, Profile::interactive());
+
+        let error = serde_json::from_value::<Profile>(json!("turbo")).unwrap_err();
+        assert!(error.to_string().contains("unknown profile 'turbo'"));
+
+        let profile: Profile =
+            serde_json::from_value(json!({ "name": "custom", "max_iterations": 3 })).unwrap();
+        assert_eq!(profile.request_timeout, None);
+        assert_eq!(profile.retry, None);
+        assert_eq!(profile.tool_retries, 0);
+    }
+}
@@ -0,0 +1,221 @@
+//! Which request parameters an OpenAI-compatible server accepts.
+//!
+//! Servers differ in the parameters they reject: some answer 400 to
+//! `parallel_tool_calls`, others to `response_format`. An agent's
+//! [`Provider`] declares the parameters the server supports; configured
+//! parameters it does not support are dropped from outgoing requests with a
+//! [`Warning::ParameterDropped`](crate::Warning::ParameterDropped) instead of
+//! failing the run.
+//!
+//! ```no_run
+//! use aiform::prelude::*;
+//! use aiform::provider::{Capabilities, Provider};
+//!
+//! # fn example() -> Result<()> {
+//! let agent = Agent::builder()
+//!     .model("llama3.1")
+//!     .provider(Provider::Custom(Capabilities::all().without("response_format")))
+//!     .strip_rejected_parameters(true)
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! With [`AgentBuilder::strip_rejected_parameters`](crate::AgentBuilder::strip_rejected_parameters),
+//! a request rejected with an error naming one of its parameters is sent once
+//! more without it, and the parameter is dropped from later requests for the
+//! same model for the rest of the process.
+
+use crate::error::Error;
+use async_openai::{error::OpenAIError, types::CreateChatCompletionRequest};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+
+/// Request fields that are never dropped, since no request works without
+/// them.
+const REQUIRED: &[&str] = &["model", "messages", "stream"];
+
+/// The request parameters a server does not accept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    unsupported: BTreeSet<String>,
+}
+
+impl Capabilities {
+    /// Capabilities of a server that accepts every parameter.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Marks a request parameter, e.g. `"response_format"`, as unsupported.
+    pub fn without(mut self, parameter: impl Into<String>) -> Self {
+        self.unsupported.insert(parameter.into());
+        self
+    }
+
+    /// Returns whether the server accepts the request parameter.
+    pub fn supports(&self, parameter: &str) -> bool {
+        !self.unsupported.contains(parameter)
+    }
+}
+
+/// The kind of server an agent talks to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Provider {
+    /// The OpenAI API, which accepts every parameter.
+    #[default]
+    OpenAI,
+    /// OpenRouter, which accepts every parameter and ignores the ones the
+    /// routed model does not support.
+    OpenRouter,
+    /// A self-hosted OpenAI-compatible server such as vLLM, llama.cpp or
+    /// Ollama. Drops the parameters such servers commonly reject.
+    Compatible,
+    /// A server with explicitly declared capabilities.
+    Custom(Capabilities),
+}
+
+impl Provider {
+    /// Returns the parameters the provider supports.
+    pub fn capabilities(&self) -> Capabilities {
+        match self {
+            Provider::OpenAI | Provider::OpenRouter => Capabilities::all(),
+            Provider::Compatible => Capabilities::all()
+                .without("parallel_tool_calls")
+                .without("logit_bias")
+                .without("logprobs")
+                .without("top_logprobs")
+                .without("service_tier"),
+            Provider::Custom(capabilities) => capabilities.clone(),
+        }
+    }
+}
+
+/// Parameters learned to be rejected, by model, for the process lifetime.
+fn learned() -> &'static Mutex<HashMap<String, HashSet<String>>> {
+    static LEARNED: OnceLock<Mutex<HashMap<String, HashSet<String>>>> = OnceLock::new();
+    LEARNED.get_or_init(Default::default)
+}
+
+/// Records that `model` rejects `parameter`.
+pub(crate) fn learn_rejected(model: &str, parameter: &str) {
+    learned()
+        .lock()
+        .unwrap()
+        .entry(model.to_string())
+        .or_default()
+        .insert(parameter.to_string());
+}
+
+/// Removes set parameters that the capabilities exclude or that the model
+/// was learned to reject, returning the names of the removed parameters.
+pub(crate) fn strip_unsupported(
+    request: &mut CreateChatCompletionRequest,
+    capabilities: &Capabilities,
+) -> crate::Result<Vec<String>> {
+    let learned = learned()
+        .lock()
+        .unwrap()
+        .get(&request.model)
+        .cloned()
+        .unwrap_or_default();
+    let mut value = serde_json::to_value(&*request)?;
+    let Some(fields) = value.as_object_mut() else {
+        return Ok(Vec::new());
+    };
+    let dropped: Vec<String> = fields
+        .iter()
+        .filter(|(name, value)| !value.is_null() && !REQUIRED.contains(&name.as_str()))
+        .map(|(name, _)| name.clone())
+        .filter(|name| !capabilities.supports(name) || learned.contains(name))
+        .collect();
+    if dropped.is_empty() {
+        return Ok(dropped);
+    }
+    for name in &dropped {
+        fields.remove(name);
+    }
+    *request = serde_json::from_value(value)?;
+    Ok(dropped)
+}
+
+/// Returns the optional request parameter a rejected request's error names,
+/// if any.
+pub(crate) fn rejected_parameter(
+    error: &Error,
+    request: &CreateChatCompletionRequest,
+) -> Option<String> {
+    let Error::OpenAI(OpenAIError::ApiError(api)) = error else {
+        return None;
+    };
+    if error.is_retryable() {
+        return None;
+    }
+    let value = serde_json::to_value(request).ok()?;
+    let present = value
+        .as_object()?
+        .iter()
+        .filter(|(name, value)| !value.is_null() && !REQUIRED.contains(&name.as_str()))
+        .map(|(name, _)| name.as_str());
+    let param = api.param.as_deref();
+    let message = api.message.to_lowercase();
+    present
+        .filter(|name| param == Some(name) || message.contains(name))
+        .max_by_key(|name| name.len())
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn request(extra: Value) -> CreateChatCompletionRequest {
+        let mut value = json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "Hi" }],
+        });
+        value
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_strip_unsupported() {
+        let mut request = request(json!({ "temperature": 0.5, "logprobs": true }));
+        let dropped =
+            strip_unsupported(&mut request, &Provider::Compatible.capabilities()).unwrap();
+        assert_eq!(dropped, vec!["logprobs"]);
+        assert_eq!(request.logprobs, None);
+        assert_eq!(request.temperature, Some(0.5));
+
+        let dropped = strip_unsupported(&mut request, &Provider::OpenAI.capabilities()).unwrap();
+        assert!(dropped.is_empty());
+    }
+
+    #[test]
+    fn test_rejected_parameter() {
+        let request = request(json!({ "top_p": 0.5, "temperature": 0.5 }));
+        let error = |message: &str, code: &str| {
+            Error::OpenAI(OpenAIError::ApiError(
+                serde_json::from_value(json!({ "message": message, "code": code })).unwrap(),
+            ))
+        };
+
+        let rejected = error("Unsupported parameter: 'temperature'", "400");
+        assert_eq!(
+            rejected_parameter(&rejected, &request),
+            Some("temperature".into())
+        );
+        // Parameters the request does not set are not blamed.
+        let unrelated = error("'response_format' is not supported", "400");
+        assert_eq!(rejected_parameter(&unrelated, &request), None);
+        let transient = error("temperature service overloaded", "503");
+        assert_eq!(rejected_parameter(&transient, &request), None);
+        let messages = error("messages must not be empty", "400");
+        assert_eq!(rejected_parameter(&messages, &request), None);
+    }
+}
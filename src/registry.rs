@@ -0,0 +1,226 @@
+//! Named agents, for looking them up and delegating between them.
+//!
+//! An [`AgentRegistry`] holds agents by name. Agents can be registered at
+//! any time, also while other tasks look agents up, and
+//! [`as_tools`](AgentRegistry::as_tools) offers every registered agent to a
+//! supervisor as an [`AgentTool`] named after it:
+//!
+//! ```no_run
+//! use aiform::prelude::*;
+//! use aiform::registry::AgentRegistry;
+//! use std::sync::Arc;
+//!
+//! # async fn example(researcher: Agent, analyst: Agent) -> Result<()> {
+//! let registry = AgentRegistry::new();
+//! registry.register("researcher", Arc::new(researcher))?;
+//! registry.register("analyst", Arc::new(analyst))?;
+//!
+//! let supervisor = Agent::builder()
+//!     .model("gpt-4")
+//!     .system_prompt("Delegate research to the researcher and analysis to the analyst.")
+//!     .tools(registry.as_tools())
+//!     .build()?;
+//! let answer = supervisor.run("How fast is Rust adoption growing?").await?;
+//!
+//! // Or call an agent directly.
+//! let analysis = registry.get("analyst")?.run(answer).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    agent::participant_name,
+    error::{Error, Result},
+    Agent, AgentTool, ToolSet,
+};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+/// Agents by name; see the [module docs](self).
+///
+/// Registering and looking up take `&self`, so a registry shared through an
+/// `Arc` can gain agents while they are in use.
+#[derive(Default)]
+pub struct AgentRegistry {
+    agents: RwLock<BTreeMap<String, Arc<Agent>>>,
+}
+
+impl AgentRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers an agent under `name`.
+    ///
+    /// The name is also the name of the agent's tool in
+    /// [`as_tools`](Self::as_tools), so it may only contain ASCII letters,
+    /// digits, `_` and `-`, and be at most 64 characters long.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfiguration`] if the name is not a valid
+    /// tool name or an agent is already registered under it.
+    pub fn register(&self, name: impl Into<String>, agent: Arc<Agent>) -> Result<()> {
+        let name = name.into();
+        if name.is_empty() || participant_name(&name) != name {
+            return Err(Error::InvalidConfiguration(format!(
+                "Agent name '{}' is not a valid tool name; use 1 to 64 ASCII letters, \
+                 digits, '_' or '-'",
+                name
+            )));
+        }
+        let mut agents = self.agents.write().unwrap();
+        if agents.contains_key(&name) {
+            return Err(Error::InvalidConfiguration(format!(
+                "An agent named '{}' is already registered",
+                name
+            )));
+        }
+        agents.insert(name, agent);
+        Ok(())
+    }
+
+    /// Returns the agent registered under `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AgentNotFound`] if no agent is registered under it.
+    pub fn get(&self, name: &str) -> Result<Arc<Agent>> {
+        self.agents
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::AgentNotFound(name.to_string()))
+    }
+
+    /// Returns the names of the registered agents, in alphabetical order.
+    pub fn list(&self) -> Vec<String> {
+        self.agents.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Returns a tool set with one [`AgentTool`] per registered agent, named
+    /// after it.
+    ///
+    /// The tool set holds the agents registered when it is created; agents
+    /// registered later are offered by the next call.
+    pub fn as_tools(&self) -> ToolSet {
+        let agents = self.agents.read().unwrap();
+        agents
+            .iter()
+            .fold(ToolSet::builder(), |tools, (name, agent)| {
+                let description =
+                    format!("Send a message to the {} agent and get its answer", name);
+                tools.register(Box::new(AgentTool::new(
+                    name.clone(),
+                    description,
+                    agent.clone(),
+                )))
+            })
+            .build()
+            .expect("registered agent names are unique")
+    }
+}
+
+impl std::fmt::Debug for AgentRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AgentRegistry")
+            .field("agents", &self.list())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockChatBackend;
+    use serde_json::json;
+
+    fn agent(backend: Arc<MockChatBackend>) -> Arc<Agent> {
+        Arc::new(
+            Agent::builder()
+                .model("mock-model")
+                .backend(backend)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_register_and_get() {
+        let registry = AgentRegistry::new();
+        let analyst = agent(Arc::new(MockChatBackend::new()));
+        registry.register("analyst", analyst.clone()).unwrap();
+        registry
+            .register("researcher", agent(Arc::new(MockChatBackend::new())))
+            .unwrap();
+
+        assert!(Arc::ptr_eq(&registry.get("analyst").unwrap(), &analyst));
+        assert!(matches!(
+            registry.get("lawyer"),
+            Err(Error::AgentNotFound(ref name)) if name == "lawyer"
+        ));
+        assert_eq!(registry.list(), ["analyst", "researcher"]);
+
+        for name in ["analyst", "travel desk", ""] {
+            assert!(matches!(
+                registry.register(name, analyst.clone()),
+                Err(Error::InvalidConfiguration(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agents_register_while_shared() {
+        let registry = Arc::new(AgentRegistry::new());
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                let registry = registry.clone();
+                tokio::spawn(async move {
+                    let backend = Arc::new(MockChatBackend::new());
+                    registry
+                        .register(format!("agent_{}", i), agent(backend))
+                        .unwrap();
+                    registry.get(&format!("agent_{}", i)).unwrap();
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(registry.list().len(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_delegates_through_registry_tools() {
+        let registry = AgentRegistry::new();
+        let analyst_backend =
+            Arc::new(MockChatBackend::new().respond_with_text("The trend is positive."));
+        registry
+            .register("analyst", agent(analyst_backend.clone()))
+            .unwrap();
+        registry
+            .register("researcher", agent(Arc::new(MockChatBackend::new())))
+            .unwrap();
+
+        let tools = registry.as_tools();
+        assert_eq!(tools.tool_names(), ["analyst", "researcher"]);
+
+        let supervisor_backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_tool_call("analyst", json!({ "message": "Adoption grew 23%" }))
+                .respond_with_text("The analyst sees a positive trend."),
+        );
+        let supervisor = Agent::builder()
+            .model("mock-model")
+            .backend(supervisor_backend)
+            .tools(tools)
+            .build()
+            .unwrap();
+        let answer = supervisor.run("How is Rust adoption going?").await.unwrap();
+        assert_eq!(answer, "The analyst sees a positive trend.");
+        let asked = serde_json::to_value(&analyst_backend.requests()[0].messages).unwrap();
+        assert_eq!(asked[0]["content"], "Adoption grew 23%");
+    }
+}
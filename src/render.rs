@@ -0,0 +1,284 @@
+//! Deterministic rendering of tool results into user-visible text.
+//!
+//! When the numbers in an answer must be exactly what a tool returned, let the
+//! crate render the answer instead of the model. Register a [`Template`] for a
+//! tool with [`AgentBuilder::terminal_tool`](crate::AgentBuilder::terminal_tool)
+//! and call [`Agent::run_rendered`](crate::Agent::run_rendered): as soon as the
+//! model calls that tool, its JSON output is substituted into the template and
+//! returned as the answer.
+//!
+//! # Syntax
+//!
+//! - `{field}` and `{field.nested.path}` insert values from the tool output.
+//!   Numeric segments index into arrays: `{flights.0.price}`.
+//! - `{{` and `}}` produce literal braces.
+//!
+//! ```
+//! use aiform::render::Template;
+//! use serde_json::json;
+//!
+//! let template = Template::parse("Found {count} flights, cheapest is {cheapest.price}").unwrap();
+//! let text = template
+//!     .render(&json!({ "count": 3, "cheapest": { "price": 129.5 } }))
+//!     .unwrap();
+//! assert_eq!(text, "Found 3 flights, cheapest is 129.5");
+//! ```
+
+use crate::error::{Error, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Output of a tool, either free text or structured JSON.
+///
+/// Tools may return this instead of a `String`. JSON output is serialized
+/// before it is handed to the model, and can be rendered with a [`Template`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolOutput {
+    /// Plain text passed to the model as-is.
+    Text(String),
+    /// Structured output, serialized to JSON for the model.
+    Json(Value),
+}
+
+impl ToolOutput {
+    /// Creates JSON output from any serializable value.
+    pub fn json(value: impl Serialize) -> Result<Self> {
+        Ok(ToolOutput::Json(serde_json::to_value(value)?))
+    }
+}
+
+impl From<ToolOutput> for String {
+    fn from(output: ToolOutput) -> Self {
+        match output {
+            ToolOutput::Text(text) => text,
+            ToolOutput::Json(value) => value.to_string(),
+        }
+    }
+}
+
+/// What to do when a template references a field the tool output lacks.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum MissingField {
+    /// Fail rendering with [`Error::Render`].
+    #[default]
+    Error,
+    /// Substitute an empty string.
+    Empty,
+    /// Leave the `{path}` placeholder in the output.
+    Keep,
+    /// Substitute the given text.
+    Placeholder(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Field(Vec<String>),
+}
+
+/// A parsed `{field.path}` template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+    missing: MissingField,
+}
+
+impl Template {
+    /// Parses a template.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfiguration`] for unbalanced braces or empty
+    /// field paths.
+    pub fn parse(source: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            Error::InvalidConfiguration(format!("Invalid template {:?}: {}", source, reason))
+        };
+
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut path = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('{') | None => return Err(invalid("unclosed '{'")),
+                            Some(c) => path.push(c),
+                        }
+                    }
+                    let path: Vec<String> = path
+                        .trim()
+                        .split('.')
+                        .map(|s| s.trim().to_string())
+                        .collect();
+                    if path.iter().any(|segment| segment.is_empty()) {
+                        return Err(invalid("empty field path"));
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Field(path));
+                }
+                '}' => return Err(invalid("unmatched '}'; use '}}' for a literal brace")),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self {
+            segments,
+            missing: MissingField::default(),
+        })
+    }
+
+    /// Sets the policy for fields missing from the rendered value.
+    pub fn missing_fields(mut self, policy: MissingField) -> Self {
+        self.missing = policy;
+        self
+    }
+
+    /// Renders the template against a JSON value.
+    ///
+    /// Strings are inserted verbatim, `null` renders as an empty string, and
+    /// objects and arrays render as compact JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Render`] when a field is missing and the policy is
+    /// [`MissingField::Error`].
+    pub fn render(&self, value: &Value) -> Result<String> {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => out.push_str(text),
+                Segment::Field(path) => match lookup(value, path) {
+                    Some(Value::String(s)) => out.push_str(s),
+                    Some(Value::Null) => {}
+                    Some(other) => out.push_str(&other.to_string()),
+                    None => match &self.missing {
+                        MissingField::Error => {
+                            return Err(Error::Render(format!(
+                                "field '{}' is missing from the tool output",
+                                path.join(".")
+                            )))
+                        }
+                        MissingField::Empty => {}
+                        MissingField::Keep => {
+                            out.push('{');
+                            out.push_str(&path.join("."));
+                            out.push('}');
+                        }
+                        MissingField::Placeholder(text) => out.push_str(text),
+                    },
+                },
+            }
+        }
+        Ok(out)
+    }
+
+    /// Renders the template against a tool result string.
+    ///
+    /// The result must be JSON, as produced by [`ToolOutput::Json`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Render`] if the result is not JSON or rendering fails.
+    pub fn render_str(&self, tool_result: &str) -> Result<String> {
+        let value: Value = serde_json::from_str(tool_result).map_err(|e| {
+            Error::Render(format!("tool output is not JSON ({}): {}", e, tool_result))
+        })?;
+        self.render(&value)
+    }
+}
+
+fn lookup<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter()
+        .try_fold(value, |current, segment| match current {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_nested_paths() {
+        let template =
+            Template::parse("{route.from} -> {route.to}: {flights.1.price} ({count})").unwrap();
+        let value = json!({
+            "count": 2,
+            "route": { "from": "AMS", "to": "LIS" },
+            "flights": [{ "price": 99 }, { "price": 129.5 }]
+        });
+        assert_eq!(template.render(&value).unwrap(), "AMS -> LIS: 129.5 (2)");
+    }
+
+    #[test]
+    fn test_value_formatting() {
+        let template = Template::parse("{s}|{n}|{b}|{null}|{list}").unwrap();
+        let value = json!({ "s": "text", "n": 1.5, "b": true, "null": null, "list": [1, 2] });
+        assert_eq!(template.render(&value).unwrap(), "text|1.5|true||[1,2]");
+    }
+
+    #[test]
+    fn test_missing_fields() {
+        let value = json!({ "count": 3 });
+        let template = Template::parse("{count} flights from {origin.code}").unwrap();
+
+        let err = template.render(&value).unwrap_err();
+        assert!(err.to_string().contains("origin.code"));
+
+        let empty = template.clone().missing_fields(MissingField::Empty);
+        assert_eq!(empty.render(&value).unwrap(), "3 flights from ");
+
+        let keep = template.clone().missing_fields(MissingField::Keep);
+        assert_eq!(keep.render(&value).unwrap(), "3 flights from {origin.code}");
+
+        let placeholder = template.missing_fields(MissingField::Placeholder("n/a".into()));
+        assert_eq!(placeholder.render(&value).unwrap(), "3 flights from n/a");
+    }
+
+    #[test]
+    fn test_escaping() {
+        let template = Template::parse("{{literal}} {value} }}").unwrap();
+        assert_eq!(
+            template.render(&json!({ "value": "{x}" })).unwrap(),
+            "{literal} {x} }"
+        );
+
+        assert!(Template::parse("{unclosed").is_err());
+        assert!(Template::parse("stray }").is_err());
+        assert!(Template::parse("{a..b}").is_err());
+        assert!(Template::parse("{}").is_err());
+    }
+
+    #[test]
+    fn test_tool_output() {
+        let output = ToolOutput::json(json!({ "count": 3 })).unwrap();
+        assert_eq!(String::from(output), r#"{"count":3}"#);
+        assert_eq!(String::from(ToolOutput::Text("hi".into())), "hi");
+
+        let template = Template::parse("{count}").unwrap();
+        assert_eq!(template.render_str(r#"{"count":3}"#).unwrap(), "3");
+        assert!(template.render_str("not json").is_err());
+    }
+}
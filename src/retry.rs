@@ -0,0 +1,121 @@
+//! Retrying transient API failures with exponential backoff.
+//!
+//! With a [`RetryPolicy`] set via
+//! [`AgentBuilder::retry`](crate::AgentBuilder::retry), completion requests
+//! that fail with a retryable error (see [`Error::is_retryable`]) are sent
+//! again after a growing delay. Tool failures and rejected requests are never
+//! retried, and the conversation built up so far is kept across attempts.
+//!
+//! ```no_run
+//! use aiform::prelude::*;
+//! use aiform::retry::RetryPolicy;
+//! use std::time::Duration;
+//!
+//! # fn example() -> Result<()> {
+//! let agent = Agent::builder()
+//!     .model("gpt-4o")
+//!     .retry(RetryPolicy {
+//!         max_retries: 3,
+//!         initial_backoff: Duration::from_millis(500),
+//!         max_backoff: Duration::from_secs(10),
+//!         jitter: true,
+//!     })
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Error::is_retryable`]: crate::Error::is_retryable
+
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// How often and how patiently to retry failed completion requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; zero disables retrying.
+    pub max_retries: usize,
+    /// Delay before the first retry. Each further retry doubles it.
+    #[serde(with = "crate::duration")]
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between attempts.
+    #[serde(with = "crate::duration")]
+    pub max_backoff: Duration,
+    /// Randomize each delay between half and all of its value, so clients
+    /// that failed together do not retry in lockstep.
+    #[serde(default)]
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Returns the delay before retry number `retry`, starting at 1.
+    pub fn backoff(&self, retry: usize) -> Duration {
+        let exponent = retry.saturating_sub(1).min(31) as u32;
+        let delay = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        if !self.jitter {
+            return delay;
+        }
+        let half = delay / 2;
+        let random = RandomState::new().build_hasher().finish();
+        half + half.mul_f64(random as f64 / u64::MAX as f64)
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Three retries starting at half a second, capped at 30 seconds, with
+    /// jitter.
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            jitter: false,
+        };
+        let delays: Vec<_> = (1..=5).map(|retry| policy.backoff(retry)).collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500].map(Duration::from_millis));
+        assert_eq!(policy.backoff(200), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_jitter_stays_within_half_and_full_delay() {
+        let policy = RetryPolicy {
+            jitter: true,
+            ..RetryPolicy::default()
+        };
+        for _ in 0..100 {
+            let delay = policy.backoff(2);
+            assert!(delay >= Duration::from_millis(500));
+            assert!(delay <= Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn test_serializes_durations_as_text() {
+        let value = serde_json::to_value(RetryPolicy::default()).unwrap();
+        assert_eq!(value["initial_backoff"], "500ms");
+        assert_eq!(
+            serde_json::from_value::<RetryPolicy>(value).unwrap(),
+            RetryPolicy::default()
+        );
+    }
+}
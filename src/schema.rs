@@ -0,0 +1,231 @@
+//! Schema adjustments shared by the derives and the structured output path.
+
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+
+/// Makes a schema also accept `null`, as the schema of an `Option` field.
+///
+/// A single `"type"` becomes a `[type, "null"]` union, and `null` is added to
+/// an `"enum"`. A schema without a single type, such as an enum's `oneOf`, is
+/// wrapped in an `anyOf` with `{"type": "null"}`, keeping its description on
+/// the outside. Schemas that already accept `null` are returned unchanged.
+pub fn nullable(schema: Value) -> Value {
+    let Value::Object(mut schema) = schema else {
+        return schema;
+    };
+    if is_nullable(&schema) {
+        return Value::Object(schema);
+    }
+    match schema.get("type").cloned() {
+        Some(Value::String(ty)) => {
+            schema.insert("type".into(), json!([ty, "null"]));
+            if let Some(Value::Array(values)) = schema.get_mut("enum") {
+                values.push(Value::Null);
+            }
+            Value::Object(schema)
+        }
+        Some(Value::Array(mut types)) => {
+            types.push(json!("null"));
+            schema.insert("type".into(), Value::Array(types));
+            Value::Object(schema)
+        }
+        _ => {
+            let description = schema.remove("description");
+            let mut wrapped = json!({ "anyOf": [schema, { "type": "null" }] });
+            if let Some(description) = description {
+                wrapped["description"] = description;
+            }
+            wrapped
+        }
+    }
+}
+
+/// Returns whether a schema already accepts `null`.
+fn is_nullable(schema: &serde_json::Map<String, Value>) -> bool {
+    let null = json!("null");
+    match schema.get("type") {
+        Some(Value::String(ty)) => ty == "null",
+        Some(Value::Array(types)) => types.contains(&null),
+        _ => schema
+            .get("anyOf")
+            .and_then(Value::as_array)
+            .is_some_and(|variants| variants.iter().any(|v| v["type"] == null)),
+    }
+}
+
+/// Builds a top-level schema, attaching the definitions its types collected
+/// as `$defs`.
+///
+/// A recursive type's schema is a `$ref` to its definition; at the top level
+/// the definition itself is used, so the schema stays an object.
+pub fn with_defs(build: impl FnOnce(&mut Map<String, Value>) -> Value) -> Value {
+    let mut defs = Map::new();
+    let mut schema = build(&mut defs);
+    if let Some(definition) = reference(&schema).and_then(|name| defs.get(name)) {
+        schema = definition.clone();
+    }
+    if let (false, Value::Object(schema)) = (defs.is_empty(), &mut schema) {
+        schema.insert("$defs".into(), Value::Object(defs));
+    }
+    schema
+}
+
+/// Defines a recursive type in `defs` under `name`, unless it already is,
+/// and returns a `$ref` to the definition.
+pub fn define(
+    defs: &mut Map<String, Value>,
+    name: &str,
+    build: impl FnOnce(&mut Map<String, Value>) -> Value,
+) -> Value {
+    if !defs.contains_key(name) {
+        // Reserved first, so types referring back to this one stop here.
+        defs.insert(name.into(), Value::Null);
+        let definition = build(defs);
+        defs.insert(name.into(), definition);
+    }
+    json!({ "$ref": format!("#/$defs/{}", name) })
+}
+
+/// Moves a schema's `$defs` into `defs`, so a nested schema's references
+/// resolve against the top-level definitions.
+pub fn hoist_defs(schema: Value, defs: &mut Map<String, Value>) -> Value {
+    let Value::Object(mut schema) = schema else {
+        return schema;
+    };
+    if let Some(Value::Object(nested)) = schema.remove("$defs") {
+        for (name, definition) in nested {
+            defs.entry(name).or_insert(definition);
+        }
+    }
+    Value::Object(schema)
+}
+
+/// Returns the definition name a schema consisting only of a `$ref` points to.
+fn reference(schema: &Value) -> Option<&str> {
+    let schema = schema.as_object().filter(|schema| schema.len() == 1)?;
+    schema.get("$ref")?.as_str()?.strip_prefix("#/$defs/")
+}
+
+/// Adapts a generated schema to the subset strict structured outputs
+/// accept: objects list every property as required, optional ones become
+/// nullable, extra properties are rejected, and `oneOf` becomes `anyOf`.
+///
+/// Applying it to a schema it already adapted changes nothing.
+pub fn strict(schema: Value) -> Value {
+    let Value::Object(mut schema) = schema else {
+        return schema;
+    };
+    if let Some(one_of) = schema.remove("oneOf") {
+        schema.insert("anyOf".into(), one_of);
+    }
+    if let Some(Value::Array(variants)) = schema.remove("anyOf") {
+        let variants = variants.into_iter().map(strict).collect();
+        schema.insert("anyOf".into(), Value::Array(variants));
+    }
+    if let Some(items) = schema.remove("items") {
+        schema.insert("items".into(), strict(items));
+    }
+    if let Some(Value::Object(defs)) = schema.remove("$defs") {
+        let defs = defs
+            .into_iter()
+            .map(|(name, definition)| (name, strict(definition)))
+            .collect();
+        schema.insert("$defs".into(), Value::Object(defs));
+    }
+    if let Some(Value::Object(properties)) = schema.remove("properties") {
+        let required: HashSet<String> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|name| name.as_str().map(String::from))
+            .collect();
+        let names: Vec<Value> = properties.keys().cloned().map(Value::String).collect();
+        let properties = properties
+            .into_iter()
+            .map(|(name, property)| {
+                let property = strict(property);
+                let property = if required.contains(&name) {
+                    property
+                } else {
+                    nullable(property)
+                };
+                (name, property)
+            })
+            .collect();
+        schema.insert("properties".into(), Value::Object(properties));
+        schema.insert("required".into(), Value::Array(names));
+        schema.insert("additionalProperties".into(), Value::Bool(false));
+    }
+    Value::Object(schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nullable() {
+        assert_eq!(
+            nullable(json!({ "type": "string", "description": "A nickname" })),
+            json!({ "type": ["string", "null"], "description": "A nickname" })
+        );
+        assert_eq!(
+            nullable(json!({ "type": "string", "enum": ["a", "b"] })),
+            json!({ "type": ["string", "null"], "enum": ["a", "b", null] })
+        );
+        let one_of = json!({ "oneOf": [{ "type": "string" }], "description": "A pet" });
+        assert_eq!(
+            nullable(one_of),
+            json!({
+                "anyOf": [{ "oneOf": [{ "type": "string" }] }, { "type": "null" }],
+                "description": "A pet"
+            })
+        );
+
+        let nullable_string = nullable(json!({ "type": "string" }));
+        assert_eq!(nullable(nullable_string.clone()), nullable_string);
+        let nullable_enum = nullable(json!({ "oneOf": [] }));
+        assert_eq!(nullable(nullable_enum.clone()), nullable_enum);
+    }
+
+    #[test]
+    fn test_strict_schema() {
+        let schema = strict(json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "nickname": { "type": "string" },
+                "alias": { "type": ["string", "null"] },
+                "pets": {
+                    "type": "array",
+                    "items": {
+                        "oneOf": [
+                            { "type": "object", "properties": { "kind": { "type": "string" } }, "required": ["kind"] },
+                            { "type": "string" }
+                        ]
+                    }
+                }
+            },
+            "required": ["name", "pets"]
+        }));
+        assert_eq!(
+            schema["required"],
+            json!(["alias", "name", "nickname", "pets"])
+        );
+        assert_eq!(schema["additionalProperties"], false);
+        assert_eq!(
+            schema["properties"]["nickname"],
+            json!({ "type": ["string", "null"] })
+        );
+        assert_eq!(
+            schema["properties"]["alias"],
+            json!({ "type": ["string", "null"] })
+        );
+        let variants = &schema["properties"]["pets"]["items"]["anyOf"];
+        assert_eq!(variants[0]["additionalProperties"], false);
+        assert_eq!(variants[1], json!({ "type": "string" }));
+
+        assert_eq!(strict(schema.clone()), schema);
+    }
+}
@@ -0,0 +1,709 @@
+//! Conversation persistence and session lifecycle.
+//!
+//! A [`ConversationStore`] keeps conversations by id and records when each
+//! one was last active. [`MemoryStore`] keeps them in process and
+//! [`JsonFileStore`] keeps one JSON file per conversation. An agent can save
+//! its conversation after every turn with
+//! [`AgentBuilder::autosave`](crate::AgentBuilder::autosave). [`Sessions`] builds on a store to run agents against
+//! stored conversations, and expires idle ones, optionally archiving them
+//! first. [`spawn_sweeper`] runs expiry periodically in the background.
+//!
+//! ```no_run
+//! use aiform::prelude::*;
+//! use aiform::store::{spawn_sweeper, ExpiryPolicy, MemoryStore, Sessions};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! # async fn example(agent: Agent) -> Result<()> {
+//! let sessions = Arc::new(Sessions::new(Arc::new(MemoryStore::new())).on_archive(
+//!     |id, conversation| async move {
+//!         println!("archiving {} ({} messages)", id, conversation.len());
+//!         Ok(())
+//!     },
+//! ));
+//!
+//! let sweeper = spawn_sweeper(
+//!     sessions.clone(),
+//!     Duration::from_secs(60),
+//!     ExpiryPolicy::idle_for(Duration::from_secs(30 * 60)),
+//! );
+//!
+//! let answer = sessions.run(&agent, "user-42", "Hello!").await?;
+//! sweeper.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    agent::Agent,
+    conversation::Conversation,
+    error::{Error, Result},
+};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// Boxed future returned by [`ConversationStore`] methods.
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Callback invoked with a conversation before it is expired.
+pub type ArchiveHandler =
+    Arc<dyn Fn(String, Conversation) -> StoreFuture<'static, ()> + Send + Sync>;
+
+/// A source of the current time, replaceable in tests.
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Storage for conversations keyed by id.
+///
+/// Implementations record the time of every [`save`](Self::save) as the
+/// conversation's last activity.
+pub trait ConversationStore: Send + Sync {
+    /// Stores a conversation, replacing any previous version.
+    fn save<'a>(&'a self, id: &'a str, conversation: &'a Conversation) -> StoreFuture<'a, ()>;
+
+    /// Loads a conversation, or `None` if the id is unknown.
+    fn load<'a>(&'a self, id: &'a str) -> StoreFuture<'a, Option<Conversation>>;
+
+    /// Lists the ids of all stored conversations.
+    fn list(&self) -> StoreFuture<'_, Vec<String>>;
+
+    /// Deletes a conversation, returning whether it existed.
+    fn delete<'a>(&'a self, id: &'a str) -> StoreFuture<'a, bool>;
+
+    /// Returns when the conversation was last saved.
+    fn last_active<'a>(&'a self, id: &'a str) -> StoreFuture<'a, Option<SystemTime>>;
+}
+
+struct StoredConversation {
+    conversation: Conversation,
+    last_active: SystemTime,
+}
+
+/// An in-process [`ConversationStore`].
+pub struct MemoryStore {
+    conversations: RwLock<HashMap<String, StoredConversation>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store using the system clock.
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Creates an empty store that timestamps saves with the given clock.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            conversations: RwLock::new(HashMap::new()),
+            clock,
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConversationStore for MemoryStore {
+    fn save<'a>(&'a self, id: &'a str, conversation: &'a Conversation) -> StoreFuture<'a, ()> {
+        let stored = StoredConversation {
+            conversation: conversation.clone(),
+            last_active: self.clock.now(),
+        };
+        self.conversations
+            .write()
+            .unwrap()
+            .insert(id.to_string(), stored);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn load<'a>(&'a self, id: &'a str) -> StoreFuture<'a, Option<Conversation>> {
+        let conversation = self
+            .conversations
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|stored| stored.conversation.clone());
+        Box::pin(async { Ok(conversation) })
+    }
+
+    fn list(&self) -> StoreFuture<'_, Vec<String>> {
+        let ids = self.conversations.read().unwrap().keys().cloned().collect();
+        Box::pin(async { Ok(ids) })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> StoreFuture<'a, bool> {
+        let existed = self.conversations.write().unwrap().remove(id).is_some();
+        Box::pin(async move { Ok(existed) })
+    }
+
+    fn last_active<'a>(&'a self, id: &'a str) -> StoreFuture<'a, Option<SystemTime>> {
+        let last_active = self
+            .conversations
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|stored| stored.last_active);
+        Box::pin(async move { Ok(last_active) })
+    }
+}
+
+/// A [`ConversationStore`] keeping one JSON file per conversation in a
+/// directory.
+///
+/// A conversation with id `id` is stored as `id.json`, and its last activity
+/// is the file's modification time. Ids may only contain ASCII letters,
+/// digits, `-`, `_` and `.`, and may not start with `.`.
+pub struct JsonFileStore {
+    dir: PathBuf,
+}
+
+impl JsonFileStore {
+    /// Uses `dir` for storage, creating it on first save.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf> {
+        let valid = !id.is_empty()
+            && !id.starts_with('.')
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(Error::InvalidConfiguration(format!(
+                "Conversation id '{}' cannot be used as a file name",
+                id
+            )));
+        }
+        Ok(self.dir.join(format!("{}.json", id)))
+    }
+}
+
+fn io_error(action: &str, id: &str, e: std::io::Error) -> Error {
+    Error::Other(format!("Failed to {} conversation {}: {}", action, id, e).into())
+}
+
+impl ConversationStore for JsonFileStore {
+    fn save<'a>(&'a self, id: &'a str, conversation: &'a Conversation) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.path(id)?;
+            let json = serde_json::to_vec_pretty(conversation)?;
+            tokio::fs::create_dir_all(&self.dir)
+                .await
+                .map_err(|e| io_error("save", id, e))?;
+            // Write then rename so readers never see a partial file.
+            let partial = path.with_extension("partial");
+            tokio::fs::write(&partial, json)
+                .await
+                .map_err(|e| io_error("save", id, e))?;
+            tokio::fs::rename(&partial, &path)
+                .await
+                .map_err(|e| io_error("save", id, e))
+        })
+    }
+
+    fn load<'a>(&'a self, id: &'a str) -> StoreFuture<'a, Option<Conversation>> {
+        Box::pin(async move {
+            match tokio::fs::read(self.path(id)?).await {
+                Ok(json) => Ok(Some(serde_json::from_slice(&json)?)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(io_error("load", id, e)),
+            }
+        })
+    }
+
+    fn list(&self) -> StoreFuture<'_, Vec<String>> {
+        Box::pin(async move {
+            let list_error = |e: std::io::Error| {
+                Error::Other(format!("Failed to list conversations: {}", e).into())
+            };
+            let mut entries = match tokio::fs::read_dir(&self.dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => return Err(list_error(e)),
+            };
+            let mut ids = Vec::new();
+            while let Some(entry) = entries.next_entry().await.map_err(list_error)? {
+                let name = entry.file_name();
+                if let Some(id) = name.to_str().and_then(|name| name.strip_suffix(".json")) {
+                    if self.path(id).is_ok() {
+                        ids.push(id.to_string());
+                    }
+                }
+            }
+            ids.sort();
+            Ok(ids)
+        })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.path(id)?).await {
+                Ok(()) => Ok(true),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+                Err(e) => Err(io_error("delete", id, e)),
+            }
+        })
+    }
+
+    fn last_active<'a>(&'a self, id: &'a str) -> StoreFuture<'a, Option<SystemTime>> {
+        Box::pin(async move {
+            match tokio::fs::metadata(self.path(id)?).await {
+                Ok(metadata) => metadata
+                    .modified()
+                    .map(Some)
+                    .map_err(|e| io_error("inspect", id, e)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(io_error("inspect", id, e)),
+            }
+        })
+    }
+}
+
+/// Exclusive access to a session, held for the duration of a run.
+///
+/// While a guard is held, expiry skips the session.
+pub struct SessionGuard {
+    _lock: tokio::sync::OwnedMutexGuard<()>,
+}
+
+/// Runs agents against stored conversations and manages their lifetime.
+pub struct Sessions {
+    store: Arc<dyn ConversationStore>,
+    clock: Arc<dyn Clock>,
+    archive: Option<ArchiveHandler>,
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl Sessions {
+    /// Creates a session helper over a store.
+    pub fn new(store: Arc<dyn ConversationStore>) -> Self {
+        Self {
+            store,
+            clock: Arc::new(SystemClock),
+            archive: None,
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the clock used to measure idle time.
+    ///
+    /// Use the same clock as the store.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets a callback that receives each conversation before it expires.
+    ///
+    /// If the callback fails, the conversation is kept and the error is
+    /// returned from [`expire_older_than`](Self::expire_older_than).
+    pub fn on_archive<F, Fut>(mut self, archive: F) -> Self
+    where
+        F: Fn(String, Conversation) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.archive = Some(Arc::new(move |id, conversation| {
+            Box::pin(archive(id, conversation))
+        }));
+        self
+    }
+
+    /// Returns the underlying store.
+    pub fn store(&self) -> &Arc<dyn ConversationStore> {
+        &self.store
+    }
+
+    /// Waits for exclusive access to a session.
+    pub async fn acquire(&self, id: &str) -> SessionGuard {
+        let lock = self.lock_for(id);
+        SessionGuard {
+            _lock: lock.lock_owned().await,
+        }
+    }
+
+    /// Sends a message in a session and stores the updated conversation.
+    ///
+    /// A new session starts with the agent's system prompt. The session is
+    /// held for the whole run, and its last activity is updated when the
+    /// conversation is saved. Nothing is saved if the run fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from the store or the agent run.
+    pub async fn run(&self, agent: &Agent, id: &str, message: impl Into<String>) -> Result<String> {
+        let _guard = self.acquire(id).await;
+
+        let mut conversation = match self.store.load(id).await? {
+            Some(conversation) => conversation,
+            None => agent.new_conversation(),
+        };
+        conversation.add_user_message(message);
+
+        let answer = agent.run_conversation(&mut conversation).await?;
+        if !agent.appends_final_response() {
+            conversation.add_assistant_message(&answer);
+        }
+        self.store.save(id, &conversation).await?;
+
+        Ok(answer)
+    }
+
+    /// Deletes sessions idle for longer than `max_idle`, returning their ids.
+    ///
+    /// Sessions with a run in progress are skipped. Each conversation is
+    /// passed to the archive callback, if set, before it is deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from the store or the archive callback. Sessions
+    /// expired before the error remain deleted.
+    pub async fn expire_older_than(&self, max_idle: Duration) -> Result<Vec<String>> {
+        let now = self.clock.now();
+        let is_expired = |last_active: Option<SystemTime>| {
+            last_active.is_some_and(|at| now.duration_since(at).unwrap_or_default() > max_idle)
+        };
+
+        let mut expired = Vec::new();
+        for id in self.store.list().await? {
+            if !is_expired(self.store.last_active(&id).await?) {
+                continue;
+            }
+
+            let lock = self.lock_for(&id);
+            let Ok(_guard) = lock.try_lock() else {
+                // A run is in progress; it will refresh the session.
+                continue;
+            };
+
+            // The session may have been saved since it was listed.
+            if !is_expired(self.store.last_active(&id).await?) {
+                continue;
+            }
+
+            if let Some(ref archive) = self.archive {
+                if let Some(conversation) = self.store.load(&id).await? {
+                    archive(id.clone(), conversation).await?;
+                }
+            }
+
+            if self.store.delete(&id).await? {
+                expired.push(id.clone());
+            }
+            self.locks.lock().unwrap().remove(&id);
+        }
+
+        Ok(expired)
+    }
+
+    fn lock_for(&self, id: &str) -> Arc<tokio::sync::Mutex<()>> {
+        self.locks
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+/// When the background sweeper expires sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpiryPolicy {
+    /// Sessions idle for longer than this are expired.
+    pub max_idle: Duration,
+}
+
+impl ExpiryPolicy {
+    /// Expires sessions idle for longer than `max_idle`.
+    pub fn idle_for(max_idle: Duration) -> Self {
+        Self { max_idle }
+    }
+}
+
+/// Callback invoked when a sweep fails.
+type SweepErrorHandler = Arc<dyn Fn(&Error) + Send + Sync>;
+
+/// Handle to a sweeper started with [`spawn_sweeper`].
+///
+/// Dropping the handle stops the sweeper after its current sweep.
+pub struct SweeperHandle {
+    shutdown: Option<tokio::sync::oneshot::Sender<()>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl SweeperHandle {
+    /// Stops the sweeper, waiting for an in-progress sweep to finish.
+    pub async fn shutdown(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        let _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for SweeperHandle {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+/// Periodically expires idle sessions on the current Tokio runtime.
+///
+/// The first sweep runs after one `interval`. Sweep errors are ignored and
+/// retried on the next tick; use [`spawn_sweeper_with`] to observe them.
+pub fn spawn_sweeper(
+    sessions: Arc<Sessions>,
+    interval: Duration,
+    policy: ExpiryPolicy,
+) -> SweeperHandle {
+    spawn_sweeper_with(sessions, interval, policy, |_| {})
+}
+
+/// Like [`spawn_sweeper`], calling `on_error` when a sweep fails.
+pub fn spawn_sweeper_with(
+    sessions: Arc<Sessions>,
+    interval: Duration,
+    policy: ExpiryPolicy,
+    on_error: impl Fn(&Error) + Send + Sync + 'static,
+) -> SweeperHandle {
+    let (shutdown, mut shutdown_rx) = tokio::sync::oneshot::channel();
+    let on_error: SweepErrorHandler = Arc::new(on_error);
+
+    let task = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The first tick completes immediately.
+        ticker.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown_rx => break,
+                _ = ticker.tick() => {
+                    if let Err(e) = sessions.expire_older_than(policy.max_idle).await {
+                        on_error(&e);
+                    }
+                }
+            }
+        }
+    });
+
+    SweeperHandle {
+        shutdown: Some(shutdown),
+        task,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockChatBackend;
+
+    /// A clock that only moves when told to.
+    struct ManualClock(Mutex<SystemTime>);
+
+    impl ManualClock {
+        fn new() -> Arc<Self> {
+            Arc::new(Self(Mutex::new(SystemTime::UNIX_EPOCH)))
+        }
+
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> SystemTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn sessions(clock: &Arc<ManualClock>) -> Sessions {
+        let store = Arc::new(MemoryStore::with_clock(clock.clone()));
+        Sessions::new(store).clock(clock.clone())
+    }
+
+    async fn save(sessions: &Sessions, id: &str) {
+        let mut conversation = Conversation::new();
+        conversation.add_user_message(id);
+        sessions.store().save(id, &conversation).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expiry_selects_idle_sessions() {
+        let clock = ManualClock::new();
+        let sessions = sessions(&clock);
+
+        save(&sessions, "old").await;
+        clock.advance(20 * MINUTE);
+        save(&sessions, "recent").await;
+        clock.advance(15 * MINUTE);
+
+        let expired = sessions.expire_older_than(30 * MINUTE).await.unwrap();
+        assert_eq!(expired, vec!["old".to_string()]);
+        assert_eq!(sessions.store().list().await.unwrap(), vec!["recent"]);
+
+        // Exactly at the limit is not yet expired.
+        clock.advance(15 * MINUTE);
+        assert!(sessions
+            .expire_older_than(30 * MINUTE)
+            .await
+            .unwrap()
+            .is_empty());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            sessions.expire_older_than(30 * MINUTE).await.unwrap(),
+            vec!["recent".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_archive_receives_conversation_before_deletion() {
+        let clock = ManualClock::new();
+        let archived = Arc::new(Mutex::new(Vec::new()));
+        let sink = archived.clone();
+        let sessions = sessions(&clock).on_archive(move |id, conversation| {
+            let sink = sink.clone();
+            async move {
+                sink.lock().unwrap().push((id, conversation.len()));
+                Ok(())
+            }
+        });
+
+        save(&sessions, "a").await;
+        clock.advance(2 * MINUTE);
+        sessions.expire_older_than(MINUTE).await.unwrap();
+
+        assert_eq!(*archived.lock().unwrap(), vec![("a".to_string(), 1)]);
+        assert!(sessions.store().load("a").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failed_archive_keeps_conversation() {
+        let clock = ManualClock::new();
+        let sessions = sessions(&clock)
+            .on_archive(|_, _| async { Err(Error::Other("cold storage unavailable".into())) });
+
+        save(&sessions, "a").await;
+        clock.advance(2 * MINUTE);
+        assert!(sessions.expire_older_than(MINUTE).await.is_err());
+        assert!(sessions.store().load("a").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_active_sessions_are_skipped() {
+        let clock = ManualClock::new();
+        let sessions = sessions(&clock);
+
+        save(&sessions, "busy").await;
+        save(&sessions, "idle").await;
+        clock.advance(2 * MINUTE);
+
+        let guard = sessions.acquire("busy").await;
+        assert_eq!(
+            sessions.expire_older_than(MINUTE).await.unwrap(),
+            vec!["idle".to_string()]
+        );
+        drop(guard);
+
+        assert_eq!(
+            sessions.expire_older_than(MINUTE).await.unwrap(),
+            vec!["busy".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_refreshes_last_active() {
+        let clock = ManualClock::new();
+        let sessions = sessions(&clock);
+        let backend = Arc::new(
+            MockChatBackend::new()
+                .respond_with_text("Hi!")
+                .respond_with_text("Again!"),
+        );
+        let agent = Agent::builder()
+            .model("mock-model")
+            .system_prompt("Be brief")
+            .backend(backend)
+            .build()
+            .unwrap();
+
+        assert_eq!(sessions.run(&agent, "s", "Hello").await.unwrap(), "Hi!");
+        clock.advance(50 * MINUTE);
+        sessions.run(&agent, "s", "Hello again").await.unwrap();
+        clock.advance(20 * MINUTE);
+
+        assert!(sessions
+            .expire_older_than(30 * MINUTE)
+            .await
+            .unwrap()
+            .is_empty());
+        let conversation = sessions.store().load("s").await.unwrap().unwrap();
+        // System prompt plus two user/assistant exchanges.
+        assert_eq!(conversation.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_json_file_store() {
+        let dir = std::env::temp_dir().join(format!("aiform-conversations-{}", std::process::id()));
+        let store = JsonFileStore::new(&dir);
+        assert!(store.list().await.unwrap().is_empty());
+        assert!(store.load("a").await.unwrap().is_none());
+
+        let mut conversation = Conversation::with_system("Be brief");
+        conversation.add_user_message("Hello");
+        store.save("b", &conversation).await.unwrap();
+        store.save("a.1", &Conversation::new()).await.unwrap();
+
+        assert_eq!(store.list().await.unwrap(), vec!["a.1", "b"]);
+        let loaded = store.load("b").await.unwrap().unwrap();
+        assert_eq!(loaded.messages(), conversation.messages());
+        assert!(store.last_active("b").await.unwrap().is_some());
+
+        assert!(store.delete("b").await.unwrap());
+        assert!(!store.delete("b").await.unwrap());
+        assert!(store.last_active("b").await.unwrap().is_none());
+        assert!(matches!(
+            store.save("../escape", &conversation).await,
+            Err(Error::InvalidConfiguration(_))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sweeper_expires_and_shuts_down() {
+        let clock = ManualClock::new();
+        let sessions = Arc::new(sessions(&clock));
+        save(&sessions, "a").await;
+        clock.advance(10 * MINUTE);
+
+        let sweeper = spawn_sweeper(sessions.clone(), MINUTE, ExpiryPolicy::idle_for(5 * MINUTE));
+        tokio::time::sleep(MINUTE + Duration::from_secs(1)).await;
+        assert!(sessions.store().list().await.unwrap().is_empty());
+
+        sweeper.shutdown().await;
+    }
+}
@@ -0,0 +1,193 @@
+//! Incremental events from streaming agent runs.
+//!
+//! [`Agent::run_stream`](crate::Agent::run_stream) yields [`AgentEvent`]s
+//! while the agent loop runs: text as the model produces it, and the start
+//! and result of every tool call. Each completion is streamed and assembled
+//! before tools are dispatched, so the loop behaves exactly like
+//! [`Agent::run`](crate::Agent::run).
+//!
+//! ```no_run
+//! use aiform::prelude::*;
+//! use aiform::stream::AgentEvent;
+//! use futures::StreamExt;
+//!
+//! # async fn example(agent: Agent) -> Result<()> {
+//! let mut events = std::pin::pin!(agent.run_stream("Plan a weekend in Lisbon"));
+//! while let Some(event) = events.next().await {
+//!     match event? {
+//!         AgentEvent::TextDelta(text) => print!("{}", text),
+//!         AgentEvent::ToolCallStarted { name, .. } => println!("\n[calling {}]", name),
+//!         AgentEvent::Completed(_) => println!(),
+//!         _ => {}
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::error::{Error, Result};
+use async_openai::{
+    error::OpenAIError,
+    types::{ChatCompletionResponseStream, CreateChatCompletionResponse},
+};
+use futures::{Stream, StreamExt};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Something that happened during a streaming run, or a run started with
+/// [`Agent::run_with_events`](crate::Agent::run_with_events).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AgentEvent {
+    /// The agent is about to send a completion request.
+    IterationStarted {
+        /// The number of the iteration, starting at 1.
+        iteration: usize,
+        /// The agent's iteration limit.
+        max_iterations: usize,
+    },
+    /// The model answered a completion request.
+    ResponseReceived {
+        /// The number of the iteration the response belongs to.
+        iteration: usize,
+        /// How many tools the model called; zero when it answered.
+        tool_calls: usize,
+    },
+    /// A piece of the model's text output. Only sent by streaming runs.
+    TextDelta(String),
+    /// The model called a tool, which is about to run.
+    ToolCallStarted {
+        /// The id of the tool call.
+        id: String,
+        /// The name of the tool.
+        name: String,
+    },
+    /// A tool call finished and its result was added to the conversation.
+    ToolCallFinished {
+        /// The id of the tool call.
+        id: String,
+        /// The name of the tool.
+        name: String,
+        /// The result as sent to the model.
+        result: String,
+        /// How long the tool took to produce the result.
+        duration: Duration,
+    },
+    /// The run finished with this answer. Always the last event.
+    Completed(String),
+}
+
+/// Sending half of a streaming run's events.
+pub(crate) type EventSender = mpsc::UnboundedSender<AgentEvent>;
+
+/// Turns a run into a stream of the events it sends, followed by
+/// [`AgentEvent::Completed`] or the run's error.
+pub(crate) fn event_stream<'a, F>(
+    run: impl FnOnce(EventSender) -> F,
+) -> impl Stream<Item = Result<AgentEvent>> + Send + 'a
+where
+    F: Future<Output = Result<String>> + Send + 'a,
+{
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let driver = run(sender);
+    let state = EventStreamState {
+        driver: Some(Box::pin(driver)),
+        receiver,
+        result: None,
+    };
+    futures::stream::unfold(state, |mut state| async move {
+        if let Some(ref mut driver) = state.driver {
+            tokio::select! {
+                biased;
+                Some(event) = state.receiver.recv() => return Some((Ok(event), state)),
+                result = driver => {
+                    state.driver = None;
+                    state.result = Some(result);
+                }
+            }
+        }
+        // The run is over; deliver buffered events before the final one.
+        if let Ok(event) = state.receiver.try_recv() {
+            return Some((Ok(event), state));
+        }
+        let last = state.result.take()?.map(AgentEvent::Completed);
+        Some((last, state))
+    })
+}
+
+struct EventStreamState<'a> {
+    driver: Option<Pin<Box<dyn Future<Output = Result<String>> + Send + 'a>>>,
+    receiver: mpsc::UnboundedReceiver<AgentEvent>,
+    result: Option<Result<String>>,
+}
+
+/// Assembles a streamed completion into a regular response, passing each
+/// text delta to `on_text` as it arrives.
+pub(crate) async fn collect_response(
+    mut stream: ChatCompletionResponseStream,
+    mut on_text: impl FnMut(&str),
+) -> Result<CreateChatCompletionResponse> {
+    let mut header = None;
+    let mut content: Option<String> = None;
+    let mut tool_calls: Vec<(String, String, String)> = Vec::new();
+    let mut finish_reason = Value::Null;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(Error::from)?;
+        header.get_or_insert_with(|| (chunk.id.clone(), chunk.created, chunk.model.clone()));
+        let Some(choice) = chunk.choices.into_iter().next() else {
+            continue;
+        };
+
+        if let Some(text) = choice.delta.content {
+            on_text(&text);
+            content.get_or_insert_with(String::new).push_str(&text);
+        }
+        for call in choice.delta.tool_calls.into_iter().flatten() {
+            let index = call.index as usize;
+            if tool_calls.len() <= index {
+                tool_calls.resize_with(index + 1, Default::default);
+            }
+            let (id, name, arguments) = &mut tool_calls[index];
+            if let Some(call_id) = call.id {
+                *id = call_id;
+            }
+            if let Some(function) = call.function {
+                name.push_str(&function.name.unwrap_or_default());
+                arguments.push_str(&function.arguments.unwrap_or_default());
+            }
+        }
+        if let Some(reason) = choice.finish_reason {
+            finish_reason = serde_json::to_value(reason)?;
+        }
+    }
+
+    let (id, created, model) = header.ok_or_else(|| {
+        Error::OpenAI(OpenAIError::StreamError(
+            "stream ended without any chunks".into(),
+        ))
+    })?;
+    let mut message = json!({ "role": "assistant", "content": content });
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = tool_calls
+            .into_iter()
+            .map(|(id, name, arguments)| {
+                json!({
+                    "id": id,
+                    "type": "function",
+                    "function": { "name": name, "arguments": arguments },
+                })
+            })
+            .collect();
+    }
+    Ok(serde_json::from_value(json!({
+        "id": id,
+        "object": "chat.completion",
+        "created": created,
+        "model": model,
+        "choices": [{ "index": 0, "message": message, "finish_reason": finish_reason }],
+    }))?)
+}
@@ -0,0 +1,281 @@
+//! A scripted chat backend for testing agents without a network.
+//!
+//! Enabled by the `test-util` feature. A [`MockChatBackend`] replays
+//! scripted responses in order and records every request, so the agent loop
+//! can be exercised deterministically:
+//!
+//! ```
+//! use aiform::prelude::*;
+//! use aiform::testing::MockChatBackend;
+//! use std::sync::Arc;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<()> {
+//! let backend = Arc::new(MockChatBackend::new().respond_with_text("Bonjour !"));
+//! let agent = Agent::builder()
+//!     .model("mock-model")
+//!     .backend(backend.clone())
+//!     .build()?;
+//!
+//! assert_eq!(agent.run("Say hello in French").await?, "Bonjour !");
+//! let request = backend.last_request().unwrap();
+//! assert_eq!(request.model, "mock-model");
+//! assert_eq!(request.messages.len(), 1);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A backend that runs out of scripted responses panics.
+
+use crate::backend::{BackendFuture, ChatBackend, ChatRequest};
+use async_openai::{
+    error::OpenAIError,
+    types::{
+        ChatCompletionResponseStream, CreateChatCompletionRequest, CreateChatCompletionResponse,
+    },
+};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Replays scripted responses in order and records every request.
+///
+/// Responses are queued with the `respond_with_*` methods. Tool calls get
+/// the ids `call_{n}_{i}`, where `n` counts the responses queued or sent
+/// before and `i` is the call's position in the message.
+#[derive(Default)]
+pub struct MockChatBackend {
+    responses: Mutex<VecDeque<(std::result::Result<Value, OpenAIError>, Duration)>>,
+    requests: Mutex<Vec<CreateChatCompletionRequest>>,
+    bodies: Mutex<Vec<Value>>,
+    models: Mutex<Option<std::result::Result<Vec<String>, OpenAIError>>>,
+    latency: Duration,
+}
+
+impl MockChatBackend {
+    /// Creates a backend with no scripted responses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a raw `choices[0].message` object with the given finish reason.
+    pub fn respond_with_message(self, message: Value, finish_reason: &str) -> Self {
+        let response = json!({
+            "id": "mock",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "mock-model",
+            "choices": [{
+                "index": 0,
+                "message": message,
+                "finish_reason": finish_reason,
+            }],
+            "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 },
+        });
+        self.responses
+            .lock()
+            .unwrap()
+            .push_back((Ok(response), Duration::ZERO));
+        self
+    }
+
+    /// Queues a failed completion.
+    pub fn respond_with_error(self, error: OpenAIError) -> Self {
+        self.responses
+            .lock()
+            .unwrap()
+            .push_back((Err(error), Duration::ZERO));
+        self
+    }
+
+    /// Delays the most recently queued response by `delay`, on top of
+    /// the [`latency`](Self::latency).
+    pub fn delayed(self, delay: Duration) -> Self {
+        if let Some(last) = self.responses.lock().unwrap().back_mut() {
+            last.1 = delay;
+        }
+        self
+    }
+
+    /// Sets the result of the next `list_models` call. Defaults to
+    /// `["mock-model"]`.
+    pub fn models(self, models: std::result::Result<Vec<&str>, OpenAIError>) -> Self {
+        let models = models.map(|ids| ids.into_iter().map(String::from).collect());
+        *self.models.lock().unwrap() = Some(models);
+        self
+    }
+
+    /// Delays every response by `latency`.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Queues a final text answer.
+    pub fn respond_with_text(self, content: &str) -> Self {
+        self.respond_with_message(json!({ "role": "assistant", "content": content }), "stop")
+    }
+
+    /// Queues a single tool call with JSON arguments.
+    pub fn respond_with_tool_call(self, name: &str, args: Value) -> Self {
+        self.respond_with_tool_calls(&[(name, args)])
+    }
+
+    /// Queues several tool calls in one assistant message.
+    pub fn respond_with_tool_calls(self, calls: &[(&str, Value)]) -> Self {
+        let offset = self.requests_len() + self.responses.lock().unwrap().len();
+        let tool_calls: Vec<Value> = calls
+            .iter()
+            .enumerate()
+            .map(|(i, (name, args))| {
+                json!({
+                    "id": format!("call_{}_{}", offset, i),
+                    "type": "function",
+                    "function": { "name": name, "arguments": args.to_string() },
+                })
+            })
+            .collect();
+        self.respond_with_message(
+            json!({ "role": "assistant", "content": null, "tool_calls": tool_calls }),
+            "tool_calls",
+        )
+    }
+
+    /// Queues a tool call whose arguments are sent verbatim, e.g. truncated JSON.
+    pub fn respond_with_raw_tool_call(
+        self,
+        name: &str,
+        arguments: &str,
+        finish_reason: &str,
+    ) -> Self {
+        let tool_call = json!({
+            "id": "call_raw",
+            "type": "function",
+            "function": { "name": name, "arguments": arguments },
+        });
+        self.respond_with_message(
+            json!({ "role": "assistant", "content": null, "tool_calls": [tool_call] }),
+            finish_reason,
+        )
+    }
+
+    /// Returns every request received so far.
+    pub fn requests(&self) -> Vec<CreateChatCompletionRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Returns the last request received, if any.
+    pub fn last_request(&self) -> Option<CreateChatCompletionRequest> {
+        self.requests.lock().unwrap().last().cloned()
+    }
+
+    /// Returns the JSON body of every request received so far, with any
+    /// [extra body fields](crate::AgentBuilder::extra_body).
+    pub fn bodies(&self) -> Vec<Value> {
+        self.bodies.lock().unwrap().clone()
+    }
+
+    fn requests_len(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+}
+
+impl ChatBackend for MockChatBackend {
+    fn create_chat_completion<'a>(
+        &'a self,
+        request: ChatRequest<'a>,
+    ) -> BackendFuture<'a, CreateChatCompletionResponse> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push(request.request().clone());
+        self.bodies
+            .lock()
+            .unwrap()
+            .push(serde_json::to_value(request).unwrap());
+        let next = self.responses.lock().unwrap().pop_front();
+        Box::pin(async move {
+            let Some((response, delay)) = next else {
+                panic!("MockChatBackend ran out of scripted responses");
+            };
+            let delay = self.latency + delay;
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            response.map(|value| serde_json::from_value(value).expect("invalid mock response"))
+        })
+    }
+
+    /// Replays the next scripted response as chunks: the content word by
+    /// word, then each tool call's name followed by its arguments in two
+    /// fragments.
+    fn create_chat_completion_stream<'a>(
+        &'a self,
+        request: ChatRequest<'a>,
+    ) -> BackendFuture<'a, ChatCompletionResponseStream> {
+        let response = self.create_chat_completion(request);
+        Box::pin(async move {
+            let response = serde_json::to_value(response.await?).unwrap();
+            let choice = &response["choices"][0];
+            let message = &choice["message"];
+            let mut deltas = vec![json!({ "role": "assistant" })];
+
+            if let Some(content) = message["content"].as_str() {
+                deltas.extend(
+                    content
+                        .split_inclusive(' ')
+                        .map(|word| json!({ "content": word })),
+                );
+            }
+            for (index, call) in message["tool_calls"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .enumerate()
+            {
+                let arguments = call["function"]["arguments"].as_str().unwrap_or_default();
+                let (head, tail) = arguments.split_at(arguments.len() / 2);
+                deltas.push(json!({ "tool_calls": [{
+                    "index": index,
+                    "id": call["id"],
+                    "type": "function",
+                    "function": { "name": call["function"]["name"], "arguments": head },
+                }] }));
+                deltas.push(json!({ "tool_calls": [{
+                    "index": index,
+                    "function": { "arguments": tail },
+                }] }));
+            }
+
+            let last = deltas.len() - 1;
+            let chunks: Vec<_> = deltas
+                .into_iter()
+                .enumerate()
+                .map(|(i, delta)| {
+                    let chunk = json!({
+                        "id": "mock",
+                        "object": "chat.completion.chunk",
+                        "created": 0,
+                        "model": "mock-model",
+                        "choices": [{
+                            "index": 0,
+                            "delta": delta,
+                            "finish_reason": if i == last { choice["finish_reason"].clone() } else { Value::Null },
+                        }],
+                    });
+                    Ok(serde_json::from_value(chunk).expect("invalid mock chunk"))
+                })
+                .collect();
+            Ok(Box::pin(futures::stream::iter(chunks)) as ChatCompletionResponseStream)
+        })
+    }
+
+    fn list_models(&self) -> BackendFuture<'_, Vec<String>> {
+        let models = self.models.lock().unwrap().take();
+        Box::pin(async move {
+            tokio::time::sleep(self.latency).await;
+            models.unwrap_or_else(|| Ok(vec!["mock-model".to_string()]))
+        })
+    }
+}
@@ -0,0 +1,434 @@
+//! Token counting and per-message breakdowns of a conversation.
+//!
+//! A [`TokenCounter`] estimates how many tokens a piece of text uses. Plug in
+//! a real tokenizer for exact counts, enable the `tiktoken` feature for
+//! `TiktokenCounter`, or use the [`ApproxTokenCounter`] heuristic.
+//! [`Conversation::estimate_tokens`] and [`ToolSet::estimate_tokens`] size a
+//! request, and [`Conversation::token_breakdown`] shows which messages take
+//! up the context:
+//!
+//! ```
+//! use aiform::prelude::*;
+//! use aiform::tokens::ApproxTokenCounter;
+//!
+//! let mut conversation = Conversation::with_system("You are helpful");
+//! conversation.add_user_message("Summarize this report: ...");
+//!
+//! println!("{} tokens", conversation.estimate_tokens(&ApproxTokenCounter));
+//! let breakdown = conversation.token_breakdown(&ApproxTokenCounter);
+//! for message in breakdown.heaviest(3) {
+//!     println!("#{} {} uses {} tokens", message.index, message.role, message.tokens);
+//! }
+//! print!("{}", breakdown);
+//! ```
+
+use crate::conversation::Conversation;
+use crate::ToolSet;
+use async_openai::types::ChatCompletionRequestMessage;
+use serde_json::Value;
+use std::fmt;
+
+/// Estimates the number of tokens in text.
+pub trait TokenCounter: Send + Sync {
+    /// Counts the tokens in `text`.
+    fn count(&self, text: &str) -> usize;
+
+    /// Tokens every message costs beyond its text, for the role and framing.
+    fn message_overhead(&self) -> usize {
+        3
+    }
+
+    /// Tokens the request costs beyond its messages, for priming the reply.
+    fn reply_overhead(&self) -> usize {
+        3
+    }
+
+    /// Tokens every tool definition costs beyond its name, description and
+    /// parameter schema.
+    fn tool_overhead(&self) -> usize {
+        8
+    }
+}
+
+/// Estimates one token per four characters, which is close for English text
+/// with OpenAI tokenizers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApproxTokenCounter;
+
+impl TokenCounter for ApproxTokenCounter {
+    fn count(&self, text: &str) -> usize {
+        text.chars().count().div_ceil(4)
+    }
+}
+
+/// Counts exactly with the tokenizer of an OpenAI model.
+#[cfg(feature = "tiktoken")]
+pub struct TiktokenCounter {
+    bpe: tiktoken_rs::CoreBPE,
+}
+
+#[cfg(feature = "tiktoken")]
+impl TiktokenCounter {
+    /// Creates a counter with the tokenizer `model` uses, e.g. `"gpt-4o"`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfiguration`](crate::Error::InvalidConfiguration)
+    /// if the model's tokenizer is unknown.
+    pub fn for_model(model: &str) -> crate::Result<Self> {
+        let bpe = tiktoken_rs::get_bpe_from_model(model).map_err(|e| {
+            crate::Error::InvalidConfiguration(format!("No tokenizer for model '{}': {}", model, e))
+        })?;
+        Ok(Self { bpe })
+    }
+}
+
+#[cfg(feature = "tiktoken")]
+impl fmt::Debug for TiktokenCounter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TiktokenCounter").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "tiktoken")]
+impl TokenCounter for TiktokenCounter {
+    fn count(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+}
+
+/// Counts the tokens of one message, including its overhead.
+///
+/// Counts the content, the name, and for assistant tool calls the function
+/// names and the raw argument text.
+pub fn count_message(counter: &dyn TokenCounter, message: &ChatCompletionRequestMessage) -> usize {
+    let value = serde_json::to_value(message).unwrap_or_default();
+    let mut tokens = counter.message_overhead();
+    let mut count = |text: &str| tokens += counter.count(text);
+
+    match &value["content"] {
+        Value::String(text) => count(text),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .for_each(&mut count),
+        _ => {}
+    }
+    if let Some(name) = value["name"].as_str() {
+        count(name);
+    }
+    for call in value["tool_calls"].as_array().into_iter().flatten() {
+        let function = &call["function"];
+        count(function["name"].as_str().unwrap_or_default());
+        count(function["arguments"].as_str().unwrap_or_default());
+    }
+    tokens
+}
+
+/// Counts the tokens a request with these messages would use.
+pub fn count_messages(
+    counter: &dyn TokenCounter,
+    messages: &[ChatCompletionRequestMessage],
+) -> usize {
+    messages
+        .iter()
+        .map(|message| count_message(counter, message))
+        .sum::<usize>()
+        + counter.reply_overhead()
+}
+
+/// The token count of one message in a [`TokenBreakdown`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageTokens {
+    /// Position of the message in the conversation.
+    pub index: usize,
+    /// The message's role, e.g. `"user"` or `"tool"`.
+    pub role: String,
+    /// Tokens used by the message, including its overhead.
+    pub tokens: usize,
+    /// Fraction of all message tokens used by this message.
+    pub share: f64,
+}
+
+/// The share of actual prompt-token growth attributed to a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageCost {
+    /// Position of the message in the conversation.
+    pub index: usize,
+    /// The message's role.
+    pub role: String,
+    /// Estimated tokens of the message.
+    pub estimated_tokens: usize,
+    /// Actual prompt tokens attributed to the message.
+    pub attributed_tokens: f64,
+}
+
+/// Per-message token counts of a conversation.
+///
+/// Dereferences to the list of [`MessageTokens`] in conversation order; its
+/// `Display` implementation renders a text bar chart.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TokenBreakdown {
+    messages: Vec<MessageTokens>,
+}
+
+impl TokenBreakdown {
+    fn new(counter: &dyn TokenCounter, messages: &[ChatCompletionRequestMessage]) -> Self {
+        let counts: Vec<(String, usize)> = messages
+            .iter()
+            .map(|message| {
+                let role = serde_json::to_value(message)
+                    .ok()
+                    .and_then(|value| value["role"].as_str().map(String::from))
+                    .unwrap_or_default();
+                (role, count_message(counter, message))
+            })
+            .collect();
+        let total: usize = counts.iter().map(|(_, tokens)| tokens).sum();
+        let messages = counts
+            .into_iter()
+            .enumerate()
+            .map(|(index, (role, tokens))| MessageTokens {
+                index,
+                role,
+                tokens,
+                share: if total == 0 {
+                    0.0
+                } else {
+                    tokens as f64 / total as f64
+                },
+            })
+            .collect();
+        Self { messages }
+    }
+
+    /// Returns the total tokens of all messages, excluding the reply overhead.
+    pub fn total(&self) -> usize {
+        self.messages.iter().map(|message| message.tokens).sum()
+    }
+
+    /// Returns the `n` messages using the most tokens, heaviest first.
+    pub fn heaviest(&self, n: usize) -> Vec<&MessageTokens> {
+        let mut messages: Vec<_> = self.messages.iter().collect();
+        messages.sort_by(|a, b| b.tokens.cmp(&a.tokens).then(a.index.cmp(&b.index)));
+        messages.truncate(n);
+        messages
+    }
+
+    /// Attributes the actual growth in prompt tokens to the messages from
+    /// `since` onwards, in proportion to their estimated counts.
+    ///
+    /// Pass the conversation length before a run as `since`, and the
+    /// difference between the prompt tokens the provider reported for the
+    /// run's first request and the previous run's as `prompt_growth`.
+    pub fn attribute_growth(&self, since: usize, prompt_growth: u64) -> Vec<MessageCost> {
+        let added = self.messages.get(since..).unwrap_or_default();
+        let estimated: usize = added.iter().map(|message| message.tokens).sum();
+        added
+            .iter()
+            .map(|message| MessageCost {
+                index: message.index,
+                role: message.role.clone(),
+                estimated_tokens: message.tokens,
+                attributed_tokens: if estimated == 0 {
+                    prompt_growth as f64 / added.len() as f64
+                } else {
+                    prompt_growth as f64 * message.tokens as f64 / estimated as f64
+                },
+            })
+            .collect()
+    }
+
+    /// Returns the per-message counts.
+    pub fn into_vec(self) -> Vec<MessageTokens> {
+        self.messages
+    }
+}
+
+impl std::ops::Deref for TokenBreakdown {
+    type Target = [MessageTokens];
+
+    fn deref(&self) -> &Self::Target {
+        &self.messages
+    }
+}
+
+impl fmt::Display for TokenBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const WIDTH: f64 = 30.0;
+        let digits = self.total().to_string().len();
+        for message in &self.messages {
+            let bar = "█".repeat((message.share * WIDTH).round() as usize);
+            writeln!(
+                f,
+                "#{:<3} {:<9} {:>digits$} {:>5.1}% {}",
+                message.index,
+                message.role,
+                message.tokens,
+                message.share * 100.0,
+                bar,
+                digits = digits,
+            )?;
+        }
+        writeln!(f, "total {} tokens", self.total())
+    }
+}
+
+impl Conversation {
+    /// Estimates the tokens a request with this conversation would use,
+    /// including each message's overhead but not tool definitions; see
+    /// [`ToolSet::estimate_tokens`].
+    pub fn estimate_tokens(&self, counter: &dyn TokenCounter) -> usize {
+        count_messages(counter, self.messages())
+    }
+
+    /// Counts the tokens a request with this conversation would use; the
+    /// same as [`estimate_tokens`](Self::estimate_tokens).
+    pub fn token_count(&self, counter: &dyn TokenCounter) -> usize {
+        self.estimate_tokens(counter)
+    }
+
+    /// Counts the tokens of each message.
+    pub fn token_breakdown(&self, counter: &dyn TokenCounter) -> TokenBreakdown {
+        TokenBreakdown::new(counter, self.messages())
+    }
+}
+
+impl ToolSet {
+    /// Estimates the tokens the tool definitions add to a request.
+    ///
+    /// Counts each tool's name, description and parameter schema as compact
+    /// JSON, plus the counter's [`tool_overhead`](TokenCounter::tool_overhead).
+    pub fn estimate_tokens(&self, counter: &dyn TokenCounter) -> usize {
+        self.tools()
+            .iter()
+            .map(|tool| {
+                let function = &tool.function;
+                let parameters = function
+                    .parameters
+                    .as_ref()
+                    .map(Value::to_string)
+                    .unwrap_or_default();
+                counter.tool_overhead()
+                    + counter.count(&function.name)
+                    + counter.count(function.description.as_deref().unwrap_or_default())
+                    + counter.count(&parameters)
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_openai::types::ChatCompletionMessageToolCall;
+    use serde_json::json;
+
+    /// Counts whitespace-separated words, so expected values are obvious.
+    struct WordCounter;
+
+    impl TokenCounter for WordCounter {
+        fn count(&self, text: &str) -> usize {
+            text.split_whitespace().count()
+        }
+    }
+
+    fn conversation() -> Conversation {
+        let tool_call: ChatCompletionMessageToolCall = serde_json::from_value(json!({
+            "id": "call_1",
+            "type": "function",
+            "function": { "name": "search", "arguments": "{\"query\": \"rust async book\"}" },
+        }))
+        .unwrap();
+        let mut conversation = Conversation::with_system("You are helpful");
+        conversation.add_user_message("Find me a good book about async Rust please");
+        conversation.add_assistant_message_with_tools(None, vec![tool_call]);
+        conversation.add_tool_message("call_1", "Asynchronous Programming in Rust");
+        conversation
+    }
+
+    #[test]
+    fn test_breakdown_matches_whole_conversation_count() {
+        let conversation = conversation();
+        let breakdown = conversation.token_breakdown(&WordCounter);
+
+        let tokens: Vec<_> = breakdown.iter().map(|m| m.tokens).collect();
+        // Assistant: "search" plus the four words of the raw arguments.
+        assert_eq!(tokens, vec![3 + 3, 9 + 3, 5 + 3, 4 + 3]);
+        assert_eq!(
+            breakdown.total() + WordCounter.reply_overhead(),
+            conversation.token_count(&WordCounter)
+        );
+        let shares: f64 = breakdown.iter().map(|m| m.share).sum();
+        assert!((shares - 1.0).abs() < 1e-9);
+        assert_eq!(breakdown[2].role, "assistant");
+    }
+
+    #[test]
+    fn test_heaviest_and_display() {
+        let breakdown = conversation().token_breakdown(&WordCounter);
+        let heaviest: Vec<_> = breakdown.heaviest(2).iter().map(|m| m.index).collect();
+        assert_eq!(heaviest, vec![1, 2]);
+        assert_eq!(breakdown.heaviest(10).len(), 4);
+
+        let rendered = breakdown.to_string();
+        let lines: Vec<_> = rendered.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].starts_with("#1   user      12  36.4% "));
+        assert_eq!(lines[1].matches('█').count(), 11);
+        assert_eq!(lines[4], "total 33 tokens");
+    }
+
+    #[test]
+    fn test_attribute_growth() {
+        let breakdown = conversation().token_breakdown(&WordCounter);
+        let costs = breakdown.attribute_growth(2, 30);
+        assert_eq!(costs.len(), 2);
+        assert_eq!(costs[0].index, 2);
+        assert!((costs[0].attributed_tokens - 16.0).abs() < 1e-9);
+        assert!((costs[1].attributed_tokens - 14.0).abs() < 1e-9);
+        assert!(breakdown.attribute_growth(9, 30).is_empty());
+    }
+
+    #[test]
+    fn test_tool_set_estimate() {
+        let tools: ToolSet = serde_json::from_value::<Vec<_>>(json!([{
+            "type": "function",
+            "function": {
+                "name": "search",
+                "description": "Search the catalog",
+                "parameters": { "type": "object" },
+            },
+        }]))
+        .map(|tools| ToolSet {
+            tools,
+            dispatcher: std::sync::Arc::new(|_, _| Box::pin(async { Ok(String::new()) })),
+            effects: Default::default(),
+            middleware: Vec::new(),
+            cache: None,
+            handoffs: Default::default(),
+        })
+        .unwrap();
+        // The name, three description words and the one-word schema.
+        assert_eq!(tools.estimate_tokens(&WordCounter), 8 + 1 + 3 + 1);
+        assert_eq!(
+            conversation().estimate_tokens(&WordCounter),
+            conversation().token_count(&WordCounter)
+        );
+    }
+
+    #[cfg(feature = "tiktoken")]
+    #[test]
+    fn test_tiktoken_counter() {
+        let counter = TiktokenCounter::for_model("gpt-4o").unwrap();
+        assert_eq!(counter.count("hello world"), 2);
+        assert!(TiktokenCounter::for_model("not-a-model").is_err());
+    }
+
+    #[test]
+    fn test_approx_counter() {
+        assert_eq!(ApproxTokenCounter.count(""), 0);
+        assert_eq!(ApproxTokenCounter.count("abcd"), 1);
+        assert_eq!(ApproxTokenCounter.count("abcde"), 2);
+    }
+}
@@ -0,0 +1,333 @@
+//! Structured tool failures and how the agent reacts to them.
+//!
+//! A tool can fail with a [`ToolError`] to give the model a machine-readable
+//! `code` instead of free text. Tools returning `aiform::Result` can use `?`
+//! on a `ToolError`, or return it as their error type directly. Any other
+//! error is wrapped with the code [`ToolError::TOOL_FAILED`].
+//!
+//! What the agent does with a failure is decided by a [`ToolErrorPolicy`].
+//!
+//! ```
+//! use aiform::prelude::*;
+//! use aiform::tool_error::ToolError;
+//! # use serde::Deserialize;
+//! # #[derive(ToolArg, Deserialize)]
+//! # struct OrderArgs { id: String }
+//!
+//! #[tool("Look up an order")]
+//! async fn get_order(args: OrderArgs) -> Result<String> {
+//!     Err(ToolError::not_found(format!("no order with id {}", args.id)).into())
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A tool failure with a machine-readable code.
+///
+/// Sent to the model as `{"error": {"code": ..., "message": ..., "retryable": ...}}`,
+/// with a `details` field when details are set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolError {
+    /// Stable identifier for the kind of failure, e.g. [`ToolError::NOT_FOUND`].
+    pub code: String,
+    /// Human-readable description.
+    pub message: String,
+    /// Whether calling the tool again with the same arguments may succeed.
+    pub retryable: bool,
+    /// Additional structured context for the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+impl ToolError {
+    /// The requested entity does not exist.
+    pub const NOT_FOUND: &'static str = "not_found";
+    /// The caller may not perform the operation.
+    pub const PERMISSION_DENIED: &'static str = "permission_denied";
+    /// A rate limit was hit; the call may succeed later.
+    pub const RATE_LIMITED: &'static str = "rate_limited";
+    /// The arguments were invalid; the model should correct them.
+    pub const INVALID_INPUT: &'static str = "invalid_input";
+    /// The tool did not finish within the agent's tool timeout.
+    pub const TIMEOUT: &'static str = "timeout";
+    /// The model called a tool the agent does not have. Aborting ends the
+    /// run with [`crate::Error::ToolNotFound`].
+    pub const UNKNOWN_TOOL: &'static str = "unknown_tool";
+    /// The run cannot continue. Always aborts the run.
+    pub const FATAL: &'static str = "fatal";
+    /// Default code for errors that are not a `ToolError`.
+    pub const TOOL_FAILED: &'static str = "tool_failed";
+
+    /// Creates a non-retryable error.
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            code: code.into(),
+            message: message.into(),
+            retryable: false,
+            details: None,
+        }
+    }
+
+    /// Creates a [`NOT_FOUND`](Self::NOT_FOUND) error.
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(Self::NOT_FOUND, message)
+    }
+
+    /// Creates a [`PERMISSION_DENIED`](Self::PERMISSION_DENIED) error.
+    pub fn permission_denied(message: impl Into<String>) -> Self {
+        Self::new(Self::PERMISSION_DENIED, message)
+    }
+
+    /// Creates a retryable [`RATE_LIMITED`](Self::RATE_LIMITED) error.
+    pub fn rate_limited(message: impl Into<String>) -> Self {
+        Self::new(Self::RATE_LIMITED, message).retryable(true)
+    }
+
+    /// Creates an [`INVALID_INPUT`](Self::INVALID_INPUT) error.
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        Self::new(Self::INVALID_INPUT, message)
+    }
+
+    /// Creates a retryable [`TIMEOUT`](Self::TIMEOUT) error.
+    pub fn timeout(message: impl Into<String>) -> Self {
+        Self::new(Self::TIMEOUT, message).retryable(true)
+    }
+
+    /// Creates a [`FATAL`](Self::FATAL) error.
+    pub fn fatal(message: impl Into<String>) -> Self {
+        Self::new(Self::FATAL, message)
+    }
+
+    /// Sets whether the call may be retried.
+    pub fn retryable(mut self, retryable: bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    /// Attaches structured details.
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    /// Converts an error returned by a tool dispatcher.
+    ///
+    /// `ToolError`s (directly or inside [`crate::Error::Tool`]) are kept as-is,
+    /// [`crate::Error::ToolNotFound`] becomes
+    /// [`UNKNOWN_TOOL`](Self::UNKNOWN_TOOL), argument deserialization
+    /// failures and [`crate::Error::InvalidToolArguments`] become
+    /// [`INVALID_INPUT`](Self::INVALID_INPUT), and anything
+    /// else becomes [`TOOL_FAILED`](Self::TOOL_FAILED).
+    pub fn from_boxed(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        let error = match error.downcast::<ToolError>() {
+            Ok(tool_error) => return *tool_error,
+            Err(error) => error,
+        };
+        let error = match error.downcast::<crate::Error>() {
+            Ok(error) => match *error {
+                crate::Error::Tool(tool_error) => return tool_error,
+                crate::Error::ToolNotFound(name) => {
+                    return Self::new(Self::UNKNOWN_TOOL, format!("no such tool '{}'", name))
+                }
+                crate::Error::InvalidToolArguments { errors, .. } => {
+                    return Self::invalid_input(format!(
+                        "the arguments do not match the tool's schema: {}",
+                        errors.join("; ")
+                    ))
+                    .with_details(serde_json::json!({ "errors": errors }))
+                }
+                other => return Self::new(Self::TOOL_FAILED, other.to_string()),
+            },
+            Err(error) => error,
+        };
+        if error.is::<serde_json::Error>() {
+            return Self::invalid_input(error.to_string());
+        }
+        Self::new(Self::TOOL_FAILED, error.to_string())
+    }
+
+    /// Serializes the error in the shape sent to the model.
+    pub fn to_model_json(&self) -> String {
+        serde_json::json!({ "error": self }).to_string()
+    }
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ToolError {}
+
+/// What the agent does when a tool fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolErrorAction {
+    /// Stop the run with [`crate::Error::ToolExecution`].
+    Abort,
+    /// Send the serialized [`ToolError`] to the model as the tool result, so
+    /// it can correct itself or explain the failure.
+    ReturnToModel,
+}
+
+/// Code-aware handling of tool failures.
+///
+/// Retryable errors are first retried up to `max_retries` times without
+/// involving the model. If the error persists, the action registered for its
+/// code is applied, falling back to the default action. Errors with the code
+/// [`ToolError::FATAL`] always abort.
+///
+/// The default policy aborts without retrying.
+///
+/// ```
+/// use aiform::tool_error::{ToolError, ToolErrorAction, ToolErrorPolicy};
+///
+/// let policy = ToolErrorPolicy::return_to_model()
+///     .max_retries(2)
+///     .on_code(ToolError::PERMISSION_DENIED, ToolErrorAction::Abort);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolErrorPolicy {
+    default: ToolErrorAction,
+    by_code: HashMap<String, ToolErrorAction>,
+    max_retries: usize,
+}
+
+impl ToolErrorPolicy {
+    /// A policy that aborts the run on tool failures.
+    pub fn abort() -> Self {
+        Self {
+            default: ToolErrorAction::Abort,
+            by_code: HashMap::new(),
+            max_retries: 0,
+        }
+    }
+
+    /// A policy that reports tool failures to the model.
+    pub fn return_to_model() -> Self {
+        Self {
+            default: ToolErrorAction::ReturnToModel,
+            ..Self::abort()
+        }
+    }
+
+    /// Sets how many times a retryable error is retried before the policy
+    /// action applies.
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the action for errors with the given code.
+    pub fn on_code(mut self, code: impl Into<String>, action: ToolErrorAction) -> Self {
+        self.by_code.insert(code.into(), action);
+        self
+    }
+
+    /// Returns the number of automatic retries for retryable errors.
+    pub fn retries(&self) -> usize {
+        self.max_retries
+    }
+
+    /// Returns the action for an error after retries are exhausted.
+    pub fn action_for(&self, error: &ToolError) -> ToolErrorAction {
+        if error.code == ToolError::FATAL {
+            return ToolErrorAction::Abort;
+        }
+        self.by_code
+            .get(&error.code)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+impl Default for ToolErrorPolicy {
+    fn default() -> Self {
+        Self::abort()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_serialization_shape() {
+        let error = ToolError::not_found("no order 42");
+        let value: Value = serde_json::from_str(&error.to_model_json()).unwrap();
+        assert_eq!(
+            value,
+            json!({ "error": { "code": "not_found", "message": "no order 42", "retryable": false } })
+        );
+
+        let error = ToolError::rate_limited("slow down").with_details(json!({ "retry_after": 5 }));
+        let value: Value = serde_json::from_str(&error.to_model_json()).unwrap();
+        assert_eq!(value["error"]["retryable"], true);
+        assert_eq!(value["error"]["details"]["retry_after"], 5);
+    }
+
+    #[test]
+    fn test_from_boxed() {
+        let boxed: Box<dyn std::error::Error + Send + Sync> =
+            Box::new(ToolError::permission_denied("nope"));
+        assert_eq!(ToolError::from_boxed(boxed).code, "permission_denied");
+
+        let boxed: Box<dyn std::error::Error + Send + Sync> =
+            Box::new(crate::Error::from(ToolError::fatal("disk on fire")));
+        assert_eq!(ToolError::from_boxed(boxed).code, "fatal");
+
+        let boxed: Box<dyn std::error::Error + Send + Sync> =
+            Box::new(serde_json::from_str::<u32>("x").unwrap_err());
+        assert_eq!(ToolError::from_boxed(boxed).code, "invalid_input");
+
+        let boxed: Box<dyn std::error::Error + Send + Sync> =
+            Box::new(crate::Error::ToolNotFound("fly".into()));
+        assert_eq!(ToolError::from_boxed(boxed).code, "unknown_tool");
+
+        let boxed: Box<dyn std::error::Error + Send + Sync> =
+            Box::new(crate::Error::InvalidToolArguments {
+                tool_name: "fly".into(),
+                errors: vec!["missing required field `to`".into()],
+                raw: json!({}),
+            });
+        let error = ToolError::from_boxed(boxed);
+        assert_eq!(error.code, "invalid_input");
+        assert_eq!(
+            error.details,
+            Some(json!({ "errors": ["missing required field `to`"] }))
+        );
+
+        let error = ToolError::from_boxed("connection reset".into());
+        assert_eq!(error.code, "tool_failed");
+        assert_eq!(error.message, "connection reset");
+        assert!(!error.retryable);
+    }
+
+    #[test]
+    fn test_policy_by_code() {
+        let policy = ToolErrorPolicy::return_to_model()
+            .on_code(ToolError::PERMISSION_DENIED, ToolErrorAction::Abort);
+        assert_eq!(
+            policy.action_for(&ToolError::not_found("x")),
+            ToolErrorAction::ReturnToModel
+        );
+        assert_eq!(
+            policy.action_for(&ToolError::permission_denied("x")),
+            ToolErrorAction::Abort
+        );
+
+        let policy = policy.on_code(ToolError::FATAL, ToolErrorAction::ReturnToModel);
+        assert_eq!(
+            policy.action_for(&ToolError::fatal("x")),
+            ToolErrorAction::Abort
+        );
+        assert_eq!(
+            ToolErrorPolicy::default().action_for(&ToolError::not_found("x")),
+            ToolErrorAction::Abort
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Spans and events emitted with the `tracing` feature.
+//!
+//! The agent loop calls these helpers unconditionally; without the feature
+//! they do nothing and [`instrument`] returns the future unchanged.
+//!
+//! A run is an `agent.run` span. Each of its completion requests is an
+//! `agent.iteration` span and each tool call an `agent.tool` span, both
+//! children of the run's. Token usage and sizes are recorded as span
+//! fields; errors are `ERROR` events inside the span that failed.
+
+use crate::agent::Usage;
+use std::future::Future;
+use std::time::Duration;
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+
+/// Stands in for `tracing::Span` without the `tracing` feature.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Span;
+
+/// The span of a whole run.
+pub(crate) fn run_span(agent: Option<&str>, model: &str) -> Span {
+    #[cfg(feature = "tracing")]
+    {
+        tracing::info_span!(
+            "agent.run",
+            agent.name = agent.unwrap_or_default(),
+            agent.model = model,
+            iterations = tracing::field::Empty,
+            usage.prompt_tokens = tracing::field::Empty,
+            usage.completion_tokens = tracing::field::Empty,
+            usage.total_tokens = tracing::field::Empty,
+        )
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (agent, model);
+        Span
+    }
+}
+
+/// The span of one completion request of a run.
+pub(crate) fn iteration_span(iteration: usize) -> Span {
+    #[cfg(feature = "tracing")]
+    {
+        tracing::info_span!(
+            "agent.iteration",
+            iteration,
+            usage.prompt_tokens = tracing::field::Empty,
+            usage.completion_tokens = tracing::field::Empty,
+            usage.total_tokens = tracing::field::Empty,
+        )
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = iteration;
+        Span
+    }
+}
+
+/// The span of one tool call.
+pub(crate) fn tool_span(tool: &str, arguments: &serde_json::Value) -> Span {
+    #[cfg(feature = "tracing")]
+    {
+        tracing::info_span!(
+            "agent.tool",
+            tool.name = tool,
+            tool.argument_bytes = arguments.to_string().len(),
+            tool.result_bytes = tracing::field::Empty,
+            tool.latency_ms = tracing::field::Empty,
+        )
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (tool, arguments);
+        Span
+    }
+}
+
+/// Runs `future` inside `span`.
+pub(crate) fn instrument<F: Future>(future: F, span: Span) -> impl Future<Output = F::Output> {
+    #[cfg(feature = "tracing")]
+    {
+        tracing::Instrument::instrument(future, span)
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = span;
+        future
+    }
+}
+
+/// Records token usage on the current run or iteration span.
+pub(crate) fn record_usage(usage: &Usage) {
+    #[cfg(feature = "tracing")]
+    {
+        let span = Span::current();
+        span.record("usage.prompt_tokens", usage.prompt_tokens);
+        span.record("usage.completion_tokens", usage.completion_tokens);
+        span.record("usage.total_tokens", usage.total_tokens);
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = usage;
+}
+
+/// Records how many iterations the current run took.
+pub(crate) fn record_iterations(iterations: usize) {
+    #[cfg(feature = "tracing")]
+    Span::current().record("iterations", iterations);
+    #[cfg(not(feature = "tracing"))]
+    let _ = iterations;
+}
+
+/// Records the outcome of the current tool call.
+pub(crate) fn record_tool_result(result_bytes: usize, latency: Duration) {
+    #[cfg(feature = "tracing")]
+    {
+        let span = Span::current();
+        span.record("tool.result_bytes", result_bytes);
+        span.record("tool.latency_ms", latency.as_millis() as u64);
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = (result_bytes, latency);
+}
+
+/// Reports an error inside the current span.
+pub(crate) fn error(error: &dyn std::fmt::Display) {
+    #[cfg(feature = "tracing")]
+    tracing::error!(error = %error);
+    #[cfg(not(feature = "tracing"))]
+    let _ = error;
+}
+
+/// Reports a call to a tool the tool set does not have.
+pub(crate) fn unknown_tool(tool: &str) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(tool.name = tool, "unknown tool");
+    #[cfg(not(feature = "tracing"))]
+    let _ = tool;
+}
@@ -0,0 +1,144 @@
+//! Non-fatal conditions reported while an agent runs.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Callback invoked with every [`Warning`] an agent emits.
+pub type WarningHandler = Arc<dyn Fn(&Warning) + Send + Sync>;
+
+/// A condition the agent recovered from, reported via
+/// [`AgentBuilder::on_warning`](crate::AgentBuilder::on_warning).
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Warning {
+    /// A tool call's arguments were cut off by the completion token limit
+    /// and a continuation was requested.
+    TruncatedToolArguments {
+        /// The tool whose arguments were truncated.
+        tool_name: String,
+        /// Which continuation this is, starting at 1.
+        continuation: usize,
+        /// Length in bytes of the arguments received so far.
+        received_bytes: usize,
+    },
+    /// A tool failed with a retryable error and is being called again.
+    ToolRetried {
+        /// The tool that failed.
+        tool_name: String,
+        /// Which retry this is, starting at 1.
+        attempt: usize,
+        /// The error code the tool reported.
+        code: String,
+    },
+    /// A completion request failed with a retryable error and is being sent
+    /// again.
+    RequestRetried {
+        /// Which retry this is, starting at 1.
+        attempt: usize,
+        /// How long the agent waits before retrying.
+        backoff: Duration,
+        /// The error the request failed with.
+        error: String,
+    },
+    /// A configured request parameter is not supported by the agent's
+    /// provider and was left out of the request.
+    ParameterDropped {
+        /// The request parameter, e.g. `"response_format"`.
+        parameter: String,
+    },
+    /// The server rejected a request parameter; the request is sent again
+    /// without it, and later requests for the model leave it out.
+    ParameterRejected {
+        /// The request parameter the server rejected.
+        parameter: String,
+        /// The model the request was for.
+        model: String,
+    },
+    /// The ambiguity gate could not classify the request, so the run
+    /// proceeded without it.
+    AmbiguityGateFailed {
+        /// Why classification failed.
+        error: String,
+    },
+    /// The oldest messages were dropped to fit the conversation into the
+    /// agent's [`ContextStrategy`](crate::context::ContextStrategy).
+    ContextTruncated {
+        /// How many messages were dropped.
+        dropped: usize,
+    },
+    /// Older messages were replaced by a summary, as configured by the
+    /// agent's [`ContextStrategy`](crate::context::ContextStrategy).
+    ContextCompacted {
+        /// How many messages were summarized.
+        summarized: usize,
+    },
+    /// An [`AgentHook`](crate::hook::AgentHook) panicked; the run went on
+    /// without it.
+    HookPanicked {
+        /// The hook method that panicked, e.g. `"on_tool_end"`.
+        event: String,
+        /// The panic message.
+        message: String,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::TruncatedToolArguments {
+                tool_name,
+                continuation,
+                received_bytes,
+            } => write!(
+                f,
+                "Arguments for tool '{}' were truncated after {} bytes; requesting continuation {}",
+                tool_name, received_bytes, continuation
+            ),
+            Warning::ToolRetried {
+                tool_name,
+                attempt,
+                code,
+            } => write!(
+                f,
+                "Tool '{}' failed with retryable error '{}'; retry {}",
+                tool_name, code, attempt
+            ),
+            Warning::RequestRetried {
+                attempt,
+                backoff,
+                error,
+            } => write!(
+                f,
+                "Request failed with retryable error '{}'; retry {} in {:?}",
+                error, attempt, backoff
+            ),
+            Warning::ParameterDropped { parameter } => write!(
+                f,
+                "Parameter '{}' is not supported by the provider; leaving it out",
+                parameter
+            ),
+            Warning::ParameterRejected { parameter, model } => write!(
+                f,
+                "Model '{}' rejected parameter '{}'; retrying without it",
+                model, parameter
+            ),
+            Warning::AmbiguityGateFailed { error } => {
+                write!(f, "Ambiguity gate failed, proceeding: {}", error)
+            }
+            Warning::ContextTruncated { dropped } => write!(
+                f,
+                "Dropped the {} oldest messages to fit the context window",
+                dropped
+            ),
+            Warning::ContextCompacted { summarized } => write!(
+                f,
+                "Summarized the {} oldest messages to fit the context window",
+                summarized
+            ),
+            Warning::HookPanicked { event, message } => {
+                write!(f, "Hook panicked in {}: {}", event, message)
+            }
+        }
+    }
+}
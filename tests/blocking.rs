@@ -0,0 +1,49 @@
+//! Uses the blocking agent from a plain `main`, with no runtime of its own.
+
+use aiform::blocking;
+use aiform::conversation::Conversation;
+use aiform::prelude::{tool, tools, Result, ToolArg};
+use aiform::testing::MockChatBackend;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+#[derive(ToolArg, Deserialize)]
+struct WeatherArgs {
+    city: String,
+}
+
+#[tool("Get the current weather in a city")]
+async fn get_weather(args: WeatherArgs) -> Result<String> {
+    Ok(format!("21°C in {}", args.city))
+}
+
+fn main() {
+    let backend = Arc::new(
+        MockChatBackend::new()
+            .respond_with_tool_call("get_weather", json!({ "city": "Lisbon" }))
+            .respond_with_text("It is 21°C in Lisbon.")
+            .respond_with_text("Yes, it is warm enough."),
+    );
+    let agent = blocking::Agent::builder()
+        .model("mock-model")
+        .backend(backend.clone())
+        .tools(tools![GetWeatherTool])
+        .build()
+        .unwrap();
+
+    let mut conversation = Conversation::new();
+    conversation.add_user_message("What's the weather in Lisbon?");
+    let answer = agent.run_conversation(&mut conversation).unwrap();
+    assert_eq!(answer, "It is 21°C in Lisbon.");
+
+    conversation.add_user_message("Warm enough for the beach?");
+    let answer = agent.run_conversation(&mut conversation).unwrap();
+    assert_eq!(answer, "Yes, it is warm enough.");
+    assert_eq!(backend.requests().len(), 3);
+
+    let output = agent
+        .call_tool("get_weather", json!({ "city": "Porto" }))
+        .unwrap();
+    assert_eq!(output, "21°C in Porto");
+}
@@ -0,0 +1,10 @@
+#[test]
+fn tool_arg_derive() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/ui/desc_*.rs");
+    cases.pass("tests/ui/doc_comments.rs");
+    cases.pass("tests/ui/generics.rs");
+    cases.pass("tests/ui/tool_visibility.rs");
+    cases.pass("tests/ui/zero_args.rs");
+    cases.compile_fail("tests/ui/fail/*.rs");
+}
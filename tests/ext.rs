@@ -0,0 +1,183 @@
+use aiform::ext::{OpenAIClientExt, ToolCallResponse};
+use aiform::prelude::*;
+use async_openai::config::OpenAIConfig;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use wiremock::matchers::{body_partial_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[derive(ToolArg, Deserialize)]
+struct WeatherArgs {
+    city: String,
+}
+
+#[tool("Get the current weather in a city")]
+async fn get_weather(args: WeatherArgs) -> Result<String> {
+    Ok(format!("21°C in {}", args.city))
+}
+
+fn client(server: &MockServer) -> async_openai::Client<OpenAIConfig> {
+    let config = OpenAIConfig::new()
+        .with_api_base(server.uri())
+        .with_api_key("test-key");
+    async_openai::Client::with_config(config)
+}
+
+fn completion(message: Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion",
+        "created": 0,
+        "model": "gpt-4o",
+        "choices": [{ "index": 0, "message": message, "finish_reason": "stop" }],
+    }))
+}
+
+#[tokio::test]
+async fn test_call_with_tools_dispatches_tool_calls() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/chat/completions"))
+        .and(body_partial_json(json!({
+            "model": "gpt-4o",
+            "tools": [{ "type": "function", "function": { "name": "get_weather" } }],
+        })))
+        .respond_with(completion(json!({
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": { "name": "get_weather", "arguments": "{\"city\":\"Lisbon\"}" },
+            }],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let response = client(&server)
+        .call_with_tools(
+            "gpt-4o",
+            vec![msg!(user "Weather in Lisbon?")],
+            &tools![GetWeatherTool],
+        )
+        .await
+        .unwrap();
+    let ToolCallResponse::ToolCalls(calls) = response else {
+        panic!("expected tool calls, got {:?}", response);
+    };
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].id, "call_1");
+    assert_eq!(calls[0].name, "get_weather");
+    assert_eq!(calls[0].arguments, json!({ "city": "Lisbon" }));
+    assert_eq!(calls[0].output, "21°C in Lisbon");
+}
+
+#[tokio::test]
+async fn test_call_with_tools_returns_content() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/chat/completions"))
+        .respond_with(completion(
+            json!({ "role": "assistant", "content": "Hello!" }),
+        ))
+        .mount(&server)
+        .await;
+
+    let response = client(&server)
+        .call_with_tools("gpt-4o", vec![msg!(user "Hi")], &tools![GetWeatherTool])
+        .await
+        .unwrap();
+    assert_eq!(response, ToolCallResponse::Content("Hello!".into()));
+}
+
+#[tokio::test]
+async fn test_call_with_tools_reports_unknown_tools() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/chat/completions"))
+        .respond_with(completion(json!({
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": { "name": "book_flight", "arguments": "{}" },
+            }],
+        })))
+        .mount(&server)
+        .await;
+
+    let error = client(&server)
+        .call_with_tools(
+            "gpt-4o",
+            vec![msg!(user "Book it")],
+            &tools![GetWeatherTool],
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Error::ToolNotFound(ref name) if name == "book_flight"));
+}
+
+#[derive(StructuredOutput, ToolArg, Deserialize, Debug, PartialEq)]
+struct Invoice {
+    number: String,
+    total: f64,
+}
+
+#[tokio::test]
+async fn test_structured_output_requests_strict_schema() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/chat/completions"))
+        .and(body_partial_json(json!({
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "Invoice",
+                    "strict": true,
+                    "schema": { "additionalProperties": false },
+                },
+            },
+        })))
+        .respond_with(completion(json!({
+            "role": "assistant",
+            "content": "{\"number\": \"INV-7\", \"total\": 99.5}",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let invoice: Invoice = client(&server)
+        .structured_output("gpt-4o", vec![msg!(user "Extract the invoice")])
+        .await
+        .unwrap();
+    assert_eq!(
+        invoice,
+        Invoice {
+            number: "INV-7".into(),
+            total: 99.5,
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_structured_output_reports_raw_content() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/chat/completions"))
+        .respond_with(completion(json!({
+            "role": "assistant",
+            "content": "{\"number\": 7}",
+        })))
+        .mount(&server)
+        .await;
+
+    let error = client(&server)
+        .structured_output::<Invoice>("gpt-4o", vec![msg!(user "Extract the invoice")])
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, Error::StructuredOutputParse { ref raw, .. } if raw == "{\"number\": 7}")
+    );
+}
@@ -0,0 +1 @@
+//! Intentionally empty; see the `tests` directory.
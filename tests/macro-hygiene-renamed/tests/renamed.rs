@@ -0,0 +1,45 @@
+//! The macros must work when aiform is depended on under another name, with
+//! the crate path overridden like serde's `#[serde(crate = "...")]`.
+
+use renamed_aiform::prelude::*;
+
+#[derive(ToolArg, StructuredOutput, serde::Deserialize)]
+#[aiform(crate = "renamed_aiform")]
+struct EchoArgs {
+    text: String,
+    times: Option<u32>,
+}
+
+#[tool("Repeat some text", crate = "renamed_aiform")]
+async fn echo(args: EchoArgs) -> Result<String> {
+    Ok(args.text.repeat(args.times.unwrap_or(1) as usize))
+}
+
+#[test]
+fn derives_use_the_renamed_crate() {
+    let schema = <EchoArgs as ToolArg>::schema();
+    assert_eq!(schema["properties"]["text"]["type"], "string");
+    assert_eq!(schema["required"].as_array().unwrap().len(), 1);
+    let strict = <EchoArgs as StructuredOutput>::schema();
+    assert_eq!(strict["properties"]["text"], schema["properties"]["text"]);
+    assert_eq!(strict["required"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn tools_use_the_renamed_crate() {
+    assert_eq!(EchoTool::EFFECTS, ToolEffects::Mutating);
+
+    let toolset = tools![EchoTool];
+    let call = renamed_aiform::openai::types::ChatCompletionMessageToolCall {
+        id: "call_1".to_string(),
+        r#type: renamed_aiform::openai::types::ChatCompletionToolType::Function,
+        function: renamed_aiform::openai::types::FunctionCall {
+            name: "echo".to_string(),
+            arguments: r#"{"text": "ab", "times": 2}"#.to_string(),
+        },
+    };
+    let results = renamed_aiform::dispatch_tool_calls(&[call], &toolset)
+        .await
+        .unwrap();
+    assert_eq!(results, vec!["abab"]);
+}
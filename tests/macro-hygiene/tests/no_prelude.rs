@@ -0,0 +1,66 @@
+//! The macros must expand with nothing imported: no prelude, no traits and no
+//! `serde_json` dependency.
+
+#[derive(aiform::ToolArg, aiform::StructuredOutput, serde::Deserialize)]
+struct LookupArgs {
+    #[desc("The record to look up")]
+    id: u32,
+    filter: Option<Filter>,
+}
+
+#[derive(aiform::ToolArg, serde::Deserialize)]
+#[desc("How to filter results")]
+enum Filter {
+    Active,
+    Since(String),
+}
+
+#[aiform::tool("Look up a record", effects = "read_only")]
+async fn lookup(args: LookupArgs) -> Result<String, aiform::Error> {
+    let filter = match args.filter {
+        Some(Filter::Active) => "active".to_string(),
+        Some(Filter::Since(date)) => format!("since {}", date),
+        None => "all".to_string(),
+    };
+    Ok(format!("record {} ({})", args.id, filter))
+}
+
+#[test]
+fn derives_expand_without_imports() {
+    let schema = <LookupArgs as aiform::ToolArg>::schema();
+    assert_eq!(
+        schema["properties"]["id"]["description"],
+        "The record to look up"
+    );
+    assert_eq!(schema["required"][0], "id");
+    assert_eq!(
+        schema["properties"]["filter"]["description"],
+        "How to filter results"
+    );
+    let strict = <LookupArgs as aiform::StructuredOutput>::schema();
+    assert_eq!(strict["properties"]["id"], schema["properties"]["id"]);
+    assert_eq!(strict["additionalProperties"], false);
+}
+
+#[tokio::test]
+async fn tools_expand_without_imports() {
+    assert_eq!(
+        <LookupTool as aiform::Tool>::EFFECTS,
+        aiform::ToolEffects::ReadOnly
+    );
+
+    let toolset = aiform::tools![LookupTool];
+    assert_eq!(toolset.tools()[0].function.name, "lookup");
+    let call = aiform::openai::types::ChatCompletionMessageToolCall {
+        id: "call_1".to_string(),
+        r#type: aiform::openai::types::ChatCompletionToolType::Function,
+        function: aiform::openai::types::FunctionCall {
+            name: "lookup".to_string(),
+            arguments: r#"{"id": 7}"#.to_string(),
+        },
+    };
+    let results = aiform::dispatch_tool_calls(&[call], &toolset)
+        .await
+        .unwrap();
+    assert_eq!(results, vec!["record 7 (all)"]);
+}
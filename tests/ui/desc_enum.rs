@@ -0,0 +1,33 @@
+use aiform::ToolArg;
+use serde::Deserialize;
+
+#[derive(ToolArg, Deserialize)]
+#[desc("What to do with the order")]
+#[allow(dead_code)]
+enum Action {
+    #[desc("Ship the order")]
+    Ship {
+        #[desc("The carrier to use")]
+        carrier: String,
+    },
+    #[desc("Cancel the order")]
+    Cancel(#[desc("Why it was cancelled")] String),
+    Hold,
+}
+
+fn main() {
+    let schema = Action::schema();
+    assert_eq!(schema["description"], "What to do with the order");
+    let variants = schema["oneOf"].as_array().unwrap();
+    assert_eq!(variants[0]["description"], "Ship the order");
+    assert_eq!(
+        variants[0]["properties"]["Ship"]["properties"]["carrier"]["description"],
+        "The carrier to use"
+    );
+    assert_eq!(variants[1]["description"], "Cancel the order");
+    assert_eq!(
+        variants[1]["properties"]["Cancel"]["description"],
+        "Why it was cancelled"
+    );
+    assert!(variants[2].get("description").is_none());
+}
@@ -0,0 +1,27 @@
+use aiform::ToolArg;
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(ToolArg, Deserialize)]
+#[desc("Where to look up the weather")]
+#[allow(dead_code)]
+struct WeatherArgs {
+    #[desc("The city to check")]
+    city: String,
+    #[desc("How many days to forecast")]
+    days: u32,
+    #[desc("Include hourly data")]
+    hourly: Option<bool>,
+    #[desc("Readings to include")]
+    readings: Vec<String>,
+}
+
+fn main() {
+    let schema = WeatherArgs::schema();
+    assert_eq!(schema["description"], "Where to look up the weather");
+    let properties = &schema["properties"];
+    assert_eq!(properties["city"], json!({ "type": "string", "description": "The city to check" }));
+    assert_eq!(properties["days"]["description"], "How many days to forecast");
+    assert_eq!(properties["hourly"]["description"], "Include hourly data");
+    assert_eq!(properties["readings"]["description"], "Readings to include");
+}
@@ -0,0 +1,56 @@
+use aiform::{tool, Tool, ToolArg};
+use serde::Deserialize;
+
+/// Where to look up the weather.
+#[derive(ToolArg, Deserialize)]
+#[allow(dead_code)]
+struct WeatherArgs {
+    /// The city to check,
+    /// e.g. "Lisbon".
+    city: String,
+    /// Ignored in favour of the explicit description.
+    #[desc("Days to forecast")]
+    days: u32,
+    /// Units for temperatures.
+    unit: Unit,
+}
+
+#[derive(ToolArg, Deserialize)]
+#[allow(dead_code)]
+enum Unit {
+    /// Degrees Celsius.
+    Celsius,
+    /// Degrees Fahrenheit.
+    ///
+    /// Only used in the US.
+    Fahrenheit,
+}
+
+/// Get the weather forecast for a city.
+#[tool]
+async fn get_weather(args: WeatherArgs) -> Result<String, std::io::Error> {
+    Ok(args.city)
+}
+
+/// Not used as the description.
+#[tool("Get the current temperature")]
+async fn get_temperature(args: WeatherArgs) -> Result<String, std::io::Error> {
+    Ok(args.city)
+}
+
+fn main() {
+    let schema = WeatherArgs::schema();
+    assert_eq!(schema["description"], "Where to look up the weather.");
+    let properties = &schema["properties"];
+    assert_eq!(properties["city"]["description"], "The city to check, e.g. \"Lisbon\".");
+    assert_eq!(properties["days"]["description"], "Days to forecast");
+    assert_eq!(properties["unit"]["description"], "Units for temperatures.");
+
+    assert_eq!(
+        Unit::schema()["description"],
+        "- Celsius: Degrees Celsius.\n- Fahrenheit: Degrees Fahrenheit. Only used in the US."
+    );
+
+    assert_eq!(GetWeatherTool::DESCRIPTION, "Get the weather forecast for a city.");
+    assert_eq!(GetTemperatureTool::DESCRIPTION, "Get the current temperature");
+}
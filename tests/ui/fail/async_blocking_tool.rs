@@ -0,0 +1,8 @@
+use aiform::tool;
+
+#[tool("Sleep", blocking)]
+async fn sleep() -> Result<String, std::io::Error> {
+    Ok(String::new())
+}
+
+fn main() {}
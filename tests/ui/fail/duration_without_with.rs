@@ -0,0 +1,9 @@
+use aiform::ToolArg;
+use std::time::Duration;
+
+#[derive(ToolArg, serde::Deserialize)]
+struct PollArgs {
+    interval: Duration,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use aiform::ToolArg;
+use serde::Deserialize;
+
+#[derive(ToolArg, Deserialize)]
+struct PageArgs {
+    #[schema(pattern = "^[0-9]+$")]
+    page: u32,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use aiform::ToolArg;
+use serde::Deserialize;
+
+#[derive(ToolArg, Deserialize)]
+struct PageArgs {
+    #[serde(default)]
+    #[schema(default = "ten")]
+    page: u32,
+}
+
+fn main() {}
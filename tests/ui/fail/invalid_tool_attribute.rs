@@ -0,0 +1,8 @@
+use aiform::tool;
+
+#[tool(42)]
+async fn answer() -> Result<String, std::io::Error> {
+    Ok(String::new())
+}
+
+fn main() {}
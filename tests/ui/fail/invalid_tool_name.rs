@@ -0,0 +1,14 @@
+use aiform::{tool, ToolArg};
+use serde::Deserialize;
+
+#[derive(ToolArg, Deserialize)]
+struct ReadArgs {
+    path: String,
+}
+
+#[tool(name = "file.read", description = "Read a file")]
+async fn read_file(args: ReadArgs) -> Result<String, std::io::Error> {
+    Ok(args.path)
+}
+
+fn main() {}
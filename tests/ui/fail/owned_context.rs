@@ -0,0 +1,11 @@
+use aiform::tool;
+
+struct AppState;
+
+#[tool("Check the state")]
+async fn check(#[context] state: AppState) -> Result<String, std::io::Error> {
+    let _ = state;
+    Ok(String::new())
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+mod clock {
+    use aiform::tool;
+
+    /// Get the current time.
+    #[tool]
+    async fn get_current_time() -> Result<String, std::io::Error> {
+        Ok("12:00".to_string())
+    }
+}
+
+fn main() {
+    let _tools = aiform::tools![clock::GetCurrentTimeTool];
+}
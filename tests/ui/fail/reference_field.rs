@@ -0,0 +1,13 @@
+use aiform::ToolArg;
+
+#[derive(ToolArg)]
+struct GreetArgs<'a> {
+    name: &'a str,
+}
+
+#[derive(ToolArg)]
+struct SumArgs<'a> {
+    values: &'a [u32],
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use aiform::tool;
+
+struct Counter;
+
+impl Counter {
+    #[tool("Count")]
+    async fn count(&self) -> Result<String, std::io::Error> {
+        Ok(String::new())
+    }
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use aiform::ToolArg;
+
+#[derive(ToolArg)]
+union Bits {
+    int: u32,
+    float: f32,
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use aiform::tool;
+
+#[tool("Add numbers", args(a = "first operand", c = "third operand"))]
+async fn add(a: f64, b: f64) -> Result<String, std::io::Error> {
+    Ok((a + b).to_string())
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use aiform::ToolArg;
+
+#[derive(ToolArg)]
+struct SortArgs {
+    compare: fn(f64, f64) -> bool,
+}
+
+fn main() {}
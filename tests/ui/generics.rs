@@ -0,0 +1,69 @@
+use aiform::{StructuredOutput, ToolArg};
+use serde::Deserialize;
+use serde_json::json;
+use std::marker::PhantomData;
+
+#[derive(ToolArg, StructuredOutput, Deserialize)]
+struct Item {
+    sku: String,
+}
+
+/// One page of results.
+#[derive(ToolArg, StructuredOutput, Deserialize)]
+struct Page<T> {
+    items: Vec<T>,
+    cursor: Option<String>,
+}
+
+#[derive(ToolArg, Deserialize)]
+enum Either<L, R> {
+    Left(L),
+    Right { value: R },
+}
+
+#[derive(ToolArg, Deserialize)]
+struct Labeled<'a, K: Clone, V>
+where
+    V: Default,
+{
+    key: K,
+    value: Option<V>,
+    #[serde(skip)]
+    source: PhantomData<&'a str>,
+}
+
+// Only `T: Copy` is required, so `Raw` needs no `ToolArg` impl.
+#[derive(ToolArg, Deserialize)]
+#[tool_arg(bound = "T: Copy")]
+struct Counted<T> {
+    count: u32,
+    #[serde(skip)]
+    marker: PhantomData<T>,
+}
+
+#[derive(Clone, Copy)]
+struct Raw;
+
+fn main() {
+    let schema = <Page<Item> as ToolArg>::schema();
+    assert_eq!(schema["description"], "One page of results.");
+    assert_eq!(schema["properties"]["items"]["items"]["properties"]["sku"], json!({ "type": "string" }));
+    assert_eq!(schema["required"], json!(["items"]));
+
+    let schema = <Page<u64> as ToolArg>::schema();
+    assert_eq!(schema["properties"]["items"]["items"], json!({ "type": "integer" }));
+
+    let schema = <Page<Page<Item>> as StructuredOutput>::schema();
+    assert_eq!(schema["properties"]["items"]["items"]["additionalProperties"], false);
+
+    let schema = <Either<Item, u32>>::schema();
+    assert_eq!(schema["oneOf"][1]["properties"]["Right"]["properties"]["value"], json!({ "type": "integer" }));
+
+    let schema = <Labeled<'static, String, i64>>::schema();
+    assert_eq!(schema["properties"]["key"], json!({ "type": "string" }));
+    assert_eq!(schema["properties"]["value"], json!({ "type": ["integer", "null"] }));
+    assert!(schema["properties"].get("source").is_none());
+
+    let schema = <Counted<Raw>>::schema();
+    assert_eq!(schema["required"], json!(["count"]));
+}
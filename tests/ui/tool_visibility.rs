@@ -0,0 +1,42 @@
+//! Generated tool structs follow their function's visibility and
+//! attributes, and are documented.
+#![deny(missing_docs, dead_code)]
+
+use aiform::{tools, Tool};
+
+/// Tools offered to other crates.
+pub mod public {
+    use aiform::tool;
+
+    /// Get the current time.
+    #[tool]
+    pub async fn get_current_time() -> Result<String, std::io::Error> {
+        Ok("12:00".to_string())
+    }
+
+    /// Get today's date.
+    #[tool(vis = "pub(crate)")]
+    pub async fn get_date() -> Result<String, std::io::Error> {
+        Ok("2024-01-01".to_string())
+    }
+
+    /// Purge the cache.
+    #[tool]
+    #[allow(dead_code)]
+    async fn purge_cache() -> Result<String, std::io::Error> {
+        Ok("purged".to_string())
+    }
+
+    /// Only compiled where the platform has a shell.
+    #[tool]
+    #[cfg(any())]
+    pub async fn run_shell() -> Result<String, std::io::Error> {
+        Ok(String::new())
+    }
+}
+
+fn main() {
+    let toolset = tools![public::GetCurrentTimeTool, public::GetDateTool];
+    toolset.validate().unwrap();
+    assert_eq!(public::GetDateTool::NAME, "get_date");
+}
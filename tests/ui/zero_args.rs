@@ -0,0 +1,14 @@
+use aiform::{tool, tools, Tool};
+
+/// Get the current time.
+#[tool]
+async fn get_current_time() -> Result<String, std::io::Error> {
+    Ok("12:00".to_string())
+}
+
+fn main() {
+    assert_eq!(GetCurrentTimeTool::NAME, "get_current_time");
+    assert_eq!(GetCurrentTimeTool::parameters()["properties"], serde_json::json!({}));
+    let toolset = tools![GetCurrentTimeTool];
+    toolset.validate().unwrap();
+}